@@ -0,0 +1,156 @@
+//! Throughput of the generator modes, run by `cargo bench --features all`.
+//!
+//! This is a plain binary rather than a criterion suite, printing the ids per
+//! second of each mode. Pass a number of seconds to run each case for longer,
+//! like `cargo bench --features all -- 5`.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use frostflake::{
+    AtomicGenerator, BufferedGenerator, Generator, GeneratorAsync, GeneratorOptions, GeneratorPool,
+    GeneratorPoolOptions,
+};
+
+const THREADS: [usize; 3] = [1, 4, 8];
+const POOL_SIZES: [usize; 4] = [1, 2, 4, 8];
+
+fn main() {
+    // cargo passes `--bench`, which is not a duration
+    let secs = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse::<f64>().ok())
+        .unwrap_or(1.0);
+    let duration = Duration::from_secs_f64(secs);
+
+    report("single", run_single(duration, GeneratorOptions::default()));
+    // without the cost of reading the clock, which dominates the above
+    let opts = GeneratorOptions::default().time_fn(counting_clock);
+    report("single/counting-clock", run_single(duration, opts));
+    for threads in THREADS {
+        let g = Arc::new(Mutex::new(Generator::new(GeneratorOptions::default())));
+        let ids = run_threads(threads, duration, move || {
+            g.lock().unwrap().try_generate().is_ok()
+        });
+        report(&format!("mutex/{}t", threads), ids);
+    }
+    for threads in THREADS {
+        let g = Arc::new(Generator::new(GeneratorOptions::default()));
+        let ids = run_threads(threads, duration, move || g.try_generate().is_ok());
+        report(&format!("shared/{}t", threads), ids);
+    }
+    for threads in THREADS {
+        let g = Arc::new(AtomicGenerator::new(GeneratorOptions::default()));
+        let ids = run_threads(threads, duration, move || g.try_generate().is_ok());
+        report(&format!("atomic/{}t", threads), ids);
+    }
+    for threads in THREADS {
+        let g = Arc::new(BufferedGenerator::new(GeneratorOptions::default(), 4096).unwrap());
+        let ids = run_threads(threads, duration, move || g.next().is_ok());
+        report(&format!("buffered/{}t", threads), ids);
+    }
+    for size in POOL_SIZES {
+        let pool = GeneratorPool::new(size, GeneratorPoolOptions::default());
+        let ids = run_threads(8, duration, move || pool.generate().is_ok());
+        report(&format!("pool/{}w/8t", size), ids);
+    }
+    for size in POOL_SIZES {
+        let opts = GeneratorPoolOptions::default().strict_ordering(true);
+        let pool = GeneratorPool::new(size, opts);
+        let ids = run_threads(8, duration, move || pool.generate().is_ok());
+        report(&format!("ordered/{}w/8t", size), ids);
+    }
+    report("async/4t", run_async(4, duration));
+}
+
+fn report(name: &str, (ids, elapsed): (u64, Duration)) {
+    println!(
+        "{:<14} {:>14.0} ids/s",
+        name,
+        ids as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn run_single(duration: Duration, opts: GeneratorOptions) -> (u64, Duration) {
+    let g = Generator::new(opts);
+    let start = Instant::now();
+    let mut ids = 0;
+    while start.elapsed() < duration {
+        for _ in 0..1000 {
+            if g.try_generate().is_ok() {
+                ids += 1;
+            }
+        }
+    }
+    (ids, start.elapsed())
+}
+
+/// A clock advancing by a tick every 1024 readings, so that the sequence is
+/// never exhausted.
+fn counting_clock() -> u64 {
+    static READINGS: AtomicU64 = AtomicU64::new(0);
+    1483228800000 + 1 + READINGS.fetch_add(1, Ordering::Relaxed) / 1024
+}
+
+/// Calls `generate` on `threads` threads at once, counting the calls that
+/// returned an id.
+fn run_threads<F>(threads: usize, duration: Duration, generate: F) -> (u64, Duration)
+where
+    F: Fn() -> bool + Send + Sync + 'static,
+{
+    let generate = Arc::new(generate);
+    let ids = Arc::new(AtomicU64::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let (generate, ids, stop) = (generate.clone(), ids.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut n = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    if generate() {
+                        n += 1;
+                    }
+                }
+                ids.fetch_add(n, Ordering::Relaxed);
+            })
+        })
+        .collect();
+    thread::sleep(duration);
+    stop.store(true, Ordering::Relaxed);
+    for h in handles {
+        h.join().unwrap();
+    }
+    (ids.load(Ordering::Relaxed), start.elapsed())
+}
+
+fn run_async(tasks: usize, duration: Duration) -> (u64, Duration) {
+    let rt = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+    rt.block_on(async move {
+        let g = GeneratorAsync::spawn(GeneratorOptions::default());
+        let start = Instant::now();
+        let handles: Vec<_> = (0..tasks)
+            .map(|_| {
+                let g = g.clone();
+                tokio::spawn(async move {
+                    let mut n = 0;
+                    while start.elapsed() < duration {
+                        if g.try_generate().await.is_ok() {
+                            n += 1;
+                        }
+                    }
+                    n
+                })
+            })
+            .collect();
+
+        let mut ids = 0;
+        for h in handles {
+            ids += h.await.unwrap();
+        }
+        (ids, start.elapsed())
+    })
+}
@@ -0,0 +1,67 @@
+//! Decodes arbitrary strings by every string codec, and round-trips the ids
+//! that decode.
+
+#![no_main]
+
+use frostflake::checksum::{CheckedEncoding, Encoding, Placement};
+use frostflake::encode::{
+    from_base58, from_base62, from_hex, from_sortable, to_base58, to_base62, to_hex, to_sortable,
+};
+use frostflake::Snowflake;
+use libfuzzer_sys::fuzz_target;
+
+type Codec = (
+    fn(u64) -> String,
+    fn(&str) -> Result<u64, frostflake::encode::DecodeError>,
+);
+
+const CODECS: [Codec; 4] = [
+    (to_base62, from_base62),
+    (to_base58, from_base58),
+    (to_hex, from_hex),
+    (to_sortable, from_sortable),
+];
+
+const ENCODINGS: [Encoding; 5] = [
+    Encoding::Decimal,
+    Encoding::Base62,
+    Encoding::Base58,
+    Encoding::Hex,
+    Encoding::Sortable,
+];
+
+fuzz_target!(|data: &[u8]| {
+    // any id encodes and decodes again
+    if let Some(bytes) = data.first_chunk::<8>() {
+        let id = u64::from_le_bytes(*bytes);
+        for (encode, decode) in CODECS {
+            assert_eq!(decode(&encode(id)), Ok(id));
+        }
+        for encoding in ENCODINGS {
+            for placement in [Placement::End, Placement::Start] {
+                let checked = CheckedEncoding::new(encoding).placement(placement);
+                assert_eq!(checked.decode(&checked.encode(id)), Ok(id));
+            }
+        }
+    }
+
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    for (encode, decode) in CODECS {
+        if let Ok(id) = decode(s) {
+            assert_eq!(decode(&encode(id)), Ok(id));
+        }
+    }
+    for encoding in ENCODINGS {
+        for placement in [Placement::End, Placement::Start] {
+            let checked = CheckedEncoding::new(encoding).placement(placement);
+            if let Ok(id) = checked.decode(s) {
+                assert!(checked.verify(&checked.encode(id)));
+            }
+        }
+    }
+    if let Ok(id) = s.parse::<Snowflake>() {
+        assert_eq!(id.to_string().parse::<Snowflake>(), Ok(id));
+    }
+});
@@ -0,0 +1,64 @@
+//! Decodes arbitrary ids by layouts of arbitrary bit splits, epochs and
+//! tick lengths.
+
+#![no_main]
+
+use frostflake::decode::{decode, decode_str, MultiLayoutDecoder};
+use frostflake::Layout;
+use libfuzzer_sys::fuzz_target;
+use std::time::{Duration, UNIX_EPOCH};
+
+// a layout of valid bits and an id from the first bytes, and the rest
+fn split(data: &[u8]) -> Option<(Layout, u64, &[u8])> {
+    let (&[ts_bits, node_bits], rest) = data.split_first_chunk::<2>()?;
+    let (base_ts, rest) = rest.split_first_chunk::<8>()?;
+    let (tick_ms, rest) = rest.split_first_chunk::<2>()?;
+    let (id, rest) = rest.split_first_chunk::<8>()?;
+
+    let ts_bits = ts_bits % 65;
+    let node_bits = node_bits % (65 - ts_bits);
+    let layout = Layout::new(ts_bits, node_bits, 64 - ts_bits - node_bits)
+        .with_base_ts(u64::from_le_bytes(*base_ts))
+        .with_tick_ms(u16::from_le_bytes(*tick_ms) as u64 + 1);
+    Some((layout, u64::from_le_bytes(*id), rest))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((layout, id, rest)) = split(data) else {
+        return;
+    };
+
+    let fields = layout.decode(id);
+    let decoded = decode(&layout, id);
+    assert_eq!((decoded.node, decoded.seq), (fields.node, fields.seq));
+    // unless the epoch and the timestamp add up past 64bit
+    if decoded
+        .timestamp_raw
+        .checked_add(layout.base_ts())
+        .is_some()
+    {
+        assert_eq!(
+            layout.compose(fields.timestamp, fields.node, fields.seq),
+            id
+        );
+    }
+    let _ = decoded.to_string();
+    let _ = decoded.datetime();
+
+    let time = UNIX_EPOCH + Duration::from_millis(decoded.unix_ms);
+    let range = layout.id_range(time, time);
+    assert!(range.start() <= range.end());
+    let _ = layout.capacity().to_string();
+
+    if let Ok(s) = std::str::from_utf8(rest) {
+        if let Ok(decoded) = decode_str(&layout, s) {
+            assert_eq!(decoded, decode(&layout, decoded.id));
+        }
+    }
+
+    let decoder = MultiLayoutDecoder::new(Layout::default())
+        .since_id(id, layout)
+        .since_time(time, Layout::discord());
+    let _ = decoder.decode(id);
+    let _ = decoder.decode(!id);
+});
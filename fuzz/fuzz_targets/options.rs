@@ -0,0 +1,56 @@
+//! Builds options of arbitrary bit splits, epochs and node numbers, and
+//! generates ids by the ones that build.
+//!
+//! The setters of `GeneratorOptions` panic on invalid values by design, so
+//! this goes through `GeneratorOptions::builder()`, which returns errors.
+
+#![no_main]
+
+use frostflake::{Generator, GeneratorOptions, OverflowPolicy};
+use libfuzzer_sys::fuzz_target;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NOW: AtomicU64 = AtomicU64::new(0);
+
+fn now() -> u64 {
+    NOW.load(Ordering::Relaxed)
+}
+
+fn word(data: &[u8], i: usize) -> u64 {
+    data.get(i * 8..i * 8 + 8)
+        .and_then(|b| b.try_into().ok())
+        .map_or(0, u64::from_le_bytes)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&[ts_bits, node_bits, seq_bits], rest)) = data.split_first_chunk::<3>() else {
+        return;
+    };
+    let (base_ts, node) = (word(rest, 0), word(rest, 1));
+    NOW.store(word(rest, 2), Ordering::Relaxed);
+
+    let Ok(opts) = GeneratorOptions::builder()
+        .bits(ts_bits, node_bits, seq_bits)
+        .base_ts(base_ts)
+        .node(node)
+        .build()
+    else {
+        return;
+    };
+    let layout = opts.layout();
+    assert_eq!(
+        layout.ts_bits() as u32 + layout.node_bits() as u32 + layout.seq_bits() as u32,
+        64
+    );
+
+    let opts = opts.time_fn(now).overflow_policy(OverflowPolicy::Error);
+    let generator = Generator::new(opts);
+    let ids: Vec<_> = (0..3)
+        .filter_map(|_| generator.try_generate().ok())
+        .collect();
+    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    for id in ids {
+        assert_eq!(generator.extract(id).1, node);
+        assert_eq!(generator.decode(id).node, node);
+    }
+});
@@ -0,0 +1,43 @@
+/* C API of frostflake, built with the `ffi` feature. See src/ffi.rs. */
+
+#ifndef FROSTFLAKE_H
+#define FROSTFLAKE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define FROSTFLAKE_OK 0
+#define FROSTFLAKE_ERR_NULL (-1)
+#define FROSTFLAKE_ERR_CLOCK_MOVED_BACKWARDS (-2)
+#define FROSTFLAKE_ERR_TIME_BEFORE_BASE_TS (-3)
+#define FROSTFLAKE_ERR_EPOCH_EXHAUSTED (-4)
+#define FROSTFLAKE_ERR_OTHER (-99)
+
+typedef struct frostflake_generator frostflake_generator;
+
+typedef struct frostflake_decoded {
+    /* milliseconds since the Unix epoch */
+    uint64_t unix_ms;
+    uint64_t node;
+    uint64_t seq;
+} frostflake_decoded;
+
+/* Returns NULL for an invalid layout. Thread-safe once created. */
+frostflake_generator *frostflake_new(uint8_t ts_bits, uint8_t node_bits, uint8_t seq_bits,
+                                     uint64_t base_ts, uint64_t node);
+void frostflake_free(frostflake_generator *generator);
+
+int32_t frostflake_generate(const frostflake_generator *generator, uint64_t *out);
+int32_t frostflake_generate_batch(const frostflake_generator *generator, uint64_t *buf, size_t len);
+int32_t frostflake_decode(const frostflake_generator *generator, uint64_t id,
+                          frostflake_decoded *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,155 @@
+use std::fs;
+use std::thread;
+
+/// Where the worker threads of a `GeneratorPool` run, set with
+/// `GeneratorPoolOptions::affinity`.
+///
+/// Pinning keeps workers from migrating between cores, which makes the
+/// throughput of a pool on a large machine steadier. It takes effect on
+/// Linux only, and a worker that can't be pinned runs wherever the
+/// scheduler puts it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Affinity {
+    /// Leaves the workers to the scheduler.
+    #[default]
+    None,
+    /// Pins worker `i` to core `i`, wrapping around the available cores.
+    Spread,
+    /// Pins worker `i` to the `i`th of these cores, wrapping around.
+    Cores(Vec<usize>),
+    /// Places `per_node` workers on each NUMA node in turn, each pinned to a
+    /// core of its node.
+    NumaNodes { per_node: usize },
+}
+
+impl Affinity {
+    /// Core of each of `size` workers, if pinned.
+    pub(crate) fn cores(&self, size: usize) -> Vec<Option<usize>> {
+        match self {
+            Affinity::None => vec![None; size],
+            Affinity::Spread => {
+                let cores = thread::available_parallelism().map_or(1, |n| n.get());
+                (0..size).map(|i| Some(i % cores)).collect()
+            }
+            Affinity::Cores(cores) if cores.is_empty() => vec![None; size],
+            Affinity::Cores(cores) => (0..size).map(|i| Some(cores[i % cores.len()])).collect(),
+            Affinity::NumaNodes { per_node } => numa_cores(&numa_nodes(), *per_node, size),
+        }
+    }
+}
+
+// places per_node workers on each node in turn, on the cores of the node in
+// turn
+fn numa_cores(nodes: &[Vec<usize>], per_node: usize, size: usize) -> Vec<Option<usize>> {
+    let nodes: Vec<_> = nodes.iter().filter(|cores| !cores.is_empty()).collect();
+    if nodes.is_empty() || per_node == 0 {
+        return vec![None; size];
+    }
+
+    (0..size)
+        .map(|i| {
+            let node = nodes[i / per_node % nodes.len()];
+            let round = i / (per_node * nodes.len());
+            Some(node[(round * per_node + i % per_node) % node.len()])
+        })
+        .collect()
+}
+
+// cores of each NUMA node, or all cores as one node if unknown
+fn numa_nodes() -> Vec<Vec<usize>> {
+    let mut nodes: Vec<(usize, Vec<usize>)> = fs::read_dir("/sys/devices/system/node")
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let id = name.to_str()?.strip_prefix("node")?.parse().ok()?;
+            let list = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            Some((id, parse_cpu_list(list.trim())?))
+        })
+        .collect();
+    nodes.sort();
+
+    if nodes.is_empty() {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        return vec![(0..cores).collect()];
+    }
+    nodes.into_iter().map(|(_, cores)| cores).collect()
+}
+
+// a list like `0-3,8-11` of /sys
+fn parse_cpu_list(s: &str) -> Option<Vec<usize>> {
+    let mut cores = vec![];
+    for range in s.split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((lo, hi)) => cores.extend(lo.parse::<usize>().ok()?..=hi.parse().ok()?),
+            None => cores.push(range.parse().ok()?),
+        }
+    }
+    Some(cores)
+}
+
+/// Pins the calling thread to `core`, and tells whether it worked.
+#[cfg(target_os = "linux")]
+pub(crate) fn pin(core: usize) -> bool {
+    // SAFETY: the set is a plain bit mask, initialized by zeroing
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin(_core: usize) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(
+            parse_cpu_list("0-3,8-9,12"),
+            Some(vec![0, 1, 2, 3, 8, 9, 12])
+        );
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+    }
+
+    #[test]
+    fn test_cores() {
+        let nodes = [vec![0, 1, 2], vec![4, 5]];
+        assert_eq!(
+            numa_cores(&nodes, 2, 7),
+            [
+                Some(0),
+                Some(1),
+                Some(4),
+                Some(5),
+                Some(2),
+                Some(0),
+                Some(4)
+            ]
+        );
+        assert_eq!(numa_cores(&[], 2, 2), [None, None]);
+
+        assert_eq!(Affinity::None.cores(2), [None, None]);
+        assert_eq!(
+            Affinity::Cores(vec![3, 5]).cores(3),
+            [Some(3), Some(5), Some(3)]
+        );
+        assert_eq!(Affinity::Spread.cores(1), [Some(0)]);
+        assert_eq!(Affinity::NumaNodes { per_node: 1 }.cores(4).len(), 4);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pin() {
+        let pinned = thread::spawn(|| pin(0)).join().unwrap();
+        assert!(pinned);
+    }
+}
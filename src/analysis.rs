@@ -0,0 +1,370 @@
+//! Checks of a configuration before deploying it.
+//!
+//! [`estimate`] works out how long a [`Layout`] lasts, how many ids a process
+//! can generate per second, and how likely its node numbers are to collide
+//! across a fleet, and warns about what looks risky.
+//!
+//! ```rust
+//! use frostflake::analysis::{estimate, AnalysisConfig, Warning};
+//! use frostflake::node_id::NodeIdStrategy;
+//! use frostflake::Layout;
+//!
+//! let cfg = AnalysisConfig::new(Layout::default())
+//!     .node_strategy(NodeIdStrategy::Hostname)
+//!     .ids_per_sec(100_000)
+//!     .processes(38);
+//! let report = estimate(&cfg);
+//! assert!(report.collision_probability > 0.49);
+//! assert!(matches!(report.warnings[0], Warning::NodeCollision { .. }));
+//! println!("{}", report);
+//! ```
+
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::layout::{Capacity, Layout};
+use crate::node_id::NodeIdStrategy;
+
+// remaining lifetime of an epoch below which it is reported as ending
+const EPOCH_ENDING: Duration = Duration::from_secs(365 * 86400);
+
+/// What [`estimate`] checks: a layout, how node numbers are assigned, and
+/// the load of the fleet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalysisConfig {
+    layout: Layout,
+    node_strategy: Option<NodeIdStrategy>,
+    ids_per_sec: u64,
+    processes: u64,
+}
+
+impl AnalysisConfig {
+    /// Starts with a single process, generating no ids, with a node number
+    /// set by hand.
+    pub fn new(layout: Layout) -> Self {
+        AnalysisConfig {
+            layout,
+            node_strategy: None,
+            ids_per_sec: 0,
+            processes: 1,
+        }
+    }
+
+    /// Where the processes derive their node numbers from, as set with
+    /// `GeneratorOptions::node_strategy`.
+    ///
+    /// Without a strategy, node numbers are taken to be assigned uniquely,
+    /// by hand or by a [`NodeLease`](crate::node_id::NodeLease).
+    pub fn node_strategy(mut self, strategy: NodeIdStrategy) -> Self {
+        self.node_strategy = Some(strategy);
+        self
+    }
+
+    /// Ids per second of the whole fleet at peak, taken to be spread evenly
+    /// over the processes.
+    pub fn ids_per_sec(mut self, ids_per_sec: u64) -> Self {
+        self.ids_per_sec = ids_per_sec;
+        self
+    }
+
+    /// Number of processes generating ids at once, each with a node number
+    /// of its own.
+    pub fn processes(mut self, processes: u64) -> Self {
+        self.processes = processes;
+        self
+    }
+}
+
+/// Result of [`estimate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Capacity of the layout, as of [`Layout::capacity`].
+    pub capacity: Capacity,
+    /// How long ids can still be generated before the timestamp field is
+    /// exhausted.
+    pub remaining: Duration,
+    /// Ids a single process generates per second at peak.
+    pub ids_per_sec_per_process: u64,
+    /// Chance that any two processes get the same node number, within the
+    /// scope the node strategy is unique in.
+    pub collision_probability: f64,
+    /// What looks risky, most severe first.
+    pub warnings: Vec<Warning>,
+}
+
+/// A risk found by [`estimate`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// There are more processes than node numbers the node strategy can
+    /// give, so some of them are bound to share one.
+    TooManyProcesses { processes: u64, max: u64 },
+    /// Node numbers are hashed or cut to the node bits, and two processes
+    /// share one with the chance of the birthday bound.
+    NodeCollision { probability: f64 },
+    /// Node numbers are only unique within a part of the fleet, like a
+    /// subnet or a host.
+    NotUniqueAcross { unique_within: &'static str },
+    /// A process has to generate more ids per second than its sequence
+    /// allows, so it waits for the next tick or fails with
+    /// `Error::SequenceExhausted`.
+    SequenceExhausted {
+        ids_per_sec: u64,
+        max_ids_per_sec: u64,
+    },
+    /// The timestamp field is exhausted already.
+    EpochExhausted,
+    /// The timestamp field is exhausted within a year.
+    EpochEnding { remaining: Duration },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::TooManyProcesses { processes, max } => write!(
+                f,
+                "{} processes share at most {} node numbers",
+                processes, max
+            ),
+            Warning::NodeCollision { probability } => write!(
+                f,
+                "node numbers of two processes collide with a chance of {:.3}%",
+                probability * 100.0
+            ),
+            Warning::NotUniqueAcross { unique_within } => {
+                write!(f, "node numbers are only unique within {}", unique_within)
+            }
+            Warning::SequenceExhausted {
+                ids_per_sec,
+                max_ids_per_sec,
+            } => write!(
+                f,
+                "a process generates {} ids/sec, over the {} ids/sec of its sequence",
+                ids_per_sec, max_ids_per_sec
+            ),
+            Warning::EpochExhausted => write!(f, "the epoch is exhausted"),
+            Warning::EpochEnding { remaining } => write!(
+                f,
+                "the epoch is exhausted in {} days",
+                remaining.as_secs() / 86400
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} remaining_days={} ids_per_sec_per_process={} collision_probability={}",
+            self.capacity,
+            self.remaining.as_secs() / 86400,
+            self.ids_per_sec_per_process,
+            self.collision_probability
+        )?;
+        for warning in &self.warnings {
+            write!(f, "\nwarning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// Estimates the lifetime, throughput and collision risk of `cfg`.
+pub fn estimate(cfg: &AnalysisConfig) -> Report {
+    estimate_at(cfg, SystemTime::now())
+}
+
+fn estimate_at(cfg: &AnalysisConfig, now: SystemTime) -> Report {
+    let capacity = cfg.layout.capacity();
+    let remaining = capacity
+        .exhausted_at
+        .duration_since(now)
+        .unwrap_or_default();
+    let ids_per_sec_per_process = cfg.ids_per_sec.div_ceil(cfg.processes.max(1));
+    let processes = cfg.processes;
+
+    let mut warnings = vec![];
+    let mut collision_probability = 0.0;
+
+    // node numbers the strategy can give at most, and whether it picks them
+    // like a hash
+    let (max_nodes, hashed, unique_within) = match cfg.node_strategy {
+        None => (capacity.max_nodes, false, None),
+        Some(NodeIdStrategy::MacAddress | NodeIdStrategy::Hostname) => {
+            (capacity.max_nodes, true, None)
+        }
+        #[cfg(feature = "k8s")]
+        Some(NodeIdStrategy::PodIp) => (capacity.max_nodes, true, None),
+        Some(NodeIdStrategy::IpAddress) => {
+            (capacity.max_nodes.min(256), false, Some("a /24 subnet"))
+        }
+        Some(NodeIdStrategy::PodOrdinal) => (capacity.max_nodes, false, Some("a StatefulSet")),
+        #[cfg(feature = "k8s")]
+        Some(NodeIdStrategy::StatefulSetOrdinal) => {
+            (capacity.max_nodes, false, Some("a StatefulSet"))
+        }
+        Some(NodeIdStrategy::RangeLease { lo, hi }) => {
+            let hi = hi.min(capacity.max_nodes - 1);
+            let ids = if lo > hi { 0 } else { hi - lo + 1 };
+            (ids, false, Some("a host"))
+        }
+    };
+
+    if processes > max_nodes {
+        collision_probability = 1.0;
+        warnings.push(Warning::TooManyProcesses {
+            processes,
+            max: max_nodes,
+        });
+    } else if hashed && processes > 1 {
+        collision_probability = birthday_bound(processes, max_nodes);
+        warnings.push(Warning::NodeCollision {
+            probability: collision_probability,
+        });
+    }
+    if let Some(unique_within) = unique_within.filter(|_| processes > 1) {
+        warnings.push(Warning::NotUniqueAcross { unique_within });
+    }
+
+    if ids_per_sec_per_process > capacity.max_ids_per_sec {
+        warnings.push(Warning::SequenceExhausted {
+            ids_per_sec: ids_per_sec_per_process,
+            max_ids_per_sec: capacity.max_ids_per_sec,
+        });
+    }
+
+    if remaining.is_zero() {
+        warnings.push(Warning::EpochExhausted);
+    } else if remaining < EPOCH_ENDING {
+        warnings.push(Warning::EpochEnding { remaining });
+    }
+
+    Report {
+        capacity,
+        remaining,
+        ids_per_sec_per_process,
+        collision_probability,
+        warnings,
+    }
+}
+
+// chance that any two of n values picked at random out of d are the same,
+// approximated by 1 - e^(-n(n-1)/2d)
+fn birthday_bound(n: u64, d: u64) -> f64 {
+    let pairs = n as f64 * (n as f64 - 1.0) / 2.0;
+    -(-pairs / d as f64).exp_m1()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    // 2024-01-01T00:00:00Z
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1704067200)
+    }
+
+    #[test]
+    fn test_estimate() {
+        let cfg = AnalysisConfig::new(Layout::default())
+            .ids_per_sec(1_000_000)
+            .processes(10);
+        let report = estimate_at(&cfg, now());
+        assert_eq!(report.ids_per_sec_per_process, 100_000);
+        assert_eq!(report.capacity.max_ids_per_sec, 4_096_000);
+        assert_eq!(report.remaining.as_secs() / 86400, 48347);
+        assert_eq!(report.collision_probability, 0.0);
+        assert!(report.warnings.is_empty());
+
+        let report = estimate_at(&cfg.ids_per_sec(50_000_000), now());
+        assert_eq!(
+            report.warnings,
+            [Warning::SequenceExhausted {
+                ids_per_sec: 5_000_000,
+                max_ids_per_sec: 4_096_000
+            }]
+        );
+
+        let report = estimate_at(&cfg.processes(1025), now());
+        assert_eq!(report.collision_probability, 1.0);
+        assert_eq!(
+            report.warnings,
+            [Warning::TooManyProcesses {
+                processes: 1025,
+                max: 1024
+            }]
+        );
+    }
+
+    #[test]
+    fn test_estimate_node_strategy() {
+        let cfg = AnalysisConfig::new(Layout::default())
+            .node_strategy(NodeIdStrategy::Hostname)
+            .processes(38);
+        let report = estimate_at(&cfg, now());
+        assert!((report.collision_probability - 0.4967).abs() < 0.0001);
+        assert_eq!(
+            report.warnings,
+            [Warning::NodeCollision {
+                probability: report.collision_probability
+            }]
+        );
+        // a single process can't collide
+        let report = estimate_at(&cfg.processes(1), now());
+        assert!(report.warnings.is_empty());
+
+        let cfg = cfg.node_strategy(NodeIdStrategy::IpAddress).processes(300);
+        assert_eq!(
+            estimate_at(&cfg, now()).warnings,
+            [
+                Warning::TooManyProcesses {
+                    processes: 300,
+                    max: 256
+                },
+                Warning::NotUniqueAcross {
+                    unique_within: "a /24 subnet"
+                }
+            ]
+        );
+
+        let cfg = cfg
+            .node_strategy(NodeIdStrategy::RangeLease { lo: 4, hi: 7 })
+            .processes(4);
+        assert_eq!(
+            estimate_at(&cfg, now()).warnings,
+            [Warning::NotUniqueAcross {
+                unique_within: "a host"
+            }]
+        );
+        assert_eq!(
+            estimate_at(&cfg.processes(5), now()).warnings[0],
+            Warning::TooManyProcesses {
+                processes: 5,
+                max: 4
+            }
+        );
+    }
+
+    #[test]
+    fn test_estimate_epoch() {
+        // 2017-01-01T00:00:00Z plus 2^35ms, a bit over a year
+        let cfg = AnalysisConfig::new(Layout::new(35, 17, 12));
+        let report = estimate_at(&cfg, now());
+        assert_eq!(report.warnings, [Warning::EpochExhausted]);
+        assert_eq!(report.remaining, Duration::ZERO);
+
+        let report = estimate_at(&cfg, UNIX_EPOCH + Duration::from_secs(1483228800 + 86400));
+        assert_eq!(report.remaining.as_secs() / 86400, 396);
+        assert!(report.warnings.is_empty());
+        let report = estimate_at(
+            &cfg,
+            UNIX_EPOCH + Duration::from_secs(1483228800 + 300 * 86400),
+        );
+        assert!(matches!(report.warnings[..], [Warning::EpochEnding { .. }]));
+        assert_eq!(
+            report.to_string().lines().last(),
+            Some("warning: the epoch is exhausted in 97 days")
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{max, shl, tick_wait, Attempt, Error, GeneratorOptions, OverflowPolicy};
+
+/// Lock-free generator, shareable across threads without a `Mutex`.
+///
+/// The timestamp and sequence number of the last id are packed into a single
+/// `AtomicU64`, and each id is claimed with a compare-and-swap on it. Threads
+/// never wait for each other, except for retrying when another thread won the
+/// race for the same sequence number.
+///
+/// Only the bit widths, `base_ts`, `node`, the time source, the overflow
+/// policy, the backward drift and the node lease are used from the options.
+/// Audit sinks, state backends, utilization alerts and the stall timeout
+/// need per-tick bookkeeping that does not fit in the packed state, and are
+/// ignored; use a `Generator` for them.
+///
+/// ```rust
+/// use frostflake::{AtomicGenerator, GeneratorOptions};
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let generator = Arc::new(AtomicGenerator::new(GeneratorOptions::default()));
+/// let handles: Vec<_> = (0..4)
+///     .map(|_| {
+///         let generator = generator.clone();
+///         thread::spawn(move || generator.generate())
+///     })
+///     .collect();
+/// for handle in handles {
+///     handle.join().unwrap();
+/// }
+/// ```
+pub struct AtomicGenerator {
+    opts: GeneratorOptions,
+    // elapsed time of the last id, followed by its sequence number
+    state: State,
+    #[cfg(feature = "debug-dedupe")]
+    dedupe: std::sync::Mutex<crate::dedupe::Dedupe>,
+}
+
+// the packed state, of this process or shared through a file
+enum State {
+    Local(AtomicU64),
+    #[cfg(all(unix, feature = "shm"))]
+    Shared(crate::shm::Mapping),
+}
+
+impl State {
+    fn atomic(&self) -> &AtomicU64 {
+        match self {
+            State::Local(state) => state,
+            #[cfg(all(unix, feature = "shm"))]
+            State::Shared(mapping) => &mapping.words()[0],
+        }
+    }
+}
+
+impl AtomicGenerator {
+    pub fn new(opts: GeneratorOptions) -> Self {
+        AtomicGenerator::with_state(opts, State::Local(AtomicU64::new(0)))
+    }
+
+    /// Same as `new`, sharing the packed state with the other processes of
+    /// the host generating through the file at `path`.
+    ///
+    /// The processes then generate ids as a single generator, of a single
+    /// node number, and need the same options. Ids are unique across them
+    /// and ascend within each process, without giving each process a node
+    /// number of its own.
+    ///
+    /// ```rust
+    /// use frostflake::{AtomicGenerator, GeneratorOptions};
+    ///
+    /// let path = std::env::temp_dir().join("frostflake-atomic-doctest");
+    /// let generator = AtomicGenerator::shared(GeneratorOptions::default(), &path).unwrap();
+    /// let other = AtomicGenerator::shared(GeneratorOptions::default(), &path).unwrap();
+    /// assert_ne!(generator.generate(), other.generate());
+    /// ```
+    #[cfg(all(unix, feature = "shm"))]
+    pub fn shared<P: AsRef<std::path::Path>>(
+        opts: GeneratorOptions,
+        path: P,
+    ) -> std::io::Result<Self> {
+        let mapping = crate::shm::Mapping::open(path.as_ref(), 1)?;
+        Ok(AtomicGenerator::with_state(opts, State::Shared(mapping)))
+    }
+
+    fn with_state(opts: GeneratorOptions, state: State) -> Self {
+        AtomicGenerator {
+            opts,
+            state,
+            #[cfg(feature = "debug-dedupe")]
+            dedupe: Default::default(),
+        }
+    }
+
+    /// Generates an id, panicking on errors like `Generator::generate`.
+    pub fn generate(&self) -> u64 {
+        self.try_generate().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate`, but returns errors instead of panicking.
+    ///
+    /// When all sequence numbers of a tick are used, this follows the
+    /// overflow policy of the options. Waiting for the next tick, or for the
+    /// clock to catch up within `max_backward_drift`, yields the thread
+    /// instead of spinning, and sleeps for ticks longer than a millisecond.
+    pub fn try_generate(&self) -> Result<u64, Error> {
+        loop {
+            let wait = match self.next_id() {
+                Attempt::Done(Err(Error::SequenceExhausted)) => match self.opts.overflow {
+                    OverflowPolicy::WaitNextTick => self.opts.wait_until(self.opts.clock.now() + 1),
+                    OverflowPolicy::Error => return Err(Error::SequenceExhausted),
+                    OverflowPolicy::Panic => panic!("{}", Error::SequenceExhausted),
+                },
+                Attempt::Done(result) => return result,
+                Attempt::Wait(wait) => wait,
+            };
+            tick_wait(wait);
+        }
+    }
+
+    fn next_id(&self) -> Attempt {
+        if let Some(lease) = &self.opts.node_lease {
+            if !lease.is_valid() {
+                return Attempt::Done(Err(Error::LeaseExpired {
+                    node: self.opts.node,
+                }));
+            }
+        }
+
+        let (ts_bits, node_bits, seq_bits) = self.opts.bits;
+        let base_ts = self.opts.base_ts;
+
+        loop {
+            // the state is loaded before reading the clock, so that a state
+            // stored by another thread in the meantime is not mistaken for the
+            // clock moving backwards
+            let current = self.state.atomic().load(Ordering::Acquire);
+            let now = self.opts.clock.now();
+            if now <= base_ts {
+                return Attempt::Done(Err(Error::TimeBeforeBaseTs { now, base_ts }));
+            }
+            let elapsed = now - base_ts;
+            if elapsed > max(ts_bits) {
+                return Attempt::Done(Err(Error::EpochExhausted { ts: now }));
+            }
+
+            let last = current.checked_shr(seq_bits as u32).unwrap_or(0);
+            let seq = if elapsed < last && last - elapsed <= self.opts.max_backward_drift {
+                return Attempt::Wait(self.opts.wait_until(last + base_ts));
+            } else if elapsed < last {
+                return Attempt::Done(Err(Error::ClockMovedBackwards {
+                    now,
+                    last_ts: last + base_ts,
+                }));
+            } else if elapsed == last {
+                let seq = (current & max(seq_bits)) + 1;
+                if seq > max(seq_bits) {
+                    return Attempt::Done(Err(Error::SequenceExhausted));
+                }
+                seq
+            } else {
+                0
+            };
+
+            let next = shl(elapsed, seq_bits) | seq;
+            if self
+                .state
+                .atomic()
+                .compare_exchange_weak(current, next, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                let id = shl(elapsed, node_bits + seq_bits) | shl(self.opts.node, seq_bits) | seq;
+                #[cfg(feature = "debug-dedupe")]
+                self.dedupe
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .check(id);
+                return Attempt::Done(Ok(id));
+            }
+        }
+    }
+}
+
+impl crate::IdGenerator for AtomicGenerator {
+    fn generate(&self) -> Result<u64, Error> {
+        self.try_generate()
+    }
+}
+
+impl crate::AsyncIdGenerator for AtomicGenerator {
+    fn generate(&self) -> crate::BoxFuture<'_, Result<u64, Error>> {
+        Box::pin(std::future::ready(self.try_generate()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_atomic() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .node(3)
+            .overflow_policy(OverflowPolicy::Error);
+        let g = AtomicGenerator::new(opts);
+        assert_eq!(g.generate(), (123 << 22) | (3 << 12));
+        assert_eq!(g.generate(), (123 << 22) | (3 << 12) | 1);
+
+        g.state
+            .atomic()
+            .store((123 << 12) | 4095, Ordering::Relaxed);
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+
+        g.state.atomic().store(124 << 12, Ordering::Relaxed);
+        assert!(matches!(
+            g.try_generate(),
+            Err(Error::ClockMovedBackwards { .. })
+        ));
+    }
+
+    #[test]
+    fn test_atomic_waits_without_spinning() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800 + 123);
+        static READS: AtomicU64 = AtomicU64::new(0);
+        fn my_time_fn() -> u64 {
+            READS.fetch_add(1, Ordering::Relaxed);
+            NOW.load(Ordering::Relaxed)
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_unit(Duration::from_secs(1))
+            .time_fn(my_time_fn)
+            .bits(42, 21, 1)
+            .max_backward_drift(5);
+        let g = AtomicGenerator::new(opts);
+        assert_eq!(g.generate(), 123 << 22);
+        assert_eq!(g.generate(), (123 << 22) + 1);
+
+        // for the next tick, then for the clock to catch up
+        for (step, id) in [(1, 124 << 22), (3, 125 << 22)] {
+            if step > 1 {
+                NOW.fetch_sub(2, Ordering::Relaxed);
+            }
+            READS.store(0, Ordering::Relaxed);
+            thread::scope(|s| {
+                let waiting = s.spawn(|| g.generate());
+                thread::sleep(Duration::from_millis(50));
+                // sleeps a millisecond at a time instead of spinning
+                assert!(READS.load(Ordering::Relaxed) < 500);
+                NOW.fetch_add(step, Ordering::Relaxed);
+                assert_eq!(waiting.join().unwrap(), id);
+            });
+        }
+    }
+
+    #[cfg(all(unix, feature = "shm"))]
+    #[test]
+    fn test_atomic_shared() {
+        let path = std::env::temp_dir().join(format!("frostflake-atomic-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let a = AtomicGenerator::shared(GeneratorOptions::default(), &path).unwrap();
+        let b = AtomicGenerator::shared(GeneratorOptions::default(), &path).unwrap();
+        let a = thread::spawn(move || stress(a, 4, 10_000));
+        let ids = stress(b, 4, 10_000)
+            .into_iter()
+            .chain(a.join().unwrap())
+            .collect::<HashSet<_>>();
+        assert_eq!(ids.len(), 80_000);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_atomic_threaded() {
+        let g = Arc::new(AtomicGenerator::new(GeneratorOptions::default()));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let g = g.clone();
+                thread::spawn(move || (0..10_000).map(|_| g.generate()).collect::<Vec<_>>())
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        for handle in handles {
+            let ids = handle.join().unwrap();
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            for id in ids {
+                assert!(seen.insert(id));
+            }
+        }
+        assert_eq!(seen.len(), 80_000);
+    }
+
+    // collects the ids of `threads` threads, checking that each thread's ids
+    // ascend
+    fn stress(g: AtomicGenerator, threads: usize, per_thread: usize) -> Vec<u64> {
+        let g = Arc::new(g);
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let g = g.clone();
+                thread::spawn(move || {
+                    let ids: Vec<_> = (0..per_thread).map(|_| g.generate()).collect();
+                    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+                    ids
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_atomic_rollover_stress() {
+        use std::collections::HashMap;
+
+        // the clock moves on every 16 readings, so that threads race for the
+        // last sequence numbers of a tick and for the first of the next one
+        static READS: AtomicU64 = AtomicU64::new(0);
+        fn my_time_fn() -> u64 {
+            1483228800000 + 1 + READS.fetch_add(1, Ordering::Relaxed) / 16
+        }
+
+        // 4 ids per tick
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 20, 2)
+            .base_ts(1483228800000);
+        let ids = stress(AtomicGenerator::new(opts), 8, 2_000);
+
+        let unique: HashSet<_> = ids.iter().collect();
+        assert_eq!(unique.len(), ids.len());
+        let mut per_tick = HashMap::new();
+        for id in &ids {
+            *per_tick.entry(id >> 22).or_insert(0) += 1;
+        }
+        assert!(per_tick.values().all(|&n| n <= 4));
+        // the sequence rolled over to the next tick many times
+        assert!(per_tick.values().filter(|&&n| n == 4).count() > 100);
+    }
+
+    #[test]
+    fn test_atomic_no_seq_bits_stress() {
+        // every id takes a tick of its own
+        static READS: AtomicU64 = AtomicU64::new(0);
+        fn my_time_fn() -> u64 {
+            1483228800000 + 1 + READS.fetch_add(1, Ordering::Relaxed) / 4
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .bits(52, 12, 0);
+        let ids = stress(AtomicGenerator::new(opts), 4, 2_000);
+        let unique: HashSet<_> = ids.iter().collect();
+        assert_eq!(unique.len(), ids.len());
+    }
+}
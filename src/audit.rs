@@ -0,0 +1,202 @@
+//! Issuance audit log.
+//!
+//! With an [`AuditSink`] set through `GeneratorOptions::audit`, a generator
+//! reports which ids it issued as one compact [`IssuanceRecord`] per tick,
+//! so that it can be proven later which instance issued which id ranges and
+//! when.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::{Core, Generator};
+
+/// Ids issued by one generator within one tick.
+///
+/// `generate` hands out the sequence numbers `0..count` of a tick, so the
+/// record covers exactly the ids composed from `tick`, `node` and those
+/// sequence numbers. Ids from `generate_not_before` are recorded one by one
+/// with a `count` of 1, as their sequence numbers count down from the top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IssuanceRecord {
+    /// Value of the timestamp field, i.e. the time elapsed since `base_ts`.
+    pub tick: u64,
+    pub node: u64,
+    pub count: u64,
+}
+
+/// Receives issuance records.
+///
+/// The records of a tick are emitted when the generator moves on to a later
+/// tick, when [`Generator::flush_audit`] is called, or when the generator is
+/// dropped.
+pub trait AuditSink {
+    fn record(&self, record: IssuanceRecord);
+}
+
+impl<F: Fn(IssuanceRecord)> AuditSink for F {
+    fn record(&self, record: IssuanceRecord) {
+        self(record)
+    }
+}
+
+/// Append-only audit log writing one `tick node count` line per record.
+///
+/// ```rust
+/// use frostflake::audit::AuditLog;
+/// use frostflake::{Generator, GeneratorOptions};
+///
+/// let log = std::env::temp_dir().join("frostflake-audit-doctest.log");
+/// let opts = GeneratorOptions::default().audit(AuditLog::open(&log).unwrap());
+/// let generator = Generator::new(opts);
+/// generator.generate();
+/// ```
+pub struct AuditLog<W: Write> {
+    writer: Mutex<W>,
+    error: Mutex<Option<io::ErrorKind>>,
+}
+
+impl AuditLog<File> {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog::new(file))
+    }
+}
+
+impl<W: Write> AuditLog<W> {
+    pub fn new(writer: W) -> Self {
+        AuditLog {
+            writer: Mutex::new(writer),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Kind of the last write error, if writing a record ever failed.
+    pub fn last_error(&self) -> Option<io::ErrorKind> {
+        *self.error.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<W: Write> AuditSink for AuditLog<W> {
+    fn record(&self, record: IssuanceRecord) {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let result = writeln!(writer, "{} {} {}", record.tick, record.node, record.count)
+            .and_then(|_| writer.flush());
+        if let Err(e) = result {
+            *self.error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e.kind());
+        }
+    }
+}
+
+impl Generator {
+    /// Emits the record of the current tick to the audit sink right away.
+    pub fn flush_audit(&self) {
+        self.core().flush_audit();
+    }
+}
+
+impl Core {
+    pub(crate) fn flush_audit(&mut self) {
+        if !self.audit_pending {
+            return;
+        }
+        self.audit_pending = false;
+
+        if let Some(sink) = &self.opts.audit {
+            sink.record(IssuanceRecord {
+                tick: self.last_ts - self.opts.base_ts,
+                node: self.opts.node,
+                count: self.seq + 1 - self.seq_start,
+            });
+        }
+    }
+
+    pub(crate) fn audit_scheduled(&self, elapsed: u64) {
+        if let Some(sink) = &self.opts.audit {
+            sink.record(IssuanceRecord {
+                tick: elapsed,
+                node: self.opts.node,
+                count: 1,
+            });
+        }
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        self.flush_audit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 123);
+
+    fn my_time_fn() -> u64 {
+        NOW.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_audit() {
+        let records = Arc::new(Mutex::new(vec![]));
+        let sink = records.clone();
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .node(3)
+            .audit(move |r| sink.lock().unwrap().push(r));
+
+        let g = Generator::new(opts);
+        g.generate();
+        g.generate();
+        assert!(records.lock().unwrap().is_empty());
+
+        NOW.store(1483228800000 + 124, Ordering::SeqCst);
+        g.generate();
+        g.generate_not_before(1483228800000 + 200).unwrap();
+        drop(g);
+
+        assert_eq!(
+            *records.lock().unwrap(),
+            vec![
+                IssuanceRecord {
+                    tick: 123,
+                    node: 3,
+                    count: 2
+                },
+                IssuanceRecord {
+                    tick: 200,
+                    node: 3,
+                    count: 1
+                },
+                IssuanceRecord {
+                    tick: 124,
+                    node: 3,
+                    count: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let log = AuditLog::new(vec![]);
+        log.record(IssuanceRecord {
+            tick: 123,
+            node: 3,
+            count: 2,
+        });
+        assert_eq!(log.last_error(), None);
+        assert_eq!(String::from_utf8(log.into_inner()).unwrap(), "123 3 2\n");
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{Core, Error, Generator};
+
+impl Generator {
+    /// Generates an id carrying the past timestamp `ts`, for backfilling
+    /// historical records.
+    ///
+    /// `ts` is in the unit of `time_fn`, like `base_ts`, and must not be
+    /// after the current time. Every timestamp has a sequence of its own,
+    /// counting down from the top of the sequence space, so ids of
+    /// `generate_at` never collide with each other, and the ids generated
+    /// live once the clock reaches a backfilled timestamp leave room for
+    /// them.
+    ///
+    /// The generator only knows the ids it issued live at its last
+    /// timestamp, though. An earlier timestamp may have been used by this
+    /// node before, by this generator or a previous run, and an id of
+    /// `generate_at` collides with one of those if that tick used the
+    /// sequence space up to the backfilled ids. Backfills are therefore best
+    /// run with a node number of their own.
+    ///
+    /// A counter is kept for every backfilled timestamp for the life of the
+    /// generator.
+    pub fn generate_at(&self, ts: u64) -> Result<u64, Error> {
+        self.core().generate_at(ts)
+    }
+}
+
+impl Core {
+    fn generate_at(&mut self, ts: u64) -> Result<u64, Error> {
+        let now = self.opts.clock.now();
+        if ts > now {
+            return Err(Error::TimeInFuture { ts, now });
+        }
+        if ts <= self.opts.base_ts {
+            return Err(Error::TimeBeforeBaseTs {
+                now: ts,
+                base_ts: self.opts.base_ts,
+            });
+        }
+
+        let elapsed = ts - self.opts.base_ts;
+        if elapsed > self.compiled.max_ts {
+            return Err(Error::EpochExhausted { ts });
+        }
+
+        let count = self.backfilled.get(&ts).copied().unwrap_or(0);
+        if count > self.compiled.max_seq
+            || (ts == self.last_ts && self.seq >= self.compiled.max_seq - count)
+        {
+            return Err(Error::SequenceExhausted);
+        }
+        let seq = self.compiled.max_seq - count;
+        self.backfilled.insert(ts, count + 1);
+
+        self.audit_scheduled(elapsed);
+        self.record_issued(1);
+        Ok(self.compose(elapsed, seq))
+    }
+
+    /// Number of sequence numbers taken by backfilled ids at `ts`.
+    pub(crate) fn backfilled_at(&self, ts: u64) -> u64 {
+        if self.backfilled.is_empty() {
+            return 0;
+        }
+        self.backfilled.get(&ts).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Generator, GeneratorOptions, OverflowPolicy};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_generate_at() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 100);
+        fn my_time_fn() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+
+        // 4 ids per tick
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .overflow_policy(OverflowPolicy::Error)
+            .base_ts(0)
+            .bits(42, 20, 2)
+            .base_ts(1483228800000);
+        let g = Generator::new(opts);
+
+        let past = 1483228800000 + 50;
+        assert_eq!(g.generate_at(past), Ok((50 << 22) + 3));
+        assert_eq!(g.generate_at(past), Ok((50 << 22) + 2));
+        assert_eq!(g.generate_at(past - 1), Ok((49 << 22) + 3));
+
+        // the current tick is shared with live ids
+        assert_eq!(g.try_generate(), Ok(100 << 22));
+        assert_eq!(
+            g.generate_at(NOW.load(Ordering::Relaxed)),
+            Ok((100 << 22) + 3)
+        );
+        assert_eq!(g.try_generate(), Ok((100 << 22) + 1));
+        assert_eq!(
+            g.generate_at(NOW.load(Ordering::Relaxed)),
+            Ok((100 << 22) + 2)
+        );
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+        assert_eq!(
+            g.generate_at(NOW.load(Ordering::Relaxed)),
+            Err(Error::SequenceExhausted)
+        );
+
+        // generate_into leaves room for backfilled ids too
+        static READS: AtomicU64 = AtomicU64::new(0);
+        fn reads_time_fn() -> u64 {
+            1483228800000 + 100 + READS.fetch_add(1, Ordering::Relaxed) / 3
+        }
+        let batch = Generator::new(GeneratorOptions::default().time_fn(reads_time_fn));
+        let backfilled = batch.generate_at(1483228800000 + 100).unwrap();
+        let mut ids = vec![0; 4096];
+        batch.generate_into(&mut ids).unwrap();
+        assert!(!ids.contains(&backfilled));
+        assert_eq!(ids.iter().filter(|&&id| id >> 22 == 100).count(), 4095);
+
+        assert_eq!(
+            g.generate_at(1483228800000 + 101),
+            Err(Error::TimeInFuture {
+                ts: 1483228800000 + 101,
+                now: 1483228800000 + 100
+            })
+        );
+        assert_eq!(
+            g.generate_at(1483228800000),
+            Err(Error::TimeBeforeBaseTs {
+                now: 1483228800000,
+                base_ts: 1483228800000
+            })
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{Core, Error, Generator};
+
+impl Generator {
+    /// Fills `buf` with fresh ids.
+    ///
+    /// The clock is read once per tick instead of once per id, and no memory
+    /// is allocated, so this suits writers with preallocated columns. When a
+    /// tick is exhausted, this waits for the next one.
+    ///
+    /// On error, the ids written so far are valid, but which part of `buf`
+    /// was written is unspecified.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let generator = Generator::new(GeneratorOptions::default());
+    /// let mut ids = [0; 10_000];
+    /// generator.generate_into(&mut ids).unwrap();
+    /// assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    /// ```
+    pub fn generate_into(&self, buf: &mut [u64]) -> Result<(), Error> {
+        self.core().generate_into(buf)
+    }
+
+    /// Generates `n` ids in one pass, in ascending order.
+    ///
+    /// This panics on errors, like `generate`. See `generate_into` for a
+    /// fallible version that writes into an existing buffer.
+    pub fn generate_many(&self, n: usize) -> Vec<u64> {
+        let mut ids = vec![0; n];
+        self.generate_into(&mut ids)
+            .unwrap_or_else(|e| panic!("{}", e));
+        ids
+    }
+}
+
+impl Core {
+    pub(crate) fn generate_into(&mut self, buf: &mut [u64]) -> Result<(), Error> {
+        self.fill(buf, true)
+    }
+
+    /// Fills `buf` with fresh ids, counting them as issued only if `issue`.
+    pub(crate) fn fill(&mut self, buf: &mut [u64], issue: bool) -> Result<(), Error> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let allowed = self.acquire((buf.len() - filled) as u64)?;
+            let result = self.next_id();
+            if issue || result.is_err() {
+                self.record(&result);
+            }
+            match result {
+                Ok(id) => {
+                    buf[filled] = id;
+                    filled += 1;
+                }
+                Err(Error::SequenceExhausted) => {
+                    self.put_back(allowed);
+                    std::hint::spin_loop();
+                    continue;
+                }
+                Err(e) => {
+                    self.put_back(allowed);
+                    return Err(e);
+                }
+            }
+
+            // the rest of the tick without reading the clock again
+            let taken = self.scheduled_at(self.last_ts) + self.backfilled_at(self.last_ts);
+            let limit = self.compiled.max_seq.saturating_sub(taken);
+            let elapsed = self.last_ts - self.opts.base_ts;
+            let n = ((limit - self.seq) as usize)
+                .min(buf.len() - filled)
+                .min(allowed as usize - 1);
+            self.put_back(allowed - 1 - n as u64);
+            for slot in &mut buf[filled..filled + n] {
+                self.seq += 1;
+                *slot = self.compose(elapsed, self.seq);
+            }
+            filled += n;
+            if issue {
+                self.record_issued(n as u64);
+            }
+            self.check_utilization(self.last_ts, self.seq);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Generator, GeneratorOptions};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_generate_into() {
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        fn my_time_fn() -> u64 {
+            1483228800000 + 1 + CALLS.fetch_add(1, Ordering::Relaxed) / 4
+        }
+
+        // 2bit sequence gives at most 4 ids per tick
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 20, 2)
+            .base_ts(1483228800000);
+        let g = Generator::new(opts);
+
+        let mut ids = [0; 10];
+        g.generate_into(&mut ids).unwrap();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(g.diagnostics().stats.issued, 10);
+
+        // one clock read per tick
+        assert_eq!(
+            ids[..4],
+            [1 << 22, (1 << 22) + 1, (1 << 22) + 2, (1 << 22) + 3]
+        );
+    }
+
+    #[test]
+    fn test_generate_into_error() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g = Generator::new(GeneratorOptions::default().time_fn(my_time_fn));
+        g.core().last_ts = 1483228800000 + 124;
+        assert!(matches!(
+            g.generate_into(&mut [0; 3]),
+            Err(Error::ClockMovedBackwards { .. })
+        ));
+        g.generate_into(&mut []).unwrap();
+    }
+
+    #[test]
+    fn test_generate_many() {
+        let g = Generator::new(GeneratorOptions::default());
+        let ids = g.generate_many(10_000);
+        assert_eq!(ids.len(), 10_000);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(g.generate() > ids[9_999]);
+        assert!(g.generate_many(0).is_empty());
+    }
+}
@@ -0,0 +1,255 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use frostflake::{Generator, GeneratorAsync, GeneratorPool, GeneratorPoolOptions, Layout};
+
+use crate::LayoutArgs;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    layout: LayoutArgs,
+
+    /// Seconds to run each benchmark
+    #[arg(long, default_value_t = 1.0)]
+    duration: f64,
+
+    /// Caller threads for the pool benchmark
+    #[arg(long, default_value_t = 4)]
+    threads: usize,
+
+    /// Number of generators in the pool
+    #[arg(long, default_value_t = 4)]
+    pool_size: usize,
+
+    /// Caller tasks for the async benchmark
+    #[arg(long, default_value_t = 4)]
+    tasks: usize,
+}
+
+/// Outcome of a single benchmark.
+struct Report {
+    ids: u64,
+    elapsed: Duration,
+    generators: usize,
+    max_seq: u64,
+}
+
+impl Report {
+    fn print(&self, name: &str, layout: &Layout) {
+        let secs = self.elapsed.as_secs_f64();
+        let per_tick = layout.capacity().max_ids_per_tick as f64;
+        let ticks = (secs * 1000.0).max(1.0) * self.generators as f64;
+
+        println!(
+            "{:<8} {:>12.0} ids/s  seq utilization avg {:>5.1}% peak {:>5.1}%",
+            name,
+            self.ids as f64 / secs,
+            self.ids as f64 / ticks / per_tick * 100.0,
+            (self.max_seq + 1) as f64 / per_tick * 100.0
+        );
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let opts = args.layout.options()?;
+    let layout = args.layout.layout();
+    let duration = Duration::from_secs_f64(args.duration);
+
+    println!("layout   {}", layout.capacity());
+
+    bench_single(opts.clone(), duration).print("single", &layout);
+    bench_pool(&args, duration)?.print("pool", &layout);
+    bench_async(opts, args.tasks, duration)?.print("async", &layout);
+
+    Ok(())
+}
+
+fn bench_single(opts: frostflake::GeneratorOptions, duration: Duration) -> Report {
+    let g = Generator::new(opts);
+    let start = Instant::now();
+    let mut ids = 0;
+    let mut max_seq = 0;
+
+    while start.elapsed() < duration {
+        for _ in 0..1000 {
+            if let Ok(id) = g.try_generate() {
+                max_seq = max_seq.max(g.extract(id).2);
+                ids += 1;
+            }
+        }
+    }
+
+    Report {
+        ids,
+        elapsed: start.elapsed(),
+        generators: 1,
+        max_seq,
+    }
+}
+
+fn bench_pool(args: &Args, duration: Duration) -> Result<Report, Box<dyn Error>> {
+    let layout = args.layout.layout();
+    let pool_bits = usize::BITS - args.pool_size.saturating_sub(1).leading_zeros();
+    let node_bits = (layout.node_bits() as u32)
+        .checked_sub(pool_bits)
+        .ok_or("pool size does not fit in node bits")?;
+    if node_bits < 64 && args.layout.node >> node_bits != 0 {
+        return Err("node number does not fit in node bits left by the pool".into());
+    }
+
+    let opts = GeneratorPoolOptions::default()
+        .base_ts(0)
+        .node(0)
+        .bits(
+            layout.ts_bits(),
+            pool_bits as u8,
+            node_bits as u8,
+            layout.seq_bits(),
+        )
+        .base_ts(layout.base_ts())
+        .node(args.layout.node);
+    let pool = GeneratorPool::try_new(args.pool_size, opts)?;
+
+    let ids = Arc::new(AtomicU64::new(0));
+    let max_seq = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+
+    let handles: Vec<_> = (0..args.threads)
+        .map(|_| {
+            let pool = pool.clone();
+            let ids = ids.clone();
+            let max_seq = max_seq.clone();
+            thread::spawn(move || {
+                let mut n = 0;
+                let mut seq = 0;
+                while start.elapsed() < duration {
+                    let id = match pool.generate() {
+                        Ok(id) => id,
+                        Err(e) if e.is_transient() => continue,
+                        Err(e) => return Err(e),
+                    };
+                    seq = seq.max(pool.extract(id).3);
+                    n += 1;
+                }
+                ids.fetch_add(n, Ordering::Relaxed);
+                max_seq.fetch_max(seq, Ordering::Relaxed);
+                Ok(())
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().map_err(|_| "pool benchmark thread panicked")??;
+    }
+
+    Ok(Report {
+        ids: ids.load(Ordering::Relaxed),
+        elapsed: start.elapsed(),
+        generators: args.pool_size,
+        max_seq: max_seq.load(Ordering::Relaxed),
+    })
+}
+
+fn bench_async(
+    opts: frostflake::GeneratorOptions,
+    tasks: usize,
+    duration: Duration,
+) -> Result<Report, Box<dyn Error>> {
+    let rt = tokio::runtime::Builder::new_current_thread().build()?;
+
+    rt.block_on(async move {
+        let decoder = Generator::new(opts.clone());
+        let g = GeneratorAsync::spawn(opts);
+        let start = Instant::now();
+
+        let handles: Vec<_> = (0..tasks)
+            .map(|_| {
+                let g = g.clone();
+                tokio::spawn(async move {
+                    let mut ids = vec![];
+                    while start.elapsed() < duration {
+                        ids.push(g.generate().await?);
+                    }
+                    Ok::<_, frostflake::Error>(ids)
+                })
+            })
+            .collect();
+
+        let mut ids = 0;
+        let mut max_seq = 0;
+        for h in handles {
+            for id in h.await?? {
+                max_seq = max_seq.max(decoder.extract(id).2);
+                ids += 1;
+            }
+        }
+
+        Ok(Report {
+            ids,
+            elapsed: start.elapsed(),
+            generators: 1,
+            max_seq,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Command};
+    use clap::Parser;
+
+    fn parse(args: &[&str]) -> Args {
+        let cli = Cli::try_parse_from(["frostflake", "bench"].iter().chain(args)).unwrap();
+        match cli.command {
+            Command::Bench(args) => args,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_args() {
+        let args = parse(&[]);
+        assert_eq!(args.duration, 1.0);
+        assert_eq!((args.threads, args.pool_size, args.tasks), (4, 4, 4));
+
+        let args = parse(&["--duration", "0.5", "--threads", "2", "--pool-size", "3"]);
+        assert_eq!(args.duration, 0.5);
+        assert_eq!((args.threads, args.pool_size, args.tasks), (2, 3, 4));
+    }
+
+    #[test]
+    fn test_bench() {
+        let args = parse(&["--threads", "2", "--pool-size", "2", "--tasks", "2"]);
+        let opts = args.layout.options().unwrap();
+        let duration = Duration::from_millis(10);
+
+        for report in [
+            bench_single(opts.clone(), duration),
+            bench_pool(&args, duration).unwrap(),
+            bench_async(opts, args.tasks, duration).unwrap(),
+        ] {
+            assert!(report.ids > 0);
+            assert!(report.elapsed >= duration);
+            assert!(report.max_seq < 4096);
+        }
+    }
+
+    #[test]
+    fn test_bench_pool_bits() {
+        let duration = Duration::from_millis(1);
+        let args = parse(&["--layout", "42,1,21", "--pool-size", "4"]);
+        let err = bench_pool(&args, duration).err().unwrap();
+        assert_eq!(err.to_string(), "pool size does not fit in node bits");
+
+        let args = parse(&["--node", "512", "--pool-size", "2"]);
+        let err = bench_pool(&args, duration).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "node number does not fit in node bits left by the pool"
+        );
+    }
+}
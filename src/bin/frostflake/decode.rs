@@ -0,0 +1,86 @@
+use std::error::Error;
+use std::io::{self, Write};
+
+use frostflake::Generator;
+
+use crate::output::{write_decoded, Format};
+use crate::LayoutArgs;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    layout: LayoutArgs,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Ids to decode
+    #[arg(required = true)]
+    ids: Vec<u64>,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    decode(&args, &mut io::stdout().lock())
+}
+
+fn decode(args: &Args, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let g = Generator::new(args.layout.options()?);
+    let base_ts = args.layout.layout().base_ts();
+
+    for &id in &args.ids {
+        write_decoded(out, &g, base_ts, id, args.format)?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Command};
+    use clap::Parser;
+
+    fn decode_args(args: &[&str]) -> Result<String, Box<dyn Error>> {
+        let cli = Cli::try_parse_from(["frostflake", "decode"].iter().chain(args))?;
+        let Command::Decode(args) = cli.command else {
+            unreachable!();
+        };
+        let mut out = vec![];
+        decode(&args, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn test_decode() {
+        let id = ((123 << 22) | (3 << 12) | 4).to_string();
+        assert_eq!(
+            decode_args(&["--base-ts", "0", &id]).unwrap(),
+            format!(
+                "id={} timestamp=123 time=1970-01-01T00:00:00.123Z node=3 seq=4\n",
+                id
+            )
+        );
+        assert_eq!(
+            decode_args(&["--base-ts", "0", "--format", "json", &id, "1"]).unwrap(),
+            format!(
+                concat!(
+                    r#"{{"id":{0},"id_str":"{0}","timestamp":123,"time":"1970-01-01T00:00:00.123Z","node":3,"seq":4}}"#,
+                    "\n",
+                    r#"{{"id":1,"id_str":"1","timestamp":0,"time":"1970-01-01T00:00:00.000Z","node":0,"seq":1}}"#,
+                    "\n",
+                ),
+                id
+            )
+        );
+
+        // fields follow the layout
+        let out = decode_args(&["--layout", "41,10,13", "--base-ts", "0", "8195"]).unwrap();
+        assert!(out.ends_with("node=1 seq=3\n"));
+
+        // ids are required, and have to be numbers
+        assert!(decode_args(&[]).is_err());
+        assert!(decode_args(&["abc"]).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::error::Error;
+use std::io::{self, BufRead, BufWriter, Write};
+
+use frostflake::Generator;
+
+use crate::output::{write_decoded, Format};
+use crate::LayoutArgs;
+
+#[derive(clap::Args)]
+pub struct Args {
+    #[command(flatten)]
+    layout: LayoutArgs,
+
+    /// Number of ids to generate
+    #[arg(long, short = 'n', default_value_t = 1)]
+    count: u64,
+
+    /// Read demand from stdin: each line is a number of ids to generate
+    /// (blank lines use --count), answered as soon as it is read
+    #[arg(long)]
+    stream: bool,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let mut w = IdWriter {
+        g: Generator::new(args.layout.options()?),
+        base_ts: args.layout.layout().base_ts(),
+        format: args.format,
+        out: BufWriter::new(io::stdout().lock()),
+    };
+
+    let result = if args.stream {
+        w.stream(io::stdin().lock(), args.count)
+    } else {
+        w.write_ids(args.count).and_then(|_| Ok(w.out.flush()?))
+    };
+
+    match result {
+        Err(e) if is_broken_pipe(e.as_ref()) => Ok(()),
+        r => r,
+    }
+}
+
+struct IdWriter<W> {
+    g: Generator,
+    base_ts: u64,
+    format: Format,
+    out: W,
+}
+
+impl<W: Write> IdWriter<W> {
+    fn stream(&mut self, input: impl BufRead, default: u64) -> Result<(), Box<dyn Error>> {
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            let count = if line.is_empty() {
+                default
+            } else {
+                line.parse()
+                    .map_err(|_| format!("invalid demand {:?}, expected a number", line))?
+            };
+
+            self.write_ids(count)?;
+            self.out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_ids(&mut self, count: u64) -> Result<(), Box<dyn Error>> {
+        for _ in 0..count {
+            // waits for the next tick when the sequence is exhausted
+            let id = self.g.try_generate()?;
+            match self.format {
+                Format::Text => writeln!(self.out, "{}", id)?,
+                Format::Json => {
+                    write_decoded(&mut self.out, &self.g, self.base_ts, id, self.format)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_broken_pipe(e: &(dyn Error + 'static)) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Command};
+    use clap::Parser;
+
+    fn parse(args: &[&str]) -> Args {
+        let cli = Cli::try_parse_from(["frostflake", "generate"].iter().chain(args)).unwrap();
+        match cli.command {
+            Command::Generate(args) => args,
+            _ => unreachable!(),
+        }
+    }
+
+    fn writer(args: &Args) -> IdWriter<Vec<u8>> {
+        IdWriter {
+            g: Generator::new(args.layout.options().unwrap()),
+            base_ts: args.layout.layout().base_ts(),
+            format: args.format,
+            out: vec![],
+        }
+    }
+
+    #[test]
+    fn test_args() {
+        let args = parse(&[]);
+        assert_eq!((args.count, args.stream), (1, false));
+        assert!(args.format == Format::Text);
+
+        let args = parse(&["-n", "3", "--stream", "--format", "json"]);
+        assert_eq!((args.count, args.stream), (3, true));
+        assert!(args.format == Format::Json);
+
+        assert!(Cli::try_parse_from(["frostflake", "generate", "--format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_write_ids() {
+        let mut w = writer(&parse(&["--node", "7"]));
+        w.write_ids(3).unwrap();
+        let out = String::from_utf8(w.out).unwrap();
+        let ids: Vec<u64> = out.lines().map(|l| l.parse().unwrap()).collect();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(ids.iter().all(|id| id >> 12 & 0x3ff == 7));
+
+        let mut w = writer(&parse(&["--node", "7", "--format", "json"]));
+        w.write_ids(2).unwrap();
+        let out = String::from_utf8(w.out).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        for line in out.lines() {
+            assert!(line.starts_with(r#"{"id":"#) && line.ends_with('}'));
+            assert!(line.contains(r#","node":7,"#));
+        }
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut w = writer(&parse(&["--stream"]));
+        w.stream(&b"2\n\n 3 \n"[..], 1).unwrap();
+        assert_eq!(String::from_utf8(w.out).unwrap().lines().count(), 6);
+
+        let mut w = writer(&parse(&["--stream"]));
+        let err = w.stream(&b"1\nmany\n2\n"[..], 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"invalid demand "many", expected a number"#
+        );
+        // the demand before is answered
+        assert_eq!(String::from_utf8(w.out).unwrap().lines().count(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+use std::process;
+
+use clap::{Parser, Subcommand};
+use frostflake::{GeneratorOptions, Layout, OptionsError};
+
+mod bench;
+mod decode;
+mod generate;
+mod output;
+mod serve;
+
+/// Generate and inspect frostflake ids.
+#[derive(Parser)]
+#[command(name = "frostflake", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate ids
+    Generate(generate::Args),
+    /// Decode ids into their fields
+    Decode(decode::Args),
+    /// Measure generation throughput on this machine
+    Bench(bench::Args),
+    /// Serve ids over the memcached protocol or HTTP
+    Serve(serve::Args),
+}
+
+/// Options shared by subcommands that need a layout.
+#[derive(clap::Args)]
+struct LayoutArgs {
+    /// Bit widths as ts,node,seq
+    #[arg(long, default_value = "42,10,12", value_parser = parse_layout)]
+    layout: Layout,
+
+    /// Base time in milliseconds
+    #[arg(long)]
+    base_ts: Option<u64>,
+
+    /// Node number
+    #[arg(long, default_value_t = 0)]
+    node: u64,
+}
+
+impl LayoutArgs {
+    fn layout(&self) -> Layout {
+        match self.base_ts {
+            Some(base_ts) => self.layout.with_base_ts(base_ts),
+            None => self.layout,
+        }
+    }
+
+    fn options(&self) -> Result<GeneratorOptions, OptionsError> {
+        GeneratorOptions::builder()
+            .layout(self.layout())
+            .node(self.node)
+            .build()
+    }
+}
+
+fn parse_layout(s: &str) -> Result<Layout, String> {
+    let bits = s
+        .split(',')
+        .map(|b| b.trim().parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    match bits[..] {
+        [ts, node, seq] if ts as u32 + node as u32 + seq as u32 == 64 => {
+            Ok(Layout::new(ts, node, seq))
+        }
+        [_, _, _] => Err("bits set should be total 64bit".to_string()),
+        _ => Err("layout should be ts,node,seq".to_string()),
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Generate(args) => generate::run(args),
+        Command::Decode(args) => decode::run(args),
+        Command::Bench(args) => bench::run(args),
+        Command::Serve(args) => serve::run(args),
+    };
+
+    if let Err(e) = result {
+        eprintln!("frostflake: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        assert!(Cli::try_parse_from(["frostflake"]).is_err());
+        assert!(Cli::try_parse_from(["frostflake", "mint"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        assert_eq!(parse_layout("41, 10,13"), Ok(Layout::new(41, 10, 13)));
+        assert_eq!(
+            parse_layout("42,10,13"),
+            Err("bits set should be total 64bit".to_string())
+        );
+        assert_eq!(
+            parse_layout("42,22"),
+            Err("layout should be ts,node,seq".to_string())
+        );
+        assert!(parse_layout("42,10,x").is_err());
+        assert!(parse_layout("300,0,0").is_err());
+    }
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        layout: LayoutArgs,
+    }
+
+    #[test]
+    fn test_layout_args() {
+        let args = Args::try_parse_from(["test", "--layout", "41,10,13", "--base-ts", "5"]);
+        let args = args.unwrap().layout;
+        assert_eq!(args.layout(), Layout::new(41, 10, 13).with_base_ts(5));
+        assert!(args.options().is_ok());
+
+        let args = Args::try_parse_from(["test", "--node", "1024"])
+            .unwrap()
+            .layout;
+        assert_eq!(args.layout(), Layout::new(42, 10, 12));
+        assert!(args.options().is_err());
+        assert!(Args::try_parse_from(["test", "--layout", "42,10,13"]).is_err());
+    }
+}
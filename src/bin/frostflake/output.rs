@@ -0,0 +1,40 @@
+use std::io::{self, Write};
+use std::time::{Duration, UNIX_EPOCH};
+
+use frostflake::Generator;
+
+/// Output format of generated or decoded ids.
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Plain ids for `generate`, key=value fields for `decode`
+    Text,
+    /// One JSON object per line with decoded fields
+    Json,
+}
+
+/// Writes `id` with its decoded fields.
+pub fn write_decoded(
+    out: &mut impl Write,
+    g: &Generator,
+    base_ts: u64,
+    id: u64,
+    format: Format,
+) -> io::Result<()> {
+    let (ts, node, seq) = g.extract(id);
+    let time = humantime::format_rfc3339_millis(
+        UNIX_EPOCH + Duration::from_millis(base_ts.saturating_add(ts)),
+    );
+
+    match format {
+        Format::Text => writeln!(
+            out,
+            "id={} timestamp={} time={} node={} seq={}",
+            id, ts, time, node, seq
+        ),
+        Format::Json => writeln!(
+            out,
+            r#"{{"id":{},"id_str":"{}","timestamp":{},"time":"{}","node":{},"seq":{}}}"#,
+            id, id, ts, time, node, seq
+        ),
+    }
+}
@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+
+use frostflake::http::HttpServer;
+use frostflake::server::MemcachedServer;
+use frostflake::GeneratorAsync;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+use crate::LayoutArgs;
+
+#[derive(clap::Args)]
+#[command(group = clap::ArgGroup::new("listen").required(true).multiple(true))]
+pub struct Args {
+    #[command(flatten)]
+    layout: LayoutArgs,
+
+    /// Address to serve the memcached protocol on, like 127.0.0.1:11212
+    #[arg(long, group = "listen")]
+    memcached: Option<SocketAddr>,
+
+    /// Address to serve HTTP on, like 127.0.0.1:8080
+    #[arg(long, group = "listen")]
+    http: Option<SocketAddr>,
+}
+
+pub fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let opts = args.layout.options()?;
+    let layout = args.layout.layout();
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+
+    rt.block_on(async move {
+        let generator = GeneratorAsync::spawn(opts);
+        // the servers only return when accepting fails, which ends the command
+        let (tx, mut rx) = mpsc::channel::<io::Result<()>>(2);
+
+        if let Some(addr) = args.memcached {
+            let listener = TcpListener::bind(addr).await?;
+            eprintln!("memcached protocol on {}", listener.local_addr()?);
+            let server = MemcachedServer::new(generator.clone());
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _ = tx.send(server.serve(listener).await).await;
+            });
+        }
+        if let Some(addr) = args.http {
+            let listener = TcpListener::bind(addr).await?;
+            eprintln!("http on {}", listener.local_addr()?);
+            let server = HttpServer::new(generator.clone(), layout);
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _ = tx.send(server.serve(listener).await).await;
+            });
+        }
+
+        drop(tx);
+
+        match rx.recv().await {
+            Some(result) => Ok::<_, Box<dyn Error>>(result?),
+            None => Ok(()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cli, Command};
+    use clap::Parser;
+
+    #[test]
+    fn test_args() {
+        // at least one address to listen on
+        assert!(Cli::try_parse_from(["frostflake", "serve"]).is_err());
+        assert!(Cli::try_parse_from(["frostflake", "serve", "--http", "localhost"]).is_err());
+
+        let cli = Cli::try_parse_from([
+            "frostflake",
+            "serve",
+            "--memcached",
+            "127.0.0.1:11212",
+            "--http",
+            "[::1]:8080",
+        ])
+        .unwrap();
+        let Command::Serve(args) = cli.command else {
+            unreachable!();
+        };
+        assert_eq!(args.memcached, Some("127.0.0.1:11212".parse().unwrap()));
+        assert_eq!(args.http, Some("[::1]:8080".parse().unwrap()));
+    }
+}
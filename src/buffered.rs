@@ -0,0 +1,208 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossbeam::queue::ArrayQueue;
+
+use crate::metrics::Metrics;
+use crate::{Error, Generator, GeneratorOptions, IdGenerator, OptionsError};
+
+/// Generator keeping ids ready in a buffer, topped up by a background
+/// thread, for latency critical paths.
+///
+/// Taking an id is a pop from a lock-free ring buffer in the common case.
+/// The thread refills the buffer once it is half empty, and generates at the
+/// pace of the generator: when the sequence of a tick is exhausted, it waits
+/// for the next tick following the overflow policy, and the buffer drains
+/// meanwhile. [`next`](Self::next) then waits for the thread, and
+/// [`try_next`](Self::try_next) fails with [`Error::Busy`] to shed load.
+///
+/// Ids are generated before they are taken, so their timestamps are early by
+/// up to the time the buffer takes to drain. They are taken in ascending
+/// order. The buffer depth is reported to `Metrics::buffered` of the options.
+///
+/// ```rust
+/// use frostflake::{BufferedGenerator, GeneratorOptions};
+///
+/// let generator = BufferedGenerator::new(GeneratorOptions::default(), 1024).unwrap();
+/// let id = generator.next().unwrap();
+/// ```
+pub struct BufferedGenerator {
+    buffer: Arc<Buffer>,
+    refill: Option<JoinHandle<()>>,
+    // generates on the calling thread when the refill thread couldn't be
+    // spawned
+    fallback: Option<Arc<Generator>>,
+}
+
+struct Buffer {
+    ids: ArrayQueue<u64>,
+    // last error of the refill thread, reported by the next empty pop
+    error: Mutex<Option<Error>>,
+    stopped: AtomicBool,
+    metrics: Option<Arc<dyn Metrics + Send + Sync>>,
+}
+
+impl BufferedGenerator {
+    /// Starts a thread keeping up to `capacity` ids ready.
+    ///
+    /// If the thread can't be spawned, ids are generated on the calling
+    /// thread instead, without a buffer. Fails with
+    /// [`OptionsError::ZeroCount`] for a `capacity` of 0.
+    pub fn new(opts: GeneratorOptions, capacity: usize) -> Result<Self, OptionsError> {
+        if capacity == 0 {
+            return Err(OptionsError::ZeroCount { name: "capacity" });
+        }
+
+        let buffer = Arc::new(Buffer {
+            ids: ArrayQueue::new(capacity),
+            error: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+            metrics: opts.metrics.clone(),
+        });
+        let generator = Arc::new(Generator::new(opts));
+        let (shared, refilled) = (buffer.clone(), generator.clone());
+        let refill = thread::Builder::new()
+            .name("frostflake-buffer".into())
+            .spawn(move || refill(&refilled, &shared))
+            .ok();
+
+        Ok(BufferedGenerator {
+            buffer,
+            fallback: refill.is_none().then_some(generator),
+            refill,
+        })
+    }
+
+    /// Takes an id, waiting for the thread when the buffer is empty.
+    ///
+    /// Errors of the generator, like `ClockMovedBackwards`, are returned once
+    /// the buffer runs empty.
+    pub fn next(&self) -> Result<u64, Error> {
+        loop {
+            match self.try_next() {
+                Err(Error::Busy) => thread::yield_now(),
+                result => return result,
+            }
+        }
+    }
+
+    /// Same as `next`, but fails with [`Error::Busy`] instead of waiting when
+    /// the buffer is empty.
+    pub fn try_next(&self) -> Result<u64, Error> {
+        if let Some(generator) = &self.fallback {
+            return generator.try_generate();
+        }
+        let Some(id) = self.buffer.ids.pop() else {
+            self.wake();
+            let error = self.buffer.lock_error().take();
+            return Err(error.unwrap_or(Error::Busy));
+        };
+
+        let depth = self.buffer.ids.len();
+        if let Some(metrics) = &self.buffer.metrics {
+            metrics.buffered(depth);
+        }
+        if depth <= self.buffer.ids.capacity() / 2 {
+            self.wake();
+        }
+        Ok(id)
+    }
+
+    /// Number of ids ready in the buffer.
+    pub fn depth(&self) -> usize {
+        self.buffer.ids.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.ids.capacity()
+    }
+
+    fn wake(&self) {
+        if let Some(refill) = &self.refill {
+            refill.thread().unpark();
+        }
+    }
+}
+
+impl Buffer {
+    fn lock_error(&self) -> std::sync::MutexGuard<'_, Option<Error>> {
+        self.error.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn refill(generator: &Generator, buffer: &Buffer) {
+    while !buffer.stopped.load(Ordering::Acquire) {
+        if buffer.ids.is_full() {
+            thread::park();
+            continue;
+        }
+        match generator.try_generate() {
+            // the only producer, so there is room
+            Ok(id) => {
+                let _ = buffer.ids.push(id);
+            }
+            Err(e) => {
+                *buffer.lock_error() = Some(e);
+                thread::park_timeout(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+impl IdGenerator for BufferedGenerator {
+    fn generate(&self) -> Result<u64, Error> {
+        self.next()
+    }
+}
+
+impl Drop for BufferedGenerator {
+    fn drop(&mut self) {
+        self.buffer.stopped.store(true, Ordering::Release);
+        if let Some(refill) = self.refill.take() {
+            refill.thread().unpark();
+            let _ = refill.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::AtomicMetrics;
+
+    #[test]
+    fn test_buffered_generator() {
+        let metrics = Arc::new(AtomicMetrics::default());
+        let opts = GeneratorOptions::default().metrics(metrics.clone());
+        let g = BufferedGenerator::new(opts, 16).unwrap();
+        assert_eq!(g.capacity(), 16);
+
+        let mut last = 0;
+        for _ in 0..1000 {
+            let id = g.next().unwrap();
+            assert!(id > last);
+            last = id;
+        }
+        assert!(metrics.buffer_depth() < 16);
+        assert!(metrics.ids_generated() >= 1000);
+    }
+
+    #[test]
+    fn test_error() {
+        fn my_time_fn() -> u64 {
+            1
+        }
+
+        let g =
+            BufferedGenerator::new(GeneratorOptions::default().time_fn(my_time_fn), 16).unwrap();
+        assert!(matches!(g.next(), Err(Error::TimeBeforeBaseTs { .. })));
+        assert_eq!(g.depth(), 0);
+
+        assert_eq!(
+            BufferedGenerator::new(GeneratorOptions::default(), 0).err(),
+            Some(OptionsError::ZeroCount { name: "capacity" })
+        );
+    }
+}
@@ -0,0 +1,551 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::audit::AuditSink;
+use crate::error::OptionsError;
+use crate::metrics::Metrics;
+use crate::node_id::NodeLease;
+use crate::persist::StateBackend;
+use crate::{max, GeneratorOptions, Layout, OverflowPolicy, TimeSource, UtilizationAlert};
+
+/// Validating builder for [`GeneratorOptions`].
+///
+/// Unlike the setters on `GeneratorOptions`, settings can be given in any
+/// order and nothing is checked until [`build`](Self::build) is called.
+///
+/// ```rust
+/// use frostflake::GeneratorOptions;
+/// use std::time::Duration;
+///
+/// let opts = GeneratorOptions::builder()
+///     .node(300)
+///     .bits(44, 9, 11)
+///     .required_lifetime(Duration::from_secs(50 * 365 * 86400))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct GeneratorOptionsBuilder {
+    opts: GeneratorOptions,
+    required_lifetime: Option<Duration>,
+}
+
+impl GeneratorOptions {
+    pub fn builder() -> GeneratorOptionsBuilder {
+        GeneratorOptions::default().into()
+    }
+
+    /// Preset with 44bit timestamp, 8bit node and 12bit sequence.
+    ///
+    /// 44bit of milliseconds lasts about 557 years from `base_ts`.
+    pub fn long_life() -> Self {
+        GeneratorOptions {
+            bits: (44, 8, 12),
+            ..GeneratorOptions::default()
+        }
+    }
+
+    /// Preset with the layout and epoch of Twitter (X) ids, see
+    /// [`Layout::twitter`].
+    pub fn twitter() -> Self {
+        GeneratorOptions::default().with_layout(Layout::twitter())
+    }
+
+    /// Preset with the layout and epoch of Discord ids, see
+    /// [`Layout::discord`].
+    pub fn discord() -> Self {
+        GeneratorOptions::default().with_layout(Layout::discord())
+    }
+
+    /// Preset with the layout and epoch of Instagram ids, see
+    /// [`Layout::instagram`].
+    pub fn instagram() -> Self {
+        GeneratorOptions::default().with_layout(Layout::instagram())
+    }
+
+    /// Preset with the widths, epoch and 10 millisecond ticks of Sonyflake,
+    /// see [`Layout::sonyflake`] for how its ids differ from Sonyflake's.
+    ///
+    /// The time source counts 10 millisecond ticks; a custom `time_fn` has to
+    /// as well.
+    pub fn sonyflake() -> Self {
+        GeneratorOptions::default()
+            .time_unit(Duration::from_millis(10))
+            .with_layout(Layout::sonyflake())
+    }
+
+    fn with_layout(self, layout: Layout) -> Self {
+        GeneratorOptions {
+            bits: (layout.ts_bits(), layout.node_bits(), layout.seq_bits()),
+            base_ts: layout.base_ts(),
+            tick_ms: layout.tick_ms(),
+            ..self
+        }
+    }
+}
+
+/// Options with the bit widths, `base_ts` and tick length of `layout`, like
+/// [`GeneratorOptionsBuilder::layout`].
+///
+/// Panics if `base_ts` exceeds the timestamp bits, or the timestamp has no
+/// bits.
+impl From<Layout> for GeneratorOptions {
+    fn from(layout: Layout) -> Self {
+        let opts = GeneratorOptions::default()
+            .base_ts(0)
+            .bits(layout.ts_bits(), layout.node_bits(), layout.seq_bits())
+            .base_ts(layout.base_ts());
+        GeneratorOptions {
+            tick_ms: layout.tick_ms(),
+            ..opts
+        }
+    }
+}
+
+impl From<GeneratorOptions> for GeneratorOptionsBuilder {
+    fn from(opts: GeneratorOptions) -> Self {
+        GeneratorOptionsBuilder {
+            opts,
+            required_lifetime: None,
+        }
+    }
+}
+
+impl GeneratorOptionsBuilder {
+    /// Like `GeneratorOptions::bits`, this clears the type bits, the shard
+    /// bits and the fields of the options.
+    pub fn bits(mut self, ts_bits: u8, node_bits: u8, seq_bits: u8) -> Self {
+        self.opts.bits = (ts_bits, node_bits, seq_bits);
+        self.opts.type_bits = 0;
+        self.opts.shard_bits = 0;
+        self.opts.fields = None;
+        self
+    }
+
+    pub fn base_ts(mut self, base_ts: u64) -> Self {
+        self.opts.base_ts = base_ts;
+        self
+    }
+
+    /// Sets bit widths, base_ts and the tick length at once.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.opts.tick_ms = layout.tick_ms();
+        self.bits(layout.ts_bits(), layout.node_bits(), layout.seq_bits())
+            .base_ts(layout.base_ts())
+    }
+
+    pub fn node(mut self, node: u64) -> Self {
+        self.opts.node = node;
+        self
+    }
+
+    pub fn time_fn(mut self, time_fn: fn() -> u64) -> Self {
+        self.opts = self.opts.time_fn(time_fn);
+        self
+    }
+
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.opts = self.opts.overflow_policy(policy);
+        self
+    }
+
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.opts = self.opts.stall_timeout(timeout);
+        self
+    }
+
+    pub fn max_backward_drift(mut self, ms: u64) -> Self {
+        self.opts = self.opts.max_backward_drift(ms);
+        self
+    }
+
+    pub fn randomize_seq_start(mut self, enabled: bool) -> Self {
+        self.opts = self.opts.randomize_seq_start(enabled);
+        self
+    }
+
+    pub fn audit<A: AuditSink + Send + Sync + 'static>(mut self, sink: A) -> Self {
+        self.opts = self.opts.audit(sink);
+        self
+    }
+
+    pub fn time_source<T: TimeSource + Send + Sync + 'static>(mut self, clock: T) -> Self {
+        self.opts = self.opts.time_source(clock);
+        self
+    }
+
+    /// `build()` fails unless `threshold` is between 0.0 and 1.0.
+    pub fn utilization_alert<F>(mut self, threshold: f64, hook: F) -> Self
+    where
+        F: Fn(UtilizationAlert) + Send + Sync + 'static,
+    {
+        self.opts.utilization_alert = Some((threshold, Arc::new(hook)));
+        self
+    }
+
+    pub fn metrics<M: Metrics + Send + Sync + 'static>(mut self, metrics: M) -> Self {
+        self.opts = self.opts.metrics(metrics);
+        self
+    }
+
+    pub fn state_backend<B: StateBackend + Send + Sync + 'static>(mut self, backend: B) -> Self {
+        self.opts = self.opts.state_backend(backend);
+        self
+    }
+
+    /// Takes the node number from `lease`, replacing the one set by `node`.
+    pub fn node_lease<L: NodeLease + Send + Sync + 'static>(mut self, lease: L) -> Self {
+        self.opts.node = lease.node();
+        self.opts.node_lease = Some(Arc::new(lease));
+        self
+    }
+
+    /// How long ids must keep being generated from now on.
+    ///
+    /// `build()` fails if the timestamp field is exhausted before that.
+    /// This assumes `time_fn` counts the ticks of the time unit.
+    pub fn required_lifetime(mut self, lifetime: Duration) -> Self {
+        self.required_lifetime = Some(lifetime);
+        self
+    }
+
+    pub fn build(self) -> Result<GeneratorOptions, OptionsError> {
+        let opts = self.opts;
+        let (ts_bits, node_bits, seq_bits) = opts.bits;
+
+        let total = ts_bits as u32 + node_bits as u32 + seq_bits as u32;
+        if total != 64 {
+            return Err(OptionsError::InvalidBits { total });
+        }
+        if ts_bits == 0 {
+            return Err(OptionsError::NoTimestampBits);
+        }
+        if opts.base_ts > max(ts_bits) {
+            return Err(OptionsError::BaseTsOutOfRange {
+                base_ts: opts.base_ts,
+                max: max(ts_bits),
+            });
+        }
+        let reserved = opts.type_bits as u32 + opts.shard_bits as u32;
+        if reserved > node_bits as u32 {
+            return Err(OptionsError::NodeBitsTooNarrow {
+                node_bits,
+                reserved,
+            });
+        }
+        if opts.node > max(opts.node_bits()) {
+            return Err(OptionsError::NodeOutOfRange {
+                node: opts.node,
+                max: max(opts.node_bits()),
+            });
+        }
+
+        if let Some((threshold, _)) = &opts.utilization_alert {
+            if !(0.0..=1.0).contains(threshold) {
+                return Err(OptionsError::InvalidThreshold);
+            }
+        }
+
+        if let Some(required) = self.required_lifetime {
+            let exhausted_at = opts.base_ts.saturating_add(max(ts_bits));
+            let remaining = Duration::from_millis(
+                exhausted_at
+                    .saturating_sub(opts.clock.now())
+                    .saturating_mul(opts.tick_ms),
+            );
+            if remaining < required {
+                return Err(OptionsError::LifetimeTooShort {
+                    required,
+                    remaining,
+                });
+            }
+        }
+
+        Ok(opts)
+    }
+}
+
+/// Fields read when deserializing options, all optional.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OptionsConfig {
+    ts_bits: u8,
+    node_bits: u8,
+    seq_bits: u8,
+    base_ts: u64,
+    node: u64,
+    overflow_policy: OverflowPolicy,
+    stall_timeout_ms: Option<u64>,
+    max_backward_drift_ms: u64,
+}
+
+#[cfg(feature = "serde")]
+impl Default for OptionsConfig {
+    fn default() -> Self {
+        let opts = GeneratorOptions::default();
+        OptionsConfig {
+            ts_bits: opts.bits.0,
+            node_bits: opts.bits.1,
+            seq_bits: opts.bits.2,
+            base_ts: opts.base_ts,
+            node: opts.node,
+            overflow_policy: opts.overflow,
+            stall_timeout_ms: None,
+            max_backward_drift_ms: opts.max_backward_drift,
+        }
+    }
+}
+
+/// Reads `ts_bits`, `node_bits`, `seq_bits`, `base_ts`, `node`,
+/// `overflow_policy`, `stall_timeout_ms` and `max_backward_drift_ms`, with
+/// the defaults for missing fields, and validates them like [`GeneratorOptionsBuilder::build`].
+///
+/// ```toml
+/// node_bits = 8
+/// seq_bits = 14
+/// node = 42
+/// overflow_policy = "error"
+/// ```
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for GeneratorOptions {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let config = OptionsConfig::deserialize(deserializer)?;
+        let mut builder = GeneratorOptions::builder()
+            .bits(config.ts_bits, config.node_bits, config.seq_bits)
+            .base_ts(config.base_ts)
+            .node(config.node)
+            .overflow_policy(config.overflow_policy)
+            .max_backward_drift(config.max_backward_drift_ms);
+        if let Some(ms) = config.stall_timeout_ms {
+            builder = builder.stall_timeout(Duration::from_millis(ms));
+        }
+        builder.build().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YEAR: u64 = 365 * 86400;
+
+    fn my_time_fn() -> u64 {
+        1483228800000 + 123
+    }
+
+    #[test]
+    fn test_build_default() {
+        let opts = GeneratorOptions::builder().build().unwrap();
+        assert_eq!(opts.bits, (42, 10, 12));
+        assert_eq!(opts.base_ts, 1483228800000);
+        assert_eq!(opts.node, 0);
+    }
+
+    #[test]
+    fn test_build_any_order() {
+        let opts = GeneratorOptions::builder()
+            .node(1000)
+            .base_ts(0)
+            .bits(36, 10, 18)
+            .build()
+            .unwrap();
+        assert_eq!(opts.bits, (36, 10, 18));
+        assert_eq!(opts.node, 1000);
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let r = GeneratorOptions::builder().bits(42, 10, 10).build();
+        assert_eq!(r.err(), Some(OptionsError::InvalidBits { total: 62 }));
+        // a sum that wraps to 64 in 8bit
+        let r = GeneratorOptions::builder().bits(200, 100, 20).build();
+        assert_eq!(r.err(), Some(OptionsError::InvalidBits { total: 320 }));
+
+        let r = GeneratorOptions::builder().bits(42, 4, 18).node(16).build();
+        assert_eq!(
+            r.err(),
+            Some(OptionsError::NodeOutOfRange { node: 16, max: 15 })
+        );
+
+        let r = GeneratorOptions::builder()
+            .bits(0, 40, 24)
+            .base_ts(0)
+            .build();
+        assert_eq!(r.err(), Some(OptionsError::NoTimestampBits));
+
+        let r = GeneratorOptions::builder().bits(30, 10, 24).build();
+        assert!(matches!(r, Err(OptionsError::BaseTsOutOfRange { .. })));
+
+        // bits clears the type bits, like GeneratorOptions::bits
+        let typed = GeneratorOptions::default().bits4(42, 4, 6, 12).node(3);
+        let opts = GeneratorOptionsBuilder::from(typed.clone())
+            .bits(42, 0, 22)
+            .node(0)
+            .build()
+            .unwrap();
+        assert_eq!((opts.bits, opts.type_bits), ((42, 0, 22), 0));
+
+        let mut narrow = typed;
+        narrow.bits = (52, 2, 10);
+        let r = GeneratorOptionsBuilder::from(narrow).build();
+        assert_eq!(
+            r.err(),
+            Some(OptionsError::NodeBitsTooNarrow {
+                node_bits: 2,
+                reserved: 4
+            })
+        );
+
+        let r = GeneratorOptions::builder()
+            .utilization_alert(1.5, |_| {})
+            .build();
+        assert_eq!(r.err(), Some(OptionsError::InvalidThreshold));
+        let r = GeneratorOptions::builder()
+            .utilization_alert(f64::NAN, |_| {})
+            .build();
+        assert_eq!(r.err(), Some(OptionsError::InvalidThreshold));
+    }
+
+    #[test]
+    fn test_build_after_shard_bits() {
+        let sharded = GeneratorOptions::default().shard_bits(6);
+        let opts = GeneratorOptionsBuilder::from(sharded.clone())
+            .bits(42, 0, 22)
+            .build()
+            .unwrap();
+        assert_eq!((opts.bits, opts.shard_bits), ((42, 0, 22), 0));
+        assert_eq!(opts.node_bits(), 0);
+
+        let mut narrow = sharded;
+        narrow.bits = (52, 4, 8);
+        let r = GeneratorOptionsBuilder::from(narrow).build();
+        assert_eq!(
+            r.err(),
+            Some(OptionsError::NodeBitsTooNarrow {
+                node_bits: 4,
+                reserved: 6
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_node_lease() {
+        struct Lease;
+        impl NodeLease for Lease {
+            fn node(&self) -> u64 {
+                200
+            }
+            fn is_valid(&self) -> bool {
+                true
+            }
+        }
+
+        // the lease is checked against bits given afterwards
+        let r = GeneratorOptions::builder()
+            .node_lease(Lease)
+            .bits(42, 6, 16)
+            .build();
+        assert_eq!(
+            r.err(),
+            Some(OptionsError::NodeOutOfRange { node: 200, max: 63 })
+        );
+
+        let opts = GeneratorOptions::builder()
+            .node_lease(Lease)
+            .build()
+            .unwrap();
+        assert_eq!(opts.node, 200);
+        assert!(opts.node_lease.is_some());
+    }
+
+    #[test]
+    fn test_build_layout() {
+        let layout = Layout::recommend(100, 100_000, Duration::from_secs(50 * YEAR)).unwrap();
+        let opts = GeneratorOptions::builder()
+            .layout(layout)
+            .node(99)
+            .build()
+            .unwrap();
+        assert_eq!(opts.bits, (50, 7, 7));
+    }
+
+    #[test]
+    fn test_long_life() {
+        let opts = GeneratorOptions::long_life();
+        assert_eq!(opts.bits, (44, 8, 12));
+
+        let r = GeneratorOptionsBuilder::from(opts)
+            .time_fn(my_time_fn)
+            .required_lifetime(Duration::from_secs(500 * YEAR))
+            .build();
+        assert!(r.is_ok());
+    }
+
+    #[test]
+    fn test_presets() {
+        use crate::Generator;
+
+        fn later_time_fn() -> u64 {
+            1700000000000
+        }
+
+        let g = Generator::new(GeneratorOptions::discord().time_fn(later_time_fn).node(33));
+        let decoded = Layout::discord().decode(g.generate());
+        assert_eq!((decoded.timestamp, decoded.node), (1700000000000, 33));
+        assert_eq!(GeneratorOptions::twitter().layout(), Layout::twitter());
+        assert_eq!(GeneratorOptions::instagram().layout(), Layout::instagram());
+
+        let opts = GeneratorOptions::sonyflake().node(258);
+        assert_eq!(opts.layout(), Layout::sonyflake());
+        let id = Generator::new(opts).generate_id();
+        assert_eq!(id.node(), 258);
+        let elapsed = id.datetime().elapsed().unwrap_or_default();
+        assert!(elapsed < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_required_lifetime() {
+        // default 42bit layout lasts about 139 years from 2017
+        let r = GeneratorOptions::builder()
+            .time_fn(my_time_fn)
+            .required_lifetime(Duration::from_secs(100 * YEAR))
+            .build();
+        assert!(r.is_ok());
+
+        let r = GeneratorOptions::builder()
+            .time_fn(my_time_fn)
+            .required_lifetime(Duration::from_secs(150 * YEAR))
+            .build();
+        assert!(matches!(r, Err(OptionsError::LifetimeTooShort { .. })));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize() {
+        use serde::de::value::{Error, MapDeserializer};
+        use serde::Deserialize;
+
+        let fields = [("node_bits", 8), ("seq_bits", 14), ("node", 42)];
+        let opts =
+            GeneratorOptions::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()))
+                .unwrap();
+        assert_eq!(opts.bits, (42, 8, 14));
+        assert_eq!(opts.node, 42);
+        assert_eq!(opts.base_ts, GeneratorOptions::default().base_ts);
+
+        let fields = [("node_bits", 8), ("node", 300)];
+        let e = GeneratorOptions::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()));
+        assert!(e.is_err());
+
+        let fields = [("nodes", 1)];
+        let e = GeneratorOptions::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()));
+        assert!(e.is_err());
+
+        let fields = [("overflow_policy", "error")];
+        let opts =
+            GeneratorOptions::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()))
+                .unwrap();
+        assert_eq!(opts.overflow, OverflowPolicy::Error);
+    }
+}
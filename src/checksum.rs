@@ -0,0 +1,398 @@
+//! Check characters and check bits for ids typed by people, like ids on
+//! invoices, so that typos are caught instead of finding another id.
+//!
+//! A [`CheckedEncoding`] writes an id in one of the encodings of
+//! [`encode`](crate::encode), or in decimal, with a check character at the
+//! end or the start. Decimal ids get a check digit by the Damm algorithm,
+//! which catches every single wrong digit and every swap of two adjacent
+//! digits. The other encodings get a check character by Luhn mod N over
+//! their alphabet, which catches every single wrong character and most
+//! swaps of two adjacent characters.
+//!
+//! ```rust
+//! use frostflake::checksum::{CheckedEncoding, Encoding};
+//! use frostflake::encode::DecodeError;
+//!
+//! let decimal = CheckedEncoding::new(Encoding::Decimal);
+//! let s = decimal.encode(442730451055673344);
+//! assert_eq!(s, "4427304510556733442");
+//! assert_eq!(decimal.decode(&s), Ok(442730451055673344));
+//! // a typo
+//! assert_eq!(decimal.decode("4427304510556373442"), Err(DecodeError::ChecksumMismatch));
+//! ```
+//!
+//! Numeric ids can carry the check in their low bits instead, with
+//! [`with_check_bits`] and [`verify_bits`]. This moves the id up by the
+//! check bits, so it only works while the top bits of the id are 0. With
+//! the default layout, the top bit of the timestamp stays 0 until 2086, and
+//! the top 2 bits until 2051.
+
+use crate::encode::{
+    decode, encode, from_sortable, to_sortable, DecodeError, BASE58, BASE62, CROCKFORD, HEX,
+};
+use crate::Snowflake;
+
+const DECIMAL: &[u8; 10] = b"0123456789";
+
+// quasigroup of the Damm algorithm, of order 10 without weak totally
+// anti-symmetric pairs
+const DAMM: [[u8; 10]; 10] = [
+    [0, 3, 1, 7, 5, 9, 8, 6, 4, 2],
+    [7, 0, 9, 2, 1, 5, 4, 8, 6, 3],
+    [4, 2, 0, 6, 8, 7, 1, 3, 5, 9],
+    [1, 7, 5, 0, 9, 8, 3, 4, 2, 6],
+    [6, 1, 2, 3, 0, 4, 5, 9, 7, 8],
+    [3, 6, 7, 4, 2, 0, 9, 5, 8, 1],
+    [5, 8, 6, 9, 7, 2, 0, 1, 3, 4],
+    [8, 9, 4, 5, 3, 6, 2, 0, 1, 7],
+    [9, 4, 3, 8, 6, 1, 7, 2, 0, 5],
+    [2, 5, 8, 1, 4, 3, 6, 7, 9, 0],
+];
+
+/// Encodings of [`CheckedEncoding`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// Decimal digits, like `u64::to_string`, with a Damm check digit.
+    Decimal,
+    Base62,
+    Base58,
+    Hex,
+    /// The sortable encoding of `encode::to_sortable`. The check character
+    /// at the end keeps the strings sorting like the ids.
+    Sortable,
+}
+
+/// Where [`CheckedEncoding`] puts the check character.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Placement {
+    #[default]
+    End,
+    Start,
+}
+
+/// Encodes ids with a check character, see the
+/// [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CheckedEncoding {
+    encoding: Encoding,
+    placement: Placement,
+}
+
+impl CheckedEncoding {
+    /// Puts the check character at the end.
+    pub const fn new(encoding: Encoding) -> Self {
+        CheckedEncoding {
+            encoding,
+            placement: Placement::End,
+        }
+    }
+
+    pub const fn placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    pub fn encode(&self, id: u64) -> String {
+        let payload = self.encoding.encode(id);
+        let alphabet = self.encoding.alphabet();
+        let check = alphabet[self.encoding.check(&payload) as usize] as char;
+        match self.placement {
+            Placement::End => format!("{}{}", payload, check),
+            Placement::Start => format!("{}{}", check, payload),
+        }
+    }
+
+    /// Fails with [`DecodeError::ChecksumMismatch`] if the check character
+    /// doesn't match, and like the decoding of the encoding otherwise.
+    ///
+    /// Hex and the sortable encoding are decoded in either case, like
+    /// `encode::from_hex` and `encode::from_sortable`.
+    pub fn decode(&self, s: &str) -> Result<u64, DecodeError> {
+        if s.len() < 2 {
+            return Err(DecodeError::Empty);
+        }
+        let (payload, check, at, offset) = match self.placement {
+            Placement::End => (s.get(..s.len() - 1), s.get(s.len() - 1..), s.len() - 1, 0),
+            Placement::Start => (s.get(1..), s.get(..1), 0, 1),
+        };
+        // the check character is not ASCII
+        let (Some(payload), Some(check)) = (payload, check) else {
+            return Err(DecodeError::InvalidChar { index: at });
+        };
+
+        let id = self.encoding.decode(payload).map_err(|e| match e {
+            DecodeError::InvalidChar { index } => DecodeError::InvalidChar {
+                index: index + offset,
+            },
+            e => e,
+        })?;
+        let check = self
+            .encoding
+            .digit(check.as_bytes()[0])
+            .ok_or(DecodeError::InvalidChar { index: at })?;
+        if check != self.encoding.check(payload) {
+            return Err(DecodeError::ChecksumMismatch);
+        }
+        Ok(id)
+    }
+
+    /// Whether `s` decodes, with a matching check character.
+    pub fn verify(&self, s: &str) -> bool {
+        self.decode(s).is_ok()
+    }
+}
+
+impl Encoding {
+    fn alphabet(self) -> &'static [u8] {
+        match self {
+            Encoding::Decimal => DECIMAL,
+            Encoding::Base62 => BASE62,
+            Encoding::Base58 => BASE58,
+            Encoding::Hex => HEX,
+            Encoding::Sortable => CROCKFORD,
+        }
+    }
+
+    fn digit(self, c: u8) -> Option<u8> {
+        let c = match self {
+            Encoding::Hex => c.to_ascii_lowercase(),
+            Encoding::Sortable => c.to_ascii_uppercase(),
+            _ => c,
+        };
+        self.alphabet()
+            .iter()
+            .position(|&a| a == c)
+            .map(|d| d as u8)
+    }
+
+    fn encode(self, id: u64) -> String {
+        match self {
+            Encoding::Sortable => to_sortable(id),
+            _ => encode(id, self.alphabet()),
+        }
+    }
+
+    fn decode(self, s: &str) -> Result<u64, DecodeError> {
+        match self {
+            Encoding::Sortable => from_sortable(s),
+            _ => decode(s, |c| self.digit(c), self.alphabet().len() as u64),
+        }
+    }
+
+    // check digit of a payload of valid characters
+    fn check(self, payload: &str) -> u8 {
+        let digits = payload.bytes().filter_map(|c| self.digit(c));
+        match self {
+            Encoding::Decimal => digits.fold(0, |interim, d| DAMM[interim as usize][d as usize]),
+            _ => {
+                let digits = digits.rev().map(|d| d as u64);
+                luhn_mod_n(digits, self.alphabet().len() as u64) as u8
+            }
+        }
+    }
+}
+
+// check digit by Luhn mod n of digits given from the last one
+fn luhn_mod_n(digits: impl Iterator<Item = u64>, n: u64) -> u64 {
+    let sum: u64 = digits
+        .enumerate()
+        .map(|(i, d)| {
+            if i % 2 == 0 {
+                // doubled, with the digits of the result summed in base n
+                (2 * d) / n + (2 * d) % n
+            } else {
+                d
+            }
+        })
+        .sum();
+    (n - sum % n) % n
+}
+
+/// Moves `id` up by `bits`, 16 at most, and puts a check of it in the low
+/// bits, by Luhn mod 2^`bits` over the `bits` wide digits of `id`.
+///
+/// Returns `None` if the top `bits` of `id` are not 0, or `bits` exceeds 16.
+/// A wrong bit is always caught, so is a wrong digit, while other errors slip
+/// through with a chance of 1 in 2^`bits`.
+pub fn with_check_bits(id: u64, bits: u8) -> Option<u64> {
+    if bits > 16 {
+        return None;
+    }
+    if bits == 0 {
+        return Some(id);
+    }
+    if id.leading_zeros() < bits as u32 {
+        return None;
+    }
+    Some((id << bits) | check_bits(id, bits))
+}
+
+/// The id of [`with_check_bits`], if the check bits match. `None` as well
+/// if `bits` exceeds 16.
+pub fn verify_bits(checked: u64, bits: u8) -> Option<u64> {
+    if bits > 16 {
+        return None;
+    }
+    if bits == 0 {
+        return Some(checked);
+    }
+    let id = checked >> bits;
+    (checked & ((1 << bits) - 1) == check_bits(id, bits)).then_some(id)
+}
+
+fn check_bits(id: u64, bits: u8) -> u64 {
+    let mask = (1 << bits) - 1;
+    let digits = (0..64)
+        .step_by(bits as usize)
+        .map(|shift| (id >> shift) & mask);
+    luhn_mod_n(digits, 1 << bits)
+}
+
+impl Snowflake {
+    /// Same as `CheckedEncoding::encode`.
+    pub fn to_checked_string(&self, encoding: CheckedEncoding) -> String {
+        encoding.encode(self.as_u64())
+    }
+
+    /// Same as `CheckedEncoding::decode`, assuming the default layout like
+    /// `Snowflake::new`.
+    pub fn from_checked_string(
+        s: &str,
+        encoding: CheckedEncoding,
+    ) -> Result<Snowflake, DecodeError> {
+        encoding.decode(s).map(Snowflake::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODINGS: [Encoding; 5] = [
+        Encoding::Decimal,
+        Encoding::Base62,
+        Encoding::Base58,
+        Encoding::Hex,
+        Encoding::Sortable,
+    ];
+
+    #[test]
+    fn test_damm() {
+        // the example of the algorithm
+        assert_eq!(Encoding::Decimal.check("572"), 4);
+        assert_eq!(CheckedEncoding::new(Encoding::Decimal).encode(572), "5724");
+        assert_eq!(
+            CheckedEncoding::new(Encoding::Decimal).decode("5724"),
+            Ok(572)
+        );
+    }
+
+    #[test]
+    fn test_luhn_mod_n() {
+        // the example of Luhn mod 10, the credit card check digit
+        let digits = "7992739871".bytes().rev().map(|c| (c - b'0') as u64);
+        assert_eq!(luhn_mod_n(digits, 10), 3);
+    }
+
+    #[test]
+    fn test_checked_encoding() {
+        for encoding in ENCODINGS {
+            for placement in [Placement::End, Placement::Start] {
+                let checked = CheckedEncoding::new(encoding).placement(placement);
+                for id in [0, 1, 61, 62, 442730451055673344, u64::MAX] {
+                    let s = checked.encode(id);
+                    assert_eq!(checked.decode(&s), Ok(id), "{:?} {}", checked, s);
+                    assert!(checked.verify(&s));
+
+                    // every single wrong character is caught
+                    for i in 0..s.len() {
+                        for &c in encoding.alphabet() {
+                            let mut typo = s.clone().into_bytes();
+                            if typo[i] == c {
+                                continue;
+                            }
+                            typo[i] = c;
+                            let typo = String::from_utf8(typo).unwrap();
+                            assert!(checked.decode(&typo) != Ok(id), "{:?} {}", checked, typo);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_transposition() {
+        let decimal = CheckedEncoding::new(Encoding::Decimal);
+        let s = decimal.encode(442730451055673344);
+        for i in 0..s.len() - 1 {
+            let mut swapped = s.clone().into_bytes();
+            if swapped[i] == swapped[i + 1] {
+                continue;
+            }
+            swapped.swap(i, i + 1);
+            let swapped = String::from_utf8(swapped).unwrap();
+            assert!(!decimal.verify(&swapped), "{}", swapped);
+        }
+    }
+
+    #[test]
+    fn test_checked_decode_error() {
+        let checked = CheckedEncoding::new(Encoding::Base58);
+        assert_eq!(checked.decode(""), Err(DecodeError::Empty));
+        assert_eq!(checked.decode("2"), Err(DecodeError::Empty));
+        assert_eq!(
+            checked.decode("20z"),
+            Err(DecodeError::InvalidChar { index: 1 })
+        );
+        assert_eq!(
+            checked.decode("2z0"),
+            Err(DecodeError::InvalidChar { index: 2 })
+        );
+        assert_eq!(
+            checked.placement(Placement::Start).decode("z20"),
+            Err(DecodeError::InvalidChar { index: 2 })
+        );
+        assert_eq!(
+            checked.decode("2zé"),
+            Err(DecodeError::InvalidChar { index: 3 })
+        );
+
+        let sortable = CheckedEncoding::new(Encoding::Sortable);
+        let s = sortable.encode(442730451055673344);
+        assert_eq!(s.len(), 14);
+        assert_eq!(sortable.decode(&s.to_lowercase()), Ok(442730451055673344));
+    }
+
+    #[test]
+    fn test_check_bits() {
+        for bits in [0, 1, 3, 8, 12, 16] {
+            for id in [0, 1, 442730451055673344 >> 16, u64::MAX >> bits] {
+                let checked = with_check_bits(id, bits).unwrap();
+                assert_eq!(checked >> bits, id);
+                assert_eq!(verify_bits(checked, bits), Some(id));
+                if bits > 0 {
+                    // every single wrong bit is caught
+                    for bit in 0..64 {
+                        assert_eq!(verify_bits(checked ^ (1 << bit), bits), None);
+                    }
+                }
+            }
+        }
+        assert_eq!(with_check_bits(1 << 63, 1), None);
+        assert_eq!(with_check_bits(1 << 62, 2), None);
+        assert!(with_check_bits(1 << 61, 2).is_some());
+        assert_eq!(with_check_bits(1, 17), None);
+        assert_eq!(verify_bits(1, 17), None);
+    }
+
+    #[test]
+    fn test_snowflake() {
+        let checked = CheckedEncoding::new(Encoding::Base62);
+        let id = Snowflake::new(442730451055673344);
+        let s = id.to_checked_string(checked);
+        assert_eq!(s.len(), id.to_base62().len() + 1);
+        assert_eq!(Snowflake::from_checked_string(&s, checked), Ok(id));
+    }
+}
@@ -0,0 +1,594 @@
+//! Time sources.
+//!
+//! A generator reads the current time from a [`TimeSource`]. Functions like
+//! the ones passed to `time_fn` and closures are time sources, so a mocked
+//! clock can capture its state. [`FailoverClock`]
+//! combines two of them, [`HybridMonotonic`] is immune to clock adjustments,
+//! [`VirtualClock`] is driven by the application, and [`CoarseClock`] caches
+//! another time source for throughput. [`SharedClock`] keeps the
+//! generators of a pool on one timeline.
+
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of the current time, in the unit of `base_ts`.
+///
+/// Implemented for everything that is `Fn() -> u64`:
+///
+/// ```rust
+/// use frostflake::{Generator, GeneratorOptions};
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// let now = Arc::new(AtomicU64::new(1483228800000 + 1));
+/// let clock = now.clone();
+/// let opts = GeneratorOptions::default().time_source(move || clock.load(Ordering::SeqCst));
+/// let generator = Generator::new(opts);
+/// assert_eq!(generator.generate(), 1 << 22);
+///
+/// now.fetch_add(1, Ordering::SeqCst);
+/// assert_eq!(generator.generate(), 2 << 22);
+/// ```
+pub trait TimeSource {
+    fn now(&self) -> u64;
+}
+
+impl<F: Fn() -> u64> TimeSource for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+impl<T: TimeSource + ?Sized> TimeSource for Arc<T> {
+    fn now(&self) -> u64 {
+        (**self).now()
+    }
+}
+
+/// Reads the system time once, and advances from there with the monotonic
+/// clock, in milliseconds.
+///
+/// Timestamps never decrease, regardless of NTP adjustments or the system
+/// clock being set, so the generator never fails with
+/// `ClockMovedBackwards`. In exchange, the timestamps drift from the system
+/// time when it is adjusted after the anchor was taken. Ids only stay unique
+/// across restarts as long as the system time was right at startup.
+///
+/// ```rust
+/// use frostflake::clock::HybridMonotonic;
+/// use frostflake::{Generator, GeneratorOptions};
+///
+/// let generator = Generator::new(GeneratorOptions::default().time_source(HybridMonotonic::new()));
+/// let id = generator.generate();
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct HybridMonotonic {
+    anchor: u64,
+    started: Instant,
+}
+
+#[cfg(feature = "std")]
+impl HybridMonotonic {
+    /// Anchors to the current system time.
+    pub fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        HybridMonotonic {
+            anchor: now.as_millis() as u64,
+            started: Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for HybridMonotonic {
+    fn default() -> Self {
+        HybridMonotonic::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl TimeSource for HybridMonotonic {
+    fn now(&self) -> u64 {
+        self.anchor + self.started.elapsed().as_millis() as u64
+    }
+}
+
+/// Which time source a [`FailoverClock`] is reading from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActiveClock {
+    Primary,
+    Secondary,
+}
+
+#[cfg(feature = "std")]
+struct FailoverState {
+    active: ActiveClock,
+    // highest value seen from the primary, and when it last advanced
+    primary_last: u64,
+    primary_advanced_at: Instant,
+    last: u64,
+}
+
+#[cfg(feature = "std")]
+type SwitchHook = Box<dyn Fn(ActiveClock) + Send + Sync>;
+
+/// Reads a primary time source, and falls back to a secondary one while the
+/// primary stalls or runs backwards.
+///
+/// The primary is considered stalled when it keeps returning the same value
+/// for longer than `stall_timeout` of real time, and regressed when it returns
+/// a value lower than it did before. The clock switches back as soon as the
+/// primary advances past its previous value again.
+///
+/// Returned values never decrease, even across a switch, so a secondary that
+/// is slightly behind the primary holds the time still instead of making the
+/// generator fail with `ClockMovedBackwards`.
+///
+/// ```rust
+/// use frostflake::clock::{ActiveClock, FailoverClock};
+/// use frostflake::{Generator, GeneratorOptions};
+/// use std::time::Duration;
+///
+/// fn ntp_time() -> u64 {
+///     1483228800000 + 123
+/// }
+///
+/// fn local_time() -> u64 {
+///     1483228800000 + 456
+/// }
+///
+/// let clock = FailoverClock::new(ntp_time as fn() -> u64, local_time as fn() -> u64)
+///     .stall_timeout(Duration::from_secs(1))
+///     .on_switch(|active| eprintln!("now reading the {:?} clock", active));
+///
+/// let generator = Generator::new(GeneratorOptions::default().time_source(clock));
+/// generator.generate();
+/// ```
+#[cfg(feature = "std")]
+pub struct FailoverClock<P, S> {
+    primary: P,
+    secondary: S,
+    stall_timeout: Duration,
+    on_switch: Option<SwitchHook>,
+    state: Mutex<FailoverState>,
+}
+
+#[cfg(feature = "std")]
+impl<P: TimeSource, S: TimeSource> FailoverClock<P, S> {
+    /// Creates a clock with a stall timeout of 100ms.
+    pub fn new(primary: P, secondary: S) -> Self {
+        FailoverClock {
+            primary,
+            secondary,
+            stall_timeout: Duration::from_millis(100),
+            on_switch: None,
+            state: Mutex::new(FailoverState {
+                active: ActiveClock::Primary,
+                primary_last: 0,
+                primary_advanced_at: Instant::now(),
+                last: 0,
+            }),
+        }
+    }
+
+    /// How long the primary may return the same value before it is
+    /// considered stalled.
+    pub fn stall_timeout(mut self, stall_timeout: Duration) -> Self {
+        self.stall_timeout = stall_timeout;
+        self
+    }
+
+    /// Called with the newly active source every time the clock switches.
+    pub fn on_switch<F: Fn(ActiveClock) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.on_switch = Some(Box::new(hook));
+        self
+    }
+
+    pub fn active(&self) -> ActiveClock {
+        self.lock().active
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FailoverState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<P: TimeSource, S: TimeSource> TimeSource for FailoverClock<P, S> {
+    fn now(&self) -> u64 {
+        let primary = self.primary.now();
+        let at = Instant::now();
+
+        let mut state = self.lock();
+        let healthy = if primary > state.primary_last {
+            state.primary_last = primary;
+            state.primary_advanced_at = at;
+            true
+        } else if primary == state.primary_last {
+            at.duration_since(state.primary_advanced_at) < self.stall_timeout
+        } else {
+            false
+        };
+
+        let active = if healthy {
+            ActiveClock::Primary
+        } else {
+            ActiveClock::Secondary
+        };
+        let switched = active != state.active;
+        state.active = active;
+
+        let now = match active {
+            ActiveClock::Primary => primary,
+            ActiveClock::Secondary => self.secondary.now(),
+        }
+        .max(state.last);
+        state.last = now;
+        drop(state);
+
+        if switched {
+            if let Some(hook) = &self.on_switch {
+                hook(active);
+            }
+        }
+
+        now
+    }
+}
+
+/// Clock that only moves when the application advances it.
+///
+/// For game servers and discrete-event simulations, where time is counted in
+/// fixed simulation ticks: replaying the same inputs generates exactly the
+/// same ids. Clones share the same time, so one clone can be handed to the
+/// generator while the simulation loop keeps another.
+///
+/// ```rust
+/// use frostflake::clock::VirtualClock;
+/// use frostflake::{Generator, GeneratorOptions};
+///
+/// fn run(clock: VirtualClock) -> Vec<u64> {
+///     let generator = Generator::new(GeneratorOptions::default().time_source(clock.clone()));
+///     let mut ids = vec![];
+///     for _ in 0..3 {
+///         ids.push(generator.generate());
+///         clock.advance();
+///     }
+///     ids
+/// }
+///
+/// let start = 1483228800000 + 1;
+/// assert_eq!(run(VirtualClock::new(start, 16)), run(VirtualClock::new(start, 16)));
+/// ```
+///
+/// Don't combine this with `stall_timeout`, as a virtual clock stands still
+/// by design.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    now: Arc<AtomicU64>,
+    tick: u64,
+}
+
+#[cfg(feature = "std")]
+impl VirtualClock {
+    /// Starts at `start`, advancing by `tick` per step. Both are in the unit
+    /// of `base_ts`.
+    pub fn new(start: u64, tick: u64) -> Self {
+        VirtualClock {
+            now: Arc::new(AtomicU64::new(start)),
+            tick,
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Moves the clock one tick forward, and returns the new time.
+    pub fn advance(&self) -> u64 {
+        self.advance_by(1)
+    }
+
+    /// Moves the clock `ticks` ticks forward, and returns the new time.
+    pub fn advance_by(&self, ticks: u64) -> u64 {
+        let step = self.tick.saturating_mul(ticks);
+        self.now.fetch_add(step, Ordering::SeqCst) + step
+    }
+}
+
+#[cfg(feature = "std")]
+impl TimeSource for VirtualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
+/// Caches another time source, updated by a background thread.
+///
+/// Reading the system time for every id is measurable at millions of ids per
+/// second. A coarse clock reads it once per `interval` instead, and `now` is
+/// a single atomic load. The thread stops once the clock and all its clones
+/// are dropped. `GeneratorOptions::coarse_clock` wraps the time source of
+/// the options:
+///
+/// ```rust
+/// use frostflake::{Generator, GeneratorOptions};
+/// use std::time::Duration;
+///
+/// let opts = GeneratorOptions::default().coarse_clock(Duration::from_millis(1));
+/// let generator = Generator::new(opts);
+/// let id = generator.generate();
+/// ```
+///
+/// Ids of a generator stay unique and ascending, as they are for any time
+/// source:
+///
+/// - The cached time lags the source by up to `interval`, plus however long
+///   the thread is not scheduled, so timestamps of ids are that much early.
+/// - The cached time never decreases. When the source steps back, it stands
+///   still until the source catches up, and generating waits for the next
+///   tick meanwhile, like with a smeared leap second. The generator never
+///   sees `ClockMovedBackwards` from it.
+/// - While it stands still, all ids share one tick, so a burst uses up the
+///   sequence sooner. A `stall_timeout` of the generator should be well
+///   above `interval`.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct CoarseClock {
+    now: Arc<AtomicU64>,
+    // read on every call when the thread couldn't be spawned
+    source: Option<Arc<Mutex<dyn TimeSource + Send>>>,
+}
+
+#[cfg(feature = "std")]
+impl CoarseClock {
+    /// Reads `source` now, and then every `interval` on a new thread.
+    ///
+    /// If the thread can't be spawned, the clock reads `source` on every
+    /// call instead.
+    pub fn new<T: TimeSource + Send + 'static>(source: T, interval: Duration) -> Self {
+        let source = Arc::new(Mutex::new(source));
+        let now = Arc::new(AtomicU64::new(read_locked(&*source)));
+        let cached = Arc::downgrade(&now);
+        let shared = source.clone();
+        let spawned = std::thread::Builder::new()
+            .name("frostflake-coarse-clock".into())
+            .spawn(move || {
+                while let Some(now) = cached.upgrade() {
+                    now.fetch_max(read_locked(&*shared), Ordering::Release);
+                    drop(now);
+                    std::thread::sleep(interval);
+                }
+            });
+
+        CoarseClock {
+            now,
+            source: match spawned {
+                Ok(_) => None,
+                Err(_) => Some(source),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn read_locked<T: TimeSource + ?Sized>(source: &Mutex<T>) -> u64 {
+    source.lock().unwrap_or_else(|e| e.into_inner()).now()
+}
+
+#[cfg(feature = "std")]
+impl TimeSource for CoarseClock {
+    fn now(&self) -> u64 {
+        if let Some(source) = &self.source {
+            let now = read_locked(&**source);
+            return self.now.fetch_max(now, Ordering::AcqRel).max(now);
+        }
+        self.now.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(feature = "std")]
+impl core::fmt::Debug for CoarseClock {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CoarseClock")
+            .field("now", &self.now)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A time source read by all generators of a pool, that never goes back
+/// behind a time any of them has seen.
+///
+/// Generators reading a time source each on their own may see it step
+/// back, when the source isn't monotonic across threads, like a clock of
+/// each CPU. Through a `SharedClock`, a reading behind the latest one of any
+/// generator is raised to it, and counted as skew. Set it up with
+/// `GeneratorPoolOptions::shared_clock`.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct SharedClock<T> {
+    source: T,
+    last: AtomicU64,
+    skewed: AtomicU64,
+    max_skew: AtomicU64,
+}
+
+#[cfg(feature = "std")]
+impl<T: TimeSource> SharedClock<T> {
+    pub fn new(source: T) -> Self {
+        SharedClock {
+            source,
+            last: AtomicU64::new(0),
+            skewed: AtomicU64::new(0),
+            max_skew: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of readings behind the latest one.
+    pub fn skewed(&self) -> u64 {
+        self.skewed.load(Ordering::Relaxed)
+    }
+
+    /// Largest distance a reading was behind the latest one, in the unit of
+    /// the source.
+    pub fn max_skew(&self) -> u64 {
+        self.max_skew.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: TimeSource> TimeSource for SharedClock<T> {
+    fn now(&self) -> u64 {
+        let now = self.source.now();
+        let last = self.last.fetch_max(now, Ordering::AcqRel);
+        if now >= last {
+            return now;
+        }
+        self.skewed.fetch_add(1, Ordering::Relaxed);
+        self.max_skew.fetch_max(last - now, Ordering::Relaxed);
+        last
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    static PRIMARY: AtomicU64 = AtomicU64::new(1000);
+    static SECONDARY: AtomicU64 = AtomicU64::new(2000);
+
+    fn primary() -> u64 {
+        PRIMARY.load(Ordering::SeqCst)
+    }
+
+    fn secondary() -> u64 {
+        SECONDARY.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_failover() {
+        let switches = Arc::new(Mutex::new(vec![]));
+        let recorded = switches.clone();
+        let clock = FailoverClock::new(primary as fn() -> u64, secondary as fn() -> u64)
+            .stall_timeout(Duration::ZERO)
+            .on_switch(move |active| recorded.lock().unwrap().push(active));
+
+        assert_eq!(clock.now(), 1000);
+        assert_eq!(clock.active(), ActiveClock::Primary);
+
+        // stalled
+        assert_eq!(clock.now(), 2000);
+        assert_eq!(clock.active(), ActiveClock::Secondary);
+
+        // recovered, but never goes backwards
+        PRIMARY.store(1500, Ordering::SeqCst);
+        assert_eq!(clock.now(), 2000);
+        assert_eq!(clock.active(), ActiveClock::Primary);
+
+        // regressed
+        PRIMARY.store(1400, Ordering::SeqCst);
+        SECONDARY.store(2001, Ordering::SeqCst);
+        assert_eq!(clock.now(), 2001);
+        assert_eq!(clock.active(), ActiveClock::Secondary);
+
+        assert_eq!(
+            *switches.lock().unwrap(),
+            vec![
+                ActiveClock::Secondary,
+                ActiveClock::Primary,
+                ActiveClock::Secondary
+            ]
+        );
+    }
+
+    #[test]
+    fn test_closure() {
+        let counter = Arc::new(AtomicU64::new(10));
+        let shared = counter.clone();
+        let clock = FailoverClock::new(move || shared.fetch_add(1, Ordering::SeqCst), || 0);
+        assert_eq!(clock.now(), 10);
+        assert_eq!(clock.now(), 11);
+        assert_eq!(counter.load(Ordering::SeqCst), 12);
+    }
+
+    #[test]
+    fn test_hybrid_monotonic() {
+        let clock = HybridMonotonic::new();
+        let system = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let first = clock.now();
+        assert!(first.abs_diff(system) < 1000);
+
+        std::thread::sleep(Duration::from_millis(5));
+        let second = clock.now();
+        assert!(second >= first + 5);
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let clock = VirtualClock::new(1000, 16);
+        let shared = clock.clone();
+        assert_eq!(shared.now(), 1000);
+        assert_eq!(clock.advance(), 1016);
+        assert_eq!(shared.now(), 1016);
+        assert_eq!(clock.advance_by(3), 1064);
+        assert_eq!(shared.now(), 1064);
+    }
+
+    #[test]
+    fn test_coarse_clock() {
+        let source = Arc::new(AtomicU64::new(1000));
+        let shared = source.clone();
+        let clock = CoarseClock::new(
+            move || shared.load(Ordering::SeqCst),
+            Duration::from_millis(1),
+        );
+        assert_eq!(clock.now(), 1000);
+
+        let wait_for = |ts| {
+            let started = Instant::now();
+            while clock.now() != ts {
+                assert!(started.elapsed() < Duration::from_secs(5));
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        };
+        source.store(1005, Ordering::SeqCst);
+        wait_for(1005);
+
+        // stands still until the source catches up
+        source.store(1002, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(clock.now(), 1005);
+        source.store(1006, Ordering::SeqCst);
+        wait_for(1006);
+    }
+
+    #[test]
+    fn test_shared_clock() {
+        let source = Arc::new(AtomicU64::new(1000));
+        let shared = source.clone();
+        let clock = SharedClock::new(move || shared.load(Ordering::SeqCst));
+        assert_eq!(clock.now(), 1000);
+
+        source.store(990, Ordering::SeqCst);
+        assert_eq!(clock.now(), 1000);
+        source.store(997, Ordering::SeqCst);
+        assert_eq!(clock.now(), 1000);
+        assert_eq!((clock.skewed(), clock.max_skew()), (2, 10));
+
+        source.store(1001, Ordering::SeqCst);
+        assert_eq!(clock.now(), 1001);
+        assert_eq!(clock.skewed(), 2);
+    }
+}
@@ -0,0 +1,546 @@
+//! Wrappers adding behavior on top of any [`IdGenerator`].
+//!
+//! Wrappers implement `IdGenerator` themselves, so they can be stacked.
+//! [`IdGeneratorExt`] provides shorthands for that:
+//!
+//! ```rust
+//! use frostflake::combinators::IdGeneratorExt;
+//! use frostflake::{Generator, GeneratorOptions, IdGenerator};
+//!
+//! let g = Generator::new(GeneratorOptions::default())
+//!     .retrying()
+//!     .validated(|id| id != 0)
+//!     .rate_limited(10_000, 100);
+//! let id = g.generate().unwrap();
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{BoxIdGenerator, Error, IdGenerator, SharedGenerator};
+
+/// A pair of ids where `id` was generated after `last` but is not greater.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    pub last: u64,
+    pub id: u64,
+}
+
+/// Checks that every id is greater than the previous one.
+///
+/// Calls are serialized by this wrapper so that "previous" is well defined
+/// even when the inner generator is shared between threads. Meant for
+/// staging environments to catch clock or configuration issues.
+///
+/// Violations are always recorded. By default they also panic in debug
+/// builds; change that with [`panic_on_violation`](Self::panic_on_violation).
+///
+/// ```rust
+/// use frostflake::combinators::MonotonicChecked;
+/// use frostflake::{Generator, GeneratorOptions, IdGenerator};
+///
+/// let g = MonotonicChecked::new(Generator::new(GeneratorOptions::default()));
+/// let id = g.generate().unwrap();
+/// assert_eq!(g.violation_count(), 0);
+/// ```
+pub struct MonotonicChecked<G> {
+    inner: G,
+    panic_on_violation: bool,
+    state: Mutex<MonotonicState>,
+}
+
+#[derive(Default)]
+struct MonotonicState {
+    last: Option<u64>,
+    count: u64,
+    last_violation: Option<Violation>,
+}
+
+impl<G: IdGenerator> MonotonicChecked<G> {
+    pub fn new(inner: G) -> Self {
+        MonotonicChecked {
+            inner,
+            panic_on_violation: cfg!(debug_assertions),
+            state: Mutex::new(MonotonicState::default()),
+        }
+    }
+
+    pub fn panic_on_violation(mut self, panic: bool) -> Self {
+        self.panic_on_violation = panic;
+        self
+    }
+
+    pub fn violation_count(&self) -> u64 {
+        self.lock().count
+    }
+
+    pub fn last_violation(&self) -> Option<Violation> {
+        self.lock().last_violation
+    }
+
+    pub fn into_inner(self) -> G {
+        self.inner
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MonotonicState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<G: IdGenerator> IdGenerator for MonotonicChecked<G> {
+    fn generate(&self) -> Result<u64, Error> {
+        let mut state = self.lock();
+        let id = self.inner.generate()?;
+
+        if let Some(last) = state.last.filter(|last| id <= *last) {
+            let violation = Violation { last, id };
+            state.count += 1;
+            state.last_violation = Some(violation);
+            if self.panic_on_violation {
+                drop(state);
+                panic!("id {} is not greater than previous id {}", id, last);
+            }
+        } else {
+            state.last = Some(id);
+        }
+
+        Ok(id)
+    }
+}
+
+/// How long [`Retrying`] waits between attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Retry immediately.
+    None,
+    /// Wait the same duration before every retry.
+    Fixed(Duration),
+    /// Start with `initial` and double it on every retry, up to `max`.
+    Exponential { initial: Duration, max: Duration },
+}
+
+impl Backoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        match *self {
+            Backoff::None => Duration::ZERO,
+            Backoff::Fixed(d) => d,
+            Backoff::Exponential { initial, max } => initial
+                .checked_mul(1 << attempt.min(31))
+                .map_or(max, |d| d.min(max)),
+        }
+    }
+}
+
+/// Retries transient errors before surfacing them.
+///
+/// [`Error::SequenceExhausted`] and [`Error::ClockMovedBackwards`] usually go
+/// away once the clock moves on, so they are retried up to `max_retries`
+/// times. Other errors are returned immediately.
+///
+/// ```rust
+/// use frostflake::combinators::{Backoff, Retrying};
+/// use frostflake::{Generator, GeneratorOptions, IdGenerator};
+/// use std::time::Duration;
+///
+/// let g = Retrying::new(Generator::new(GeneratorOptions::default()))
+///     .max_retries(5)
+///     .backoff(Backoff::Fixed(Duration::from_micros(200)));
+/// let id = g.generate().unwrap();
+/// ```
+pub struct Retrying<G> {
+    inner: G,
+    max_retries: u32,
+    backoff: Backoff,
+}
+
+impl<G: IdGenerator> Retrying<G> {
+    /// Retries up to 10 times, backing off exponentially from 100us to 10ms.
+    pub fn new(inner: G) -> Self {
+        Retrying {
+            inner,
+            max_retries: 10,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_micros(100),
+                max: Duration::from_millis(10),
+            },
+        }
+    }
+
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn into_inner(self) -> G {
+        self.inner
+    }
+}
+
+impl<G: IdGenerator> IdGenerator for Retrying<G> {
+    fn generate(&self) -> Result<u64, Error> {
+        let mut attempt = 0;
+        loop {
+            match self.inner.generate() {
+                Err(e) if e.is_transient() && attempt < self.max_retries => {
+                    let delay = self.backoff.delay(attempt);
+                    if !delay.is_zero() {
+                        thread::sleep(delay);
+                    }
+                    attempt += 1;
+                }
+                r => return r,
+            }
+        }
+    }
+}
+
+/// Token bucket limiting how fast ids are handed out.
+///
+/// Calls beyond the limit fail with [`Error::RateLimited`].
+pub struct RateLimited<G> {
+    inner: G,
+    bucket: Mutex<Bucket>,
+}
+
+/// Token bucket of [`RateLimited`] and `GeneratorOptions::max_rate`.
+#[derive(Debug)]
+pub(crate) struct Bucket {
+    ids_per_sec: u64,
+    burst: u64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Starts full.
+    pub(crate) fn new(ids_per_sec: u64, burst: u64) -> Self {
+        Bucket {
+            ids_per_sec,
+            burst,
+            tokens: burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.updated).as_secs_f64() * self.ids_per_sec as f64;
+        self.tokens = (self.tokens + refill).min(self.burst as f64);
+        self.updated = now;
+    }
+
+    /// Takes up to `n` tokens, and returns how many were taken.
+    pub(crate) fn take(&mut self, n: u64) -> u64 {
+        self.refill();
+        let taken = (self.tokens as u64).min(n);
+        self.tokens -= taken as f64;
+        taken
+    }
+
+    /// Returns `n` tokens taken but not used.
+    pub(crate) fn put_back(&mut self, n: u64) {
+        self.tokens = (self.tokens + n as f64).min(self.burst as f64);
+    }
+
+    /// How long until a token is available, `None` if one is now.
+    pub(crate) fn wait(&mut self) -> Option<Duration> {
+        self.refill();
+        (self.tokens < 1.0)
+            .then(|| Duration::from_secs_f64((1.0 - self.tokens) / self.ids_per_sec as f64))
+    }
+}
+
+impl<G: IdGenerator> RateLimited<G> {
+    /// Allows `ids_per_sec` ids per second on average, and bursts up to `burst` ids.
+    pub fn new(inner: G, ids_per_sec: u64, burst: u64) -> Self {
+        RateLimited {
+            inner,
+            bucket: Mutex::new(Bucket::new(ids_per_sec, burst)),
+        }
+    }
+
+    pub fn into_inner(self) -> G {
+        self.inner
+    }
+}
+
+impl<G: IdGenerator> IdGenerator for RateLimited<G> {
+    fn generate(&self) -> Result<u64, Error> {
+        let taken = self
+            .bucket
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take(1);
+        if taken == 0 {
+            return Err(Error::RateLimited);
+        }
+        self.inner.generate()
+    }
+}
+
+/// Receives the outcome of every call made through [`Instrumented`].
+pub trait Observer {
+    fn observe(&self, result: &Result<u64, Error>, elapsed: Duration);
+}
+
+impl<F: Fn(&Result<u64, Error>, Duration)> Observer for F {
+    fn observe(&self, result: &Result<u64, Error>, elapsed: Duration) {
+        self(result, elapsed)
+    }
+}
+
+/// Simple [`Observer`] counting generated ids and errors.
+#[derive(Debug, Default)]
+pub struct Counters {
+    generated: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Counters {
+    pub fn generated(&self) -> u64 {
+        self.generated.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+}
+
+impl Observer for Counters {
+    fn observe(&self, result: &Result<u64, Error>, _elapsed: Duration) {
+        let counter = if result.is_ok() {
+            &self.generated
+        } else {
+            &self.errors
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<O: Observer + ?Sized> Observer for std::sync::Arc<O> {
+    fn observe(&self, result: &Result<u64, Error>, elapsed: Duration) {
+        (**self).observe(result, elapsed)
+    }
+}
+
+/// Reports every call and its latency to an [`Observer`].
+pub struct Instrumented<G, O> {
+    inner: G,
+    observer: O,
+}
+
+impl<G: IdGenerator, O: Observer> Instrumented<G, O> {
+    pub fn new(inner: G, observer: O) -> Self {
+        Instrumented { inner, observer }
+    }
+
+    pub fn into_inner(self) -> G {
+        self.inner
+    }
+}
+
+impl<G: IdGenerator, O: Observer> IdGenerator for Instrumented<G, O> {
+    fn generate(&self) -> Result<u64, Error> {
+        let start = Instant::now();
+        let result = self.inner.generate();
+        self.observer.observe(&result, start.elapsed());
+        result
+    }
+}
+
+/// Rejects ids not accepted by a predicate with [`Error::Rejected`].
+pub struct Validated<G, F> {
+    inner: G,
+    validate: F,
+}
+
+impl<G: IdGenerator, F: Fn(u64) -> bool> Validated<G, F> {
+    pub fn new(inner: G, validate: F) -> Self {
+        Validated { inner, validate }
+    }
+
+    pub fn into_inner(self) -> G {
+        self.inner
+    }
+}
+
+impl<G: IdGenerator, F: Fn(u64) -> bool> IdGenerator for Validated<G, F> {
+    fn generate(&self) -> Result<u64, Error> {
+        let id = self.inner.generate()?;
+        if (self.validate)(id) {
+            Ok(id)
+        } else {
+            Err(Error::Rejected { id })
+        }
+    }
+}
+
+/// Shorthands for wrapping generators.
+pub trait IdGeneratorExt: IdGenerator + Sized {
+    fn monotonic_checked(self) -> MonotonicChecked<Self> {
+        MonotonicChecked::new(self)
+    }
+
+    fn retrying(self) -> Retrying<Self> {
+        Retrying::new(self)
+    }
+
+    fn rate_limited(self, ids_per_sec: u64, burst: u64) -> RateLimited<Self> {
+        RateLimited::new(self, ids_per_sec, burst)
+    }
+
+    fn instrumented<O: Observer>(self, observer: O) -> Instrumented<Self, O> {
+        Instrumented::new(self, observer)
+    }
+
+    fn validated<F: Fn(u64) -> bool>(self, validate: F) -> Validated<Self, F> {
+        Validated::new(self, validate)
+    }
+
+    fn boxed(self) -> BoxIdGenerator
+    where
+        Self: Send + Sync + 'static,
+    {
+        Box::new(self)
+    }
+
+    fn shared(self) -> SharedGenerator
+    where
+        Self: Send + Sync + 'static,
+    {
+        SharedGenerator::new(self)
+    }
+}
+
+impl<G: IdGenerator> IdGeneratorExt for G {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    struct Scripted(Mutex<VecDeque<Result<u64, Error>>>);
+
+    impl Scripted {
+        fn new(ids: &[u64]) -> Self {
+            Scripted(Mutex::new(ids.iter().map(|id| Ok(*id)).collect()))
+        }
+
+        fn results(results: Vec<Result<u64, Error>>) -> Self {
+            Scripted(Mutex::new(results.into()))
+        }
+    }
+
+    impl IdGenerator for Scripted {
+        fn generate(&self) -> Result<u64, Error> {
+            self.0.lock().unwrap().pop_front().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_monotonic_checked() {
+        let g = MonotonicChecked::new(Scripted::new(&[1, 2, 2, 1, 3])).panic_on_violation(false);
+        for _ in 0..5 {
+            g.generate().unwrap();
+        }
+        assert_eq!(g.violation_count(), 2);
+        assert_eq!(g.last_violation(), Some(Violation { last: 2, id: 1 }));
+    }
+
+    #[test]
+    #[should_panic(expected = "not greater than previous id")]
+    fn test_monotonic_checked_panic() {
+        let g = MonotonicChecked::new(Scripted::new(&[2, 1])).panic_on_violation(true);
+        let _ = g.generate();
+        let _ = g.generate();
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let b = Backoff::Exponential {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+        };
+        assert_eq!(b.delay(0), Duration::from_millis(1));
+        assert_eq!(b.delay(2), Duration::from_millis(4));
+        assert_eq!(b.delay(3), Duration::from_millis(5));
+        assert_eq!(b.delay(100), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_retrying() {
+        let g = Retrying::new(Scripted::results(vec![
+            Err(Error::SequenceExhausted),
+            Err(Error::ClockMovedBackwards { now: 1, last_ts: 2 }),
+            Ok(1),
+        ]))
+        .backoff(Backoff::None);
+        assert_eq!(g.generate(), Ok(1));
+
+        let g = Retrying::new(Scripted::results(vec![
+            Err(Error::SequenceExhausted),
+            Err(Error::SequenceExhausted),
+            Ok(1),
+        ]))
+        .max_retries(1)
+        .backoff(Backoff::None);
+        assert_eq!(g.generate(), Err(Error::SequenceExhausted));
+
+        let err = Error::TimeBeforeBaseTs { now: 1, base_ts: 2 };
+        let g = Retrying::new(Scripted::results(vec![Err(err.clone()), Ok(1)]));
+        assert_eq!(g.generate(), Err(err));
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        let g = Scripted::new(&[1, 2, 3]).rate_limited(1, 2);
+        assert_eq!(g.generate(), Ok(1));
+        assert_eq!(g.generate(), Ok(2));
+        assert_eq!(g.generate(), Err(Error::RateLimited));
+    }
+
+    #[test]
+    fn test_instrumented() {
+        let counters = std::sync::Arc::new(Counters::default());
+        let g = Scripted::results(vec![Ok(1), Err(Error::SequenceExhausted), Ok(2)])
+            .instrumented(counters.clone());
+        for _ in 0..3 {
+            let _ = g.generate();
+        }
+        assert_eq!(counters.generated(), 2);
+        assert_eq!(counters.errors(), 1);
+    }
+
+    #[test]
+    fn test_validated() {
+        let g = Scripted::new(&[2, 3]).validated(|id| id % 2 == 0);
+        assert_eq!(g.generate(), Ok(2));
+        assert_eq!(g.generate(), Err(Error::Rejected { id: 3 }));
+    }
+
+    #[test]
+    fn test_stacked() {
+        let counters = std::sync::Arc::new(Counters::default());
+        let g = Scripted::results(vec![Err(Error::SequenceExhausted), Ok(4), Ok(5)])
+            .retrying()
+            .validated(|id| id % 2 == 0)
+            .instrumented(counters.clone());
+        assert_eq!(g.generate(), Ok(4));
+        assert_eq!(g.generate(), Err(Error::Rejected { id: 5 }));
+        assert_eq!(counters.generated(), 1);
+        assert_eq!(counters.errors(), 1);
+    }
+
+    #[test]
+    fn test_boxed_stack() {
+        let g: BoxIdGenerator = Scripted::new(&[2]).validated(|id| id > 1).boxed();
+        assert_eq!(g.generate(), Ok(2));
+    }
+}
@@ -0,0 +1,445 @@
+//! Reading options from the environment or a config file.
+//!
+//! The same settings are read from both, so that a binary deployed to many
+//! nodes can take its layout from a shared file and its node number from the
+//! environment:
+//!
+//! |Key|Environment variable|
+//! |---|---|
+//! |`ts_bits`|`FROSTFLAKE_TS_BITS`|
+//! |`pool_bits` (pools only)|`FROSTFLAKE_POOL_BITS`|
+//! |`node_bits`|`FROSTFLAKE_NODE_BITS`|
+//! |`seq_bits`|`FROSTFLAKE_SEQ_BITS`|
+//! |`base_ts`|`FROSTFLAKE_BASE_TS`|
+//! |`node`|`FROSTFLAKE_NODE`|
+//! |`overflow_policy`|`FROSTFLAKE_OVERFLOW_POLICY`|
+//! |`stall_timeout_ms` (`Generator` only)|`FROSTFLAKE_STALL_TIMEOUT_MS`|
+//! |`max_backward_drift_ms` (`Generator` only)|`FROSTFLAKE_MAX_BACKWARD_DRIFT_MS`|
+//!
+//! Missing settings take the default values. `overflow_policy` is one of
+//! `wait_next_tick`, `error` or `panic`.
+//!
+//! Config files are flat TOML: `key = value` lines with integers or quoted
+//! strings, and `#` comments. Tables and other types of values are rejected.
+//!
+//! ```rust
+//! use frostflake::GeneratorOptions;
+//!
+//! let path = std::env::temp_dir().join("frostflake-config-doctest.toml");
+//! std::fs::write(&path, "node_bits = 8\nseq_bits = 14\nnode = 42 # this host\n").unwrap();
+//! let opts = GeneratorOptions::from_toml(&path).unwrap();
+//! assert_eq!(opts.layout().node_bits(), 8);
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::error::OptionsError;
+use crate::{GeneratorOptions, OverflowPolicy};
+
+const ENV_PREFIX: &str = "FROSTFLAKE_";
+
+/// Errors returned when reading options.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// The config file could not be read.
+    Io(io::Error),
+    /// A line of the config file is not a `key = value` pair.
+    Syntax {
+        line: usize,
+        message: String,
+    },
+    UnknownKey {
+        key: String,
+    },
+    InvalidValue {
+        key: String,
+        value: String,
+    },
+    /// The settings are read fine, but do not make valid options.
+    Options(OptionsError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "failed to read config: {}", e),
+            ConfigError::Syntax { line, message } => {
+                write!(f, "syntax error at line {}: {}", line, message)
+            }
+            ConfigError::UnknownKey { key } => write!(f, "unknown key {}", key),
+            ConfigError::InvalidValue { key, value } => {
+                write!(f, "invalid value {} for {}", value, key)
+            }
+            ConfigError::Options(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            ConfigError::Options(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<OptionsError> for ConfigError {
+    fn from(e: OptionsError) -> Self {
+        ConfigError::Options(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Int(u64),
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Str(v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+/// Settings read so far; `None` for the ones not given.
+#[derive(Debug, Default)]
+struct Settings {
+    ts_bits: Option<u8>,
+    pool_bits: Option<u8>,
+    node_bits: Option<u8>,
+    seq_bits: Option<u8>,
+    base_ts: Option<u64>,
+    node: Option<u64>,
+    overflow_policy: Option<OverflowPolicy>,
+    stall_timeout_ms: Option<u64>,
+    max_backward_drift_ms: Option<u64>,
+}
+
+impl Settings {
+    /// Reads `FROSTFLAKE_*` variables; other variables are ignored.
+    fn from_vars<I>(vars: I, pool: bool) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut settings = Settings::default();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let value = match value.parse() {
+                Ok(v) => Value::Int(v),
+                Err(_) => Value::Str(value),
+            };
+            settings.set(&key.to_ascii_lowercase(), value, pool)?;
+        }
+        Ok(settings)
+    }
+
+    fn from_toml(text: &str, pool: bool) -> Result<Self, ConfigError> {
+        let mut settings = Settings::default();
+        for (i, line) in text.lines().enumerate() {
+            let syntax = |message: &str| ConfigError::Syntax {
+                line: i + 1,
+                message: message.to_string(),
+            };
+
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(syntax("tables are not supported"));
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| syntax("expected key = value"))?;
+            let key = key.trim();
+            let value = parse_value(value.trim())
+                .ok_or_else(|| syntax("expected an integer or a string"))?;
+            settings.set(key, value, pool)?;
+        }
+        Ok(settings)
+    }
+
+    fn read_toml<P: AsRef<Path>>(path: P, pool: bool) -> Result<Self, ConfigError> {
+        Settings::from_toml(&fs::read_to_string(path)?, pool)
+    }
+
+    fn set(&mut self, key: &str, value: Value, pool: bool) -> Result<(), ConfigError> {
+        let invalid = |value: &Value| ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        let int = |value: &Value| match value {
+            Value::Int(v) => Ok(*v),
+            Value::Str(_) => Err(invalid(value)),
+        };
+        let bits =
+            |value: &Value| int(value).and_then(|v| u8::try_from(v).map_err(|_| invalid(value)));
+
+        match key {
+            "ts_bits" => self.ts_bits = Some(bits(&value)?),
+            "pool_bits" if pool => self.pool_bits = Some(bits(&value)?),
+            "node_bits" => self.node_bits = Some(bits(&value)?),
+            "seq_bits" => self.seq_bits = Some(bits(&value)?),
+            "base_ts" => self.base_ts = Some(int(&value)?),
+            "node" => self.node = Some(int(&value)?),
+            "stall_timeout_ms" if !pool => self.stall_timeout_ms = Some(int(&value)?),
+            "max_backward_drift_ms" if !pool => self.max_backward_drift_ms = Some(int(&value)?),
+            "overflow_policy" => {
+                self.overflow_policy = Some(match &value {
+                    Value::Str(s) if s == "wait_next_tick" => OverflowPolicy::WaitNextTick,
+                    Value::Str(s) if s == "error" => OverflowPolicy::Error,
+                    Value::Str(s) if s == "panic" => OverflowPolicy::Panic,
+                    _ => return Err(invalid(&value)),
+                })
+            }
+            _ => {
+                return Err(ConfigError::UnknownKey {
+                    key: key.to_string(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn into_options(self) -> Result<GeneratorOptions, ConfigError> {
+        let defaults = GeneratorOptions::default();
+        let (ts_bits, node_bits, seq_bits) = defaults.bits;
+
+        let mut builder = GeneratorOptions::builder()
+            .bits(
+                self.ts_bits.unwrap_or(ts_bits),
+                self.node_bits.unwrap_or(node_bits),
+                self.seq_bits.unwrap_or(seq_bits),
+            )
+            .base_ts(self.base_ts.unwrap_or(defaults.base_ts))
+            .node(self.node.unwrap_or(defaults.node))
+            .overflow_policy(self.overflow_policy.unwrap_or(defaults.overflow))
+            .max_backward_drift(
+                self.max_backward_drift_ms
+                    .unwrap_or(defaults.max_backward_drift),
+            );
+        if let Some(ms) = self.stall_timeout_ms {
+            builder = builder.stall_timeout(Duration::from_millis(ms));
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Removes a `#` comment, unless it is within a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    for q in ['"', '\''] {
+        if let Some(s) = value.strip_prefix(q).and_then(|v| v.strip_suffix(q)) {
+            // escapes are not needed for any of the settings
+            return (!s.contains(q) && !s.contains('\\')).then(|| Value::Str(s.to_string()));
+        }
+    }
+
+    // TOML allows underscores between digits
+    if value.starts_with('_') || value.ends_with('_') || value.contains("__") {
+        return None;
+    }
+    value.replace('_', "").parse().ok().map(Value::Int)
+}
+
+impl GeneratorOptions {
+    /// Reads options from `FROSTFLAKE_*` environment variables, see
+    /// [`config`](crate::config).
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Settings::from_vars(std::env::vars(), false)?.into_options()
+    }
+
+    /// Reads options from a flat TOML file, see [`config`](crate::config).
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Settings::read_toml(path, false)?.into_options()
+    }
+}
+
+#[cfg(feature = "std-thread")]
+impl Settings {
+    fn into_pool_options(self) -> Result<crate::GeneratorPoolOptions, ConfigError> {
+        let defaults = crate::GeneratorPoolOptions::default();
+        let (ts_bits, pool_bits, node_bits, seq_bits) = defaults.bits;
+
+        let bits = (
+            self.ts_bits.unwrap_or(ts_bits),
+            self.pool_bits.unwrap_or(pool_bits),
+            self.node_bits.unwrap_or(node_bits),
+            self.seq_bits.unwrap_or(seq_bits),
+        );
+        let opts = crate::GeneratorPoolOptions::validated(
+            bits,
+            self.base_ts.unwrap_or(defaults.base_ts),
+            self.node.unwrap_or(defaults.node),
+        )?;
+        Ok(opts.overflow_policy(self.overflow_policy.unwrap_or(defaults.overflow)))
+    }
+}
+
+#[cfg(feature = "std-thread")]
+impl crate::GeneratorPoolOptions {
+    /// Reads pool options from `FROSTFLAKE_*` environment variables, see
+    /// [`config`](crate::config).
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Settings::from_vars(std::env::vars(), true)?.into_pool_options()
+    }
+
+    /// Reads pool options from a flat TOML file, see [`config`](crate::config).
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Settings::read_toml(path, true)?.into_pool_options()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_vars() {
+        let settings = Settings::from_vars(
+            vars(&[
+                ("PATH", "/bin"),
+                ("FROSTFLAKE_NODE_BITS", "8"),
+                ("FROSTFLAKE_SEQ_BITS", "14"),
+                ("FROSTFLAKE_NODE", "42"),
+                ("FROSTFLAKE_OVERFLOW_POLICY", "error"),
+            ]),
+            false,
+        )
+        .unwrap();
+        let opts = settings.into_options().unwrap();
+        assert_eq!(opts.bits, (42, 8, 14));
+        assert_eq!(opts.node, 42);
+        assert_eq!(opts.overflow, OverflowPolicy::Error);
+
+        assert!(matches!(
+            Settings::from_vars(vars(&[("FROSTFLAKE_NODE", "x")]), false),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            Settings::from_vars(vars(&[("FROSTFLAKE_POOL_BITS", "4")]), false),
+            Err(ConfigError::UnknownKey { .. })
+        ));
+        assert!(matches!(
+            Settings::from_vars(vars(&[("FROSTFLAKE_NODE", "5000")]), false)
+                .unwrap()
+                .into_options(),
+            Err(ConfigError::Options(OptionsError::NodeOutOfRange { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let text = r#"
+            # layout shared by all hosts
+            node_bits = 8
+            seq_bits = 14
+            base_ts = 1_483_228_800_000
+            overflow_policy = "wait_next_tick" # default
+            stall_timeout_ms = 500
+            max_backward_drift_ms = 5
+        "#;
+        let opts = Settings::from_toml(text, false)
+            .unwrap()
+            .into_options()
+            .unwrap();
+        assert_eq!(opts.bits, (42, 8, 14));
+        assert_eq!(opts.base_ts, 1483228800000);
+        assert_eq!(opts.stall_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(opts.max_backward_drift, 5);
+
+        for (text, line) in [
+            ("node", 1),
+            ("\n[generator]", 2),
+            ("node = 1.5", 1),
+            ("node = \"a", 1),
+        ] {
+            match Settings::from_toml(text, false) {
+                Err(ConfigError::Syntax { line: l, .. }) => assert_eq!(l, line, "{}", text),
+                r => panic!("unexpected {:?} for {}", r, text),
+            }
+        }
+        assert!(matches!(
+            Settings::from_toml("nodes = 1", false),
+            Err(ConfigError::UnknownKey { .. })
+        ));
+        assert!(matches!(
+            Settings::from_toml("ts_bits = 300", false),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_toml_file() {
+        let path =
+            std::env::temp_dir().join(format!("frostflake-config-{}.toml", std::process::id()));
+        fs::write(&path, "node = 3\n").unwrap();
+        assert_eq!(GeneratorOptions::from_toml(&path).unwrap().node, 3);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            GeneratorOptions::from_toml(&path),
+            Err(ConfigError::Io(_))
+        ));
+    }
+
+    #[cfg(feature = "std-thread")]
+    #[test]
+    fn test_pool_options() {
+        let opts = Settings::from_toml("pool_bits = 2\nnode_bits = 8\nnode = 200", true)
+            .unwrap()
+            .into_pool_options()
+            .unwrap();
+        assert_eq!(opts.bits, (42, 2, 8, 12));
+        assert_eq!(opts.node, 200);
+
+        assert!(matches!(
+            Settings::from_toml("pool_bits = 2", true)
+                .unwrap()
+                .into_pool_options(),
+            Err(ConfigError::Options(OptionsError::InvalidBits {
+                total: 62
+            }))
+        ));
+        assert!(matches!(
+            Settings::from_toml("stall_timeout_ms = 1", true),
+            Err(ConfigError::UnknownKey { .. })
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+//! Decoding ids of any layout to wall-clock time, for log forensics.
+//!
+//! Given a [`Layout`], either a preset like [`Layout::discord`] or a custom
+//! one, ids of other systems are split into their fields and their timestamp
+//! is converted to a `SystemTime`, taking the epoch and tick length of the
+//! layout into account.
+//!
+//! ```rust
+//! use frostflake::decode::decode;
+//! use frostflake::Layout;
+//!
+//! let decoded = decode(&Layout::discord(), 175928847299117063);
+//! assert_eq!(decoded.unix_ms, 1462015105796);
+//! assert_eq!(decoded.to_string(), "2016-04-30T11:18:25.796Z node=32 seq=7");
+//! ```
+
+use std::fmt;
+use std::num::ParseIntError;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::timefmt::rfc3339_millis;
+use crate::Layout;
+
+/// An id decoded by [`decode`].
+///
+/// Displayed as the RFC 3339 time followed by the node and sequence fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Decoded {
+    pub id: u64,
+    /// Timestamp field of the id, in ticks since `base_ts` of the layout.
+    pub timestamp_raw: u64,
+    /// Milliseconds since the Unix epoch.
+    pub unix_ms: u64,
+    pub node: u64,
+    pub seq: u64,
+}
+
+impl Decoded {
+    /// When the id was created.
+    pub fn datetime(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.unix_ms)
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} node={} seq={}",
+            rfc3339_millis(self.unix_ms),
+            self.node,
+            self.seq
+        )
+    }
+}
+
+/// An id of a [`GeneratorPool`](crate::GeneratorPool) or
+/// [`GeneratorPoolAsync`](crate::tokio::GeneratorPoolAsync), decoded by
+/// their `decode`.
+///
+/// Displayed like [`Decoded`], with the pool number before the node.
+#[cfg(any(feature = "tokio", feature = "std-thread"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PoolDecoded {
+    pub id: u64,
+    /// Timestamp field of the id, in milliseconds since `base_ts` of the
+    /// pool.
+    pub timestamp_raw: u64,
+    /// Milliseconds since the Unix epoch.
+    pub unix_ms: u64,
+    pub pool_id: u64,
+    pub node: u64,
+    pub seq: u64,
+}
+
+#[cfg(any(feature = "tokio", feature = "std-thread"))]
+impl PoolDecoded {
+    /// When the id was created.
+    pub fn datetime(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.unix_ms)
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "std-thread"))]
+impl fmt::Display for PoolDecoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pool={} node={} seq={}",
+            rfc3339_millis(self.unix_ms),
+            self.pool_id,
+            self.node,
+            self.seq
+        )
+    }
+}
+
+/// Splits `id` by `layout` and converts its timestamp to milliseconds since
+/// the Unix epoch.
+pub fn decode(layout: &Layout, id: u64) -> Decoded {
+    // a zero base_ts keeps the timestamp field as it is
+    let decoded = layout.with_base_ts(0).decode(id);
+    let ticks = decoded.timestamp.saturating_add(layout.base_ts());
+    Decoded {
+        id,
+        timestamp_raw: decoded.timestamp,
+        unix_ms: ticks.saturating_mul(layout.tick_ms()),
+        node: decoded.node,
+        seq: decoded.seq,
+    }
+}
+
+/// Same as [`decode`], for ids as decimal strings, like they appear in logs
+/// and JSON.
+pub fn decode_str(layout: &Layout, id: &str) -> Result<Decoded, ParseIntError> {
+    Ok(decode(layout, id.trim().parse()?))
+}
+
+/// Decodes ids generated under several layouts over time, like before and
+/// after a change of the bit layout.
+///
+/// Layouts are added oldest first, each with where it starts: at an id,
+/// when the new ids all sort after the old ones, or at a time. An id takes
+/// the newest layout that applies to it, or the first layout if none does.
+///
+/// ```rust
+/// use frostflake::decode::MultiLayoutDecoder;
+/// use frostflake::Layout;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let old = Layout::default();
+/// let new = Layout::new(41, 11, 12).with_base_ts(old.base_ts());
+/// let switched = UNIX_EPOCH + Duration::from_millis(1600000000000);
+/// let decoder = MultiLayoutDecoder::new(old).since_time(switched, new);
+///
+/// let old_id = old.compose(1500000000000, 1000, 1);
+/// let new_id = new.compose(1700000000000, 2000, 1);
+/// assert_eq!(decoder.decode(old_id).node, 1000);
+/// assert_eq!(decoder.decode(new_id).node, 2000);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiLayoutDecoder {
+    // oldest first, the first one from the start
+    layouts: Vec<(ValidFrom, Layout)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValidFrom {
+    Start,
+    Id(u64),
+    // milliseconds since the Unix epoch
+    Time(u64),
+}
+
+impl MultiLayoutDecoder {
+    /// Starts with the original layout, used for ids no later layout
+    /// applies to.
+    pub fn new(layout: Layout) -> Self {
+        MultiLayoutDecoder {
+            layouts: vec![(ValidFrom::Start, layout)],
+        }
+    }
+
+    /// Decodes ids from `first_id` on by `layout`.
+    pub fn since_id(mut self, first_id: u64, layout: Layout) -> Self {
+        self.layouts.push((ValidFrom::Id(first_id), layout));
+        self
+    }
+
+    /// Decodes ids by `layout` when their time, as decoded by `layout`, is
+    /// `time` or later.
+    ///
+    /// This relies on ids of the earlier layouts decoding to an earlier
+    /// time by `layout`, which holds when the timestamp keeps its epoch and
+    /// loses bits, but not always otherwise; when the ids of the new layout
+    /// sort after the old ones, `since_id` is exact.
+    pub fn since_time(mut self, time: SystemTime, layout: Layout) -> Self {
+        let ms = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        self.layouts.push((ValidFrom::Time(ms), layout));
+        self
+    }
+
+    /// The layout `id` was generated under.
+    pub fn layout_of(&self, id: u64) -> &Layout {
+        self.layouts
+            .iter()
+            .rev()
+            .find(|(from, layout)| match *from {
+                ValidFrom::Start => true,
+                ValidFrom::Id(first_id) => id >= first_id,
+                ValidFrom::Time(ms) => decode(layout, id).unix_ms >= ms,
+            })
+            .map_or(&self.layouts[0].1, |(_, layout)| layout)
+    }
+
+    /// Same as [`decode`] by the layout of `id`.
+    pub fn decode(&self, id: u64) -> Decoded {
+        decode(self.layout_of(id), id)
+    }
+
+    /// Same as [`decode_str`] by the layout of `id`.
+    pub fn decode_str(&self, id: &str) -> Result<Decoded, ParseIntError> {
+        Ok(self.decode(id.trim().parse()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let decoded = decode(&Layout::discord(), 175928847299117063);
+        assert_eq!(
+            decoded,
+            Decoded {
+                id: 175928847299117063,
+                timestamp_raw: 175928847299117063 >> 22,
+                unix_ms: 1462015105796,
+                node: 32,
+                seq: 7,
+            }
+        );
+        assert_eq!(
+            decoded.datetime(),
+            UNIX_EPOCH + Duration::from_millis(1462015105796)
+        );
+
+        // ticks of 10 milliseconds
+        let layout = Layout::sonyflake();
+        let decoded = decode(&layout, layout.compose(160000000000, 3, 1));
+        assert_eq!(
+            (decoded.unix_ms, decoded.node, decoded.seq),
+            (1600000000000, 3, 1)
+        );
+    }
+
+    #[test]
+    fn test_decode_str() {
+        let layout = Layout::default();
+        assert_eq!(
+            decode_str(&layout, " 442730451055673344\n").unwrap(),
+            decode(&layout, 442730451055673344)
+        );
+        assert!(decode_str(&layout, "-1").is_err());
+        assert!(decode_str(&layout, "abc").is_err());
+    }
+
+    #[test]
+    fn test_multi_layout() {
+        let old = Layout::default();
+        let new = Layout::new(41, 11, 12).with_base_ts(old.base_ts());
+        let old_id = old.compose(1500000000000, 1000, 1);
+        let new_id = new.compose(1700000000000, 2000, 2);
+
+        let decoder = MultiLayoutDecoder::new(old);
+        assert_eq!(decoder.layout_of(new_id), &old);
+
+        let at = UNIX_EPOCH + Duration::from_millis(1600000000000);
+        let decoder = decoder.since_time(at, new);
+        assert_eq!(decoder.layout_of(old_id), &old);
+        assert_eq!(decoder.layout_of(new_id), &new);
+        assert_eq!(
+            decoder.decode_str(&new_id.to_string()).unwrap(),
+            decode(&new, new_id)
+        );
+
+        // by id, then back to the original seconds layout
+        let secs = Layout::new(42, 10, 12).with_tick_ms(1000).with_base_ts(0);
+        let decoder = MultiLayoutDecoder::new(old)
+            .since_id(new_id, new)
+            .since_id(u64::MAX - 10, secs);
+        assert_eq!(decoder.decode(old_id).node, 1000);
+        assert_eq!(decoder.decode(new_id).node, 2000);
+        assert_eq!(decoder.layout_of(u64::MAX), &secs);
+    }
+}
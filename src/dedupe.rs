@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+
+// ids remembered per generation; the last two generations are checked
+const GENERATION: usize = 1 << 16;
+// 16 bits of Bloom filter per id, for a false positive rate below 0.1%
+const BLOOM_BITS: usize = GENERATION * 16;
+const HASHES: u64 = 4;
+
+/// Recent ids of a generator, checked for duplicates with `debug-dedupe`
+/// feature.
+///
+/// A Bloom filter tells most new ids apart with a few memory reads, and the
+/// ids it can't tell apart are looked up exactly, so a duplicate is never
+/// reported by mistake. Only the last 64k to 128k ids are remembered.
+pub(crate) struct Dedupe {
+    current: Generation,
+    previous: Generation,
+}
+
+struct Generation {
+    bloom: Box<[u64]>,
+    ids: HashSet<u64>,
+}
+
+impl Default for Dedupe {
+    fn default() -> Self {
+        Dedupe {
+            current: Generation::new(),
+            previous: Generation::new(),
+        }
+    }
+}
+
+impl Dedupe {
+    /// Remembers `id`, panicking if it is among the recent ids.
+    pub(crate) fn check(&mut self, id: u64) {
+        if self.current.contains(id) || self.previous.contains(id) {
+            panic!(
+                "id {} was generated twice. check that no two generators share a node number, and that the clock and the saved state are sane",
+                id
+            );
+        }
+
+        if self.current.ids.len() == GENERATION {
+            self.previous = std::mem::replace(&mut self.current, Generation::new());
+        }
+        self.current.insert(id);
+    }
+}
+
+impl Generation {
+    fn new() -> Self {
+        Generation {
+            bloom: vec![0; BLOOM_BITS / 64].into_boxed_slice(),
+            ids: HashSet::with_capacity(GENERATION),
+        }
+    }
+
+    fn contains(&self, id: u64) -> bool {
+        bits(id).all(|bit| self.bloom[bit / 64] & (1 << (bit % 64)) != 0) && self.ids.contains(&id)
+    }
+
+    fn insert(&mut self, id: u64) {
+        for bit in bits(id) {
+            self.bloom[bit / 64] |= 1 << (bit % 64);
+        }
+        self.ids.insert(id);
+    }
+}
+
+// bits of the Bloom filter for `id`, by double hashing
+fn bits(id: u64) -> impl Iterator<Item = usize> {
+    // finalizer of MurmurHash3
+    let mut h = id;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^= h >> 33;
+
+    let (h1, h2) = (h & 0xffffffff, (h >> 32) | 1);
+    (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % BLOOM_BITS as u64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedupe() {
+        let mut dedupe = Dedupe::default();
+        for id in 0..GENERATION as u64 * 3 {
+            dedupe.check(id);
+        }
+        assert_eq!(dedupe.current.ids.len(), GENERATION);
+        assert!(dedupe.previous.contains(GENERATION as u64));
+        // forgotten after two generations
+        assert!(!dedupe.previous.contains(0) && !dedupe.current.contains(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "id 123 was generated twice")]
+    fn test_dedupe_crash() {
+        let mut dedupe = Dedupe::default();
+        dedupe.check(123);
+        dedupe.check(124);
+        dedupe.check(123);
+    }
+
+    #[test]
+    #[should_panic(expected = "was generated twice")]
+    fn test_generator_crash() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let mut g = crate::Generator::new(crate::GeneratorOptions::default().time_fn(my_time_fn));
+        g.generate();
+        // as if the state was lost, like by a restart with a bad snapshot
+        g.core_mut().last_ts = 0;
+        g.generate();
+    }
+}
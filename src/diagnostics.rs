@@ -0,0 +1,260 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{Core, Error, Generator, Layout};
+
+/// Counters kept by a [`Generator`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GeneratorStats {
+    pub issued: u64,
+    /// Reserved ids dropped without being committed.
+    pub burned: u64,
+    pub sequence_exhausted: u64,
+    pub clock_moved_backwards: u64,
+    pub clock_stalled: u64,
+}
+
+impl GeneratorStats {
+    pub(crate) fn record(&mut self, result: &Result<u64, Error>) {
+        match result {
+            Ok(_) => self.issued += 1,
+            Err(Error::SequenceExhausted) => self.sequence_exhausted += 1,
+            Err(Error::ClockMovedBackwards { .. }) => self.clock_moved_backwards += 1,
+            Err(Error::ClockStalled { .. }) => self.clock_stalled += 1,
+            Err(_) => {}
+        }
+    }
+}
+
+/// Raised by `GeneratorOptions::utilization_alert`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtilizationAlert {
+    /// Value of the timestamp field of the tick.
+    pub tick: u64,
+    pub node: u64,
+    /// Sequence numbers used so far within the tick.
+    pub used: u64,
+    /// Sequence numbers available per tick.
+    pub capacity: u64,
+}
+
+pub(crate) type UtilizationHook = dyn Fn(UtilizationAlert) + Send + Sync;
+
+/// Clock state as seen by a [`Generator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ClockHealth {
+    /// Current value of the time source.
+    pub now: u64,
+    /// Timestamp of the last generated id.
+    pub last_ts: u64,
+    /// Whether the time source is behind the last generated id.
+    pub behind: bool,
+    pub stall_timeout: Option<Duration>,
+    /// How long the time source has been returning `last_ts`, if tracked.
+    pub unchanged_for: Option<Duration>,
+}
+
+/// State of the node lease of a [`Generator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LeaseHealth {
+    pub node: u64,
+    pub valid: bool,
+    /// When the lease expires unless it is renewed, if the lease tells.
+    pub expires_at: Option<SystemTime>,
+}
+
+/// Effective state of a [`Generator`], as returned by
+/// [`Generator::diagnostics`].
+///
+/// With the `serde` feature this implements `Serialize`, so it can be
+/// dumped into support bundles or served from a debug endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Diagnostics {
+    pub layout: Layout,
+    pub node: u64,
+    /// Sequence number of the last generated id.
+    pub last_seq: u64,
+    /// Number of future timestamps holding ids from `generate_not_before`.
+    pub scheduled_ticks: usize,
+    pub stats: GeneratorStats,
+    pub clock: ClockHealth,
+    /// Set when the node number is leased, see `GeneratorOptions::node_lease`.
+    pub lease: Option<LeaseHealth>,
+}
+
+impl Core {
+    pub(crate) fn check_utilization(&mut self, now: u64, seq: u64) {
+        let Some((threshold, hook)) = &self.opts.utilization_alert else {
+            return;
+        };
+        if self.alerted_ts == now {
+            return;
+        }
+
+        let capacity = self.compiled.max_seq.saturating_add(1);
+        let used = seq + 1 - self.seq_start + self.scheduled.get(&now).copied().unwrap_or(0);
+        if used as f64 > capacity as f64 * threshold {
+            self.alerted_ts = now;
+            hook(UtilizationAlert {
+                tick: now - self.opts.base_ts,
+                node: self.opts.node,
+                used,
+                capacity,
+            });
+        }
+    }
+}
+
+impl Generator {
+    /// Reports the effective options and the current state.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let generator = Generator::new(GeneratorOptions::default().node(3));
+    /// generator.generate();
+    ///
+    /// let diagnostics = generator.diagnostics();
+    /// assert_eq!(diagnostics.node, 3);
+    /// assert_eq!(diagnostics.stats.issued, 1);
+    /// ```
+    pub fn diagnostics(&self) -> Diagnostics {
+        let core = self.core();
+        let now = core.opts.clock.now();
+
+        Diagnostics {
+            layout: core.opts.layout(),
+            node: core.opts.node,
+            last_seq: core.seq,
+            scheduled_ticks: core.scheduled.len(),
+            stats: core.stats,
+            clock: ClockHealth {
+                now,
+                last_ts: core.last_ts,
+                behind: now < core.last_ts,
+                stall_timeout: core.opts.stall_timeout,
+                unchanged_for: core
+                    .last_ts_at
+                    .filter(|_| now == core.last_ts)
+                    .map(|at| at.elapsed()),
+            },
+            lease: core.opts.node_lease.as_ref().map(|lease| LeaseHealth {
+                node: lease.node(),
+                valid: lease.is_valid(),
+                expires_at: lease.expires_at(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+
+    #[test]
+    fn test_diagnostics() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g = Generator::new(GeneratorOptions::default().time_fn(my_time_fn).node(3));
+        g.generate();
+        g.generate();
+        g.core().last_ts += 1;
+        assert!(g.try_generate().is_err());
+
+        let d = g.diagnostics();
+        assert_eq!(d.layout, Layout::default());
+        assert_eq!(d.node, 3);
+        assert_eq!(d.last_seq, 1);
+        assert_eq!(
+            d.stats,
+            GeneratorStats {
+                issued: 2,
+                clock_moved_backwards: 1,
+                ..GeneratorStats::default()
+            }
+        );
+        assert_eq!(d.clock.now, 1483228800000 + 123);
+        assert!(d.clock.behind);
+        assert_eq!(d.clock.unchanged_for, None);
+        assert_eq!(d.lease, None);
+    }
+
+    #[test]
+    fn test_lease_health() {
+        use crate::node_id::NodeLease;
+
+        struct Lease;
+
+        impl NodeLease for Lease {
+            fn node(&self) -> u64 {
+                5
+            }
+
+            fn is_valid(&self) -> bool {
+                false
+            }
+        }
+
+        let g = Generator::new(GeneratorOptions::default().node_lease(Lease));
+        assert_eq!(
+            g.diagnostics().lease,
+            Some(LeaseHealth {
+                node: 5,
+                valid: false,
+                expires_at: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_utilization_alert() {
+        use std::sync::{Arc, Mutex};
+
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let alerts = Arc::new(Mutex::new(vec![]));
+        let sink = alerts.clone();
+        let opts = GeneratorOptions::default()
+            .base_ts(0)
+            .bits(52, 8, 4)
+            .base_ts(1483228800000)
+            .time_fn(my_time_fn)
+            .utilization_alert(0.5, move |alert| sink.lock().unwrap().push(alert));
+
+        let g = Generator::new(opts);
+        for _ in 0..8 {
+            g.generate();
+        }
+        assert!(alerts.lock().unwrap().is_empty());
+
+        for _ in 0..8 {
+            g.generate();
+        }
+        assert_eq!(
+            *alerts.lock().unwrap(),
+            vec![UtilizationAlert {
+                tick: 123,
+                node: 0,
+                used: 9,
+                capacity: 16,
+            }]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        fn assert_serialize<T: serde::Serialize>(_: &T) {}
+
+        let g = Generator::new(GeneratorOptions::default());
+        assert_serialize(&g.diagnostics());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{bitmask, max, shl, shr, Error, OverflowPolicy, TimeSource};
+
+/// Generator without locks or allocations, for `no_std` targets.
+///
+/// This is the core of `Generator` on its own: it takes `&mut self`, reads
+/// the time from the given [`TimeSource`], like the clock of an RTOS, and
+/// supports only the bit widths, `base_ts`, the node number and the overflow
+/// policy. It is available with and without the `std` feature.
+///
+/// ```rust
+/// use frostflake::EmbeddedGenerator;
+///
+/// fn rtos_millis() -> u64 {
+///     1483228800000 + 1
+/// }
+///
+/// let mut generator = EmbeddedGenerator::new(rtos_millis).node(3);
+/// let id = generator.generate();
+/// assert_eq!(generator.extract(id), (1, 3, 0));
+/// ```
+///
+/// The constructor and setters are `const fn`, so the generator can be set up
+/// in a `static`, like one behind the mutex of a critical section, and bits
+/// that don't add up to 64 fail to compile:
+///
+/// ```rust
+/// use frostflake::EmbeddedGenerator;
+///
+/// # fn rtos_millis() -> u64 {
+/// #     1483228800000 + 1
+/// # }
+/// static GENERATOR: EmbeddedGenerator<fn() -> u64> =
+///     EmbeddedGenerator::new(rtos_millis as fn() -> u64).bits(44, 8, 12).node(3);
+///
+/// let mut generator = GENERATOR.clone();
+/// let id = generator.generate();
+/// assert_eq!(generator.extract(id), (1, 3, 0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct EmbeddedGenerator<C> {
+    clock: C,
+    bits: (u8, u8, u8),
+    base_ts: u64,
+    node: u64,
+    overflow: OverflowPolicy,
+    last_ts: u64,
+    seq: u64,
+}
+
+impl<C: TimeSource> EmbeddedGenerator<C> {
+    /// Creates a generator with the default layout of `GeneratorOptions`,
+    /// reading milliseconds from `clock`.
+    pub const fn new(clock: C) -> Self {
+        EmbeddedGenerator {
+            clock,
+            bits: (42, 10, 12),
+            base_ts: 1483228800000, // 2017-01-01T00:00:00Z as milliseconds
+            node: 0,
+            overflow: OverflowPolicy::WaitNextTick,
+            last_ts: 0,
+            seq: 0,
+        }
+    }
+
+    pub const fn bits(mut self, ts_bits: u8, node_bits: u8, seq_bits: u8) -> Self {
+        assert!(
+            64 == ts_bits as u32 + node_bits as u32 + seq_bits as u32,
+            "bits set should be total 64bit"
+        );
+        assert!(ts_bits > 0, "ts_bits should be at least 1bit");
+        assert!(
+            self.base_ts <= max(ts_bits),
+            "base_ts exceeds ts_bits limit, set base_ts first"
+        );
+        assert!(
+            self.node <= max(node_bits),
+            "node number exceeeds node_bits limit, set node number first"
+        );
+
+        self.bits = (ts_bits, node_bits, seq_bits);
+        self
+    }
+
+    pub const fn node(mut self, node: u64) -> Self {
+        assert!(
+            node <= max(self.bits.1),
+            "node number exceeds node_bits limit, set bit width first"
+        );
+
+        self.node = node;
+        self
+    }
+
+    pub const fn base_ts(mut self, base_ts: u64) -> Self {
+        assert!(
+            base_ts <= max(self.bits.0),
+            "base_ts exceeds ts_bits limit, set bit width first"
+        );
+
+        self.base_ts = base_ts;
+        self
+    }
+
+    /// Sets what happens when all sequence numbers of a tick are used.
+    pub const fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
+    pub fn generate(&mut self) -> u64 {
+        self.try_generate().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate`, but returns an error instead of panicking.
+    ///
+    /// When the sequence is exhausted, this follows the overflow policy,
+    /// spinning on the clock for `WaitNextTick`.
+    pub fn try_generate(&mut self) -> Result<u64, Error> {
+        loop {
+            match self.next_id() {
+                Err(Error::SequenceExhausted) => match self.overflow {
+                    OverflowPolicy::WaitNextTick => core::hint::spin_loop(),
+                    OverflowPolicy::Error => return Err(Error::SequenceExhausted),
+                    OverflowPolicy::Panic => panic!("{}", Error::SequenceExhausted),
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// Splits an id into elapsed time since `base_ts`, node number and
+    /// sequence number.
+    pub fn extract(&self, id: u64) -> (u64, u64, u64) {
+        let (_, node_bits, seq_bits) = self.bits;
+        (
+            shr(id, node_bits + seq_bits),
+            shr(id, seq_bits) & max(node_bits),
+            id & max(seq_bits),
+        )
+    }
+
+    fn next_id(&mut self) -> Result<u64, Error> {
+        let (ts_bits, node_bits, seq_bits) = self.bits;
+        let now = self.clock.now();
+        if now <= self.base_ts {
+            return Err(Error::TimeBeforeBaseTs {
+                now,
+                base_ts: self.base_ts,
+            });
+        }
+        if now < self.last_ts {
+            return Err(Error::ClockMovedBackwards {
+                now,
+                last_ts: self.last_ts,
+            });
+        }
+        let elapsed = now - self.base_ts;
+        if elapsed > max(ts_bits) {
+            return Err(Error::EpochExhausted { ts: now });
+        }
+
+        let seq = if now == self.last_ts { self.seq + 1 } else { 0 };
+        if seq > max(seq_bits) {
+            return Err(Error::SequenceExhausted);
+        }
+        self.last_ts = now;
+        self.seq = seq;
+
+        let ts_mask = bitmask(node_bits + seq_bits);
+        let node_mask = bitmask(seq_bits) ^ ts_mask;
+        Ok((shl(elapsed, node_bits + seq_bits) & ts_mask)
+            | (shl(self.node, seq_bits) & node_mask)
+            | seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_generate() {
+        let now = Cell::new(1483228800000 + 1);
+        let mut g = EmbeddedGenerator::new(|| now.get())
+            .base_ts(0)
+            .bits(42, 20, 2)
+            .base_ts(1483228800000)
+            .node(5)
+            .overflow_policy(OverflowPolicy::Error);
+
+        let ids: Vec<_> = (0..4).map(|_| g.generate()).collect();
+        assert_eq!(ids[0], (1 << 22) | (5 << 2));
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(g.extract(ids[3]), (1, 5, 3));
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+
+        now.set(1483228800000 + 2);
+        let id = g.generate();
+        assert_eq!(g.extract(id), (2, 5, 0));
+
+        now.set(1483228800000 + 1);
+        assert!(matches!(
+            g.try_generate(),
+            Err(Error::ClockMovedBackwards { .. })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_same_ids_as_generator() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g = crate::Generator::new(
+            crate::GeneratorOptions::default()
+                .time_fn(my_time_fn)
+                .node(7),
+        );
+        let mut e = EmbeddedGenerator::new(my_time_fn).node(7);
+        for _ in 0..10 {
+            assert_eq!(e.generate(), g.generate());
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Short string representations of ids, for URLs and other places where
+//! the 19 digits of a decimal id are too long.
+//!
+//! |Encoding|Alphabet|Longest id|
+//! |---|---|---|
+//! |base62|`0-9`, `A-Z`, `a-z`|11 characters|
+//! |base58|Bitcoin alphabet, without `0`, `O`, `I` and `l`|11 characters|
+//! |hex|`0-9`, `a-f`|16 characters|
+//! |sortable|Crockford's base32, `0-9` and `A-Z` without `I`, `L`, `O` and `U`|13 characters|
+//!
+//! Leading zeros are not written, so the encoded ids of a generator only
+//! sort like the ids themselves when they have the same length. Base58
+//! leaves out characters that are easily confused, for ids meant to be read
+//! or typed by people. The sortable encoding is always 13 characters, padded
+//! with zeros, so its strings sort lexicographically like the ids, and thus
+//! by timestamp, as message keys or row keys of range scans.
+//!
+//! ```rust
+//! use frostflake::encode::{from_base62, to_base62};
+//!
+//! let id = 442730451055673344;
+//! assert_eq!(to_base62(id), "Whi1rh7NIm");
+//! assert_eq!(from_base62("Whi1rh7NIm"), Ok(id));
+//! ```
+
+use std::fmt;
+
+use crate::Snowflake;
+
+pub(crate) const BASE62: &[u8; 62] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+pub(crate) const BASE58: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+pub(crate) const HEX: &[u8; 16] = b"0123456789abcdef";
+pub(crate) const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Errors returned when decoding an id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeError {
+    Empty,
+    /// The character at `index` is not in the alphabet.
+    InvalidChar {
+        index: usize,
+    },
+    /// The value does not fit in 64bit.
+    Overflow,
+    /// The check character doesn't match, see
+    /// [`checksum`](crate::checksum).
+    ChecksumMismatch,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "encoded id is empty"),
+            DecodeError::InvalidChar { index } => write!(f, "invalid character at {}", index),
+            DecodeError::Overflow => write!(f, "encoded id exceeds 64bit"),
+            DecodeError::ChecksumMismatch => write!(f, "check character doesn't match"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub(crate) fn encode(mut id: u64, alphabet: &[u8]) -> String {
+    let base = alphabet.len() as u64;
+    let mut buf = [0; 64];
+    let mut i = buf.len();
+    loop {
+        i -= 1;
+        buf[i] = alphabet[(id % base) as usize];
+        id /= base;
+        if id == 0 {
+            break;
+        }
+    }
+    // the alphabets are ASCII
+    buf[i..].iter().map(|&c| c as char).collect()
+}
+
+pub(crate) fn decode(
+    s: &str,
+    digit: impl Fn(u8) -> Option<u8>,
+    base: u64,
+) -> Result<u64, DecodeError> {
+    if s.is_empty() {
+        return Err(DecodeError::Empty);
+    }
+
+    s.bytes().enumerate().try_fold(0u64, |id, (index, c)| {
+        let d = digit(c).ok_or(DecodeError::InvalidChar { index })?;
+        id.checked_mul(base)
+            .and_then(|id| id.checked_add(d as u64))
+            .ok_or(DecodeError::Overflow)
+    })
+}
+
+pub fn to_base62(id: u64) -> String {
+    encode(id, BASE62)
+}
+
+pub fn from_base62(s: &str) -> Result<u64, DecodeError> {
+    decode(
+        s,
+        |c| BASE62.iter().position(|&a| a == c).map(|d| d as u8),
+        62,
+    )
+}
+
+pub fn to_base58(id: u64) -> String {
+    encode(id, BASE58)
+}
+
+pub fn from_base58(s: &str) -> Result<u64, DecodeError> {
+    decode(
+        s,
+        |c| BASE58.iter().position(|&a| a == c).map(|d| d as u8),
+        58,
+    )
+}
+
+pub fn to_hex(id: u64) -> String {
+    encode(id, HEX)
+}
+
+/// Decodes hex in either case.
+pub fn from_hex(s: &str) -> Result<u64, DecodeError> {
+    decode(s, |c| (c as char).to_digit(16).map(|d| d as u8), 16)
+}
+
+/// Writes `id` as 13 characters of Crockford's base32, padded with zeros, so
+/// that the strings of ids sort like the ids.
+pub fn to_sortable(id: u64) -> String {
+    // 65 bits, the first character only holds the top 4 bits
+    (0..13)
+        .rev()
+        .map(|i| CROCKFORD[((id as u128 >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+/// Decodes Crockford's base32 in either case, with or without padding.
+pub fn from_sortable(s: &str) -> Result<u64, DecodeError> {
+    decode(
+        s,
+        |c| {
+            let c = c.to_ascii_uppercase();
+            CROCKFORD.iter().position(|&a| a == c).map(|d| d as u8)
+        },
+        32,
+    )
+}
+
+impl Snowflake {
+    pub fn to_base62(&self) -> String {
+        to_base62(self.as_u64())
+    }
+
+    pub fn to_base58(&self) -> String {
+        to_base58(self.as_u64())
+    }
+
+    pub fn to_hex(&self) -> String {
+        to_hex(self.as_u64())
+    }
+
+    /// Fixed width string sorting lexicographically in timestamp order, see
+    /// the [module documentation](self).
+    pub fn to_sortable_string(&self) -> String {
+        to_sortable(self.as_u64())
+    }
+
+    /// Decodes an id, assuming the default layout like `Snowflake::new`.
+    pub fn from_base62(s: &str) -> Result<Snowflake, DecodeError> {
+        from_base62(s).map(Snowflake::new)
+    }
+
+    /// Decodes an id, assuming the default layout like `Snowflake::new`.
+    pub fn from_base58(s: &str) -> Result<Snowflake, DecodeError> {
+        from_base58(s).map(Snowflake::new)
+    }
+
+    /// Decodes an id, assuming the default layout like `Snowflake::new`.
+    pub fn from_hex(s: &str) -> Result<Snowflake, DecodeError> {
+        from_hex(s).map(Snowflake::new)
+    }
+
+    /// Decodes an id, assuming the default layout like `Snowflake::new`.
+    pub fn from_sortable_string(s: &str) -> Result<Snowflake, DecodeError> {
+        from_sortable(s).map(Snowflake::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        for id in [0, 1, 57, 58, 61, 62, 442730451055673344, u64::MAX] {
+            assert_eq!(from_base62(&to_base62(id)), Ok(id));
+            assert_eq!(from_base58(&to_base58(id)), Ok(id));
+            assert_eq!(from_hex(&to_hex(id)), Ok(id));
+            assert_eq!(from_sortable(&to_sortable(id)), Ok(id));
+        }
+    }
+
+    #[test]
+    fn test_sortable() {
+        assert_eq!(to_sortable(0), "0000000000000");
+        assert_eq!(to_sortable(31), "000000000000Z");
+        assert_eq!(to_sortable(u64::MAX), "FZZZZZZZZZZZZ");
+        assert_eq!(from_sortable("z"), Ok(31));
+        assert_eq!(from_sortable("G000000000000"), Err(DecodeError::Overflow));
+        assert_eq!(
+            from_sortable("0U"),
+            Err(DecodeError::InvalidChar { index: 1 })
+        );
+
+        let mut ids = [u64::MAX, 0, 1 << 22, 31, 32, 442730451055673344, 1 << 63];
+        let mut strings: Vec<_> = ids.iter().map(|&id| to_sortable(id)).collect();
+        ids.sort();
+        strings.sort();
+        assert_eq!(strings, ids.map(to_sortable));
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(to_base62(0), "0");
+        assert_eq!(to_base62(61), "z");
+        assert_eq!(to_base62(62), "10");
+        assert_eq!(to_base62(u64::MAX), "LygHa16AHYF");
+        assert_eq!(to_base58(0), "1");
+        assert_eq!(to_base58(57), "z");
+        assert_eq!(to_base58(58), "21");
+        assert_eq!(to_base58(u64::MAX).len(), 11);
+        assert_eq!(to_hex(0xdeadbeef), "deadbeef");
+        assert_eq!(to_hex(u64::MAX), "ffffffffffffffff");
+        assert_eq!(from_hex("DEADBEEF"), Ok(0xdeadbeef));
+    }
+
+    #[test]
+    fn test_decode_error() {
+        assert_eq!(from_base62(""), Err(DecodeError::Empty));
+        assert_eq!(
+            from_base62("ab-c"),
+            Err(DecodeError::InvalidChar { index: 2 })
+        );
+        assert_eq!(from_base58("0"), Err(DecodeError::InvalidChar { index: 0 }));
+        assert_eq!(from_base62("LygHa16AHYG"), Err(DecodeError::Overflow));
+        assert_eq!(from_hex("10000000000000000"), Err(DecodeError::Overflow));
+    }
+
+    #[test]
+    fn test_snowflake() {
+        let id = Snowflake::new(442730451055673344);
+        assert_eq!(Snowflake::from_base62(&id.to_base62()), Ok(id));
+        assert_eq!(Snowflake::from_base58(&id.to_base58()), Ok(id));
+        assert_eq!(Snowflake::from_hex(&id.to_hex()), Ok(id));
+        assert_eq!(
+            Snowflake::from_sortable_string(&id.to_sortable_string()),
+            Ok(id)
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use alloc::string::String;
+use core::fmt;
+use core::time::Duration;
+
+/// Errors returned when generating ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// `time_fn` returned a time earlier than the previous call.
+    ClockMovedBackwards { now: u64, last_ts: u64 },
+    /// All sequence numbers of the current timestamp are used.
+    SequenceExhausted,
+    /// `time_fn` returned a time not after `base_ts`.
+    TimeBeforeBaseTs { now: u64, base_ts: u64 },
+    /// The timestamp does not fit in the timestamp bits.
+    EpochExhausted { ts: u64 },
+    /// The rate limit is exceeded.
+    RateLimited,
+    /// The generated id was rejected by a validator.
+    Rejected { id: u64 },
+    /// The generator task or worker is gone.
+    ChannelClosed,
+    /// The generator task was shut down or aborted.
+    GeneratorClosed,
+    /// The generator task dropped the request without an answer, as
+    /// generating panicked.
+    Canceled,
+    /// The queue of the generator task is full.
+    Busy,
+    /// No id was generated before the deadline.
+    DeadlineExceeded,
+    /// `time_fn` kept returning `ts` for longer than the stall timeout.
+    ClockStalled { ts: u64, stalled_for: Duration },
+    /// The lease of the node number expired.
+    LeaseExpired { node: u64 },
+    /// Generating panicked in a pool worker, which was restarted.
+    WorkerPanicked { reason: String },
+    /// The segment is used up, and the segment store failed to allocate the
+    /// next one.
+    SegmentUnavailable { reason: String },
+    /// More threads are generating than the thread-local generator has
+    /// thread numbers for.
+    TooManyThreads { max: u64 },
+    /// An id was requested for a timestamp after the current time.
+    TimeInFuture { ts: u64, now: u64 },
+    /// The clock is further off the time of the NTP server than allowed by
+    /// `GeneratorOptions::verify_clock_ntp`.
+    ClockOffset {
+        offset_ms: i64,
+        max_offset: Duration,
+    },
+    /// The NTP server of `GeneratorOptions::verify_clock_ntp` didn't answer.
+    NtpUnavailable { reason: String },
+    /// The arguments don't fit the options of the generator, like a field
+    /// value exceeding its bits.
+    InvalidOptions(OptionsError),
+}
+
+impl Error {
+    /// Whether the error is likely to go away by retrying after a while.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::ClockMovedBackwards { .. } | Error::SequenceExhausted | Error::Busy
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ClockMovedBackwards { now, last_ts } => write!(
+                f,
+                "clock moved backwards. check your NTP setup (now={}, last_ts={})",
+                now, last_ts
+            ),
+            Error::SequenceExhausted => write!(f, "seq number exceeds seq_bits!"),
+            Error::TimeBeforeBaseTs { now, base_ts } => write!(
+                f,
+                "time_fn returned the time before base_ts (now={}, base_ts={})",
+                now, base_ts
+            ),
+            Error::EpochExhausted { ts } => {
+                write!(f, "timestamp {} exceeds ts_bits limit", ts)
+            }
+            Error::RateLimited => write!(f, "rate limit exceeded"),
+            Error::Rejected { id } => write!(f, "id {} was rejected by validator", id),
+            Error::ChannelClosed => write!(f, "generator is not running"),
+            Error::GeneratorClosed => write!(f, "generator was shut down"),
+            Error::Canceled => write!(f, "request was canceled by the generator"),
+            Error::Busy => write!(f, "generator is busy"),
+            Error::DeadlineExceeded => write!(f, "deadline exceeded"),
+            Error::ClockStalled { ts, stalled_for } => write!(
+                f,
+                "time_fn returned {} for {:?}, the clock seems to be stalled",
+                ts, stalled_for
+            ),
+            Error::LeaseExpired { node } => write!(f, "lease of node {} expired", node),
+            Error::WorkerPanicked { reason } => write!(f, "worker panicked: {}", reason),
+            Error::SegmentUnavailable { reason } => {
+                write!(f, "no id segment available: {}", reason)
+            }
+            Error::TooManyThreads { max } => {
+                write!(f, "more than {} threads are generating ids", max)
+            }
+            Error::TimeInFuture { ts, now } => {
+                write!(f, "timestamp {} is in the future (now={})", ts, now)
+            }
+            Error::ClockOffset {
+                offset_ms,
+                max_offset,
+            } => write!(
+                f,
+                "clock is {}ms off the NTP server, more than {:?}. check your NTP setup",
+                offset_ms, max_offset
+            ),
+            Error::NtpUnavailable { reason } => {
+                write!(f, "NTP server is unavailable: {}", reason)
+            }
+            Error::InvalidOptions(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl From<OptionsError> for Error {
+    fn from(e: OptionsError) -> Self {
+        Error::InvalidOptions(e)
+    }
+}
+
+/// Message of a panic caught with `catch_unwind`.
+#[cfg(any(feature = "tokio", feature = "std-thread"))]
+pub(crate) fn panic_message(panic: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Errors returned when validating generator options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OptionsError {
+    /// The bit widths do not add up to 64.
+    InvalidBits { total: u32 },
+    /// The timestamp has no bits, so that no id can be generated.
+    NoTimestampBits,
+    /// `base_ts` does not fit in the timestamp bits.
+    BaseTsOutOfRange { base_ts: u64, max: u64 },
+    /// The node number does not fit in the node bits.
+    NodeOutOfRange { node: u64, max: u64 },
+    /// The timestamp field runs out before the required lifetime is over.
+    LifetimeTooShort {
+        required: Duration,
+        remaining: Duration,
+    },
+    /// The pool has more workers than the pool bits can tell apart.
+    PoolTooLarge { size: usize, max: u64 },
+    /// The node bits can't tell that many threads apart.
+    TooManyThreads { threads: u64, max: u64 },
+    /// The utilization alert threshold is not between 0.0 and 1.0.
+    InvalidThreshold,
+    /// No layout fits the requirements into 64bit.
+    Unsatisfiable {
+        ts_bits: u32,
+        node_bits: u32,
+        seq_bits: u32,
+    },
+    /// Two fields of a layout have the same name.
+    DuplicateField { name: String },
+    /// The layout has no field of that name.
+    UnknownField { name: String },
+    /// The value does not fit in the bits of the field.
+    FieldOutOfRange { name: String, value: u64, max: u64 },
+    /// The options have no fields to set values of.
+    NoFields,
+    /// The options have no scrambler for public ids.
+    NoScrambler,
+    /// The entity type does not fit in the type bits.
+    TypeOutOfRange { entity_type: u64, max: u64 },
+    /// The type and shard bits don't fit in the node bits.
+    NodeBitsTooNarrow { node_bits: u8, reserved: u32 },
+    /// A count or size that has to be positive is 0.
+    ZeroCount { name: &'static str },
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionsError::InvalidBits { total } => {
+                write!(f, "bits set should be total 64bit, got {}bit", total)
+            }
+            OptionsError::NoTimestampBits => write!(f, "ts_bits should be at least 1bit"),
+            OptionsError::BaseTsOutOfRange { base_ts, max } => {
+                write!(f, "base_ts {} exceeds ts_bits limit {}", base_ts, max)
+            }
+            OptionsError::NodeOutOfRange { node, max } => {
+                write!(f, "node number {} exceeds node_bits limit {}", node, max)
+            }
+            OptionsError::LifetimeTooShort {
+                required,
+                remaining,
+            } => write!(
+                f,
+                "timestamp bits are exhausted in {} days, but {} days are required",
+                remaining.as_secs() / 86400,
+                required.as_secs() / 86400
+            ),
+            OptionsError::PoolTooLarge { size, max } => write!(
+                f,
+                "pool size {} exceeds {} workers allowed by pool_bits",
+                size, max
+            ),
+            OptionsError::TooManyThreads { threads, max } => write!(
+                f,
+                "{} threads exceed {} threads allowed by node_bits",
+                threads, max
+            ),
+            OptionsError::InvalidThreshold => {
+                write!(f, "utilization threshold should be between 0.0 and 1.0")
+            }
+            OptionsError::Unsatisfiable {
+                ts_bits,
+                node_bits,
+                seq_bits,
+            } => write!(
+                f,
+                "requirements need {}bit timestamp + {}bit node + {}bit sequence = {}bit, exceeds 64bit",
+                ts_bits,
+                node_bits,
+                seq_bits,
+                ts_bits + node_bits + seq_bits
+            ),
+            OptionsError::DuplicateField { name } => write!(f, "field {} is defined twice", name),
+            OptionsError::UnknownField { name } => write!(f, "no field named {}", name),
+            OptionsError::FieldOutOfRange { name, value, max } => write!(
+                f,
+                "value {} of field {} exceeds its limit {}",
+                value, name, max
+            ),
+            OptionsError::NoFields => write!(
+                f,
+                "no fields are set, set them with GeneratorOptions::fields"
+            ),
+            OptionsError::NoScrambler => write!(
+                f,
+                "no scrambler is set, set one with GeneratorOptions::scrambler"
+            ),
+            OptionsError::TypeOutOfRange { entity_type, max } => write!(
+                f,
+                "entity type exceeds type_bits limit (entity_type={}, max={})",
+                entity_type, max
+            ),
+            OptionsError::NodeBitsTooNarrow {
+                node_bits,
+                reserved,
+            } => write!(
+                f,
+                "{}bit of type and shard bits exceed node_bits {}",
+                reserved, node_bits
+            ),
+            OptionsError::ZeroCount { name } => write!(f, "{} should be positive", name),
+        }
+    }
+}
+
+impl core::error::Error for OptionsError {}
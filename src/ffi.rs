@@ -0,0 +1,223 @@
+//! C API, for generating ids with the same layout from other languages.
+//!
+//! Built with the `ffi` feature, e.g. as a shared library by
+//! `cargo rustc --release --features ffi --crate-type cdylib`. The matching
+//! declarations are in `include/frostflake.h`:
+//!
+//! ```c
+//! frostflake_generator *g = frostflake_new(42, 10, 12, 1483228800000, 3);
+//! uint64_t id;
+//! if (frostflake_generate(g, &id) == FROSTFLAKE_OK) {
+//!     frostflake_decoded d;
+//!     frostflake_decode(g, id, &d);
+//! }
+//! frostflake_free(g);
+//! ```
+//!
+//! Functions return `FROSTFLAKE_OK` or one of the negative error codes, and
+//! a generator may be used from several threads at once.
+
+use std::ptr;
+
+use crate::decode::decode;
+use crate::{Error, Generator, GeneratorOptions};
+
+pub const FROSTFLAKE_OK: i32 = 0;
+/// A pointer argument is null.
+pub const FROSTFLAKE_ERR_NULL: i32 = -1;
+/// See [`Error::ClockMovedBackwards`].
+pub const FROSTFLAKE_ERR_CLOCK_MOVED_BACKWARDS: i32 = -2;
+/// See [`Error::TimeBeforeBaseTs`].
+pub const FROSTFLAKE_ERR_TIME_BEFORE_BASE_TS: i32 = -3;
+/// See [`Error::EpochExhausted`].
+pub const FROSTFLAKE_ERR_EPOCH_EXHAUSTED: i32 = -4;
+/// Any other error.
+pub const FROSTFLAKE_ERR_OTHER: i32 = -99;
+
+/// An id split by [`frostflake_decode`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrostflakeDecoded {
+    /// Milliseconds since the Unix epoch.
+    pub unix_ms: u64,
+    pub node: u64,
+    pub seq: u64,
+}
+
+/// Creates a generator with the given layout, reading the system time in
+/// milliseconds.
+///
+/// Returns null if the bits don't add up to 64, or `base_ts` or `node` don't
+/// fit in their bits. Release the generator with [`frostflake_free`].
+#[no_mangle]
+pub extern "C" fn frostflake_new(
+    ts_bits: u8,
+    node_bits: u8,
+    seq_bits: u8,
+    base_ts: u64,
+    node: u64,
+) -> *mut Generator {
+    let opts = GeneratorOptions::builder()
+        .bits(ts_bits, node_bits, seq_bits)
+        .base_ts(base_ts)
+        .node(node)
+        .build();
+    match opts {
+        Ok(opts) => Box::into_raw(Box::new(Generator::new(opts))),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Releases a generator created by [`frostflake_new`].
+///
+/// # Safety
+///
+/// `generator` must be null or returned by `frostflake_new`, and must not be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn frostflake_free(generator: *mut Generator) {
+    if !generator.is_null() {
+        drop(Box::from_raw(generator));
+    }
+}
+
+/// Generates an id into `out`.
+///
+/// # Safety
+///
+/// `generator` must be returned by `frostflake_new`, and `out` must be valid
+/// for writing a `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn frostflake_generate(generator: *const Generator, out: *mut u64) -> i32 {
+    let Some(generator) = generator.as_ref() else {
+        return FROSTFLAKE_ERR_NULL;
+    };
+    if out.is_null() {
+        return FROSTFLAKE_ERR_NULL;
+    }
+    match generator.try_generate() {
+        Ok(id) => {
+            *out = id;
+            FROSTFLAKE_OK
+        }
+        Err(e) => status(&e),
+    }
+}
+
+/// Fills `buf` with `len` ascending ids.
+///
+/// # Safety
+///
+/// `generator` must be returned by `frostflake_new`, and `buf` must be valid
+/// for writing `len` values of `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn frostflake_generate_batch(
+    generator: *const Generator,
+    buf: *mut u64,
+    len: usize,
+) -> i32 {
+    let Some(generator) = generator.as_ref() else {
+        return FROSTFLAKE_ERR_NULL;
+    };
+    if len == 0 {
+        return FROSTFLAKE_OK;
+    }
+    if buf.is_null() {
+        return FROSTFLAKE_ERR_NULL;
+    }
+    match generator.generate_into(std::slice::from_raw_parts_mut(buf, len)) {
+        Ok(()) => FROSTFLAKE_OK,
+        Err(e) => status(&e),
+    }
+}
+
+/// Splits `id` by the layout of `generator` into `out`.
+///
+/// # Safety
+///
+/// `generator` must be returned by `frostflake_new`, and `out` must be valid
+/// for writing a `frostflake_decoded`.
+#[no_mangle]
+pub unsafe extern "C" fn frostflake_decode(
+    generator: *const Generator,
+    id: u64,
+    out: *mut FrostflakeDecoded,
+) -> i32 {
+    let Some(generator) = generator.as_ref() else {
+        return FROSTFLAKE_ERR_NULL;
+    };
+    if out.is_null() {
+        return FROSTFLAKE_ERR_NULL;
+    }
+    let decoded = decode(&generator.core().opts.layout(), id);
+    *out = FrostflakeDecoded {
+        unix_ms: decoded.unix_ms,
+        node: decoded.node,
+        seq: decoded.seq,
+    };
+    FROSTFLAKE_OK
+}
+
+fn status(e: &Error) -> i32 {
+    match e {
+        Error::ClockMovedBackwards { .. } => FROSTFLAKE_ERR_CLOCK_MOVED_BACKWARDS,
+        Error::TimeBeforeBaseTs { .. } => FROSTFLAKE_ERR_TIME_BEFORE_BASE_TS,
+        Error::EpochExhausted { .. } => FROSTFLAKE_ERR_EPOCH_EXHAUSTED,
+        _ => FROSTFLAKE_ERR_OTHER,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let g = frostflake_new(42, 10, 12, 1483228800000, 3);
+        assert!(!g.is_null());
+
+        unsafe {
+            let mut id = 0;
+            assert_eq!(frostflake_generate(g, &mut id), FROSTFLAKE_OK);
+
+            let mut ids = [0; 100];
+            assert_eq!(
+                frostflake_generate_batch(g, ids.as_mut_ptr(), ids.len()),
+                FROSTFLAKE_OK
+            );
+            assert!(id < ids[0]);
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+
+            let mut d = FrostflakeDecoded::default();
+            assert_eq!(frostflake_decode(g, id, &mut d), FROSTFLAKE_OK);
+            assert_eq!(d.node, 3);
+            assert!(d.unix_ms > 1483228800000);
+
+            frostflake_free(g);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(frostflake_new(42, 10, 10, 0, 0).is_null());
+        assert!(frostflake_new(42, 10, 12, 0, 1024).is_null());
+
+        unsafe {
+            let mut id = 0;
+            assert_eq!(
+                frostflake_generate(ptr::null(), &mut id),
+                FROSTFLAKE_ERR_NULL
+            );
+
+            let g = frostflake_new(41, 10, 13, (1 << 41) - 1, 0);
+            assert_eq!(frostflake_generate(g, ptr::null_mut()), FROSTFLAKE_ERR_NULL);
+            // the epoch lies in the future
+            assert_eq!(
+                frostflake_generate(g, &mut id),
+                FROSTFLAKE_ERR_TIME_BEFORE_BASE_TS
+            );
+            frostflake_free(g);
+            frostflake_free(ptr::null_mut());
+        }
+    }
+}
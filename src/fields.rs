@@ -0,0 +1,358 @@
+//! Layouts with any number of named fields between the timestamp and the
+//! sequence number.
+
+use std::sync::Arc;
+
+use crate::error::OptionsError;
+use crate::{max, Error, Generator};
+
+/// Builds a [`FieldLayout`], like timestamp, datacenter, rack, node and
+/// sequence.
+///
+/// The named fields take the place of the node number of a plain layout,
+/// the first one in the highest bits, so the ids are still ordinary ids to
+/// decoders that don't know about the fields.
+///
+/// ```rust
+/// use frostflake::{Generator, GeneratorOptions, LayoutBuilder};
+///
+/// let fields = LayoutBuilder::new()
+///     .timestamp(41)
+///     .field("dc", 5)
+///     .field("rack", 3)
+///     .field("node", 3)
+///     .sequence(12)
+///     .build()
+///     .unwrap();
+/// let generator = Generator::new(GeneratorOptions::default().fields(fields));
+///
+/// let id = generator.generate_with(&[("dc", 2), ("rack", 1), ("node", 7)]);
+/// let decoded = generator.decode_fields(id);
+/// assert_eq!(decoded.get("rack"), Some(1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LayoutBuilder {
+    ts_bits: u8,
+    fields: Vec<(String, u8)>,
+    seq_bits: u8,
+}
+
+impl Default for LayoutBuilder {
+    fn default() -> Self {
+        LayoutBuilder::new()
+    }
+}
+
+impl LayoutBuilder {
+    /// Starts with the 42bit timestamp and 12bit sequence of the default
+    /// layout, and no fields.
+    pub fn new() -> Self {
+        LayoutBuilder {
+            ts_bits: 42,
+            fields: vec![],
+            seq_bits: 12,
+        }
+    }
+
+    pub fn timestamp(mut self, bits: u8) -> Self {
+        self.ts_bits = bits;
+        self
+    }
+
+    /// Adds a field below the ones added before.
+    pub fn field(mut self, name: &str, bits: u8) -> Self {
+        self.fields.push((name.to_string(), bits));
+        self
+    }
+
+    pub fn sequence(mut self, bits: u8) -> Self {
+        self.seq_bits = bits;
+        self
+    }
+
+    /// Fails if the bits don't add up to 64, the timestamp has no bits, or a
+    /// name is used twice.
+    pub fn build(self) -> Result<FieldLayout, OptionsError> {
+        let total = self.ts_bits as u32
+            + self.seq_bits as u32
+            + self
+                .fields
+                .iter()
+                .map(|(_, bits)| *bits as u32)
+                .sum::<u32>();
+        if total != 64 {
+            return Err(OptionsError::InvalidBits { total });
+        }
+        if self.ts_bits == 0 {
+            return Err(OptionsError::NoTimestampBits);
+        }
+        for (i, (name, _)) in self.fields.iter().enumerate() {
+            if self.fields[..i].iter().any(|(other, _)| other == name) {
+                return Err(OptionsError::DuplicateField { name: name.clone() });
+            }
+        }
+
+        Ok(FieldLayout {
+            ts_bits: self.ts_bits,
+            fields: self.fields.into(),
+            seq_bits: self.seq_bits,
+        })
+    }
+}
+
+/// Layout with named fields, built by [`LayoutBuilder`] and set with
+/// `GeneratorOptions::fields`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldLayout {
+    ts_bits: u8,
+    fields: Arc<[(String, u8)]>,
+    seq_bits: u8,
+}
+
+impl FieldLayout {
+    /// Bit widths of the timestamp, all fields together and the sequence, as
+    /// passed to `GeneratorOptions::bits`.
+    pub fn bits(&self) -> (u8, u8, u8) {
+        let field_bits = self.fields.iter().map(|(_, bits)| bits).sum();
+        (self.ts_bits, field_bits, self.seq_bits)
+    }
+
+    /// Names and bit widths of the fields, highest first.
+    pub fn fields(&self) -> impl Iterator<Item = (&str, u8)> {
+        self.fields
+            .iter()
+            .map(|(name, bits)| (name.as_str(), *bits))
+    }
+
+    /// Packs named field values into the value of the node field. Fields
+    /// left out are 0.
+    pub fn pack(&self, values: &[(&str, u64)]) -> Result<u64, OptionsError> {
+        let mut node = 0;
+        for (name, value) in values {
+            let Some((shift, bits)) = self.position(name) else {
+                return Err(OptionsError::UnknownField {
+                    name: name.to_string(),
+                });
+            };
+            if *value > max(bits) {
+                return Err(OptionsError::FieldOutOfRange {
+                    name: name.to_string(),
+                    value: *value,
+                    max: max(bits),
+                });
+            }
+            node |= value.checked_shl(shift as u32).unwrap_or(0);
+        }
+        Ok(node)
+    }
+
+    /// Splits the value of the node field into the named fields.
+    pub fn unpack(&self, node: u64) -> Vec<(String, u64)> {
+        self.fields
+            .iter()
+            .map(|(name, bits)| {
+                let (shift, _) = self.position(name).unwrap_or_default();
+                let value = node.checked_shr(shift as u32).unwrap_or(0) & max(*bits);
+                (name.clone(), value)
+            })
+            .collect()
+    }
+
+    // offset within the node field and bit width of a field
+    fn position(&self, name: &str) -> Option<(u8, u8)> {
+        let mut shift = self.bits().1;
+        for (field, bits) in self.fields.iter() {
+            shift -= bits;
+            if field == name {
+                return Some((shift, *bits));
+            }
+        }
+        None
+    }
+}
+
+/// An id split by [`Generator::decode_fields`].
+///
+/// Like [`DecodedId`](crate::DecodedId), `timestamp` is counted from the Unix
+/// epoch, in the unit of `time_fn`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DecodedFields {
+    pub timestamp: u64,
+    /// Values of the fields, highest first.
+    pub fields: Vec<(String, u64)>,
+    pub seq: u64,
+}
+
+impl DecodedFields {
+    /// Value of the field `name`.
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+impl Generator {
+    /// Same as `generate`, with the fields of `GeneratorOptions::fields` set
+    /// to `values` instead of the node number.
+    ///
+    /// Panics if the options have no fields, a field is unknown, or a value
+    /// exceeds its bits.
+    pub fn generate_with(&self, values: &[(&str, u64)]) -> u64 {
+        self.try_generate_with(values)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate_with`, but returns an error instead of panicking
+    /// when generating fails.
+    pub fn try_generate_with(&self, values: &[(&str, u64)]) -> Result<u64, Error> {
+        let (fields, (_, node_bits, seq_bits)) = {
+            let opts = &self.core().opts;
+            (
+                opts.fields.clone().ok_or(OptionsError::NoFields)?,
+                opts.bits,
+            )
+        };
+        let node = fields.pack(values)?;
+
+        let node_mask = max(node_bits).checked_shl(seq_bits as u32).unwrap_or(0);
+        let id = self.try_generate()?;
+        Ok((id & !node_mask) | node.checked_shl(seq_bits as u32).unwrap_or(0))
+    }
+
+    /// Splits an id into the timestamp, the fields of
+    /// `GeneratorOptions::fields` and the sequence number.
+    ///
+    /// Without fields, the node number is the only field, named `node`.
+    pub fn decode_fields(&self, id: u64) -> DecodedFields {
+        let core = self.core();
+        let decoded = core.opts.layout().decode(id);
+        let fields = match &core.opts.fields {
+            Some(fields) => fields.unpack(decoded.node),
+            None => vec![("node".to_string(), decoded.node)],
+        };
+
+        DecodedFields {
+            timestamp: decoded.timestamp,
+            fields,
+            seq: decoded.seq,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+
+    fn dc_rack_node() -> FieldLayout {
+        LayoutBuilder::new()
+            .timestamp(41)
+            .field("dc", 5)
+            .field("rack", 3)
+            .field("node", 3)
+            .sequence(12)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_build() {
+        let fields = dc_rack_node();
+        assert_eq!(fields.bits(), (41, 11, 12));
+        assert_eq!(
+            fields.fields().collect::<Vec<_>>(),
+            [("dc", 5), ("rack", 3), ("node", 3)]
+        );
+
+        assert_eq!(
+            LayoutBuilder::new().field("node", 9).build(),
+            Err(OptionsError::InvalidBits { total: 63 })
+        );
+        assert_eq!(
+            LayoutBuilder::new()
+                .field("node", 5)
+                .field("node", 5)
+                .build(),
+            Err(OptionsError::DuplicateField {
+                name: "node".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_pack() {
+        let fields = dc_rack_node();
+        let node = fields.pack(&[("dc", 31), ("node", 1)]).unwrap();
+        assert_eq!(node, (31 << 6) | 1);
+        assert_eq!(
+            fields.unpack(node),
+            [
+                ("dc".to_string(), 31),
+                ("rack".to_string(), 0),
+                ("node".to_string(), 1)
+            ]
+        );
+
+        assert!(matches!(
+            fields.pack(&[("shelf", 1)]),
+            Err(OptionsError::UnknownField { .. })
+        ));
+        assert_eq!(
+            fields.pack(&[("rack", 8)]),
+            Err(OptionsError::FieldOutOfRange {
+                name: "rack".to_string(),
+                value: 8,
+                max: 7
+            })
+        );
+    }
+
+    #[test]
+    fn test_generate_with() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .fields(dc_rack_node());
+        let g = Generator::new(opts);
+
+        let id = g.generate_with(&[("dc", 2), ("rack", 1), ("node", 7)]);
+        assert_eq!(id, (123 << 23) | (((2 << 6) | (1 << 3) | 7) << 12));
+        let decoded = g.decode_fields(id);
+        assert_eq!(decoded.timestamp, 1483228800000 + 123);
+        assert_eq!(
+            (decoded.get("dc"), decoded.get("rack"), decoded.get("node")),
+            (Some(2), Some(1), Some(7))
+        );
+
+        // the sequence is shared by all field values
+        let id = g.generate_with(&[("dc", 3)]);
+        assert_eq!(g.decode_fields(id).seq, 1);
+
+        assert_eq!(
+            g.try_generate_with(&[("dc", 32)]),
+            Err(Error::InvalidOptions(OptionsError::FieldOutOfRange {
+                name: "dc".to_string(),
+                value: 32,
+                max: 31
+            }))
+        );
+        let g = Generator::new(GeneratorOptions::default());
+        assert_eq!(
+            g.try_generate_with(&[("node", 1)]),
+            Err(Error::InvalidOptions(OptionsError::NoFields))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "no fields are set")]
+    fn test_generate_with_crash() {
+        let g = Generator::new(GeneratorOptions::default());
+        g.generate_with(&[("node", 1)]);
+    }
+}
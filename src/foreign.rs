@@ -0,0 +1,170 @@
+//! Decoders for snowflake ids of well-known services.
+//!
+//! Timestamps are converted to milliseconds since the Unix epoch.
+//!
+//! ```rust
+//! use frostflake::foreign::decode_discord;
+//!
+//! let id = decode_discord(175928847299117063);
+//! assert_eq!(id.timestamp, 1462015105796);
+//! assert_eq!((id.worker, id.process, id.increment), (1, 0, 7));
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::layout::{DISCORD_EPOCH, INSTAGRAM_EPOCH, SONYFLAKE_EPOCH, TWITTER_EPOCH};
+
+/// Fields of a Discord id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DiscordId {
+    pub timestamp: u64,
+    pub worker: u64,
+    pub process: u64,
+    pub increment: u64,
+}
+
+/// Fields of a Twitter (X) id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TwitterId {
+    pub timestamp: u64,
+    pub datacenter: u64,
+    pub worker: u64,
+    pub sequence: u64,
+}
+
+/// Fields of an Instagram id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstagramId {
+    pub timestamp: u64,
+    pub shard: u64,
+    pub sequence: u64,
+}
+
+macro_rules! created_at {
+    ($($ty:ty),*) => {
+        $(
+            impl $ty {
+                /// When the id was created.
+                pub fn created_at(&self) -> SystemTime {
+                    UNIX_EPOCH + Duration::from_millis(self.timestamp)
+                }
+            }
+        )*
+    };
+}
+
+/// Fields of a Sonyflake id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SonyflakeId {
+    pub timestamp: u64,
+    pub sequence: u64,
+    pub machine: u64,
+}
+
+created_at!(DiscordId, TwitterId, InstagramId, SonyflakeId);
+
+/// Decodes a Discord id: 42bit timestamp, 5bit worker, 5bit process and
+/// 12bit increment.
+pub fn decode_discord(id: u64) -> DiscordId {
+    DiscordId {
+        timestamp: (id >> 22) + DISCORD_EPOCH,
+        worker: (id >> 17) & 0x1f,
+        process: (id >> 12) & 0x1f,
+        increment: id & 0xfff,
+    }
+}
+
+/// Decodes a Twitter id: 41bit timestamp, 5bit datacenter, 5bit worker and
+/// 12bit sequence.
+pub fn decode_twitter(id: u64) -> TwitterId {
+    TwitterId {
+        timestamp: (id >> 22) + TWITTER_EPOCH,
+        datacenter: (id >> 17) & 0x1f,
+        worker: (id >> 12) & 0x1f,
+        sequence: id & 0xfff,
+    }
+}
+
+/// Decodes an Instagram id: 41bit timestamp, 13bit shard and 10bit
+/// sequence.
+pub fn decode_instagram(id: u64) -> InstagramId {
+    InstagramId {
+        timestamp: (id >> 23) + INSTAGRAM_EPOCH,
+        shard: (id >> 10) & 0x1fff,
+        sequence: id & 0x3ff,
+    }
+}
+
+/// Decodes a Sonyflake id: 39bit timestamp of 10 millisecond ticks, 8bit
+/// sequence and 16bit machine id.
+pub fn decode_sonyflake(id: u64) -> SonyflakeId {
+    SonyflakeId {
+        timestamp: ((id >> 24) + SONYFLAKE_EPOCH) * 10,
+        sequence: (id >> 16) & 0xff,
+        machine: id & 0xffff,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discord() {
+        // example from the Discord API documentation
+        let id = decode_discord(175928847299117063);
+        assert_eq!(
+            id,
+            DiscordId {
+                timestamp: 1462015105796,
+                worker: 1,
+                process: 0,
+                increment: 7,
+            }
+        );
+        assert_eq!(
+            id.created_at(),
+            UNIX_EPOCH + Duration::from_millis(1462015105796)
+        );
+    }
+
+    #[test]
+    fn test_twitter() {
+        let id = ((1600000000000 - TWITTER_EPOCH) << 22) | (3 << 17) | (17 << 12) | 42;
+        assert_eq!(
+            decode_twitter(id),
+            TwitterId {
+                timestamp: 1600000000000,
+                datacenter: 3,
+                worker: 17,
+                sequence: 42,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sonyflake() {
+        let id = ((160000000000 - SONYFLAKE_EPOCH) << 24) | (5 << 16) | 258;
+        assert_eq!(
+            decode_sonyflake(id),
+            SonyflakeId {
+                timestamp: 1600000000000,
+                sequence: 5,
+                machine: 258,
+            }
+        );
+    }
+
+    #[test]
+    fn test_instagram() {
+        let id = ((1387263000000 - INSTAGRAM_EPOCH) << 23) | (1341 << 10) | 1;
+        assert_eq!(
+            decode_instagram(id),
+            InstagramId {
+                timestamp: 1387263000000,
+                shard: 1341,
+                sequence: 1,
+            }
+        );
+    }
+}
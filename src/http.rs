@@ -0,0 +1,486 @@
+//! Serving ids over HTTP with JSON responses, to run frostflake as a
+//! sidecar id service.
+//!
+//! |Request|Response|
+//! |---|---|
+//! |`GET /id`|`{"id":"442730451055673344"}`|
+//! |`GET /ids?count=N`|`{"ids":["442730451055673344",...]}`|
+//! |`GET /decode/:id`|`{"id":"442730451055673344","timestamp":1588782894884,"node":0,"seq":0}`|
+//! |`GET /stats`|`{"pid":4242,"uptime":3600,...,"clock_stalled":0}`|
+//!
+//! Ids are returned as strings, since JSON numbers above 2^53 lose precision
+//! in JavaScript and other languages parsing them as doubles. `timestamp` is
+//! in the unit of `time_fn`, counted from the Unix epoch rather than
+//! `base_ts`. Errors are returned as `{"error":"..."}`, with status 429 when
+//! the generator is over its `GeneratorOptions::max_rate`. Requests with a
+//! line over 8KB or more than 100 headers are rejected, and the connection
+//! closed.
+//!
+//! `/stats` has the same stats as the `stats` command of the
+//! [memcached server](crate::server), under the same names: the uptime,
+//! connections and gets like katsubushi reports them, then the node number,
+//! layout, ids issued, queue depth and clock anomalies of the generator.
+//!
+//! ```ignore
+//! use frostflake::http::HttpServer;
+//! use frostflake::{GeneratorAsync, GeneratorOptions};
+//! use tokio::net::TcpListener;
+//!
+//! #[tokio::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let opts = GeneratorOptions::default();
+//!     let layout = opts.layout();
+//!     let generator = GeneratorAsync::spawn(opts);
+//!     let listener = TcpListener::bind("127.0.0.1:8080").await?;
+//!     HttpServer::new(generator, layout).serve(listener).await
+//! }
+//! ```
+
+use std::fmt::Write as _;
+use std::io;
+use std::sync::Arc;
+
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpListener;
+
+use crate::server_stats::{self, ServerStats};
+use crate::{Error, GeneratorAsync, Layout};
+
+const MAX_LINE: usize = 8192;
+const MAX_HEADERS: usize = 100;
+
+/// Most ids returned by a single `/ids` request.
+pub const MAX_COUNT: usize = 10_000;
+
+#[derive(Clone)]
+pub struct HttpServer {
+    generator: Arc<GeneratorAsync>,
+    layout: Layout,
+    stats: Arc<ServerStats>,
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn ok(body: String) -> Self {
+        Response {
+            status: "200 OK",
+            body,
+        }
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        let mut body = String::from("{\"error\":\"");
+        for c in message.chars() {
+            match c {
+                '"' => body.push_str("\\\""),
+                '\\' => body.push_str("\\\\"),
+                c if c.is_control() => {
+                    let _ = write!(body, "\\u{:04x}", c as u32);
+                }
+                c => body.push(c),
+            }
+        }
+        body.push_str("\"}");
+        Response { status, body }
+    }
+
+    fn generate_error(e: Error) -> Self {
+        if e == Error::RateLimited {
+            Response::error("429 Too Many Requests", &e.to_string())
+        } else if e.is_transient() {
+            Response::error("503 Service Unavailable", &e.to_string())
+        } else {
+            Response::error("500 Internal Server Error", &e.to_string())
+        }
+    }
+}
+
+impl HttpServer {
+    /// `layout` should be the layout of the generator, for decoding ids.
+    pub fn new(generator: Arc<GeneratorAsync>, layout: Layout) -> Self {
+        HttpServer {
+            generator,
+            layout,
+            stats: Arc::new(ServerStats::new()),
+        }
+    }
+
+    /// Accepts connections on `listener`, each handled in its own task.
+    ///
+    /// This only returns when accepting fails.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                let _ = server.handle(stream).await;
+            });
+        }
+    }
+
+    /// Answers requests on a single connection, keeping it alive unless the
+    /// client asks otherwise.
+    pub async fn handle<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: S) -> io::Result<()> {
+        let _connection = self.stats.connected();
+        let mut stream = BufReader::new(stream);
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            if read_line(&mut stream, &mut line).await? == 0 {
+                return Ok(());
+            }
+            if line.len() > MAX_LINE {
+                return write_response(
+                    &mut stream,
+                    Response::error("414 URI Too Long", "request line too long"),
+                    false,
+                )
+                .await;
+            }
+            let mut parts = line.split_whitespace();
+            let (Some(method), Some(target), Some(version)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                return write_response(
+                    &mut stream,
+                    Response::error("400 Bad Request", "malformed request"),
+                    false,
+                )
+                .await;
+            };
+            let (method, target) = (method.to_string(), target.to_string());
+            let mut keep_alive = version == "HTTP/1.1";
+
+            let mut content_length = 0;
+            for headers in 0.. {
+                let mut header = String::new();
+                if read_line(&mut stream, &mut header).await? == 0 {
+                    return Ok(());
+                }
+                if header.len() > MAX_LINE || headers == MAX_HEADERS {
+                    return write_response(
+                        &mut stream,
+                        Response::error("431 Request Header Fields Too Large", "headers too long"),
+                        false,
+                    )
+                    .await;
+                }
+                let header = header.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                let Some((name, value)) = header.split_once(':') else {
+                    continue;
+                };
+                let value = value.trim();
+                if name.eq_ignore_ascii_case("content-length") {
+                    let Ok(length) = value.parse() else {
+                        return write_response(
+                            &mut stream,
+                            Response::error("400 Bad Request", "invalid content-length"),
+                            false,
+                        )
+                        .await;
+                    };
+                    content_length = length;
+                } else if name.eq_ignore_ascii_case("connection") {
+                    if value.eq_ignore_ascii_case("close") {
+                        keep_alive = false;
+                    } else if value.eq_ignore_ascii_case("keep-alive") {
+                        keep_alive = true;
+                    }
+                }
+            }
+            // bodies are not used, but must be skipped to read the next request
+            tokio::io::copy(
+                &mut (&mut stream).take(content_length),
+                &mut tokio::io::sink(),
+            )
+            .await?;
+
+            let response = if method == "GET" {
+                self.route(&target).await
+            } else {
+                Response::error("405 Method Not Allowed", "only GET is supported")
+            };
+            write_response(&mut stream, response, keep_alive).await?;
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn route(&self, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        match path {
+            "/id" => {
+                let result = self.generator.try_generate().await;
+                self.stats.get(1, result.is_ok());
+                match result {
+                    Ok(id) => Response::ok(format!("{{\"id\":\"{}\"}}", id)),
+                    Err(e) => Response::generate_error(e),
+                }
+            }
+            "/ids" => {
+                let count = query
+                    .split('&')
+                    .find_map(|kv| kv.strip_prefix("count="))
+                    .map_or(Some(1), |n| n.parse::<usize>().ok());
+                let Some(count @ 1..=MAX_COUNT) = count else {
+                    return Response::error(
+                        "400 Bad Request",
+                        &format!("count should be between 1 and {}", MAX_COUNT),
+                    );
+                };
+
+                let result = self.generator.generate_many(count).await;
+                self.stats.get(count as u64, result.is_ok());
+                match result {
+                    Ok(ids) => {
+                        let mut body = String::from("{\"ids\":[");
+                        for (i, id) in ids.iter().enumerate() {
+                            if i > 0 {
+                                body.push(',');
+                            }
+                            let _ = write!(body, "\"{}\"", id);
+                        }
+                        body.push_str("]}");
+                        Response::ok(body)
+                    }
+                    Err(e) => Response::generate_error(e),
+                }
+            }
+            "/stats" => match self.stats.report(&self.generator).await {
+                Ok(stats) => Response::ok(server_stats::json(&stats)),
+                Err(e) => Response::generate_error(e),
+            },
+            _ => match path.strip_prefix("/decode/").map(str::parse::<u64>) {
+                Some(Ok(id)) => Response::ok(self.decode(id)),
+                Some(Err(_)) => Response::error("400 Bad Request", "invalid id"),
+                None => Response::error("404 Not Found", "not found"),
+            },
+        }
+    }
+
+    fn decode(&self, id: u64) -> String {
+        let decoded = self.layout.decode(id);
+        format!(
+            "{{\"id\":\"{}\",\"timestamp\":{},\"node\":{},\"seq\":{}}}",
+            id, decoded.timestamp, decoded.node, decoded.seq
+        )
+    }
+}
+
+// reads a line like `read_line`, but at most one byte past `MAX_LINE`, so
+// that a longer line is caught before it is buffered in full
+async fn read_line<R: AsyncBufRead + Unpin>(
+    stream: &mut R,
+    line: &mut String,
+) -> io::Result<usize> {
+    stream.take(MAX_LINE as u64 + 1).read_line(line).await
+}
+
+async fn write_response<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    response: Response,
+    keep_alive: bool,
+) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        response.status,
+        response.body.len(),
+        if keep_alive { "keep-alive" } else { "close" }
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+    use tokio::net::TcpStream;
+
+    async fn get<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut BufReader<S>,
+        request: &str,
+    ) -> (String, String) {
+        stream
+            .get_mut()
+            .write_all(request.as_bytes())
+            .await
+            .unwrap();
+
+        let mut status = String::new();
+        stream.read_line(&mut status).await.unwrap();
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await.unwrap();
+            if header == "\r\n" {
+                break;
+            }
+            if let Some(n) = header.strip_prefix("Content-Length: ") {
+                length = n.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+        (
+            status.trim_end().to_string(),
+            String::from_utf8(body).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_http_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let opts = GeneratorOptions::default().node(3);
+        let layout = opts.layout();
+        let generator = GeneratorAsync::spawn(opts);
+        tokio::spawn(HttpServer::new(generator, layout).serve(listener));
+
+        let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+
+        let (status, body) = get(&mut stream, "GET /id HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let id: u64 = body
+            .strip_prefix("{\"id\":\"")
+            .and_then(|s| s.strip_suffix("\"}"))
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let (_, body) = get(&mut stream, "GET /ids?count=3 HTTP/1.1\r\n\r\n").await;
+        let ids: Vec<u64> = body
+            .strip_prefix("{\"ids\":[")
+            .and_then(|s| s.strip_suffix("]}"))
+            .unwrap()
+            .split(',')
+            .map(|s| s.trim_matches('"').parse().unwrap())
+            .collect();
+        assert_eq!(ids.len(), 3);
+        assert!(id < ids[0] && ids[0] < ids[1] && ids[1] < ids[2]);
+
+        let (status, body) = get(
+            &mut stream,
+            &format!(
+                "GET /decode/{} HTTP/1.1\r\n\r\n",
+                (123 << 22) | (3 << 12) | 7
+            ),
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            body,
+            format!(
+                "{{\"id\":\"{}\",\"timestamp\":{},\"node\":3,\"seq\":7}}",
+                (123 << 22) | (3 << 12) | 7,
+                1483228800000u64 + 123
+            )
+        );
+
+        let (status, _) = get(&mut stream, "GET /ids?count=0 HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let (status, _) = get(&mut stream, "GET /decode/x HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let (status, _) = get(
+            &mut stream,
+            "POST /id HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}",
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+
+        let (status, body) = get(&mut stream, "GET /stats HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(body.starts_with(&format!("{{\"pid\":{},", std::process::id())));
+        assert!(body.ends_with(",\"clock_stalled\":0}"));
+        for stat in [
+            "\"version\":\"",
+            "\"curr_connections\":1,",
+            "\"cmd_get\":2,",
+            "\"get_hits\":4,",
+            "\"node\":3,",
+            "\"ids_issued\":4,",
+        ] {
+            assert!(body.contains(stat), "{} in {}", stat, body);
+        }
+
+        let (status, body) = get(
+            &mut stream,
+            "GET /nope HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        assert_eq!(body, "{\"error\":\"not found\"}");
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let opts = GeneratorOptions::default().max_rate(1);
+        let layout = opts.layout();
+        tokio::spawn(HttpServer::new(GeneratorAsync::spawn(opts), layout).serve(listener));
+
+        let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        let (status, _) = get(&mut stream, "GET /id HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let (status, body) = get(&mut stream, "GET /id HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, "HTTP/1.1 429 Too Many Requests");
+        assert_eq!(body, "{\"error\":\"rate limit exceeded\"}");
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let opts = GeneratorOptions::default();
+        let layout = opts.layout();
+        let server = HttpServer::new(GeneratorAsync::spawn(opts), layout);
+        let connect = || {
+            let (client, stream) = tokio::io::duplex(4 * MAX_LINE);
+            let server = server.clone();
+            tokio::spawn(async move { server.handle(stream).await });
+            BufReader::new(client)
+        };
+
+        // a line without an end is cut off at the limit
+        let request = format!("GET /{}", "a".repeat(2 * MAX_LINE));
+        let (status, _) = get(&mut connect(), &request).await;
+        assert_eq!(status, "HTTP/1.1 414 URI Too Long");
+
+        let request = format!("GET /id HTTP/1.1\r\nX: {}", "a".repeat(2 * MAX_LINE));
+        let (status, _) = get(&mut connect(), &request).await;
+        assert_eq!(status, "HTTP/1.1 431 Request Header Fields Too Large");
+
+        let request = format!(
+            "GET /id HTTP/1.1\r\n{}\r\n",
+            "X: 1\r\n".repeat(MAX_HEADERS + 1)
+        );
+        let (status, _) = get(&mut connect(), &request).await;
+        assert_eq!(status, "HTTP/1.1 431 Request Header Fields Too Large");
+
+        let request = "GET /id HTTP/1.1\r\nContent-Length: x\r\n\r\n";
+        let (status, body) = get(&mut connect(), request).await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert_eq!(body, "{\"error\":\"invalid content-length\"}");
+    }
+
+    #[test]
+    fn test_error_escape() {
+        let response = Response::error("500 Internal Server Error", "a \"b\"\n\\");
+        assert_eq!(response.body, r#"{"error":"a \"b\"\u000a\\"}"#);
+    }
+}
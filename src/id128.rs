@@ -0,0 +1,380 @@
+//! 128bit ids, for layouts that don't fit in 64bit.
+//!
+//! `Generator128` works like `Generator`, with the same time sources and
+//! overflow policies, but composes `u128` ids. Besides custom bit layouts,
+//! there are presets compatible with UUIDv7 and ULID, which keep their ids
+//! sortable by time and can be stored in UUID columns or passed to systems
+//! expecting ULIDs.
+//!
+//! ```rust
+//! use frostflake::id128::{Generator128, Generator128Options};
+//!
+//! let generator = Generator128::new(Generator128Options::uuid_v7().node(3));
+//! let id = generator.generate();
+//! assert_eq!(generator.format(id).len(), 36);
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::{max, Error, OverflowPolicy, TimeSource};
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Layout of the ids of a `Generator128`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format128 {
+    /// Timestamp, node and sequence bits as set by `bits`, written as 32 hex
+    /// digits.
+    Custom,
+    /// UUIDv7 of RFC 9562: 48bit unix milliseconds, the version, a 12bit
+    /// sequence, the variant and a 62bit node, written as a hyphenated UUID.
+    UuidV7,
+    /// ULID: 48bit unix milliseconds, a 64bit node and a 16bit sequence,
+    /// written in Crockford's base32.
+    Ulid,
+}
+
+#[derive(Clone)]
+pub struct Generator128Options {
+    format: Format128,
+    bits: (u8, u8, u8),
+    base_ts: u64,
+    node: u128,
+    overflow: OverflowPolicy,
+    clock: Arc<dyn TimeSource + Send + Sync>,
+}
+
+impl Default for Generator128Options {
+    /// 48bit timestamp, 64bit node and 16bit sequence, with the `base_ts` of
+    /// `GeneratorOptions`.
+    fn default() -> Self {
+        Generator128Options {
+            format: Format128::Custom,
+            bits: (48, 64, 16),
+            base_ts: 1483228800000,
+            node: 0,
+            overflow: OverflowPolicy::default(),
+            clock: Arc::new(crate::default_time_fn),
+        }
+    }
+}
+
+impl Generator128Options {
+    /// Preset for UUIDv7 compatible ids.
+    ///
+    /// The 74 bits RFC 9562 leaves for randomness hold the sequence and the
+    /// node number instead, so that ids are unique without a random source.
+    pub fn uuid_v7() -> Self {
+        Generator128Options {
+            format: Format128::UuidV7,
+            bits: (48, 62, 12),
+            base_ts: 0,
+            ..Generator128Options::default()
+        }
+    }
+
+    /// Preset for ULID compatible ids.
+    ///
+    /// The 80 bits of randomness of a ULID hold the node number and the
+    /// sequence instead.
+    pub fn ulid() -> Self {
+        Generator128Options {
+            format: Format128::Ulid,
+            bits: (48, 64, 16),
+            base_ts: 0,
+            ..Generator128Options::default()
+        }
+    }
+
+    /// Sets the bit widths of a custom layout.
+    ///
+    /// The timestamp and the sequence take at most 64bit each. The layout of
+    /// the presets is fixed, so this panics for them.
+    pub fn bits(mut self, ts_bits: u8, node_bits: u8, seq_bits: u8) -> Self {
+        assert!(
+            self.format == Format128::Custom,
+            "bits of a preset can't be changed"
+        );
+        assert!(
+            128 == ts_bits as u32 + node_bits as u32 + seq_bits as u32,
+            "bits set should be total 128bit"
+        );
+        assert!(
+            ts_bits <= 64 && seq_bits <= 64,
+            "ts_bits and seq_bits should be 64bit at most"
+        );
+        assert!(
+            self.base_ts <= max(ts_bits),
+            "base_ts exceeds ts_bits limit, set base_ts first"
+        );
+        assert!(
+            self.node <= max128(node_bits),
+            "node number exceeeds node_bits limit, set node number first"
+        );
+
+        self.bits = (ts_bits, node_bits, seq_bits);
+        self
+    }
+
+    pub fn node(mut self, node: u128) -> Self {
+        assert!(
+            node <= max128(self.bits.1),
+            "node number exceeds node_bits limit, set bit width first"
+        );
+
+        self.node = node;
+        self
+    }
+
+    pub fn base_ts(mut self, base_ts: u64) -> Self {
+        assert!(
+            base_ts <= max(self.bits.0),
+            "base_ts exceeds ts_bits limit, set bit width first"
+        );
+
+        self.base_ts = base_ts;
+        self
+    }
+
+    pub fn time_fn(self, time_fn: fn() -> u64) -> Self {
+        self.time_source(time_fn)
+    }
+
+    /// Same as `time_fn`, for closures and time sources that are not plain
+    /// functions, see [`clock`](crate::clock).
+    pub fn time_source<T: TimeSource + Send + Sync + 'static>(mut self, clock: T) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets what happens when all sequence numbers of a tick are used.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
+    pub fn format(&self) -> Format128 {
+        self.format
+    }
+}
+
+impl fmt::Debug for Generator128Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Generator128Options")
+            .field("format", &self.format)
+            .field("bits", &self.bits)
+            .field("base_ts", &self.base_ts)
+            .field("node", &self.node)
+            .field("overflow", &self.overflow)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Generates 128bit ids.
+pub struct Generator128 {
+    opts: Generator128Options,
+    // elapsed time and sequence number of the last id
+    state: Mutex<(u64, u64)>,
+}
+
+impl Generator128 {
+    pub fn new(opts: Generator128Options) -> Self {
+        Generator128 {
+            opts,
+            state: Mutex::new((0, 0)),
+        }
+    }
+
+    /// Generates an id, panicking on errors like `Generator::generate`.
+    pub fn generate(&self) -> u128 {
+        self.try_generate().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate`, but returns errors instead of panicking.
+    pub fn try_generate(&self) -> Result<u128, Error> {
+        loop {
+            match self.next_id() {
+                Err(Error::SequenceExhausted) => match self.opts.overflow {
+                    OverflowPolicy::WaitNextTick => std::thread::yield_now(),
+                    OverflowPolicy::Error => return Err(Error::SequenceExhausted),
+                    OverflowPolicy::Panic => panic!("{}", Error::SequenceExhausted),
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// Generates an id, written as its format does.
+    pub fn generate_string(&self) -> Result<String, Error> {
+        self.try_generate().map(|id| self.format(id))
+    }
+
+    /// Writes `id` as a hyphenated UUID, a ULID or 32 hex digits, depending
+    /// on the format of the options.
+    pub fn format(&self, id: u128) -> String {
+        match self.opts.format {
+            Format128::Custom => format!("{:032x}", id),
+            Format128::UuidV7 => to_uuid_string(id),
+            Format128::Ulid => to_ulid_string(id),
+        }
+    }
+
+    /// Splits an id into elapsed time since `base_ts`, node number and
+    /// sequence number.
+    pub fn extract(&self, id: u128) -> (u64, u128, u64) {
+        let (_, node_bits, seq_bits) = self.opts.bits;
+        match self.opts.format {
+            // the version and variant bits sit between the fields
+            Format128::UuidV7 => (
+                (id >> 80) as u64,
+                id & max128(62),
+                ((id >> 64) & 0xfff) as u64,
+            ),
+            _ => (
+                (id >> (node_bits + seq_bits)) as u64,
+                (id >> seq_bits) & max128(node_bits),
+                (id & max128(seq_bits)) as u64,
+            ),
+        }
+    }
+
+    fn next_id(&self) -> Result<u128, Error> {
+        let (ts_bits, _, seq_bits) = self.opts.bits;
+        let base_ts = self.opts.base_ts;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let now = self.opts.clock.now();
+        if now <= base_ts {
+            return Err(Error::TimeBeforeBaseTs { now, base_ts });
+        }
+        let elapsed = now - base_ts;
+        if elapsed > max(ts_bits) {
+            return Err(Error::EpochExhausted { ts: now });
+        }
+
+        let (last, last_seq) = *state;
+        let seq = if elapsed < last {
+            return Err(Error::ClockMovedBackwards {
+                now,
+                last_ts: last + base_ts,
+            });
+        } else if elapsed == last {
+            if last_seq >= max(seq_bits) {
+                return Err(Error::SequenceExhausted);
+            }
+            last_seq + 1
+        } else {
+            0
+        };
+        *state = (elapsed, seq);
+
+        Ok(self.compose(elapsed, seq))
+    }
+
+    fn compose(&self, elapsed: u64, seq: u64) -> u128 {
+        let (_, node_bits, seq_bits) = self.opts.bits;
+        let (elapsed, node, seq) = (elapsed as u128, self.opts.node, seq as u128);
+        match self.opts.format {
+            Format128::UuidV7 => (elapsed << 80) | (0x7 << 76) | (seq << 64) | (0b10 << 62) | node,
+            _ => (elapsed << (node_bits + seq_bits)) | (node << seq_bits) | seq,
+        }
+    }
+}
+
+impl Default for Generator128 {
+    fn default() -> Self {
+        Generator128::new(Generator128Options::default())
+    }
+}
+
+/// Writes `id` as a hyphenated UUID in lower case, like
+/// `0189c0c7-3f00-7000-8000-000000000003`.
+pub fn to_uuid_string(id: u128) -> String {
+    let hex = format!("{:032x}", id);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Writes `id` as a ULID, 26 characters of Crockford's base32.
+pub fn to_ulid_string(id: u128) -> String {
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD[((id >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+fn max128(shift: u8) -> u128 {
+    !u128::MAX.checked_shl(shift as u32).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn my_time_fn() -> u64 {
+        1700000000000
+    }
+
+    #[test]
+    fn test_generate() {
+        let opts = Generator128Options::default()
+            .time_fn(my_time_fn)
+            .bits(48, 72, 8)
+            .node(1 << 70);
+        let g = Generator128::new(opts);
+
+        let elapsed = 1700000000000u128 - 1483228800000;
+        assert_eq!(g.generate(), (elapsed << 80) | (1 << 78));
+        assert_eq!(g.generate(), (elapsed << 80) | (1 << 78) | 1);
+        assert_eq!(g.extract(g.generate()), (elapsed as u64, 1 << 70, 2));
+    }
+
+    #[test]
+    fn test_uuid_v7() {
+        let g = Generator128::new(Generator128Options::uuid_v7().time_fn(my_time_fn).node(3));
+
+        let id = g.generate();
+        assert_eq!(g.format(id), "018bcfe5-6800-7000-8000-000000000003");
+        let id = g.generate();
+        assert_eq!(g.format(id), "018bcfe5-6800-7001-8000-000000000003");
+        assert_eq!(g.extract(id), (1700000000000, 3, 1));
+    }
+
+    #[test]
+    fn test_ulid() {
+        let g = Generator128::new(Generator128Options::ulid().time_fn(my_time_fn).node(3));
+
+        let id = g.generate_string().unwrap();
+        assert_eq!(id, "01HF7YAT000000000000006000");
+        assert_eq!(id.len(), 26);
+        assert_eq!(g.extract(g.generate()), (1700000000000, 3, 1));
+    }
+
+    #[test]
+    fn test_sequence_exhausted() {
+        let opts = Generator128Options::default()
+            .time_fn(my_time_fn)
+            .bits(64, 63, 1)
+            .overflow_policy(OverflowPolicy::Error);
+        let g = Generator128::new(opts);
+
+        let ids: HashSet<_> = (0..2).map(|_| g.try_generate().unwrap()).collect();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+    }
+
+    #[test]
+    #[should_panic(expected = "bits of a preset can't be changed")]
+    fn test_preset_bits_crash() {
+        let _ = Generator128Options::ulid().bits(48, 64, 16);
+    }
+}
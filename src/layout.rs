@@ -0,0 +1,593 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::OptionsError;
+use crate::timefmt::rfc3339_millis;
+use crate::{bitmask, max, shl, shr};
+
+/// Bit layout of an id, together with its epoch.
+///
+/// Timestamps are in ticks of `tick_ms` milliseconds, 1 unless set with
+/// [`with_tick_ms`](Self::with_tick_ms), and so is `base_ts`.
+///
+/// The constructors are `const fn`, so a layout can be a constant shared by
+/// generators and decoders, and bits that don't add up to 64 fail to
+/// compile instead of panicking at runtime:
+///
+/// ```rust
+/// use frostflake::{Generator, GeneratorOptions, Layout};
+///
+/// static LAYOUT: Layout = Layout::new(44, 8, 12).with_base_ts(1483228800000);
+///
+/// let generator = Generator::new(GeneratorOptions::from(LAYOUT).node(3));
+/// assert_eq!(LAYOUT.decode(generator.generate()).node, 3);
+/// ```
+///
+/// ```compile_fail
+/// use frostflake::Layout;
+///
+/// static LAYOUT: Layout = Layout::new(44, 8, 13);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash))
+)]
+pub struct Layout {
+    ts_bits: u8,
+    node_bits: u8,
+    seq_bits: u8,
+    base_ts: u64,
+    tick_ms: u64,
+}
+
+pub(crate) const DEFAULT_LAYOUT: Layout = Layout {
+    ts_bits: 42,
+    node_bits: 10,
+    seq_bits: 12,
+    base_ts: 1483228800000, // 2017-01-01T00:00:00Z as milliseconds
+    tick_ms: 1,
+};
+
+pub(crate) const TWITTER_EPOCH: u64 = 1288834974657;
+pub(crate) const DISCORD_EPOCH: u64 = 1420070400000;
+// the epoch of the id function published by Instagram engineering
+pub(crate) const INSTAGRAM_EPOCH: u64 = 1314220021721;
+// 2014-09-01T00:00:00Z, in the 10 millisecond ticks of Sonyflake
+pub(crate) const SONYFLAKE_EPOCH: u64 = 140952960;
+
+impl Default for Layout {
+    fn default() -> Self {
+        DEFAULT_LAYOUT
+    }
+}
+
+/// Fields read when deserializing a layout, the ones of the default layout
+/// when missing.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LayoutConfig {
+    ts_bits: u8,
+    node_bits: u8,
+    seq_bits: u8,
+    base_ts: u64,
+    tick_ms: u64,
+}
+
+#[cfg(feature = "serde")]
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        let Layout {
+            ts_bits,
+            node_bits,
+            seq_bits,
+            base_ts,
+            tick_ms,
+        } = DEFAULT_LAYOUT;
+        LayoutConfig {
+            ts_bits,
+            node_bits,
+            seq_bits,
+            base_ts,
+            tick_ms,
+        }
+    }
+}
+
+/// Reads the fields a layout is serialized with, failing for bits that
+/// don't add up to 64 and for a `tick_ms` of 0.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Layout {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let config = LayoutConfig::deserialize(deserializer)?;
+        let total = config.ts_bits as u32 + config.node_bits as u32 + config.seq_bits as u32;
+        if total != 64 {
+            return Err(serde::de::Error::custom(OptionsError::InvalidBits {
+                total,
+            }));
+        }
+        if config.tick_ms == 0 {
+            return Err(serde::de::Error::custom("tick_ms should be at least 1"));
+        }
+
+        Ok(
+            Layout::new(config.ts_bits, config.node_bits, config.seq_bits)
+                .with_base_ts(config.base_ts)
+                .with_tick_ms(config.tick_ms),
+        )
+    }
+}
+
+impl Layout {
+    pub const fn new(ts_bits: u8, node_bits: u8, seq_bits: u8) -> Self {
+        assert!(
+            64 == ts_bits as u32 + node_bits as u32 + seq_bits as u32,
+            "bits set should be total 64bit"
+        );
+
+        Layout {
+            ts_bits,
+            node_bits,
+            seq_bits,
+            ..DEFAULT_LAYOUT
+        }
+    }
+
+    pub const fn with_base_ts(mut self, base_ts: u64) -> Self {
+        self.base_ts = base_ts;
+        self
+    }
+
+    /// Sets the length of a tick of the timestamp field, for time sources
+    /// that don't count milliseconds. `base_ts` is counted in ticks as well.
+    pub const fn with_tick_ms(mut self, tick_ms: u64) -> Self {
+        assert!(tick_ms > 0, "tick_ms should be at least 1");
+
+        self.tick_ms = tick_ms;
+        self
+    }
+
+    /// Layout of Twitter (X) ids: 42bit timestamp, 10bit datacenter and
+    /// worker, and 12bit sequence. Twitter has a 41bit timestamp behind the
+    /// sign bit; here the sign bit is the top bit of the timestamp, which
+    /// stays 0 for 69 years after the epoch.
+    pub const fn twitter() -> Self {
+        Layout::new(42, 10, 12).with_base_ts(TWITTER_EPOCH)
+    }
+
+    /// Layout of Discord ids: 42bit timestamp, 10bit worker and process, and
+    /// 12bit increment.
+    pub const fn discord() -> Self {
+        Layout::new(42, 10, 12).with_base_ts(DISCORD_EPOCH)
+    }
+
+    /// Layout of Instagram ids: 41bit timestamp, 13bit shard and 10bit
+    /// sequence.
+    pub const fn instagram() -> Self {
+        Layout::new(41, 13, 10).with_base_ts(INSTAGRAM_EPOCH)
+    }
+
+    /// Layout with the widths and epoch of Sonyflake: 40bit timestamp of 10
+    /// millisecond ticks, 16bit machine id and 8bit sequence. Sonyflake has a
+    /// 39bit timestamp behind the sign bit; here the sign bit is the top bit
+    /// of the timestamp, which stays 0 for 174 years after the epoch.
+    ///
+    /// Sonyflake places the sequence above the machine id, while this keeps
+    /// the node above the sequence like every layout. It decodes the ids of
+    /// `GeneratorOptions::sonyflake`, but not those of Sonyflake itself; use
+    /// [`foreign::decode_sonyflake`](crate::foreign::decode_sonyflake) for
+    /// those.
+    pub const fn sonyflake() -> Self {
+        Layout::new(40, 16, 8)
+            .with_base_ts(SONYFLAKE_EPOCH)
+            .with_tick_ms(10)
+    }
+
+    pub const fn ts_bits(&self) -> u8 {
+        self.ts_bits
+    }
+
+    pub const fn node_bits(&self) -> u8 {
+        self.node_bits
+    }
+
+    pub const fn seq_bits(&self) -> u8 {
+        self.seq_bits
+    }
+
+    pub const fn base_ts(&self) -> u64 {
+        self.base_ts
+    }
+
+    pub const fn tick_ms(&self) -> u64 {
+        self.tick_ms
+    }
+
+    /// Computes the bit widths for the given requirements.
+    ///
+    /// Node bits are sized for `max_nodes` generators, sequence bits for
+    /// `peak_ids_per_sec` ids per second on a single node, and timestamp bits
+    /// so that ids can be generated for `required_lifetime` counted from the
+    /// epoch. Bits left over go to the timestamp.
+    ///
+    /// The returned layout uses the default epoch; change it with
+    /// [`with_base_ts`](Self::with_base_ts).
+    ///
+    /// ```rust
+    /// use frostflake::Layout;
+    /// use std::time::Duration;
+    ///
+    /// let layout = Layout::recommend(500, 1_000_000, Duration::from_secs(30 * 365 * 86400)).unwrap();
+    /// assert_eq!((layout.ts_bits(), layout.node_bits(), layout.seq_bits()), (45, 9, 10));
+    /// ```
+    pub fn recommend(
+        max_nodes: u64,
+        peak_ids_per_sec: u64,
+        required_lifetime: Duration,
+    ) -> Result<Layout, OptionsError> {
+        let node_bits = bits_for(max_nodes.saturating_sub(1));
+        let seq_bits = bits_for(peak_ids_per_sec.div_ceil(1000).saturating_sub(1));
+        let lifetime = u64::try_from(required_lifetime.as_millis()).unwrap_or(u64::MAX);
+        let ts_bits = bits_for(lifetime);
+
+        if node_bits + seq_bits + ts_bits > 64 {
+            return Err(OptionsError::Unsatisfiable {
+                ts_bits,
+                node_bits,
+                seq_bits,
+            });
+        }
+
+        Ok(Layout {
+            ts_bits: (64 - node_bits - seq_bits) as u8,
+            node_bits: node_bits as u8,
+            seq_bits: seq_bits as u8,
+            ..Layout::default()
+        })
+    }
+}
+
+/// An id split into its fields, as returned by [`Layout::decode`].
+///
+/// Unlike [`DecomposedId`](crate::DecomposedId), `timestamp` is counted from
+/// the Unix epoch rather than `base_ts`, in ticks of the layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DecodedId {
+    pub timestamp: u64,
+    pub node: u64,
+    pub seq: u64,
+}
+
+impl Layout {
+    /// Splits an id into its fields, without needing a generator.
+    ///
+    /// ```rust
+    /// use frostflake::Layout;
+    ///
+    /// let layout = Layout::new(42, 10, 12).with_base_ts(1483228800000);
+    /// let decoded = layout.decode((123 << 22) | (3 << 12) | 7);
+    /// assert_eq!(decoded.timestamp, 1483228800000 + 123);
+    /// assert_eq!((decoded.node, decoded.seq), (3, 7));
+    /// ```
+    pub const fn decode(&self, id: u64) -> DecodedId {
+        let elapsed = shr(id, self.node_bits + self.seq_bits) & max(self.ts_bits);
+
+        DecodedId {
+            timestamp: elapsed.saturating_add(self.base_ts),
+            node: shr(id, self.seq_bits) & max(self.node_bits),
+            seq: id & max(self.seq_bits),
+        }
+    }
+
+    /// Builds an id from its fields, the reverse of [`decode`](Self::decode).
+    ///
+    /// `ts` is counted from the Unix epoch. Fields wider than their bits are
+    /// truncated, and timestamps before `base_ts` are treated as `base_ts`.
+    pub const fn compose(&self, ts: u64, node: u64, seq: u64) -> u64 {
+        let elapsed = ts.saturating_sub(self.base_ts) & max(self.ts_bits);
+
+        shl(elapsed, self.node_bits + self.seq_bits)
+            | shl(node & max(self.node_bits), self.seq_bits)
+            | (seq & max(self.seq_bits))
+    }
+}
+
+impl Layout {
+    /// Smallest id that can be generated at `time`, for range scans by
+    /// creation time.
+    ///
+    /// Times before `base_ts` are treated as `base_ts`, and times past the
+    /// end of the timestamp field as its last tick.
+    pub fn min_id_for_time(&self, time: SystemTime) -> u64 {
+        self.compose(self.ticks(time), 0, 0)
+    }
+
+    /// Largest id that can be generated at `time`, by any node.
+    pub fn max_id_for_time(&self, time: SystemTime) -> u64 {
+        self.compose(self.ticks(time), u64::MAX, u64::MAX)
+    }
+
+    /// Ids that can be generated from `from` to `to`, both inclusive, as in
+    /// `WHERE id BETWEEN x AND y`.
+    ///
+    /// ```rust
+    /// use frostflake::Layout;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let day = UNIX_EPOCH + Duration::from_secs(1704067200); // 2024-01-01
+    /// let ids = Layout::default().id_range(day, day + Duration::from_secs(86400));
+    /// assert!(ids.contains(&(Layout::default().compose(1704067200000 + 1, 3, 0))));
+    /// ```
+    pub fn id_range(&self, from: SystemTime, to: SystemTime) -> RangeInclusive<u64> {
+        self.min_id_for_time(from)..=self.max_id_for_time(to)
+    }
+
+    // timestamp of `time` in ticks since the Unix epoch, within the field
+    fn ticks(&self, time: SystemTime) -> u64 {
+        let ms = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        let ticks = ms / self.tick_ms;
+        ticks.min(self.base_ts.saturating_add(max(self.ts_bits)))
+    }
+}
+
+/// Capacity of a [`Layout`], as returned by [`Layout::capacity`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capacity {
+    /// Number of distinct node numbers.
+    pub max_nodes: u64,
+    /// Ids a single node can generate within one tick.
+    pub max_ids_per_tick: u64,
+    /// Ids a single node can generate within one second.
+    pub max_ids_per_sec: u64,
+    /// Time span the timestamp field covers, counted from the epoch.
+    pub lifetime: Duration,
+    /// When the timestamp field is exhausted.
+    pub exhausted_at: SystemTime,
+}
+
+impl fmt::Display for Capacity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exhausted_at = self
+            .exhausted_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        write!(
+            f,
+            "max_nodes={} max_ids_per_tick={} max_ids_per_sec={} exhausted_at={}",
+            self.max_nodes,
+            self.max_ids_per_tick,
+            self.max_ids_per_sec,
+            rfc3339_millis(exhausted_at)
+        )
+    }
+}
+
+impl Layout {
+    /// Reports how many nodes and ids the layout allows, and how long it lasts.
+    ///
+    /// ```rust
+    /// use frostflake::Layout;
+    ///
+    /// let capacity = Layout::default().capacity();
+    /// assert_eq!(capacity.max_nodes, 1024);
+    /// assert_eq!(capacity.max_ids_per_tick, 4096);
+    /// println!("{}", capacity);
+    /// ```
+    pub fn capacity(&self) -> Capacity {
+        let max_ids_per_tick = max(self.seq_bits).saturating_add(1);
+        let lifetime = max(self.ts_bits);
+        let ms = |ticks: u64| Duration::from_millis(ticks.saturating_mul(self.tick_ms));
+
+        Capacity {
+            max_nodes: max(self.node_bits).saturating_add(1),
+            max_ids_per_tick,
+            max_ids_per_sec: max_ids_per_tick.saturating_mul(1000) / self.tick_ms,
+            lifetime: ms(lifetime),
+            exhausted_at: UNIX_EPOCH + ms(self.base_ts.saturating_add(lifetime)),
+        }
+    }
+}
+
+/// Shifts and masks of a layout for a fixed node, worked out once when a
+/// generator is made rather than for every id.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CompiledLayout {
+    ts_shift: u32,
+    ts_mask: u64,
+    // the node field, already shifted into place
+    node_part: u64,
+    pub(crate) max_ts: u64,
+    pub(crate) max_seq: u64,
+}
+
+impl CompiledLayout {
+    pub(crate) const fn new((ts_bits, node_bits, seq_bits): (u8, u8, u8), node: u64) -> Self {
+        let ts_shift = node_bits + seq_bits;
+        let node_mask = bitmask(seq_bits) ^ bitmask(ts_shift);
+        CompiledLayout {
+            ts_shift: ts_shift as u32,
+            ts_mask: bitmask(ts_shift),
+            node_part: shl(node, seq_bits) & node_mask,
+            max_ts: max(ts_bits),
+            max_seq: max(seq_bits),
+        }
+    }
+
+    /// Id of the node with the given timestamp and sequence, without
+    /// branches: a shift of 64 wraps to 0, and its mask is empty.
+    #[inline]
+    pub(crate) fn compose(&self, elapsed: u64, seq: u64) -> u64 {
+        (elapsed.wrapping_shl(self.ts_shift) & self.ts_mask) | self.node_part | seq & self.max_seq
+    }
+}
+
+/// Number of bits needed to hold `value`.
+fn bits_for(value: u64) -> u32 {
+    64 - value.leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YEAR: u64 = 365 * 86400;
+
+    #[test]
+    fn test_bits_for() {
+        assert_eq!(bits_for(0), 0);
+        assert_eq!(bits_for(1), 1);
+        assert_eq!(bits_for(1023), 10);
+        assert_eq!(bits_for(1024), 11);
+    }
+
+    #[test]
+    fn test_recommend() {
+        // 1024 nodes and 4096 ids/ms fit the default layout exactly
+        let layout = Layout::recommend(1024, 4_096_000, Duration::from_secs(100 * YEAR)).unwrap();
+        assert_eq!(layout, Layout::default());
+
+        let layout = Layout::recommend(1, 1000, Duration::from_secs(YEAR)).unwrap();
+        assert_eq!(layout.node_bits(), 0);
+        assert_eq!(layout.seq_bits(), 0);
+        assert_eq!(layout.ts_bits(), 64);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let capacity = Layout::default().capacity();
+        assert_eq!(capacity.max_nodes, 1024);
+        assert_eq!(capacity.max_ids_per_tick, 4096);
+        assert_eq!(capacity.max_ids_per_sec, 4_096_000);
+        assert_eq!(capacity.lifetime, Duration::from_millis((1 << 42) - 1));
+        assert_eq!(
+            capacity.to_string(),
+            "max_nodes=1024 max_ids_per_tick=4096 max_ids_per_sec=4096000 exhausted_at=2156-05-15T07:35:11.103Z"
+        );
+
+        let capacity = Layout::new(64, 0, 0).with_base_ts(0).capacity();
+        assert_eq!(capacity.max_nodes, 1);
+        assert_eq!(capacity.max_ids_per_tick, 1);
+        assert_eq!(capacity.lifetime, Duration::from_millis(u64::MAX));
+    }
+
+    #[test]
+    fn test_decode_compose() {
+        let layout = Layout::new(42, 10, 12).with_base_ts(1000);
+        let id = layout.compose(1000 + 123, 3, 7);
+        assert_eq!(id, (123 << 22) | (3 << 12) | 7);
+        assert_eq!(
+            layout.decode(id),
+            DecodedId {
+                timestamp: 1000 + 123,
+                node: 3,
+                seq: 7,
+            }
+        );
+
+        // fields are truncated to their widths
+        assert_eq!(layout.compose(1000, 1024 + 3, 4096 + 7), (3 << 12) | 7);
+
+        let layout = Layout::new(64, 0, 0).with_base_ts(0);
+        assert_eq!(layout.compose(u64::MAX, 1, 1), u64::MAX);
+        assert_eq!(layout.decode(u64::MAX).timestamp, u64::MAX);
+        assert_eq!(layout.decode(u64::MAX).node, 0);
+
+        let layout = Layout::new(0, 0, 64);
+        assert_eq!(layout.decode(42).seq, 42);
+        assert_eq!(layout.decode(42).timestamp, layout.base_ts());
+    }
+
+    #[test]
+    fn test_compiled_layout() {
+        for bits in [(42, 10, 12), (64, 0, 0), (0, 0, 64), (0, 64, 0), (1, 62, 1)] {
+            let layout = Layout::new(bits.0, bits.1, bits.2).with_base_ts(0);
+            for node in [0, 3, u64::MAX] {
+                let compiled = CompiledLayout::new(bits, node);
+                for (elapsed, seq) in [(0, 0), (123, 7), (u64::MAX, u64::MAX)] {
+                    assert_eq!(
+                        compiled.compose(elapsed, seq),
+                        layout.compose(elapsed, node, seq),
+                        "{:?} {} {} {}",
+                        bits,
+                        node,
+                        elapsed,
+                        seq
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_presets() {
+        // example from the Discord API documentation
+        let decoded = Layout::discord().decode(175928847299117063);
+        assert_eq!(decoded.timestamp, 1462015105796);
+        assert_eq!((decoded.node, decoded.seq), ((1 << 5), 7));
+
+        let id = ((1600000000000 - TWITTER_EPOCH) << 22) | (3 << 17) | (17 << 12) | 42;
+        let decoded = Layout::twitter().decode(id);
+        assert_eq!(decoded.timestamp, 1600000000000);
+        assert_eq!((decoded.node, decoded.seq), ((3 << 5) | 17, 42));
+
+        let id = ((1387263000000 - INSTAGRAM_EPOCH) << 23) | (1341 << 10) | 1;
+        let decoded = Layout::instagram().decode(id);
+        assert_eq!(decoded.timestamp, 1387263000000);
+        assert_eq!((decoded.node, decoded.seq), (1341, 1));
+
+        let capacity = Layout::sonyflake().capacity();
+        assert_eq!(capacity.max_nodes, 65536);
+        assert_eq!(capacity.max_ids_per_sec, 25600);
+        // 2^40 ticks of 10ms last about 348 years, the sign bit staying clear
+        // for the first 2^39
+        assert_eq!(
+            capacity.lifetime,
+            Duration::from_millis(((1 << 40) - 1) * 10)
+        );
+    }
+
+    #[test]
+    fn test_id_range() {
+        let layout = Layout::new(42, 10, 12).with_base_ts(1000);
+        let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+
+        assert_eq!(layout.min_id_for_time(at(1000 + 123)), 123 << 22);
+        assert_eq!(layout.max_id_for_time(at(1000 + 123)), (124 << 22) - 1);
+        assert_eq!(
+            layout.id_range(at(1000 + 123), at(1000 + 125)),
+            (123 << 22)..=((126 << 22) - 1)
+        );
+
+        // clamped to the timestamp field
+        assert_eq!(layout.min_id_for_time(at(0)), 0);
+        assert_eq!(layout.max_id_for_time(at(u64::MAX)), u64::MAX);
+
+        // ticks of 10 milliseconds
+        let layout = Layout::sonyflake();
+        let end = layout.max_id_for_time(at(1600000000009));
+        assert_eq!(layout.decode(end).timestamp, 160000000000);
+        assert_eq!(layout.decode(end + 1).timestamp, 160000000001);
+    }
+
+    #[test]
+    fn test_recommend_unsatisfiable() {
+        let r = Layout::recommend(1 << 20, 1 << 20, Duration::from_secs(100 * YEAR));
+        assert_eq!(
+            r,
+            Err(OptionsError::Unsatisfiable {
+                ts_bits: 42,
+                node_bits: 20,
+                seq_bits: 11,
+            })
+        );
+    }
+}
@@ -9,13 +9,21 @@
 //! ```rust
 //! use frostflake::{Generator, GeneratorOptions};
 //!
-//! let mut generator = Generator::new(GeneratorOptions::default());
+//! let generator = Generator::new(GeneratorOptions::default());
 //!
 //! let id1 = generator.generate();
 //! let id2 = generator.generate();
 //! let id3 = generator.generate();
 //! ```
 //!
+//! `generate` panics on errors, like a clock moving backwards. Servers that
+//! must not panic use `try_generate`, which returns them as `Error` instead,
+//! and so do the `try_` versions of the other calls. The setters of the options
+//! still panic on invalid values, which `GeneratorOptions::builder()` returns
+//! as `OptionsError` instead. Apart from those, the panicking calls and
+//! `OverflowPolicy::Panic`, the library doesn't panic, and `unwrap`/`expect`
+//! are denied outside of tests.
+//!
 //! ## Async generator works with tokio
 //!
 //! This requires `tokio` feature.
@@ -48,6 +56,40 @@
 //! }
 //! ```
 //!
+//! `generate` fails with `frostflake::Error`, like the other generators, so
+//! callers can match on the cause: errors of the generator like
+//! `Error::SequenceExhausted` are passed through, `Error::Canceled` means that
+//! generating panicked and the task restarted, and `Error::GeneratorClosed`
+//! that the task was shut down.
+//!
+//! `generate_timeout(Duration)` fails with `Error::DeadlineExceeded` instead of
+//! waiting longer, in the queue or for the next tick. Dropping a `generate`
+//! future, like on a timeout, is safe: the task skips requests nobody waits for
+//! anymore.
+//!
+//! Requests queue up for the generator task, 10 at most by default.
+//! `GeneratorAsync::spawn_with_capacity` sets a larger queue for bursty
+//! workloads, and `try_generate_nowait` fails with `Error::Busy` instead of
+//! waiting when it is full, to shed load.
+//!
+//! The task runs until all handles are dropped. `shutdown().await` stops it
+//! once the queued requests are answered, and `abort()` right away, failing the
+//! queued requests; either way, later requests fail with
+//! `Error::GeneratorClosed`. `GeneratorPoolAsync` has both too, for all of its
+//! tasks.
+//!
+//! Other runtimes can run the generator task with `GeneratorAsync::spawn_with`,
+//! which hands the task to a spawner instead of `tokio::spawn`. Only the
+//! channels of tokio are used then, without a tokio runtime.
+//!
+//! ```ignore
+//! use frostflake::{GeneratorAsync, GeneratorOptions};
+//!
+//! let generator = GeneratorAsync::spawn_with(GeneratorOptions::default(), |task| {
+//!     async_std::task::spawn(task);
+//! });
+//! ```
+//!
 //! ## Multi-threads generator by GeneratorPool
 //!
 //! This requires `std-thread` feature.
@@ -63,11 +105,220 @@
 //!     // pool also can be shared with threads by std::sync::Arc
 //!     let pool = pool.clone();
 //!     thread::spawn(move || {
-//!         let id = pool.generate();
+//!         let id = pool.generate().unwrap();
 //!     }).join();
 //! }
 //! ```
 //!
+//! With `tokio` feature, `GeneratorPoolAsync` runs the generators as tasks
+//! instead of threads, with the same options.
+//!
+//! ```ignore
+//! use frostflake::{GeneratorPoolAsync, GeneratorPoolOptions};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let pool = GeneratorPoolAsync::spawn(4, GeneratorPoolOptions::default());
+//!     let id = pool.generate().await.unwrap();
+//! }
+//! ```
+//!
+//! Programs mixing blocking threads and tokio can share one `GeneratorPool`:
+//! with both `std-thread` and `tokio` features,
+//! `GeneratorPoolHandleAsync::new(pool.clone())` has an async `generate()`,
+//! answered by the workers through a oneshot channel, so tasks await ids
+//! without blocking the runtime.
+//!
+//! `extract` of a pool splits an id into timestamp, pool number, node number
+//! and sequence. To decode ids without running a pool,
+//! `GeneratorPoolOptions::layout()` returns a `PoolLayout` whose `decode` does
+//! the same. `decode` of a pool returns a `decode::PoolDecoded` instead, with
+//! named fields and the time of the id, displayed like
+//! `2017-01-01T00:00:12.345Z pool=1 node=3 seq=7` for logs.
+//!
+//! ## Lock-free generator
+//!
+//! `Generator` takes `&self` and can be shared across threads as is, but
+//! generating locks its state. `AtomicGenerator` generates ids without a lock,
+//! by a compare-and-swap on a single atomic, for many threads generating at
+//! once.
+//!
+//! ```rust
+//! use frostflake::{AtomicGenerator, GeneratorOptions};
+//! use std::sync::Arc;
+//!
+//! let generator = Arc::new(AtomicGenerator::new(GeneratorOptions::default()));
+//! let id = generator.generate();
+//! ```
+//!
+//! It supports a subset of the options, see its documentation.
+//!
+//! `ThreadLocalGenerator` gives every thread a `Generator` of its own, numbered
+//! in the upper bits of the node number, so that threads never wait for each
+//! other. `ThreadLocalGenerator::new(opts, max_threads)` fails when the node
+//! bits can't tell `max_threads` threads apart, and generators of exited
+//! threads are reused by new ones.
+//!
+//! For backfills, `ParallelIdAssigner` assigns ids to millions of items on
+//! several threads: `par_assign(&mut rows, |row, id| row.id = id)` splits the
+//! rows into one part per worker, each with a generator numbered like the
+//! threads of `ThreadLocalGenerator`, and `par_generate(n)` returns the ids as
+//! a `Vec`.
+//!
+//! With `std-thread` feature, `BufferedGenerator` keeps a ring buffer of ids
+//! ready, topped up by a background thread, so that taking an id is a single
+//! lock-free pop in the common case. When the generator can't keep up, `next`
+//! waits for the thread, and `try_next` fails with `Error::Busy`.
+//!
+//! ## no_std
+//!
+//! With `default-features = false`, the crate builds without the standard
+//! library. `EmbeddedGenerator` is available then, along with `TimeSource` and
+//! `Error`: it generates on `&mut self` without locks or allocations, and reads
+//! the time from the clock it is given, like the one of an RTOS.
+//!
+//! ```rust
+//! use frostflake::EmbeddedGenerator;
+//!
+//! fn rtos_millis() -> u64 {
+//!     1483228800000 + 1
+//! }
+//!
+//! let mut generator = EmbeddedGenerator::new(rtos_millis).node(3);
+//! let id = generator.generate();
+//! ```
+//!
+//! All other types and features require `std`, which is enabled by default.
+//!
+//! ## Throughput
+//!
+//! A single generator issues at most 2^`seq_bits` ids per tick, so the default
+//! layout tops out at 4,096,000 ids per second per node, no matter how many
+//! threads share it. A pool multiplies that limit by its number of workers, but
+//! every id crosses a channel, which only pays off on machines with cores to
+//! spare. When a single generator falls short of the rate needed,
+//! `generate_many` fills a tick without reading the clock for every id.
+//!
+//! Ids of a pool are unique, but not ordered across workers: within a tick, the
+//! ids of a worker with a higher pool number sort after later ids of the
+//! others. `GeneratorPoolOptions::strict_ordering(true)` makes all workers take
+//! ids from a single generator, so they ascend in the order they are generated,
+//! at the cost of the extra generators: the pool tops out at the rate of one
+//! generator, and only the channel overhead is spread over the workers.
+//!
+//! Every worker reads the time source on its own. For a time source that isn't
+//! monotonic across threads, `GeneratorPoolOptions::shared_clock(true)` puts it
+//! behind a `clock::SharedClock`, so that no worker sees a time behind one
+//! another worker has seen, and `clock_skew()` of the pool reports how often
+//! and how far the source lagged. A panic while generating, like in a custom
+//! time source, fails the request with `Error::WorkerPanicked` instead of
+//! killing the worker.
+//!
+//! `GeneratorPoolOptions::thread_name("ids")` names the workers `ids-0`,
+//! `ids-1` and so on, for debuggers and profilers. With `affinity` feature,
+//! `GeneratorPoolOptions::affinity(...)` pins them to cores on Linux:
+//! `Affinity::Spread` one worker per core, `Affinity::Cores` to a list of
+//! cores, and `Affinity::NumaNodes { per_node }` a number of workers on each
+//! NUMA node in turn.
+//!
+//! `cargo bench --features all` measures a single generator, a generator behind
+//! a mutex and shared by threads, `AtomicGenerator`, `BufferedGenerator`, pools
+//! of 1 to 8 workers with and without strict ordering, and `GeneratorAsync` on
+//! the current machine. With `cli` feature, `frostflake bench` measures a
+//! custom layout.
+//!
+//! ## Typed ids
+//!
+//! `TypedGenerator<T>` generates `Id<T>`, a plain id tagged with the kind of
+//! entity it identifies, so that an `Id<User>` can't be passed where an
+//! `Id<Order>` is expected.
+//!
+//! ```rust
+//! use frostflake::{GeneratorOptions, Id, TypedGenerator};
+//!
+//! struct User;
+//!
+//! let users = TypedGenerator::<User>::with_options(GeneratorOptions::default());
+//! let id: Id<User> = users.generate();
+//! ```
+//!
+//! The type can also be part of the id itself:
+//! `GeneratorOptions::bits4(ts, type, node, seq)` reserves bits for an entity
+//! type above the node number, `Generator::generate_for(type)` stamps it into
+//! the id, and `Generator::extract4` decodes it again.
+//!
+//! ## 128bit ids
+//!
+//! `Generator128` generates `u128` ids for layouts that don't fit in 64bit,
+//! with presets compatible with UUIDv7 and ULID.
+//!
+//! ```rust
+//! use frostflake::id128::{Generator128, Generator128Options};
+//!
+//! let generator = Generator128::new(Generator128Options::ulid().node(3));
+//! let ulid = generator.generate_string().unwrap();
+//! ```
+//!
+//! Databases without unsigned 64bit integers store ids in signed `BIGINT`
+//! columns. `i64::try_from(snowflake)` and `Snowflake::try_from(i64)` convert
+//! between them. They fail for ids with the top bit set, which the default
+//! layout only reaches in 2086.
+//!
+//! For pagination cursors and cleanup queries, `Snowflake::first_of(ts)` and
+//! `Snowflake::last_of(ts)` are the smallest and largest ids of a timestamp,
+//! `succ()` and `pred()` step to the neighboring ids, and adding or subtracting
+//! a `Duration` moves the timestamp of an id, keeping its node and sequence, as
+//! in `WHERE id < Snowflake::first_of(now) - Duration::from_secs(86400)`.
+//! `checked_add` and `checked_sub` return `None` instead of panicking outside
+//! the timestamp field.
+//!
+//! The `cursor` module encodes such a position, an id with a direction and an
+//! optional hash of the query filter, into an opaque URL safe token signed with
+//! a secret key, so that clients can't forge or alter it. Tokens are signed
+//! with SipHash-2-4 by default, and with HMAC-SHA256 by
+//! `CursorCodec::hmac_sha256` with `hmac` feature.
+//!
+//! Ids count up, which tells anyone seeing a few of them how many ids were
+//! generated in between. The `obfuscate` module scrambles ids into public ids
+//! with `Scrambler`, a keyed and reversible permutation by the Speck64/128
+//! block cipher, and `Scrambler::unscramble` turns them back. With
+//! `GeneratorOptions::scrambler`, `Generator::generate_public` returns
+//! scrambled ids right away.
+//!
+//! For ids typed by people, like on invoices, the `checksum` module adds a
+//! check character that catches typos. `CheckedEncoding` writes ids in decimal
+//! with a Damm check digit, or in base62, base58, hex or the sortable encoding
+//! with a Luhn mod N check character, at the end or the start, and
+//! `Snowflake::to_checked_string` and `from_checked_string` use it.
+//! `checksum::with_check_bits` puts the check into the low bits of the number
+//! instead, and `verify_bits` takes it off again.
+//!
+//! For keys of byte- or string-ordered stores, like compacted Kafka topics,
+//! `Snowflake::to_be_bytes` and `Snowflake::to_sortable_string` sort like the
+//! ids, and thus by timestamp. The string is always 13 characters of
+//! Crockford's base32.
+//!
+//! With `uuid` feature, `Snowflake::to_uuid` and `Generator::generate_uuid`
+//! embed 64bit ids in version 8 UUIDs, for storage that only accepts UUID
+//! columns, and `Snowflake::from_uuid` extracts them again.
+//!
+//! ## Testing
+//!
+//! Code that takes an `IdGenerator` or `AsyncIdGenerator` works with every
+//! generator flavor. In tests, `testing::FixedGenerator` hands out a scripted
+//! sequence of ids or errors instead, and `testing::MockClock` moves the time
+//! of a real generator only when told to.
+//!
+//! Decoding takes ids from untrusted clients, like those of the servers below
+//! or ids in log lines. `fuzz/` has
+//! [cargo-fuzz](https://github.com/rust-fuzz/cargo-fuzz) targets for it, run on
+//! a nightly toolchain like `cargo +nightly fuzz run codecs`. `codecs` decodes
+//! arbitrary strings by the string codecs and checked encodings,
+//! `layout_decode` decodes arbitrary ids by layouts of arbitrary bit splits,
+//! epochs and tick lengths, and `options` builds options of arbitrary bit
+//! splits and generates ids by them. All of them check for panics and for ids
+//! that don't round-trip.
+//!
 //! ## Configurations
 //!
 //! frostflake is highly configurable.
@@ -83,6 +334,85 @@
 //! let generator = Generator::new(opts);
 //! ```
 //!
+//! To check a configuration before deploying it, `analysis::estimate` takes a
+//! layout, the node strategy, the peak ids per second of the fleet and the
+//! number of processes, and reports how long the epoch lasts, how many ids each
+//! process generates against what its sequence allows, and the chance that
+//! hashed node numbers collide by the birthday bound, with a warning for each
+//! risk.
+//!
+//! `Layout` and `EmbeddedGenerator` are built by `const fn`, so a layout can
+//! live in a `static` and be turned into options with
+//! `GeneratorOptions::from(LAYOUT)`. Bits that don't add up to 64 are then a
+//! compile error instead of a panic at startup.
+//!
+//! `Generator::decode` is `extract` with the fields named: its
+//! `decode::Decoded` has the raw `timestamp_raw`, the time of the id in
+//! `unix_ms` and `datetime()`, `node` and `seq`, implements `Display` for logs
+//! and, with `serde` feature, `Serialize`.
+//!
+//! After a change of the layout, `decode::MultiLayoutDecoder` decodes ids of
+//! both: `MultiLayoutDecoder::new(old).since_id(first_new_id, new)` switches
+//! layouts at the first id of the new one, and `since_time(time, new)` for ids
+//! that decode to that time or later by the new layout.
+//!
+//! The node number and the sequence can also be 0bit: a single machine can
+//! spend all bits on time and sequence with `bits(52, 0, 12)`, and pool options
+//! without pool bits run a single worker. Only the timestamp needs at least
+//! 1bit.
+//!
+//! Ids of Twitter's original snowflake split the node number into a 5bit
+//! datacenter and a 5bit worker.
+//! `GeneratorOptions::twitter().datacenter(dc).worker(w)` generates the same
+//! ids as the Java snowflake with the same `datacenterId` and `workerId`, and
+//! `Generator::extract_worker` decodes both again.
+//!
+//! For sharded storage, `Snowflake::shard(n)` maps an id to one of `n` shards.
+//! To keep all ids of a key, like a user, on the shard of the key,
+//! `GeneratorOptions::shard_bits(bits)` reserves the upper bits of the node
+//! number for a shard, `Generator::generate_for_key(key_hash)` stamps the shard
+//! of the hash into the id, and `Generator::shard` reads it back from the id
+//! alone.
+//!
+//! Sharding by `id % n` instead is skewed when most ticks have a single id, as
+//! their sequence numbers are all 0.
+//! `GeneratorOptions::randomize_seq_start(true)` starts the sequence of every
+//! tick at a random number in the lower half of the sequence space, keeping ids
+//! unique and ascending at the cost of up to half of the ids per tick.
+//!
+//! The node number can be split into named fields with `LayoutBuilder`, like
+//! datacenter, rack and node. `Generator::generate_with` sets them per id, and
+//! `Generator::decode_fields` reads them back by name.
+//!
+//! ```rust
+//! use frostflake::{Generator, GeneratorOptions, LayoutBuilder};
+//!
+//! let fields = LayoutBuilder::new()
+//!     .timestamp(41)
+//!     .field("dc", 5)
+//!     .field("rack", 3)
+//!     .field("node", 3)
+//!     .sequence(12)
+//!     .build()
+//!     .unwrap();
+//! let generator = Generator::new(GeneratorOptions::default().fields(fields));
+//! let id = generator.generate_with(&[("dc", 2), ("rack", 1), ("node", 7)]);
+//! ```
+//!
+//! `Generator::generate_at(ts)` generates ids carrying a past timestamp, for
+//! backfilling historical records with ids matching their event times. Every
+//! timestamp gets a sequence of its own counting down from the top, so
+//! backfilled ids are unique among themselves and live ids of the same tick
+//! make room for them. The generator doesn't know which ids its node issued at
+//! older ticks before, so backfills are best run with a node number of their
+//! own. Timestamps after the current time fail with `Error::TimeInFuture`.
+//!
+//! For transactional outboxes and sagas, `Generator::reserve(n)` takes `n` ids
+//! that count as issued only once `Reservation::commit()` is called with the
+//! transaction. A reservation dropped without a commit, like when the
+//! transaction rolls back, counts its ids as burned in `GeneratorStats::burned`
+//! and `Metrics::burned`. Reserved ids are never generated again either way.
+//!
 //! Also, time function is can be set.
 //! If you want to use plain seconds unit instead of millisedond, you can do by this:
 //!
@@ -113,9 +443,15 @@
 //! |Options| Default value|
 //! |---|---|
 //! |bits| 42=timestamp, 10=node, 12=sequence |
-//! |base\_ts|1483228800000 (2017-01-01T00:00:00Z as milliseonds)|
+//! |base\_ts|1483228800000 (2017-01-01T00:00:00Z as milliseonds); `base_time(time)` sets it from a `SystemTime`, or a `chrono::DateTime<Utc>` or `time::OffsetDateTime`, in the unit of `time_unit`|
 //! |node|0|
 //! |time\_fn|return current milliseonds|
+//! |overflow\_policy|WaitNextTick (wait for the next millisecond when the sequence is exhausted)|
+//! |max\_rate|none; `max_rate(10_000)` allows 10,000 ids per second with bursts of a second, failing beyond with `Error::RateLimited`, or waiting with `rate_limit_policy(RateLimitPolicy::Wait)`. The servers answer ids over the limit with errors, `429 Too Many Requests` over HTTP|
+//! |startup\_guard|none; `startup_guard(Duration::from_secs(1))` waits up to a second before the first id for the clock to pass the last timestamp of a previous run|
+//! |verify\_clock|off; `verify_clock(true)` fails generating until the clock is seen to advance and to be after `base_ts`, and with `sntp` feature `verify_clock_ntp("pool.ntp.org", Duration::from_millis(500))` also until it is within 500ms of the NTP server|
+//! |coarse\_clock|off; `coarse_clock(Duration::from_millis(1))` reads the clock on a background thread every millisecond instead of for every id|
+//! |time\_unit|1 millisecond; `time_unit(Duration::from_millis(10))` counts coarser ticks, with `base_ts` in the same unit|
 //!
 //! #### GeneratorPool
 //!
@@ -129,68 +465,770 @@
 //!
 //! All other options are same with Generator.
 //!
-//! ## TODO
+//! #### Loading from config files
+//!
+//! With `config` feature, `GeneratorOptions::from_toml(path)` and
+//! `GeneratorOptions::from_env()` read the options from a flat TOML file or
+//! from `FROSTFLAKE_*` environment variables, returning errors for invalid
+//! settings instead of panicking. `GeneratorPoolOptions` has the same
+//! constructors.
+//!
+//! With `serde` feature, `GeneratorOptions` and `GeneratorPoolOptions`
+//! implement `Deserialize`, reading `ts_bits`, `node_bits`, `seq_bits` (and
+//! `pool_bits` for pools), `base_ts`, `node` and `overflow_policy`. Missing
+//! fields take the default values, and invalid combinations are rejected.
+//!
+//! `Snowflake` implements `Serialize` and `Deserialize` too, as a string by
+//! default. Use `#[serde(with = "frostflake::serde_u64")]` to serialize it as a
+//! number.
+//!
+//! #### Metrics
+//!
+//! `GeneratorOptions::metrics` and `GeneratorPoolOptions::metrics` report
+//! generated ids, sequence rollovers, waits for the next tick, clock moving
+//! backwards, the queue depth of pools and the depth of buffers to a `Metrics`
+//! implementation. `metrics::AtomicMetrics` keeps them in counters and renders
+//! them in the Prometheus text format.
+//!
+//! #### Surviving restarts
+//!
+//! With `debug-dedupe` feature, every generator remembers its last 64k to 128k
+//! ids, behind a Bloom filter, and panics if it generates one of them again, to
+//! catch misconfigured clocks or restored state in tests and staging.
+//! Duplicates between generators, like two processes with the same node number,
+//! can't be seen by either of them; the issuance records of
+//! `GeneratorOptions::audit` show those after the fact.
+//!
+//! For monitoring, `Generator::last_id()` returns the id generated last and
+//! `Generator::peek_next_ts()` the timestamp the next id would carry, both
+//! without generating one, and `Generator::progress()` reads the two together.
+//! A next timestamp that stays ahead of the clock tells a generator is stalled.
+//! `GeneratorPool::progress()`, `GeneratorAsync::progress()` and
+//! `GeneratorPoolAsync::progress()` ask their workers for the same, to show
+//! per-worker progress on a dashboard.
+//!
+//! `Generator::snapshot()` returns the last timestamp and sequence number as a
+//! `GeneratorState`, which is serializable with `serde` feature, and
+//! `Generator::restore(opts, state)` continues after it, failing with
+//! `Error::ClockMovedBackwards` if the clock is behind the snapshot.
+//!
+//! `GeneratorOptions::state_backend` persists the last timestamp, so that a
+//! restarted generator issues no ids until the clock passes it, even if the
+//! host came back with its clock set back. `persist::FileState` checkpoints it
+//! to a file, a second ahead by default so the file is written only once a
+//! second, and `persist::StateBackend` can be implemented for other stores.
+//!
+//! ## C API
+//!
+//! With `ffi` feature, `frostflake_new`, `frostflake_generate`,
+//! `frostflake_generate_batch` and `frostflake_decode` are exported with the C
+//! ABI, for C, C++ or Python (via `ctypes` or `cffi`) code that needs ids of
+//! the same layout. Build a shared library with
+//! `cargo rustc --release --features ffi --crate-type cdylib`, and include
+//! `include/frostflake.h`.
+//!
+//! ## Automatic node id from Redis
+//!
+//! This requires `redis` feature.
+//!
+//! ```ignore
+//! use frostflake::{Generator, GeneratorOptions};
+//!
+//! let opts = GeneratorOptions::default()
+//!     .node_from_redis("redis://127.0.0.1:6379")
+//!     .unwrap();
+//! let generator = Generator::new(opts);
+//! ```
+//!
+//! A node id is leased with `SET NX` and kept alive by a heartbeat thread, like
+//! [katsubushi](https://github.com/kayac/go-katsubushi). Once the lease
+//! expires, generating fails with `LeaseExpired`.
+//!
+//! With `coordinator-etcd` feature, `node_from_etcd("http://127.0.0.1:2379")`
+//! leases the node id from etcd instead. The key is attached to an etcd lease
+//! kept alive by a heartbeat thread, and generating fails with `LeaseExpired`
+//! as soon as etcd reports the lease lost.
+//!
+//! For processes on a single host, the `shm` feature leases node ids through a
+//! memory-mapped file instead, like `node_from_shm("/dev/shm/frostflake")`,
+//! without a coordinator. A node id of a process that is gone is taken over by
+//! the next process, after a cool-down.
+//! `node_id::ShmNodeAllocator::new(path).host(host, process_bits)` claims only
+//! the low `process_bits` bits, after the node id of the host. Alternatively,
+//! `AtomicGenerator::shared(opts, path)` shares one timestamp and sequence
+//! between the processes, which then generate ids of a single node id together.
+//!
+//! With `k8s` feature, `NodeIdStrategy::StatefulSetOrdinal` and
+//! `NodeIdStrategy::PodIp` derive the node id from the pod name or the pod IP
+//! exposed by the downward API, for `GeneratorOptions::node_strategy`, so that
+//! pods need no node id set by hand.
+//!
+//! ## Segment ids
+//!
+//! `segment::SegmentGenerator` hands out plain ascending numbers from blocks
+//! leased from a `segment::SegmentStore`, like the segment mode of Leaf, for
+//! deployments preferring database allocated ids over time-based ones. The next
+//! block is fetched in the background once half of the current one is used.
+//! `MemorySegmentStore` and, with `redis` feature, `RedisSegmentStore` are
+//! included; a SQL database needs a small `SegmentStore` implementation on top
+//! of its driver.
+//!
+//! ## Memcached protocol server
+//!
+//! This requires `server` feature.
+//!
+//! `MemcachedServer` answers `get` and `gets` requests with fresh ids, like
+//! [katsubushi](https://github.com/kayac/go-katsubushi), so ids can be fetched
+//! with any memcache client. A multi-key get returns one id per key.
+//!
+//! ```ignore
+//! use frostflake::server::MemcachedServer;
+//! use frostflake::{GeneratorAsync, GeneratorOptions};
+//!
+//! let generator = GeneratorAsync::spawn(GeneratorOptions::default());
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:11212").await?;
+//! MemcachedServer::new(generator).serve(listener).await?;
+//! ```
+//!
+//! For monitoring, the `stats` command reports `pid`, `uptime`, `time`,
+//! `version`, `curr_connections`, `total_connections`, `cmd_get`, `get_hits`
+//! and `get_misses` like katsubushi, so its dashboards keep working. It then
+//! reports the node number, the layout, `ids_issued`, `queue_depth` and the
+//! clock anomaly counters of the generator. `GET /stats` of the HTTP server
+//! returns the same as JSON.
+//!
+//! ## HTTP server
+//!
+//! This requires `http-server` feature.
+//!
+//! `HttpServer` serves `GET /id`, `GET /ids?count=N`, `GET /decode/:id` and
+//! `GET /stats` with JSON responses, so frostflake can be deployed as a sidecar
+//! id service. Ids are returned as strings, as they do not fit in a double.
+//!
+//! ```ignore
+//! use frostflake::http::HttpServer;
+//! use frostflake::{GeneratorAsync, GeneratorOptions};
 //!
-//! - Support redis based automatic node_id generation like [katsubushi](https://github.com/kayac/go-katsubushi)
-//! - Support other async runtimes?
+//! let opts = GeneratorOptions::default();
+//! let layout = opts.layout();
+//! let generator = GeneratorAsync::spawn(opts);
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+//! HttpServer::new(generator, layout).serve(listener).await?;
+//! ```
+//!
+//! ## Command line
+//!
+//! With `cli` feature, the `frostflake` binary mints and inspects ids from scripts:
+//!
+//! ```sh
+//! frostflake generate --count 3 --layout 42,10,12 --node 5
+//! frostflake decode 1294990249151180800 --format json
+//! frostflake serve --node 5 --memcached 127.0.0.1:11212 --http 127.0.0.1:8080
+//! ```
+//!
+//! `serve` runs the memcached protocol server, the HTTP server or both on a
+//! single generator.
+//!
+//! ## TODO
 //!
 //! Patches or pull-requests are always welcome.# frostflake
 
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::u64::MAX;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+// generating must not panic outside of the documented panicking calls
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hasher, RandomState};
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "std")]
+use crate::audit::AuditSink;
+#[cfg(feature = "std")]
+use crate::combinators::Bucket;
+#[cfg(feature = "std")]
+use crate::diagnostics::UtilizationHook;
+#[cfg(feature = "std")]
+use crate::layout::CompiledLayout;
+#[cfg(feature = "std")]
+use crate::metrics::Metrics;
+#[cfg(feature = "std")]
+use crate::node_id::{NodeIdStrategy, NodeLease};
+#[cfg(feature = "std")]
+use crate::obfuscate::Scrambler;
+#[cfg(feature = "std")]
+use crate::persist::StateBackend;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+mod atomic;
+#[cfg(feature = "std")]
+pub mod audit;
+#[cfg(feature = "std")]
+mod backfill;
+#[cfg(feature = "std")]
+mod batch;
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+pub mod checksum;
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod combinators;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod cursor;
+#[cfg(feature = "std")]
+pub mod decode;
+#[cfg(feature = "debug-dedupe")]
+mod dedupe;
+#[cfg(feature = "std")]
+mod diagnostics;
+mod embedded;
+#[cfg(feature = "std")]
+pub mod encode;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+mod fields;
+#[cfg(feature = "std")]
+pub mod foreign;
+#[cfg(feature = "std")]
+pub mod id128;
+#[cfg(feature = "std")]
+mod layout;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "std")]
+pub mod node_id;
+pub mod obfuscate;
+#[cfg(feature = "std")]
+mod parallel;
+#[cfg(feature = "std")]
+pub mod persist;
+#[cfg(feature = "std")]
+mod rate_limit;
+#[cfg(feature = "std")]
+mod reserve;
+#[cfg(feature = "std")]
+mod scheduled;
+#[cfg(feature = "std")]
+pub mod segment;
+#[cfg(feature = "std")]
+mod shared;
+#[cfg(all(unix, feature = "shm"))]
+mod shm;
+#[cfg(feature = "std")]
+mod snowflake;
+#[cfg(feature = "std")]
+mod state;
+#[cfg(feature = "std")]
+pub mod testing;
+#[cfg(feature = "std")]
+mod thread_local;
+#[cfg(feature = "std")]
+mod timefmt;
+#[cfg(feature = "std")]
+mod traits;
+#[cfg(feature = "std")]
+mod typed;
+#[cfg(feature = "uuid")]
+mod uuid_v8;
+#[cfg(feature = "std")]
+mod verify;
 
 #[cfg(feature = "tokio")]
 pub mod tokio;
 
+#[cfg(feature = "affinity")]
+mod affinity;
+#[cfg(feature = "std-thread")]
+mod buffered;
 #[cfg(feature = "std-thread")]
 pub mod pool;
+#[cfg(any(feature = "tokio", feature = "std-thread"))]
+mod pool_options;
+
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(any(feature = "server", feature = "http-server"))]
+mod server_stats;
+
+#[cfg(feature = "http-server")]
+pub mod http;
 
 #[cfg(feature = "tokio")]
-pub use crate::tokio::GeneratorAsync;
+pub use crate::tokio::{GeneratorAsync, GeneratorPoolAsync, IdChunks, IdStream, TaskRestart};
+#[cfg(feature = "affinity")]
+pub use affinity::Affinity;
+#[cfg(feature = "std")]
+pub use atomic::AtomicGenerator;
+#[cfg(feature = "std-thread")]
+pub use buffered::BufferedGenerator;
+#[cfg(feature = "std")]
+pub use builder::GeneratorOptionsBuilder;
+pub use clock::TimeSource;
+#[cfg(feature = "std")]
+pub use diagnostics::{ClockHealth, Diagnostics, GeneratorStats, LeaseHealth, UtilizationAlert};
+pub use embedded::EmbeddedGenerator;
+pub use error::{Error, OptionsError};
+#[cfg(feature = "std")]
+pub use fields::{DecodedFields, FieldLayout, LayoutBuilder};
+#[cfg(feature = "std")]
+pub use layout::{Capacity, DecodedId, Layout};
+#[cfg(feature = "std")]
+pub use parallel::ParallelIdAssigner;
+#[cfg(all(feature = "std-thread", feature = "tokio"))]
+pub use pool::GeneratorPoolHandleAsync;
 #[cfg(feature = "std-thread")]
-pub use pool::{GeneratorPool, GeneratorPoolOptions};
+pub use pool::{GeneratorPool, Priority};
+#[cfg(any(feature = "tokio", feature = "std-thread"))]
+pub use pool_options::{GeneratorPoolOptions, PoolLayout};
+#[cfg(feature = "std")]
+pub use reserve::Reservation;
+#[cfg(feature = "std")]
+pub use shared::{BoxIdGenerator, SharedGenerator};
+#[cfg(feature = "serde")]
+pub use snowflake::serde_u64;
+#[cfg(feature = "std")]
+pub use snowflake::{DecomposedId, Snowflake};
+#[cfg(feature = "std")]
+pub use state::{GeneratorProgress, GeneratorState};
+#[cfg(feature = "std")]
+pub use thread_local::ThreadLocalGenerator;
+#[cfg(feature = "std")]
+pub use traits::{AsyncIdGenerator, BoxFuture, IdGenerator};
+#[cfg(feature = "std")]
+pub use typed::{Id, TypedGenerator};
 
+/// What to do when all sequence numbers of a tick are used.
+///
+/// With the `serde` feature, this is written as `wait_next_tick`, `error` or
+/// `panic`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OverflowPolicy {
+    /// Wait for the next tick and generate the id there.
+    #[default]
+    WaitNextTick,
+    /// Fail with [`Error::SequenceExhausted`].
+    Error,
+    /// Panic, even in `try_generate`.
+    Panic,
+}
+
+/// What to do when ids are asked for faster than `GeneratorOptions::max_rate`
+/// allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitPolicy {
+    /// Fail with [`Error::RateLimited`].
+    #[default]
+    Error,
+    /// Wait until the limit allows the id. `Generator` sleeps, and
+    /// `GeneratorAsync` yields to the executor meanwhile.
+    Wait,
+}
+
+// bits of the worker in the node number, as in Twitter's snowflake
+#[cfg(feature = "std")]
+const WORKER_BITS: u8 = 5;
+
+#[cfg(feature = "std")]
 #[derive(Clone)]
 pub struct GeneratorOptions {
     bits: (u8, u8, u8),
     base_ts: u64,
     node: u64,
-    time_fn: fn() -> u64,
+    overflow: OverflowPolicy,
+    // ids per second allowed, with the burst of a second
+    max_rate: Option<u64>,
+    rate_limit_policy: RateLimitPolicy,
+    clock: Arc<dyn TimeSource + Send + Sync>,
+    stall_timeout: Option<Duration>,
+    startup_guard: Option<Duration>,
+    verify_clock: bool,
+    // server and allowed offset of the NTP check of verify_clock
+    #[cfg(feature = "sntp")]
+    ntp_server: Option<(String, Duration)>,
+    max_backward_drift: u64,
+    randomize_seq_start: bool,
+    audit: Option<Arc<dyn AuditSink + Send + Sync>>,
+    utilization_alert: Option<(f64, Arc<UtilizationHook>)>,
+    state_backend: Option<Arc<dyn StateBackend + Send + Sync>>,
+    node_lease: Option<Arc<dyn NodeLease + Send + Sync>>,
+    metrics: Option<Arc<dyn Metrics + Send + Sync>>,
+    // milliseconds per unit of the time source, only used for the layout
+    tick_ms: u64,
+    // upper bits of the node field holding the entity type, set by bits4
+    type_bits: u8,
+    // bits below the entity type holding the shard of generate_for_key
+    shard_bits: u8,
+    fields: Option<FieldLayout>,
+    scrambler: Option<Scrambler>,
 }
 
+#[cfg(feature = "std")]
+/// Generates ids.
+///
+/// Generating takes `&self` and the generator is `Sync`, so it can be shared
+/// across threads in an `Arc` as is. Its state is kept behind an internal
+/// lock, which is only contended when several threads generate at once; see
+/// `AtomicGenerator` and `GeneratorPool` for that case.
 pub struct Generator {
+    core: Mutex<Core>,
+}
+
+#[cfg(feature = "std")]
+// state of a generator, behind the lock of Generator
+pub(crate) struct Core {
     opts: GeneratorOptions,
+    // masks of the layout and node of opts, which don't change
+    compiled: CompiledLayout,
+    // tokens of max_rate
+    rate_bucket: Option<Bucket>,
     last_ts: u64,
+    // real time when last_ts was first seen, tracked only with a stall timeout
+    last_ts_at: Option<Instant>,
     seq: u64,
+    // sequence number of the first id of last_ts
+    seq_start: u64,
+    // state of the random sequence starts
+    rng: u64,
+    // ids of last_ts are not reported to the audit sink yet
+    audit_pending: bool,
+    stats: GeneratorStats,
+    // last timestamp a utilization alert was raised for
+    alerted_ts: u64,
+    // ids issued by generate_not_before, per future timestamp
+    scheduled: BTreeMap<u64, u64>,
+    // ids issued by generate_at, per past timestamp
+    backfilled: BTreeMap<u64, u64>,
+    // the startup guard is done, or there is none
+    started: bool,
+    // end of the startup guard, set on the first id
+    startup_deadline: Option<Instant>,
+    // verify_clock passed, or is off
+    clock_verified: bool,
+    #[cfg(feature = "debug-dedupe")]
+    dedupe: dedupe::Dedupe,
 }
 
+#[cfg(feature = "std")]
 fn default_time_fn() -> u64 {
-    let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    // a clock before the epoch fails generating with TimeBeforeBaseTs
+    let t = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
     t.as_secs() * 1000 + (t.subsec_nanos() as u64) / 1000000
 }
 
+#[cfg(feature = "std")]
 impl Default for GeneratorOptions {
     fn default() -> Self {
         GeneratorOptions {
             bits: (42, 10, 12),
             base_ts: 1483228800000, // 2017-01-01T00:00:00Z as milliseconds
             node: 0,
-            time_fn: default_time_fn,
+            overflow: OverflowPolicy::default(),
+            max_rate: None,
+            rate_limit_policy: RateLimitPolicy::default(),
+            clock: Arc::new(default_time_fn as fn() -> u64),
+            stall_timeout: None,
+            startup_guard: None,
+            verify_clock: false,
+            #[cfg(feature = "sntp")]
+            ntp_server: None,
+            max_backward_drift: 0,
+            randomize_seq_start: false,
+            audit: None,
+            utilization_alert: None,
+            state_backend: None,
+            node_lease: None,
+            metrics: None,
+            tick_ms: 1,
+            type_bits: 0,
+            shard_bits: 0,
+            fields: None,
+            scrambler: None,
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl GeneratorOptions {
-    pub fn time_fn(mut self, time_fn: fn() -> u64) -> Self {
-        self.time_fn = time_fn;
+    pub fn layout(&self) -> Layout {
+        let (ts_bits, node_bits, seq_bits) = self.bits;
+        Layout::new(ts_bits, node_bits, seq_bits)
+            .with_base_ts(self.base_ts)
+            .with_tick_ms(self.tick_ms)
+    }
+
+    /// How long ids can be generated until the timestamp field is exhausted,
+    /// measured from the current time of `time_fn`. Zero once it is.
+    ///
+    /// ```rust
+    /// use frostflake::GeneratorOptions;
+    ///
+    /// let remaining = GeneratorOptions::default().lifetime_remaining();
+    /// assert!(remaining.as_secs() > 100 * 365 * 86400);
+    /// ```
+    pub fn lifetime_remaining(&self) -> Duration {
+        let end = self.base_ts.saturating_add(max(self.bits.0));
+        let ticks = end.saturating_sub(self.clock.now());
+        Duration::from_millis(ticks.saturating_mul(self.tick_ms))
+    }
+
+    // how long to wait for the clock to reach the tick `ts`, by
+    // `Core::next_tick_in`
+    pub(crate) fn wait_until(&self, ts: u64) -> Duration {
+        let ticks = ts.saturating_sub(self.clock.now());
+        let left = Duration::from_millis(ticks.saturating_mul(self.tick_ms));
+        match left > Duration::from_millis(1) {
+            true => Duration::from_millis(1),
+            false => Duration::ZERO,
+        }
+    }
+
+    /// When the timestamp field is exhausted and `try_generate` starts
+    /// returning `Error::EpochExhausted`, assuming `time_fn` counts from the
+    /// Unix epoch.
+    pub fn exhaustion_date(&self) -> SystemTime {
+        self.layout().capacity().exhausted_at
+    }
+
+    /// Counts timestamps in ticks of `unit` instead of milliseconds, like the
+    /// 10 millisecond ticks of Sonyflake, so that the timestamp bits last
+    /// longer at a coarser precision.
+    ///
+    /// This replaces the time function with the default clock scaled to
+    /// `unit`, and converts `base_ts` to the new unit; a `base_ts` or custom
+    /// `time_fn` set afterwards has to count in `unit` as well. Timestamps of
+    /// `extract` are in `unit`, while `Snowflake::datetime` and the
+    /// [`decode`](crate::decode) module convert them back to wall-clock time.
+    ///
+    /// ```rust
+    /// use frostflake::GeneratorOptions;
+    /// use std::time::Duration;
+    ///
+    /// let opts = GeneratorOptions::default().time_unit(Duration::from_secs(1));
+    /// assert_eq!(opts.layout().base_ts(), 1483228800);
+    /// ```
+    ///
+    /// Panics unless `unit` is a whole number of milliseconds.
+    pub fn time_unit(mut self, unit: Duration) -> Self {
+        let tick_ms = u64::try_from(unit.as_millis()).unwrap_or(u64::MAX);
+        assert!(
+            tick_ms > 0 && unit.subsec_nanos().is_multiple_of(1_000_000),
+            "time unit should be a whole number of milliseconds"
+        );
+
+        self.base_ts = self.base_ts.saturating_mul(self.tick_ms) / tick_ms;
+        self.tick_ms = tick_ms;
+        self.time_source(move || default_time_fn() / tick_ms)
+    }
+
+    pub fn time_fn(self, time_fn: fn() -> u64) -> Self {
+        self.time_source(time_fn)
+    }
+
+    /// Same as `time_fn`, for closures and time sources that are not plain
+    /// functions, see [`clock`](crate::clock).
+    pub fn time_source<T: TimeSource + Send + Sync + 'static>(mut self, clock: T) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Caches the time source set so far in a [`CoarseClock`] updated every
+    /// `interval`, trading accuracy of the timestamps for throughput.
+    ///
+    /// [`CoarseClock`]: crate::clock::CoarseClock
+    pub fn coarse_clock(self, interval: Duration) -> Self {
+        let clock = crate::clock::CoarseClock::new(self.clock.clone(), interval);
+        self.time_source(clock)
+    }
+
+    /// Sets what happens when all sequence numbers of a tick are used.
+    ///
+    /// By default, generating waits for the next tick.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
+    /// Limits generating to `ids_per_sec` ids per second on average, by a
+    /// token bucket holding a second of ids for bursts.
+    ///
+    /// This keeps a misbehaving client of a shared generator, like one of
+    /// the servers, from using up the sequence space of the others. Ids
+    /// beyond the limit follow the [`RateLimitPolicy`], failing with
+    /// [`Error::RateLimited`] by default. `generate_at` and
+    /// `generate_not_before` are not limited.
+    ///
+    /// Panics if `ids_per_sec` is 0.
+    pub fn max_rate(mut self, ids_per_sec: u64) -> Self {
+        assert!(ids_per_sec > 0, "max_rate should be positive");
+
+        self.max_rate = Some(ids_per_sec);
+        self
+    }
+
+    /// Sets what happens to ids beyond `max_rate`.
+    pub fn rate_limit_policy(mut self, policy: RateLimitPolicy) -> Self {
+        self.rate_limit_policy = policy;
+        self
+    }
+
+    /// Fails with [`Error::ClockStalled`] when `time_fn` returns the same
+    /// value for longer than `timeout` of real time.
+    ///
+    /// This catches frozen clocks and broken custom time functions, which
+    /// would otherwise show up as endless waits for the next tick. There is
+    /// no timeout by default. It should be well above the unit of `time_fn`.
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Waits up to `timeout` before the first id for the clock to pass the
+    /// timestamp the generator continues after, instead of failing with
+    /// [`Error::ClockMovedBackwards`].
+    ///
+    /// That timestamp is in the future when a restart came back with the
+    /// clock set back, and was loaded from the [`state_backend`] or given to
+    /// `Generator::resume`, e.g. from a handover. `Generator` sleeps, and
+    /// `GeneratorAsync` yields to the executor meanwhile. If the clock
+    /// doesn't catch up within `timeout`, generating fails as without the
+    /// guard. There is no guard by default.
+    ///
+    /// [`state_backend`]: GeneratorOptions::state_backend
+    pub fn startup_guard(mut self, timeout: Duration) -> Self {
+        self.startup_guard = Some(timeout);
+        self
+    }
+
+    /// Checks that the clock is sane before the first id, see
+    /// [`Generator::verify_clock`].
+    ///
+    /// Generating fails with the error of the check, like
+    /// [`Error::ClockStalled`] for a clock that doesn't advance, until the
+    /// check passes, instead of issuing ids with bogus timestamps. The check
+    /// is off by default.
+    pub fn verify_clock(mut self, enabled: bool) -> Self {
+        self.verify_clock = enabled;
+        self
+    }
+
+    /// Same as `verify_clock(true)`, also failing with
+    /// [`Error::ClockOffset`] if the clock is more than `max_offset` off the
+    /// time of the NTP server `server`, like `pool.ntp.org`, and with
+    /// [`Error::NtpUnavailable`] if the server doesn't answer.
+    ///
+    /// The clock is compared in milliseconds since the Unix epoch, so this
+    /// only makes sense for clocks counting from there, like the default.
+    #[cfg(feature = "sntp")]
+    pub fn verify_clock_ntp(mut self, server: &str, max_offset: Duration) -> Self {
+        self.ntp_server = Some((server.to_string(), max_offset));
+        self.verify_clock(true)
+    }
+
+    /// Waits for the clock to catch up when it moves backwards by up to
+    /// `ms`, instead of failing with [`Error::ClockMovedBackwards`].
+    ///
+    /// This rides out small steps back, like those of NTP adjustments, at the
+    /// cost of blocking generation for that long. Larger steps still fail.
+    /// The default is 0, failing on any step back. `ms` is in the unit of
+    /// `time_fn` if it is not milliseconds.
+    pub fn max_backward_drift(mut self, ms: u64) -> Self {
+        self.max_backward_drift = ms;
+        self
+    }
+
+    /// Starts the sequence of every tick at a random number instead of 0,
+    /// like Sonyflake and some UUIDv7 implementations do.
+    ///
+    /// With mostly one id per tick, the low bits of ids are otherwise nearly
+    /// always 0, which skews sharding by `id % n`. The start is within the
+    /// lower half of the sequence space, so ids stay unique and ascending,
+    /// but a tick holds as few as half of the ids in the worst case.
+    pub fn randomize_seq_start(mut self, enabled: bool) -> Self {
+        self.randomize_seq_start = enabled;
+        self
+    }
+
+    /// Calls `hook` once per tick when more than `threshold` of the sequence
+    /// space of that tick is used.
+    ///
+    /// `threshold` is a ratio between 0.0 and 1.0, e.g. 0.8 for 80%. This
+    /// tells that a node is running close to its capacity before ids run out
+    /// with `SequenceExhausted`, so that the pool size or the bit widths can
+    /// be adjusted in time.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let opts = GeneratorOptions::default().utilization_alert(0.8, |alert| {
+    ///     eprintln!("node {} used {}/{} ids", alert.node, alert.used, alert.capacity);
+    /// });
+    /// let generator = Generator::new(opts);
+    /// generator.generate();
+    /// ```
+    pub fn utilization_alert<F>(mut self, threshold: f64, hook: F) -> Self
+    where
+        F: Fn(UtilizationAlert) + Send + Sync + 'static,
+    {
+        assert!(
+            (0.0..=1.0).contains(&threshold),
+            "threshold should be between 0.0 and 1.0"
+        );
+
+        self.utilization_alert = Some((threshold, Arc::new(hook)));
+        self
+    }
+
+    /// Persists the high-water timestamp to `backend`, see
+    /// [`persist`](crate::persist).
+    pub fn state_backend<B: StateBackend + Send + Sync + 'static>(mut self, backend: B) -> Self {
+        self.state_backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Reports issued ids to `sink`, see [`audit`](crate::audit).
+    pub fn audit<A: AuditSink + Send + Sync + 'static>(mut self, sink: A) -> Self {
+        self.audit = Some(Arc::new(sink));
         self
     }
 
+    /// Reports generated ids and incidents to `metrics`, see
+    /// [`metrics`](crate::metrics).
+    pub fn metrics<M: Metrics + Send + Sync + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Sets the bit widths of the timestamp, node number and sequence.
+    ///
+    /// The node number and sequence can be 0bit, like `bits(52, 0, 12)` for
+    /// a single node spending all bits on time and sequence. The timestamp
+    /// needs at least 1bit.
     pub fn bits(mut self, ts_bits: u8, node_bits: u8, seq_bits: u8) -> Self {
         assert!(
-            64 == ts_bits + node_bits + seq_bits,
+            64 == ts_bits as u32 + node_bits as u32 + seq_bits as u32,
             "bits set should be total 64bit"
         );
+        assert!(ts_bits > 0, "ts_bits should be at least 1bit");
         assert!(
             self.base_ts <= max(ts_bits),
             "base_ts exceeds ts_bits limit, set base_ts first"
@@ -201,92 +1239,757 @@ impl GeneratorOptions {
         );
 
         self.bits = (ts_bits, node_bits, seq_bits);
+        self.type_bits = 0;
+        self.shard_bits = 0;
+        self.fields = None;
         self
     }
 
-    pub fn node(mut self, node: u64) -> Self {
-        assert!(
-            node <= max(self.bits.1),
-            "node number exceeds node_bits limit, set bit width first"
-        );
+    /// Sets the bit widths by a layout with named fields, generated by
+    /// `Generator::generate_with`, see [`LayoutBuilder`].
+    pub fn fields(self, fields: FieldLayout) -> Self {
+        let (ts_bits, field_bits, seq_bits) = fields.bits();
+        let mut opts = self.bits(ts_bits, field_bits, seq_bits);
+        opts.fields = Some(fields);
+        opts
+    }
 
-        self.node = node;
+    /// Sets the scrambler of the public ids of `Generator::generate_public`,
+    /// see [`obfuscate`](crate::obfuscate).
+    pub fn scrambler(mut self, scrambler: Scrambler) -> Self {
+        self.scrambler = Some(scrambler);
         self
     }
 
-    pub fn base_ts(mut self, base_ts: u64) -> Self {
+    /// Same as `bits`, with `type_bits` for an entity type between the
+    /// timestamp and the node number, stamped by `Generator::generate_for`.
+    ///
+    /// The type and the node number together take the place of the node
+    /// number of `bits`, so the ids are still ordinary ids to decoders that
+    /// don't know about the type. All types share the sequence of a tick.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let generator = Generator::new(GeneratorOptions::default().bits4(42, 4, 6, 12).node(3));
+    /// let id = generator.generate_for(5);
+    /// let (_, entity_type, node, _) = generator.extract4(id);
+    /// assert_eq!((entity_type, node), (5, 3));
+    /// ```
+    pub fn bits4(self, ts_bits: u8, type_bits: u8, node_bits: u8, seq_bits: u8) -> Self {
         assert!(
-            base_ts <= max(self.bits.0),
-            "base_ts exceeds ts_bits limit, set bit width first"
+            self.node <= max(node_bits),
+            "node number exceeeds node_bits limit, set node number first"
         );
 
-        self.base_ts = base_ts;
-        self
-    }
-}
-
-impl Generator {
-    pub fn new(opts: GeneratorOptions) -> Generator {
-        Generator {
-            opts,
-            last_ts: 0,
-            seq: 0,
-        }
+        let mut opts = self.bits(ts_bits, type_bits + node_bits, seq_bits);
+        opts.type_bits = type_bits;
+        opts
     }
 
-    pub fn generate(&mut self) -> u64 {
-        let now = (self.opts.time_fn)();
+    /// Reserves the upper `shard_bits` of the node number for a shard,
+    /// stamped by `Generator::generate_for_key`, so that the shard of a key
+    /// can be read from the ids generated for it. Set the bit widths first.
+    ///
+    /// Like the entity type of `bits4`, the shard and the rest of the node
+    /// number are the node field to decoders that don't know about shards.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let generator = Generator::new(GeneratorOptions::default().shard_bits(6).node(3));
+    /// let user_hash = 0x5eed;
+    /// let id = generator.generate_for_key(user_hash);
+    /// assert_eq!(generator.shard(id), user_hash % 64);
+    /// ```
+    pub fn shard_bits(mut self, shard_bits: u8) -> Self {
+        let node_bits = self.bits.1 - self.type_bits;
         assert!(
-            now > self.opts.base_ts,
-            "time_fn returned the time before base_ts"
+            shard_bits <= node_bits,
+            "shard_bits exceeds node_bits, set bit width first"
         );
         assert!(
-            now >= self.last_ts,
-            "clock moved backwards. check your NTP setup"
+            self.node <= max(node_bits - shard_bits),
+            "node number exceeds node_bits limit, set node number after shard bits"
         );
 
-        let elapsed = now - self.opts.base_ts;
+        self.shard_bits = shard_bits;
+        self
+    }
 
-        let seq = if now == self.last_ts { self.seq + 1 } else { 0 };
+    // bits of the node number, without the entity type and the shard
+    fn node_bits(&self) -> u8 {
+        self.bits.1 - self.type_bits - self.shard_bits
+    }
 
-        let (_, node_bits, seq_bits) = self.opts.bits;
+    // bits of the datacenter, above the worker within the node number
+    fn datacenter_bits(&self) -> u8 {
+        assert!(
+            self.node_bits() >= WORKER_BITS,
+            "node_bits should be at least 5 for a worker, set bit width first"
+        );
+        self.node_bits() - WORKER_BITS
+    }
 
-        assert!(seq <= max(seq_bits), "seq number exceeds seq_bits!");
+    // bits of the node number left below the numbers of `count` generators
+    // sharing these options, like the threads of a `ThreadLocalGenerator`
+    fn split_node_bits(&self, count: u64) -> Result<u8, OptionsError> {
+        let Some(last) = count.checked_sub(1) else {
+            return Err(OptionsError::ZeroCount { name: "generators" });
+        };
+        let split_bits = (64 - last.leading_zeros()) as u8;
+        let node_bits = self.node_bits();
+        if split_bits > node_bits {
+            return Err(OptionsError::TooManyThreads {
+                threads: count,
+                max: max(node_bits).saturating_add(1),
+            });
+        }
+        let node_bits = node_bits - split_bits;
+        if self.node > max(node_bits) {
+            return Err(OptionsError::NodeOutOfRange {
+                node: self.node,
+                max: max(node_bits),
+            });
+        }
+        Ok(node_bits)
+    }
 
-        let ts_mask = bitmask(node_bits + seq_bits);
-        let node_mask = bitmask(seq_bits) ^ ts_mask;
+    // options of generator `n`, numbered above `node_bits` from
+    // `split_node_bits`
+    fn split_node(&self, n: u64, node_bits: u8) -> GeneratorOptions {
+        let node = shl(n, node_bits) | self.node;
+        self.clone().node(node)
+    }
 
-        self.last_ts = now;
-        self.seq = seq;
+    pub fn node(mut self, node: u64) -> Self {
+        assert!(
+            node <= max(self.node_bits()),
+            "node number exceeds node_bits limit, set bit width first"
+        );
 
-        ((elapsed << (node_bits + seq_bits)) & ts_mask)
-            | ((self.opts.node << seq_bits) & node_mask)
-            | seq & max(seq_bits)
+        self.node = node;
+        self
     }
 
-    pub fn extract(&self, id: u64) -> (u64, u64, u64) {
-        let (ts_bits, node_bits, seq_bits) = self.opts.bits;
+    /// Sets the datacenter part of the node number, the bits above the 5bit
+    /// worker, as in Twitter's original snowflake.
+    ///
+    /// With [`twitter`](Self::twitter), `datacenter(dc).worker(w)` generates
+    /// the same ids as the Java snowflake with `datacenterId` `dc` and
+    /// `workerId` `w`.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let generator = Generator::new(GeneratorOptions::twitter().datacenter(2).worker(17));
+    /// let (_, datacenter, worker, _) = generator.extract_worker(generator.generate());
+    /// assert_eq!((datacenter, worker), (2, 17));
+    /// ```
+    ///
+    /// Panics if the node bits have no room for the 5bit worker, or the
+    /// datacenter exceeds the bits above it; set bit width first.
+    pub fn datacenter(mut self, datacenter: u64) -> Self {
+        assert!(
+            datacenter <= max(self.datacenter_bits()),
+            "datacenter exceeds datacenter bits limit, set bit width first"
+        );
+
+        self.node = (datacenter << WORKER_BITS) | (self.node & max(WORKER_BITS));
+        self
+    }
+
+    /// Sets the worker part of the node number, its lowest 5 bits, see
+    /// [`datacenter`](Self::datacenter).
+    ///
+    /// Panics if the node bits have no room for the 5bit worker, or the
+    /// worker exceeds it.
+    pub fn worker(mut self, worker: u64) -> Self {
+        assert!(
+            self.node_bits() >= WORKER_BITS,
+            "node_bits should be at least 5 for a worker, set bit width first"
+        );
+        assert!(worker <= max(WORKER_BITS), "worker exceeds 5bit limit");
+
+        self.node = (self.node & !max(WORKER_BITS)) | worker;
+        self
+    }
+
+    /// Derives the node number from the environment of the instance; see
+    /// [`NodeIdStrategy`] for the chance of collisions.
+    ///
+    /// Panics if the node number can't be derived, set bit width first.
+    pub fn node_strategy(self, strategy: NodeIdStrategy) -> Self {
+        let node = strategy
+            .node(self.node_bits())
+            .unwrap_or_else(|e| panic!("node number can't be derived by {:?}: {}", strategy, e));
+        self.node(node)
+    }
+
+    /// Takes the node number from `lease`, and fails with
+    /// [`Error::LeaseExpired`] once the lease is no longer valid.
+    pub fn node_lease<L: NodeLease + Send + Sync + 'static>(self, lease: L) -> Self {
+        let mut opts = self.node(lease.node());
+        opts.node_lease = Some(Arc::new(lease));
+        opts
+    }
+
+    /// Leases the node number from Redis with the default settings of
+    /// [`RedisNodeAllocator`](crate::node_id::RedisNodeAllocator), blocking
+    /// until a node number is free. Set the bit widths first.
+    #[cfg(feature = "redis")]
+    pub fn node_from_redis(self, url: &str) -> std::io::Result<Self> {
+        let lease = node_id::RedisNodeAllocator::new(url).acquire(max(self.node_bits()))?;
+        Ok(self.node_lease(lease))
+    }
+
+    /// Same as `node_from_redis`, with the default settings of
+    /// [`EtcdNodeAllocator`](crate::node_id::EtcdNodeAllocator).
+    #[cfg(feature = "coordinator-etcd")]
+    pub fn node_from_etcd(self, endpoint: &str) -> std::io::Result<Self> {
+        let lease = node_id::EtcdNodeAllocator::new(endpoint).acquire(max(self.node_bits()))?;
+        Ok(self.node_lease(lease))
+    }
+
+    /// Same as `node_from_redis`, leasing the node number from the other
+    /// processes of the host through the file at `path`, with the default
+    /// settings of [`ShmNodeAllocator`](crate::node_id::ShmNodeAllocator).
+    #[cfg(all(unix, feature = "shm"))]
+    pub fn node_from_shm<P: AsRef<std::path::Path>>(self, path: P) -> std::io::Result<Self> {
+        let lease = node_id::ShmNodeAllocator::new(path).acquire(max(self.node_bits()))?;
+        Ok(self.node_lease(lease))
+    }
+
+    pub fn base_ts(mut self, base_ts: u64) -> Self {
+        assert!(
+            base_ts <= max(self.bits.0),
+            "base_ts exceeds ts_bits limit, set bit width first"
+        );
+
+        self.base_ts = base_ts;
+        self
+    }
+
+    /// Same as `base_ts`, with the epoch as a point in time, converted to
+    /// milliseconds or to the ticks of `time_unit`, so set the unit first.
+    ///
+    /// `chrono::DateTime<Utc>` and `time::OffsetDateTime` convert into
+    /// `SystemTime`, and can be passed as they are.
+    ///
+    /// ```rust
+    /// use frostflake::GeneratorOptions;
+    /// use std::time::{Duration, UNIX_EPOCH};
+    ///
+    /// let epoch = UNIX_EPOCH + Duration::from_secs(1483228800);
+    /// let opts = GeneratorOptions::default().base_time(epoch);
+    /// assert_eq!(opts.layout().base_ts(), 1483228800000);
+    ///
+    /// let opts = GeneratorOptions::default()
+    ///     .time_unit(Duration::from_millis(10))
+    ///     .base_time(epoch);
+    /// assert_eq!(opts.layout().base_ts(), 148322880000);
+    /// ```
+    ///
+    /// Panics if the time is before 1970, or doesn't fit in `ts_bits` like
+    /// `base_ts`.
+    pub fn base_time<T: Into<SystemTime>>(self, time: T) -> Self {
+        let since_epoch = time
+            .into()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_else(|_| panic!("base time should not be before 1970"));
+        let ticks = since_epoch.as_millis() / self.tick_ms as u128;
+        self.base_ts(u64::try_from(ticks).unwrap_or(u64::MAX))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Generator {
+    pub fn new(opts: GeneratorOptions) -> Generator {
+        Generator {
+            core: Mutex::new(Core::new(opts)),
+        }
+    }
+
+    // a panic while holding the lock, e.g. in a custom time_fn, can not leave
+    // the state half updated, so a poisoned lock is fine to use
+    pub(crate) fn core(&self) -> MutexGuard<'_, Core> {
+        self.core.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn core_mut(&mut self) -> &mut Core {
+        self.core.get_mut().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Replaces the generator by a fresh one after generating panicked,
+    /// carrying the state over so that it never reissues ids.
+    #[cfg(any(feature = "tokio", feature = "std-thread"))]
+    pub(crate) fn restart(&mut self) {
+        let old = self.core_mut();
+        let mut restarted = Generator::new(old.opts.clone());
+        let new = restarted.core_mut();
+        new.last_ts = old.last_ts;
+        new.seq = old.seq;
+        new.seq_start = old.seq_start;
+        new.scheduled = std::mem::take(&mut old.scheduled);
+        new.backfilled = std::mem::take(&mut old.backfilled);
+        #[cfg(feature = "debug-dedupe")]
+        {
+            new.dedupe = std::mem::take(&mut old.dedupe);
+        }
+        new.stats = old.stats;
+        new.audit_pending = std::mem::take(&mut old.audit_pending);
+        new.started = old.started;
+        new.clock_verified = old.clock_verified;
+        new.rate_bucket = old.rate_bucket.take();
+        *self = restarted;
+    }
+
+    pub fn generate(&self) -> u64 {
+        self.try_generate().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate`, but returns an error instead of panicking.
+    ///
+    /// When the sequence is exhausted, this follows the
+    /// [`OverflowPolicy`] of the options.
+    ///
+    /// Waiting for the next tick, the startup guard or the clock to catch up
+    /// within `max_backward_drift` is done without holding the lock of the
+    /// generator, so other threads can use it meanwhile.
+    pub fn try_generate(&self) -> Result<u64, Error> {
+        let mut retry = false;
+        loop {
+            // the guard is dropped before waiting
+            let attempt = self.core().try_generate_or_wait(retry);
+            match attempt {
+                Attempt::Done(result) => return result,
+                Attempt::Wait(wait) => tick_wait(wait),
+            }
+            retry = true;
+        }
+    }
+
+    /// Same as `generate`, but returns the id as a [`Snowflake`] carrying the
+    /// layout of this generator.
+    pub fn generate_id(&self) -> Snowflake {
+        let id = self.generate();
+        Snowflake::with_layout(id, self.core().opts.layout())
+    }
+
+    pub fn extract(&self, id: u64) -> (u64, u64, u64) {
+        self.core().extract(id)
+    }
+
+    pub fn decompose(&self, id: Snowflake) -> DecomposedId {
+        self.extract(id.as_u64()).into()
+    }
+
+    /// Same as `extract`, with the fields named and the timestamp converted
+    /// to wall-clock time.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let generator = Generator::new(GeneratorOptions::default().node(3));
+    /// let decoded = generator.decode((123 << 22) | (3 << 12) | 7);
+    /// assert_eq!(decoded.timestamp_raw, 123);
+    /// assert_eq!(decoded.to_string(), "2017-01-01T00:00:00.123Z node=3 seq=7");
+    /// ```
+    pub fn decode(&self, id: u64) -> decode::Decoded {
+        decode::decode(&self.core().opts.layout(), id)
+    }
+
+    /// Same as `generate`, stamping `entity_type` into the type bits set by
+    /// `GeneratorOptions::bits4`.
+    ///
+    /// Panics if `entity_type` exceeds the type bits.
+    pub fn generate_for(&self, entity_type: u64) -> u64 {
+        self.try_generate_for(entity_type)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate_for`, but returns an error instead of panicking
+    /// when generating fails.
+    pub fn try_generate_for(&self, entity_type: u64) -> Result<u64, Error> {
+        let shift = {
+            let opts = &self.core().opts;
+            if entity_type > max(opts.type_bits) {
+                return Err(Error::InvalidOptions(OptionsError::TypeOutOfRange {
+                    entity_type,
+                    max: max(opts.type_bits),
+                }));
+            }
+            opts.bits.1 - opts.type_bits + opts.bits.2
+        };
+
+        let id = self.try_generate()?;
+        Ok(id | shl(entity_type, shift))
+    }
+
+    /// Same as `generate`, stamping the shard of `key_hash` into the shard
+    /// bits of `GeneratorOptions::shard_bits`.
+    ///
+    /// The shard is `key_hash` modulo 2^`shard_bits`, so ids of the same key
+    /// always carry the same shard. Pass a hash that stays the same across
+    /// processes and releases, unlike the one of `std::hash::DefaultHasher`.
+    pub fn generate_for_key(&self, key_hash: u64) -> u64 {
+        self.try_generate_for_key(key_hash)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate_for_key`, but returns an error instead of panicking.
+    pub fn try_generate_for_key(&self, key_hash: u64) -> Result<u64, Error> {
+        let (shard, shift) = {
+            let opts = &self.core().opts;
+            (
+                key_hash & max(opts.shard_bits),
+                opts.node_bits() + opts.bits.2,
+            )
+        };
+
+        let id = self.try_generate()?;
+        Ok(id | shl(shard, shift))
+    }
+
+    /// Shard of an id of `generate_for_key`, by the bits of
+    /// `GeneratorOptions::shard_bits`.
+    pub fn shard(&self, id: u64) -> u64 {
+        let (node_bits, shard_bits) = {
+            let opts = &self.core().opts;
+            (opts.node_bits(), opts.shard_bits)
+        };
+        shr(self.extract(id).1, node_bits) & max(shard_bits)
+    }
+
+    /// Same as `extract`, splitting the node number into the datacenter and
+    /// the worker of `GeneratorOptions::datacenter` and `worker`.
+    pub fn extract_worker(&self, id: u64) -> (u64, u64, u64, u64) {
+        let node_bits = self.core().opts.node_bits();
+        let (ts, node, seq) = self.extract(id);
+        let node = node & max(node_bits);
+        (ts, node >> WORKER_BITS, node & max(WORKER_BITS), seq)
+    }
+
+    /// Same as `extract`, splitting the node field into the entity type and
+    /// the node number, by the bits of `GeneratorOptions::bits4`.
+    pub fn extract4(&self, id: u64) -> (u64, u64, u64, u64) {
+        let (type_shift, node_bits) = {
+            let opts = &self.core().opts;
+            (opts.bits.1 - opts.type_bits, opts.node_bits())
+        };
+        let (ts, typenode, seq) = self.extract(id);
+        (
+            ts,
+            shr(typenode, type_shift),
+            typenode & max(node_bits),
+            seq,
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl Core {
+    fn new(opts: GeneratorOptions) -> Core {
+        let mut core = Core {
+            compiled: CompiledLayout::new(opts.bits, opts.node),
+            rate_bucket: opts.max_rate.map(|rate| Bucket::new(rate, rate)),
+            opts,
+            last_ts: 0,
+            last_ts_at: None,
+            seq: 0,
+            seq_start: 0,
+            rng: RandomState::new().build_hasher().finish(),
+            audit_pending: false,
+            stats: GeneratorStats::default(),
+            alerted_ts: 0,
+            scheduled: BTreeMap::new(),
+            backfilled: BTreeMap::new(),
+            started: false,
+            startup_deadline: None,
+            clock_verified: false,
+            #[cfg(feature = "debug-dedupe")]
+            dedupe: dedupe::Dedupe::default(),
+        };
+        core.clock_verified = !core.opts.verify_clock;
+        core.load_high_water();
+        core
+    }
+
+    pub(crate) fn try_generate(&mut self) -> Result<u64, Error> {
+        let mut retry = false;
+        loop {
+            match self.try_generate_or_wait(retry) {
+                Attempt::Done(result) => return result,
+                Attempt::Wait(wait) => tick_wait(wait),
+            }
+            retry = true;
+        }
+    }
+
+    /// Same as `try_generate`, but returns how long to wait instead of
+    /// waiting for the next tick, so that `Generator` can wait without
+    /// holding the lock. `retry` is set for the attempts after a wait, which
+    /// aren't counted again when the sequence is still exhausted.
+    pub(crate) fn try_generate_or_wait(&mut self, retry: bool) -> Attempt {
+        if let Err(e) = self.acquire(1) {
+            return Attempt::Done(Err(e));
+        }
+        let result = match self.next_attempt() {
+            Attempt::Done(result) => result,
+            Attempt::Wait(wait) => {
+                self.put_back(1);
+                return Attempt::Wait(wait);
+            }
+        };
+        if !retry {
+            self.record(&result);
+        }
+
+        if matches!(result, Err(Error::SequenceExhausted)) {
+            match self.opts.overflow {
+                OverflowPolicy::WaitNextTick => {
+                    self.put_back(1);
+                    if let (false, Some(metrics)) = (retry, &self.opts.metrics) {
+                        metrics.next_tick_wait();
+                    }
+                    return Attempt::Wait(self.next_tick_in());
+                }
+                OverflowPolicy::Error => {}
+                OverflowPolicy::Panic => panic!("{}", Error::SequenceExhausted),
+            }
+        } else if retry {
+            self.record(&result);
+        }
+        if result.is_err() {
+            self.put_back(1);
+        }
+        Attempt::Done(result)
+    }
+
+    // how long to wait for the clock to pass the tick of the last id: ticks
+    // of a millisecond are waited for by yielding, and longer ones in steps
+    // of a millisecond, since the clock doesn't tell how much of the current
+    // tick is left
+    fn next_tick_in(&self) -> Duration {
+        self.opts.wait_until(self.last_ts + 1)
+    }
+
+    /// Generates an id without waiting, regardless of the overflow policy.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn try_generate_once(&mut self) -> Result<u64, Error> {
+        let result = self.next_id();
+        self.record(&result);
+        result
+    }
+
+    /// Returns how long to wait for the clock to pass `last_ts` before the
+    /// first id, or `None` once the startup guard is over.
+    pub(crate) fn startup_wait(&mut self) -> Option<Duration> {
+        if self.started {
+            return None;
+        }
+        let Some(guard) = self.opts.startup_guard else {
+            self.started = true;
+            return None;
+        };
+
+        let now = self.opts.clock.now();
+        let deadline = *self
+            .startup_deadline
+            .get_or_insert_with(|| Instant::now() + guard);
+        let left = deadline.saturating_duration_since(Instant::now());
+        if now > self.last_ts || left.is_zero() {
+            self.started = true;
+            return None;
+        }
+        let ticks = self.last_ts - now + 1;
+        Some(Duration::from_millis(ticks.saturating_mul(self.opts.tick_ms)).min(left))
+    }
+
+    // waits out the startup guard and the `max_backward_drift` window with
+    // the lock held, for the callers generating in batches under it
+    fn next_id(&mut self) -> Result<u64, Error> {
+        loop {
+            match self.next_attempt() {
+                Attempt::Done(result) => return result,
+                Attempt::Wait(wait) => tick_wait(wait),
+            }
+        }
+    }
 
-        let ts = (id >> (node_bits + seq_bits)) & max(ts_bits);
-        let node = (id >> seq_bits) & max(node_bits);
-        let seq = id & max(seq_bits);
+    /// Same as `next_id`, but returns how long to wait instead of waiting
+    /// for the startup guard or the `max_backward_drift` window.
+    fn next_attempt(&mut self) -> Attempt {
+        if !self.clock_verified {
+            if let Err(e) = self.verify_clock() {
+                return Attempt::Done(Err(e));
+            }
+            self.clock_verified = true;
+        }
+        if let Some(wait) = self.startup_wait() {
+            return Attempt::Wait(wait);
+        }
 
-        (ts, node, seq)
+        let now = self.opts.clock.now();
+        if now < self.last_ts && self.last_ts - now <= self.opts.max_backward_drift {
+            return Attempt::Wait(self.opts.wait_until(self.last_ts));
+        }
+        Attempt::Done(self.next_id_at(now))
+    }
+
+    fn next_id_at(&mut self, now: u64) -> Result<u64, Error> {
+        if let Some(lease) = &self.opts.node_lease {
+            if !lease.is_valid() {
+                return Err(Error::LeaseExpired {
+                    node: self.opts.node,
+                });
+            }
+        }
+
+        if now <= self.opts.base_ts {
+            return Err(Error::TimeBeforeBaseTs {
+                now,
+                base_ts: self.opts.base_ts,
+            });
+        }
+        if now < self.last_ts {
+            return Err(Error::ClockMovedBackwards {
+                now,
+                last_ts: self.last_ts,
+            });
+        }
+
+        if let Some(timeout) = self.opts.stall_timeout {
+            if now != self.last_ts {
+                self.last_ts_at = Some(Instant::now());
+            } else if let Some(at) = self.last_ts_at {
+                let stalled_for = at.elapsed();
+                if stalled_for > timeout {
+                    return Err(Error::ClockStalled {
+                        ts: now,
+                        stalled_for,
+                    });
+                }
+            }
+        }
+
+        let elapsed = now - self.opts.base_ts;
+        if elapsed > self.compiled.max_ts {
+            return Err(Error::EpochExhausted { ts: now });
+        }
+
+        let seq = if now == self.last_ts {
+            self.seq + 1
+        } else {
+            self.next_seq_start()
+        };
+
+        let taken = self.scheduled_at(now) + self.backfilled_at(now);
+        if seq + taken > self.compiled.max_seq {
+            return Err(Error::SequenceExhausted);
+        }
+
+        if now != self.last_ts {
+            self.flush_audit();
+            if let Some(backend) = &self.opts.state_backend {
+                backend.store(now);
+            }
+            self.seq_start = seq;
+        }
+        self.check_utilization(now, seq);
+        self.last_ts = now;
+        self.seq = seq;
+        self.audit_pending = true;
+
+        Ok(self.compose(elapsed, seq))
+    }
+
+    /// First sequence number of a new tick, 0 unless `randomize_seq_start`.
+    fn next_seq_start(&mut self) -> u64 {
+        if !self.opts.randomize_seq_start {
+            return 0;
+        }
+
+        // splitmix64
+        self.rng = self.rng.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        (z ^ (z >> 31)) & max(self.opts.bits.2.saturating_sub(1))
+    }
+
+    /// Builds an id to be issued, checked for duplicates with
+    /// `debug-dedupe` feature.
+    #[inline]
+    fn compose(&mut self, elapsed: u64, seq: u64) -> u64 {
+        let id = self.id_of(elapsed, seq);
+        #[cfg(feature = "debug-dedupe")]
+        self.dedupe.check(id);
+        id
+    }
+
+    /// Id of the node of the options with the given timestamp and sequence.
+    #[inline]
+    pub(crate) fn id_of(&self, elapsed: u64, seq: u64) -> u64 {
+        self.compiled.compose(elapsed, seq)
+    }
+
+    fn extract(&self, id: u64) -> (u64, u64, u64) {
+        // a zero base_ts keeps the timestamp relative to the epoch
+        let d = self.opts.layout().with_base_ts(0).decode(id);
+        (d.timestamp, d.node, d.seq)
+    }
+}
+
+#[cfg(feature = "std")]
+/// Outcome of [`Core::try_generate_or_wait`].
+pub(crate) enum Attempt {
+    Done(Result<u64, Error>),
+    // the sequence is exhausted, or the clock is behind the last id; try
+    // again after waiting for this long
+    Wait(Duration),
+}
+
+#[cfg(feature = "std")]
+// waits for the next tick by `Core::next_tick_in`
+pub(crate) fn tick_wait(wait: Duration) {
+    match wait.is_zero() {
+        true => std::thread::yield_now(),
+        false => std::thread::sleep(wait),
     }
 }
 
-fn bitmask(shift: u8) -> u64 {
-    MAX << shift
+const fn bitmask(shift: u8) -> u64 {
+    shl(u64::MAX, shift)
 }
 
-fn max(shift: u8) -> u64 {
+const fn max(shift: u8) -> u64 {
     !bitmask(shift)
 }
 
-#[cfg(test)]
+// `value << shift`, but 0 instead of overflowing when a 0bit field leaves a
+// shift of 64
+const fn shl(value: u64, shift: u8) -> u64 {
+    match value.checked_shl(shift as u32) {
+        Some(shifted) => shifted,
+        None => 0,
+    }
+}
+
+// `value >> shift`, the same way
+const fn shr(value: u64, shift: u8) -> u64 {
+    match value.checked_shr(shift as u32) {
+        Some(shifted) => shifted,
+        None => 0,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
+    use crate::clock::VirtualClock;
     use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
     use std::sync::{Arc, Mutex};
     use std::thread;
 
@@ -298,12 +2001,272 @@ mod tests {
 
         let opts = GeneratorOptions::default().time_fn(my_time_fn);
 
-        let mut g = Generator::new(opts);
+        let g = Generator::new(opts);
         assert_eq!(g.generate(), (123 << 22));
         assert_eq!(g.generate(), (123 << 22) + 1);
         assert_eq!(g.generate(), (123 << 22) + 2);
     }
 
+    #[test]
+    fn test_try_generate() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 20, 2)
+            .base_ts(1483228800000)
+            .overflow_policy(OverflowPolicy::Error);
+
+        let g = Generator::new(opts);
+        for i in 0..4 {
+            assert_eq!(g.try_generate(), Ok((123 << 22) + i));
+        }
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+
+        let g = Generator::new(
+            GeneratorOptions::default()
+                .time_fn(my_time_fn)
+                .base_ts(1483228800000 + 123),
+        );
+        assert_eq!(
+            g.try_generate(),
+            Err(Error::TimeBeforeBaseTs {
+                now: 1483228800000 + 123,
+                base_ts: 1483228800000 + 123
+            })
+        );
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        fn my_time_fn() -> u64 {
+            1483228800000 + 1 + CALLS.fetch_add(1, Ordering::Relaxed) / 10
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 21, 1)
+            .base_ts(1483228800000);
+
+        // waits for the next tick by default
+        let g = Generator::new(opts);
+        let ids: Vec<u64> = (0..6).map(|_| g.generate()).collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_overflow_policy_waits_unlocked() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 123);
+        fn my_time_fn() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+
+        let g = Generator::new(
+            GeneratorOptions::default()
+                .time_fn(my_time_fn)
+                .bits(42, 21, 1),
+        );
+        assert_eq!(g.generate(), 123 << 22);
+        assert_eq!(g.generate(), (123 << 22) + 1);
+        std::thread::scope(|s| {
+            let waiting = s.spawn(|| g.generate());
+            std::thread::sleep(Duration::from_millis(20));
+            // the lock is free while waiting for the next tick
+            assert!((0..1000).any(|_| g.core.try_lock().is_ok()));
+            NOW.fetch_add(1, Ordering::Relaxed);
+            assert_eq!(waiting.join().unwrap(), 124 << 22);
+        });
+    }
+
+    #[test]
+    fn test_backward_drift_waits_unlocked() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 123);
+        fn my_time_fn() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+
+        let g = Generator::new(
+            GeneratorOptions::default()
+                .time_fn(my_time_fn)
+                .max_backward_drift(5),
+        );
+        assert_eq!(g.generate(), 123 << 22);
+        NOW.fetch_sub(3, Ordering::Relaxed);
+        std::thread::scope(|s| {
+            let waiting = s.spawn(|| g.generate());
+            std::thread::sleep(Duration::from_millis(20));
+            // the lock is free while the clock catches up
+            assert!((0..1000).any(|_| g.core.try_lock().is_ok()));
+            NOW.fetch_add(4, Ordering::Relaxed);
+            assert_eq!(waiting.join().unwrap(), 124 << 22);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "bits set should be total 64bit")]
+    fn test_bits_overflow() {
+        // sums to 64 when added in 8bit
+        let _ = GeneratorOptions::default().bits(200, 100, 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "seq number exceeds seq_bits!")]
+    fn test_overflow_policy_panic() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 21, 1)
+            .base_ts(1483228800000)
+            .overflow_policy(OverflowPolicy::Panic);
+        let g = Generator::new(opts);
+        for _ in 0..3 {
+            let _ = g.try_generate();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "clock moved backwards")]
+    fn test_clock_moved_backwards() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g = Generator::new(GeneratorOptions::default().time_fn(my_time_fn));
+        g.core().last_ts = 1483228800000 + 124;
+        g.generate();
+    }
+
+    #[test]
+    fn test_max_backward_drift() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 100);
+        fn my_time_fn() -> u64 {
+            NOW.fetch_add(1, Ordering::Relaxed)
+        }
+
+        let g = Generator::new(
+            GeneratorOptions::default()
+                .time_fn(my_time_fn)
+                .max_backward_drift(10),
+        );
+        let last_ts = NOW.load(Ordering::Relaxed) + 5;
+        g.core().last_ts = last_ts;
+        let (ts, _, _) = g.extract(g.try_generate().unwrap());
+        assert!(ts + 1483228800000 >= last_ts);
+
+        g.core().last_ts = NOW.load(Ordering::Relaxed) + 1000;
+        assert!(matches!(
+            g.try_generate(),
+            Err(Error::ClockMovedBackwards { .. })
+        ));
+    }
+
+    #[test]
+    fn test_randomize_seq_start() {
+        let clock = VirtualClock::new(1483228800000 + 1, 1);
+        let opts = GeneratorOptions::default()
+            .time_source(clock.clone())
+            .randomize_seq_start(true);
+        let g = Generator::new(opts);
+
+        // one id per tick
+        let mut starts = HashMap::new();
+        for _ in 0..1000 {
+            let (_, _, seq) = g.extract(g.generate());
+            assert!(seq < 2048);
+            *starts.entry(seq).or_insert(0) += 1;
+            clock.advance();
+        }
+        assert!(starts.len() > 500);
+
+        // ascending from the start within a tick
+        let first = g.generate();
+        let ids = g.generate_many(100);
+        assert_eq!(ids[0], first + 1);
+        assert!(ids.windows(2).all(|w| w[1] == w[0] + 1));
+    }
+
+    #[test]
+    fn test_startup_guard() {
+        let last_ts = default_time_fn() + 30;
+        let state = GeneratorState { last_ts, seq: 0 };
+
+        let opts = GeneratorOptions::default().startup_guard(Duration::from_secs(1));
+        let g = Generator::resume(opts, state);
+        let (ts, _, _) = g.extract(g.generate());
+        assert!(ts + GeneratorOptions::default().base_ts > last_ts);
+
+        let state = GeneratorState {
+            last_ts: default_time_fn() + 10_000,
+            seq: 0,
+        };
+        let opts = GeneratorOptions::default().startup_guard(Duration::from_millis(5));
+        let g = Generator::resume(opts, state);
+        assert!(matches!(
+            g.try_generate(),
+            Err(Error::ClockMovedBackwards { .. })
+        ));
+    }
+
+    #[test]
+    fn test_stall_timeout() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .stall_timeout(Duration::from_millis(10));
+        let g = Generator::new(opts);
+        assert_eq!(g.try_generate(), Ok(123 << 22));
+        assert_eq!(g.try_generate(), Ok((123 << 22) + 1));
+
+        std::thread::sleep(Duration::from_millis(20));
+        match g.try_generate() {
+            Err(Error::ClockStalled { ts, stalled_for }) => {
+                assert_eq!(ts, 1483228800000 + 123);
+                assert!(stalled_for >= Duration::from_millis(20));
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_node_lease() {
+        use crate::node_id::NodeLease;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        struct Lease(Arc<AtomicBool>);
+
+        impl NodeLease for Lease {
+            fn node(&self) -> u64 {
+                5
+            }
+
+            fn is_valid(&self) -> bool {
+                self.0.load(Ordering::SeqCst)
+            }
+        }
+
+        let valid = Arc::new(AtomicBool::new(true));
+        let g = Generator::new(GeneratorOptions::default().node_lease(Lease(valid.clone())));
+        let id = g.try_generate().unwrap();
+        assert_eq!(g.extract(id).1, 5);
+
+        valid.store(false, Ordering::SeqCst);
+        assert_eq!(g.try_generate(), Err(Error::LeaseExpired { node: 5 }));
+    }
+
     #[test]
     fn test_extract() {
         fn my_time_fn() -> u64 {
@@ -312,7 +2275,7 @@ mod tests {
 
         let opts = GeneratorOptions::default().time_fn(my_time_fn).node(3);
 
-        let mut g = Generator::new(opts);
+        let g = Generator::new(opts);
 
         let id = g.generate();
         let (ts, node, seq) = g.extract(id);
@@ -325,6 +2288,241 @@ mod tests {
         assert_eq!(ts, 123);
         assert_eq!(node, 3);
         assert_eq!(seq, 1);
+
+        let decoded = g.decode(id);
+        assert_eq!(
+            (decoded.timestamp_raw, decoded.node, decoded.seq),
+            (123, 3, 1)
+        );
+        assert_eq!(decoded.unix_ms, 1483228800000 + 123);
+        assert_eq!(decoded.to_string(), "2017-01-01T00:00:00.123Z node=3 seq=1");
+    }
+
+    #[test]
+    fn test_bits4() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .bits4(42, 4, 6, 12)
+            .node(63);
+        let g = Generator::new(opts);
+
+        let id = g.generate_for(15);
+        assert_eq!(id, (123 << 22) | (15 << 18) | (63 << 12));
+        assert_eq!(g.extract4(id), (123, 15, 63, 0));
+        // the type and node number are the node field to everything else
+        assert_eq!(g.extract(id), (123, (15 << 6) | 63, 0));
+
+        let id = g.generate();
+        assert_eq!(g.extract4(id), (123, 0, 63, 1));
+
+        assert_eq!(
+            g.try_generate_for(16),
+            Err(Error::InvalidOptions(OptionsError::TypeOutOfRange {
+                entity_type: 16,
+                max: 15
+            }))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "node number exceeds node_bits limit")]
+    fn test_bits4_node_crash() {
+        let _ = GeneratorOptions::default().bits4(42, 4, 6, 12).node(64);
+    }
+
+    #[test]
+    #[should_panic(expected = "entity type exceeds type_bits limit")]
+    fn test_generate_for_crash() {
+        let g = Generator::new(GeneratorOptions::default().bits4(42, 4, 6, 12));
+        g.generate_for(16);
+    }
+
+    #[test]
+    fn test_generate_for_key() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .shard_bits(6)
+            .node(15);
+        let g = Generator::new(opts);
+        let id = g.generate_for_key(0x5eed);
+        assert_eq!(id, (123 << 22) | ((0x5eed & 63) << 16) | (15 << 12));
+        assert_eq!(g.shard(id), 0x5eed & 63);
+        // the shard is part of the node field to everything else
+        assert_eq!(g.extract(id), (123, ((0x5eed & 63) << 4) | 15, 0));
+        assert_eq!(g.shard(g.generate()), 0);
+
+        // between the entity type and the node number
+        let opts = GeneratorOptions::default()
+            .bits4(42, 2, 8, 12)
+            .shard_bits(3)
+            .node(31);
+        let g = Generator::new(opts);
+        let id = g.generate_for(3);
+        assert_eq!(g.extract4(id).1, 3);
+        assert_eq!(g.extract4(id).2, 31);
+        assert_eq!(g.shard(id | (5 << 17)), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "node number exceeds node_bits limit")]
+    fn test_shard_bits_crash() {
+        let _ = GeneratorOptions::default().node(16).shard_bits(6);
+    }
+
+    #[test]
+    fn test_datacenter_worker() {
+        fn my_time_fn() -> u64 {
+            1600000000000
+        }
+
+        // an id of Twitter's snowflake with datacenterId 3 and workerId 17
+        let opts = GeneratorOptions::twitter()
+            .time_fn(my_time_fn)
+            .worker(17)
+            .datacenter(3);
+        let g = Generator::new(opts);
+        let id = g.generate();
+        assert_eq!(
+            id,
+            ((1600000000000 - 1288834974657) << 22) | (3 << 17) | (17 << 12)
+        );
+        assert_eq!(
+            g.extract_worker(id),
+            (1600000000000 - 1288834974657, 3, 17, 0)
+        );
+
+        let opts = GeneratorOptions::default()
+            .bits4(42, 2, 8, 12)
+            .datacenter(7)
+            .worker(31);
+        let g = Generator::new(opts);
+        let (_, datacenter, worker, _) = g.extract_worker(g.generate_for(3));
+        assert_eq!((datacenter, worker), (7, 31));
+    }
+
+    #[test]
+    #[should_panic(expected = "datacenter exceeds datacenter bits limit")]
+    fn test_datacenter_crash() {
+        let _ = GeneratorOptions::twitter().datacenter(32);
+    }
+
+    #[test]
+    #[should_panic(expected = "worker exceeds 5bit limit")]
+    fn test_worker_crash() {
+        let _ = GeneratorOptions::twitter().worker(32);
+    }
+
+    #[test]
+    fn test_decompose() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default().time_fn(my_time_fn).node(3);
+
+        let g = Generator::new(opts);
+
+        let id = Snowflake::from(g.generate());
+        let d = g.decompose(id);
+        assert_eq!(d.timestamp, 123);
+        assert_eq!(d.node, 3);
+        assert_eq!(d.seq, 0);
+    }
+
+    #[test]
+    fn test_epoch_exhausted() {
+        fn last_tick() -> u64 {
+            1483228800000 + (1 << 42) - 1
+        }
+        fn exhausted() -> u64 {
+            1483228800000 + (1 << 42)
+        }
+
+        let opts = GeneratorOptions::default().time_fn(last_tick);
+        assert_eq!(opts.lifetime_remaining(), Duration::ZERO);
+        assert_eq!(
+            opts.exhaustion_date(),
+            UNIX_EPOCH + Duration::from_millis(last_tick())
+        );
+        let g = Generator::new(opts);
+        assert_eq!(g.extract(g.try_generate().unwrap()).0, (1 << 42) - 1);
+
+        let g = Generator::new(GeneratorOptions::default().time_fn(exhausted));
+        assert_eq!(
+            g.try_generate(),
+            Err(Error::EpochExhausted { ts: exhausted() })
+        );
+    }
+
+    #[test]
+    fn test_lifetime_remaining() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + (1 << 42) - 1 - 1000
+        }
+
+        let opts = GeneratorOptions::default().time_fn(my_time_fn);
+        assert_eq!(opts.lifetime_remaining(), Duration::from_secs(1));
+
+        let opts = opts
+            .time_unit(Duration::from_secs(1))
+            .time_fn(|| 1483228800);
+        assert_eq!(
+            opts.lifetime_remaining(),
+            Duration::from_secs((1 << 42) - 1)
+        );
+    }
+
+    #[test]
+    fn test_time_unit() {
+        let opts = GeneratorOptions::default().time_unit(Duration::from_secs(1));
+        assert_eq!(opts.layout().base_ts(), 1483228800);
+        assert_eq!(opts.layout().tick_ms(), 1000);
+
+        let g = Generator::new(opts);
+        let id = g.generate_id();
+        let (ts, _, _) = g.extract(id.as_u64());
+        assert!(default_time_fn() / 1000 - 1483228800 - ts <= 1);
+
+        let age = SystemTime::now().duration_since(id.datetime()).unwrap();
+        assert!(age < Duration::from_secs(2));
+        assert_eq!(
+            crate::decode::decode(&id.layout(), id.as_u64()).datetime(),
+            id.datetime()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "time unit should be a whole number of milliseconds")]
+    fn test_time_unit_crash() {
+        let _ = GeneratorOptions::default().time_unit(Duration::from_micros(1500));
+    }
+
+    #[test]
+    fn test_base_time() {
+        let epoch = UNIX_EPOCH + Duration::from_millis(1483228800123);
+        let opts = GeneratorOptions::default().base_time(epoch);
+        assert_eq!(opts.layout().base_ts(), 1483228800123);
+
+        // truncated to the ticks
+        let opts = GeneratorOptions::default()
+            .time_unit(Duration::from_secs(1))
+            .base_time(epoch);
+        assert_eq!(opts.layout().base_ts(), 1483228800);
+    }
+
+    #[test]
+    #[should_panic(expected = "base_ts exceeds ts_bits limit")]
+    fn test_base_time_crash() {
+        // in 2109, after the end of 42bit milliseconds
+        let _ = GeneratorOptions::default().base_time(UNIX_EPOCH + Duration::from_millis(1 << 42));
     }
 
     #[test]
@@ -332,6 +2530,7 @@ mod tests {
         assert_eq!(bitmask(1), 0xFFFFFFFFFFFFFFFE);
         assert_eq!(bitmask(4), 0xFFFFFFFFFFFFFFF0);
         assert_eq!(bitmask(8), 0xFFFFFFFFFFFFFF00);
+        assert_eq!(bitmask(64), 0);
     }
 
     #[test]
@@ -339,11 +2538,12 @@ mod tests {
         assert_eq!(max(1), 1);
         assert_eq!(max(2), 3);
         assert_eq!(max(8), 255);
+        assert_eq!(max(64), u64::MAX);
     }
 
     #[test]
     fn test_threaded() {
-        let g = Arc::new(Mutex::new(Generator::new(GeneratorOptions::default())));
+        let g = Arc::new(Generator::new(GeneratorOptions::default()));
         let mut h = vec![];
         let results = Arc::new(Mutex::new(vec![]));
 
@@ -353,9 +2553,8 @@ mod tests {
 
             let handle = thread::spawn(move || {
                 for _ in 1..100 {
-                    let mut g = g.lock().unwrap();
-                    let mut r = r.lock().unwrap();
-                    r.push(g.generate());
+                    let id = g.try_generate().unwrap();
+                    r.lock().unwrap().push(id);
                 }
             });
             h.push(handle);
@@ -368,9 +2567,175 @@ mod tests {
         let mut hash: HashMap<u64, u64> = HashMap::new();
         for r in results.lock().unwrap().iter() {
             // check uniqueness
-            assert_eq!(hash.contains_key(r), false);
+            assert!(!hash.contains_key(r));
             hash.insert(*r, 1);
-            assert_eq!(hash.contains_key(r), true);
+            assert!(hash.contains_key(r));
+        }
+    }
+
+    #[test]
+    fn test_zero_width_fields() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        // a single node spending all bits on time and sequence
+        let opts = GeneratorOptions::default()
+            .bits(52, 0, 12)
+            .time_fn(my_time_fn)
+            .overflow_policy(OverflowPolicy::Error);
+        let g = Generator::new(opts.clone());
+        assert_eq!(g.generate(), (123 << 12));
+        assert_eq!(g.extract(g.generate()), (123, 0, 1));
+        let g = AtomicGenerator::new(opts);
+        assert_eq!(g.generate(), (123 << 12));
+        let mut g = EmbeddedGenerator::new(my_time_fn).bits(52, 0, 12);
+        assert_eq!(g.generate(), (123 << 12));
+
+        // one id per tick
+        let opts = GeneratorOptions::default()
+            .bits(54, 10, 0)
+            .node(1023)
+            .time_fn(my_time_fn)
+            .overflow_policy(OverflowPolicy::Error);
+        let g = Generator::new(opts.clone());
+        assert_eq!(g.generate(), (123 << 10) | 1023);
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+        let g = AtomicGenerator::new(opts);
+        assert_eq!(g.generate(), (123 << 10) | 1023);
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+        let mut g = EmbeddedGenerator::new(my_time_fn)
+            .bits(54, 10, 0)
+            .node(1023)
+            .overflow_policy(OverflowPolicy::Error);
+        assert_eq!(g.generate(), (123 << 10) | 1023);
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+    }
+
+    #[test]
+    #[should_panic(expected = "ts_bits should be at least 1bit")]
+    fn test_zero_ts_bits_crash() {
+        let _ = GeneratorOptions::default().base_ts(0).bits(0, 52, 12);
+    }
+
+    /// splitmix64, for arbitrary but reproducible test cases without a
+    /// property testing crate
+    struct Cases(u64);
+
+    impl Cases {
+        fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        }
+
+        /// A value in `range`, preferring its ends, where masks go wrong.
+        fn within(&mut self, range: std::ops::RangeInclusive<u64>) -> u64 {
+            let (start, end) = (*range.start(), *range.end());
+            match self.next() % 8 {
+                0 => start,
+                1 => end,
+                _ => match (end - start).checked_add(1) {
+                    Some(len) => start + self.next() % len,
+                    None => self.next(),
+                },
+            }
+        }
+
+        /// Bit widths adding up to 64, with a timestamp of at least 1bit, as
+        /// no id can be generated at `base_ts` itself.
+        fn bits(&mut self) -> (u8, u8, u8) {
+            let ts_bits = self.within(1..=64) as u8;
+            let node_bits = self.within(0..=(64 - ts_bits) as u64) as u8;
+            (ts_bits, node_bits, 64 - ts_bits - node_bits)
+        }
+    }
+
+    #[test]
+    fn test_layout_round_trip() {
+        let mut cases = Cases(1);
+        for _ in 0..10_000 {
+            let (ts_bits, node_bits, seq_bits) = cases.bits();
+            let node = cases.within(0..=max(node_bits));
+            let elapsed = cases.within(1..=max(ts_bits));
+
+            let clock = VirtualClock::new(elapsed, 1);
+            let opts = GeneratorOptions::default()
+                .base_ts(0)
+                .bits(ts_bits, node_bits, seq_bits)
+                .node(node)
+                .time_source(clock.clone())
+                .overflow_policy(OverflowPolicy::Error);
+            let g = Generator::new(opts);
+
+            let case = (ts_bits, node_bits, seq_bits, node, elapsed);
+            let id = g.generate();
+            assert_eq!(g.extract(id), (elapsed, node, 0), "{:?}", case);
+            let layout = Layout::new(ts_bits, node_bits, seq_bits).with_base_ts(0);
+            assert_eq!(layout.compose(elapsed, node, 0), id, "{:?}", case);
+
+            match g.try_generate() {
+                Ok(id) => assert_eq!(g.extract(id), (elapsed, node, 1), "{:?}", case),
+                Err(e) => {
+                    assert_eq!(seq_bits, 0, "{:?}", case);
+                    assert_eq!(e, Error::SequenceExhausted);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_layout_field_bleed() {
+        let mut cases = Cases(2);
+        for _ in 0..10_000 {
+            let (ts_bits, node_bits, seq_bits) = cases.bits();
+            let layout = Layout::new(ts_bits, node_bits, seq_bits).with_base_ts(0);
+            let case = (ts_bits, node_bits, seq_bits);
+
+            // every field only sets its own bits, however large the value
+            let ts = layout.compose(u64::MAX, 0, 0);
+            let node = layout.compose(0, u64::MAX, 0);
+            let seq = layout.compose(0, 0, u64::MAX);
+            assert_eq!(ts.count_ones(), ts_bits as u32, "{:?}", case);
+            assert_eq!(node.count_ones(), node_bits as u32, "{:?}", case);
+            assert_eq!(seq.count_ones(), seq_bits as u32, "{:?}", case);
+            assert_eq!(ts | node | seq, u64::MAX, "{:?}", case);
+            assert_eq!(ts & node | node & seq | seq & ts, 0, "{:?}", case);
+
+            let fields = (
+                cases.next() & max(ts_bits),
+                cases.next() & max(node_bits),
+                cases.next() & max(seq_bits),
+            );
+            let decoded = layout.decode(layout.compose(fields.0, fields.1, fields.2));
+            assert_eq!(
+                (decoded.timestamp, decoded.node, decoded.seq),
+                fields,
+                "{:?}",
+                case
+            );
+        }
+    }
+
+    #[test]
+    fn test_layout_ordering() {
+        let mut cases = Cases(3);
+        for _ in 0..10_000 {
+            let (ts_bits, node_bits, seq_bits) = cases.bits();
+            let layout = Layout::new(ts_bits, node_bits, seq_bits).with_base_ts(0);
+            let earlier = cases.within(0..=max(ts_bits) - 1);
+            let later = cases.within(earlier + 1..=max(ts_bits));
+
+            // any node and sequence of a later tick sorts after the earlier one
+            let a = layout.compose(earlier, u64::MAX, u64::MAX);
+            let b = layout.compose(later, 0, 0);
+            assert!(
+                a < b,
+                "{:?}",
+                (ts_bits, node_bits, seq_bits, earlier, later)
+            );
         }
     }
 }
@@ -0,0 +1,335 @@
+//! Counters for graphing generator health.
+//!
+//! A [`Metrics`] set through `GeneratorOptions::metrics` or
+//! `GeneratorPoolOptions::metrics` is told about every generated id and every
+//! incident as it happens. [`AtomicMetrics`] keeps plain counters and renders
+//! them in the Prometheus text format, for a scrape endpoint:
+//!
+//! ```rust
+//! use std::sync::Arc;
+//!
+//! use frostflake::metrics::AtomicMetrics;
+//! use frostflake::{Generator, GeneratorOptions};
+//!
+//! let metrics = Arc::new(AtomicMetrics::default());
+//! let generator = Generator::new(GeneratorOptions::default().metrics(metrics.clone()));
+//! generator.generate();
+//!
+//! assert_eq!(metrics.ids_generated(), 1);
+//! assert!(metrics.prometheus("frostflake").contains("frostflake_ids_generated_total 1"));
+//! ```
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::{Core, Error};
+
+/// Receives events of generators and pools.
+///
+/// All methods do nothing by default, so an implementation only picks the
+/// events it cares about. They are called while generating, so they should
+/// be cheap and must not block.
+pub trait Metrics {
+    /// `n` ids were generated.
+    fn generated(&self, n: u64) {
+        let _ = n;
+    }
+
+    /// `n` reserved ids were dropped without being committed, see
+    /// `Generator::reserve`.
+    fn burned(&self, n: u64) {
+        let _ = n;
+    }
+
+    /// All sequence numbers of a tick were used.
+    fn sequence_rollover(&self) {}
+
+    /// A generator waits for the next tick, following
+    /// `OverflowPolicy::WaitNextTick`.
+    fn next_tick_wait(&self) {}
+
+    /// The time source returned a time before the last id.
+    fn clock_backwards(&self) {}
+
+    /// A request was queued at a worker of a pool or at an async generator,
+    /// which now has `depth` requests waiting.
+    fn queued(&self, depth: usize) {
+        let _ = depth;
+    }
+
+    /// An id was taken from a `BufferedGenerator`, which now has `depth` ids
+    /// ready.
+    fn buffered(&self, depth: usize) {
+        let _ = depth;
+    }
+}
+
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+    fn generated(&self, n: u64) {
+        (**self).generated(n)
+    }
+
+    fn burned(&self, n: u64) {
+        (**self).burned(n)
+    }
+
+    fn sequence_rollover(&self) {
+        (**self).sequence_rollover()
+    }
+
+    fn next_tick_wait(&self) {
+        (**self).next_tick_wait()
+    }
+
+    fn clock_backwards(&self) {
+        (**self).clock_backwards()
+    }
+
+    fn queued(&self, depth: usize) {
+        (**self).queued(depth)
+    }
+
+    fn buffered(&self, depth: usize) {
+        (**self).buffered(depth)
+    }
+}
+
+/// [`Metrics`] kept in atomic counters.
+///
+/// The queue and buffer depths are gauges holding the last reported depth.
+#[derive(Debug, Default)]
+pub struct AtomicMetrics {
+    ids_generated: AtomicU64,
+    ids_burned: AtomicU64,
+    sequence_rollovers: AtomicU64,
+    next_tick_waits: AtomicU64,
+    clock_moved_backwards: AtomicU64,
+    queue_depth: AtomicUsize,
+    buffer_depth: AtomicUsize,
+}
+
+impl AtomicMetrics {
+    pub fn ids_generated(&self) -> u64 {
+        self.ids_generated.load(Ordering::Relaxed)
+    }
+
+    pub fn ids_burned(&self) -> u64 {
+        self.ids_burned.load(Ordering::Relaxed)
+    }
+
+    pub fn sequence_rollovers(&self) -> u64 {
+        self.sequence_rollovers.load(Ordering::Relaxed)
+    }
+
+    pub fn next_tick_waits(&self) -> u64 {
+        self.next_tick_waits.load(Ordering::Relaxed)
+    }
+
+    pub fn clock_moved_backwards(&self) -> u64 {
+        self.clock_moved_backwards.load(Ordering::Relaxed)
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn buffer_depth(&self) -> usize {
+        self.buffer_depth.load(Ordering::Relaxed)
+    }
+
+    /// Renders the counters in the Prometheus text exposition format, with
+    /// metric names starting with `prefix`.
+    pub fn prometheus(&self, prefix: &str) -> String {
+        let metrics = [
+            (
+                "ids_generated_total",
+                "counter",
+                "Ids generated.",
+                self.ids_generated(),
+            ),
+            (
+                "ids_burned_total",
+                "counter",
+                "Reserved ids dropped without being committed.",
+                self.ids_burned(),
+            ),
+            (
+                "sequence_rollovers_total",
+                "counter",
+                "Ticks whose sequence numbers were all used.",
+                self.sequence_rollovers(),
+            ),
+            (
+                "next_tick_waits_total",
+                "counter",
+                "Waits for the next tick after the sequence was exhausted.",
+                self.next_tick_waits(),
+            ),
+            (
+                "clock_moved_backwards_total",
+                "counter",
+                "Times the clock returned a time before the last id.",
+                self.clock_moved_backwards(),
+            ),
+            (
+                "queue_depth",
+                "gauge",
+                "Requests waiting at the worker last queued at.",
+                self.queue_depth() as u64,
+            ),
+            (
+                "buffer_depth",
+                "gauge",
+                "Ids ready in the buffer last taken from.",
+                self.buffer_depth() as u64,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {}_{} {}", prefix, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} {}", prefix, name, kind);
+            let _ = writeln!(out, "{}_{} {}", prefix, name, value);
+        }
+        out
+    }
+}
+
+impl Metrics for AtomicMetrics {
+    fn generated(&self, n: u64) {
+        self.ids_generated.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn burned(&self, n: u64) {
+        self.ids_burned.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn sequence_rollover(&self) {
+        self.sequence_rollovers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn next_tick_wait(&self) {
+        self.next_tick_waits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn clock_backwards(&self) {
+        self.clock_moved_backwards.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn queued(&self, depth: usize) {
+        self.queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    fn buffered(&self, depth: usize) {
+        self.buffer_depth.store(depth, Ordering::Relaxed);
+    }
+}
+
+impl Core {
+    /// Counts the outcome of generating an id.
+    pub(crate) fn record(&mut self, result: &Result<u64, Error>) {
+        self.stats.record(result);
+        let Some(metrics) = &self.opts.metrics else {
+            return;
+        };
+        match result {
+            Ok(_) => metrics.generated(1),
+            Err(Error::SequenceExhausted) => metrics.sequence_rollover(),
+            Err(Error::ClockMovedBackwards { .. }) => metrics.clock_backwards(),
+            Err(_) => {}
+        }
+    }
+
+    /// Counts `n` ids generated without going through `record`.
+    pub(crate) fn record_issued(&mut self, n: u64) {
+        self.stats.issued += n;
+        if let Some(metrics) = &self.opts.metrics {
+            metrics.generated(n);
+        }
+    }
+
+    /// Counts `n` reserved ids that were never committed.
+    pub(crate) fn record_burned(&mut self, n: u64) {
+        self.stats.burned += n;
+        if let Some(metrics) = &self.opts.metrics {
+            metrics.burned(n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, Generator, GeneratorOptions, OverflowPolicy};
+    use std::sync::atomic::AtomicU64;
+
+    #[test]
+    fn test_metrics() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 2);
+        fn my_time_fn() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+
+        let metrics = Arc::new(AtomicMetrics::default());
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 21, 1)
+            .base_ts(1483228800000)
+            .overflow_policy(OverflowPolicy::Error)
+            .metrics(metrics.clone());
+        let g = Generator::new(opts);
+
+        g.generate_many(2);
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+        NOW.store(1483228800000 + 1, Ordering::Relaxed);
+        assert!(matches!(
+            g.try_generate(),
+            Err(Error::ClockMovedBackwards { .. })
+        ));
+        NOW.store(1483228800000 + 3, Ordering::Relaxed);
+        g.generate();
+
+        assert_eq!(metrics.ids_generated(), 3);
+        assert_eq!(metrics.sequence_rollovers(), 1);
+        assert_eq!(metrics.next_tick_waits(), 0);
+        assert_eq!(metrics.clock_moved_backwards(), 1);
+    }
+
+    #[test]
+    fn test_next_tick_wait() {
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        fn my_time_fn() -> u64 {
+            1483228800000 + 1 + CALLS.fetch_add(1, Ordering::Relaxed) / 4
+        }
+
+        let metrics = Arc::new(AtomicMetrics::default());
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 21, 1)
+            .base_ts(1483228800000)
+            .metrics(metrics.clone());
+        let g = Generator::new(opts);
+
+        for _ in 0..3 {
+            g.generate();
+        }
+        assert_eq!(metrics.ids_generated(), 3);
+        assert_eq!(metrics.next_tick_waits(), 1);
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let metrics = AtomicMetrics::default();
+        metrics.generated(5);
+        metrics.queued(2);
+
+        let text = metrics.prometheus("ids");
+        assert!(
+            text.contains("# TYPE ids_ids_generated_total counter\nids_ids_generated_total 5\n")
+        );
+        assert!(text.contains("# TYPE ids_queue_depth gauge\nids_queue_depth 2\n"));
+    }
+}
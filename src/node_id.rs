@@ -0,0 +1,716 @@
+//! Node id allocation.
+//!
+//! When a node id is released, the instance that held it may have generated
+//! ids slightly into the future relative to a replacement instance whose
+//! clock is behind. Handing the same node id out again right away opens a
+//! window for duplicate ids, so released ids are kept in quarantine for a
+//! cool-down period that should be longer than the maximum clock skew.
+//!
+//! During a rolling restart there is no need to wait that long: a process
+//! can hand its node id over to its replacement through a [`Handover`] file,
+//! together with the state of its generator.
+//!
+//! Across machines, node ids can be leased from a coordinator, see
+//! [`NodeLease`], or derived from the environment of the instance, see
+//! [`NodeIdStrategy`]. Processes of a single host can lease them from each
+//! other through shared memory, with `ShmNodeAllocator` of the `shm`
+//! feature.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::GeneratorState;
+
+#[cfg(feature = "coordinator-etcd")]
+mod etcd;
+#[cfg(any(feature = "redis", feature = "coordinator-etcd"))]
+mod lease;
+#[cfg(feature = "redis")]
+pub(crate) mod redis;
+#[cfg(all(unix, feature = "shm"))]
+mod shm;
+
+#[cfg(feature = "coordinator-etcd")]
+pub use self::etcd::{EtcdNodeAllocator, EtcdNodeLease};
+#[cfg(feature = "redis")]
+pub use self::redis::{RedisNodeAllocator, RedisNodeLease};
+#[cfg(all(unix, feature = "shm"))]
+pub use self::shm::{ShmNodeAllocator, ShmNodeLease};
+
+/// Node id leased from a coordinator.
+///
+/// A generator holding a lease through `GeneratorOptions::node_lease`
+/// refuses to generate ids with [`Error::LeaseExpired`](crate::Error) once
+/// the lease is no longer valid, since another instance may have been given
+/// the same node id by then.
+pub trait NodeLease {
+    fn node(&self) -> u64;
+    fn is_valid(&self) -> bool;
+
+    /// When the lease expires unless it is renewed, if known.
+    fn expires_at(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// Registers `callback` to be called once when the lease is revoked, so
+    /// that the application can stop taking traffic before another instance
+    /// may get the same node id. It is called right away if the lease is
+    /// already revoked.
+    ///
+    /// The default drops `callback`, for leases that are never revoked.
+    fn on_revoked(&self, callback: Box<dyn FnOnce() + Send>) {
+        drop(callback);
+    }
+}
+
+/// Where to derive a node id from, for `GeneratorOptions::node_strategy`.
+///
+/// None of these guarantee unique node ids, they only make collisions
+/// unlikely in common setups:
+///
+/// - `MacAddress` takes the low bits of the MAC address. Those are assigned
+///   sequentially by vendors, so they rarely collide within a fleet bought
+///   together, but virtual interfaces may share addresses.
+/// - `Hostname` hashes the hostname. Any two hosts collide with a chance of
+///   1 in 2^`node_bits`, so with 10 bits a fleet of 38 hosts already has a
+///   50% chance of a collision somewhere.
+/// - `IpAddress` takes the last octet of the IP address used for outgoing
+///   traffic. This is unique within a /24 subnet, but not across subnets.
+/// - `PodOrdinal` takes the ordinal at the end of `POD_NAME`, as set for the
+///   pods of a Kubernetes StatefulSet (`web-0`, `web-1`, ...). This is unique
+///   within a StatefulSet, but not across StatefulSets.
+/// - `RangeLease` claims a free id from `lo` to `hi` by locking a file for
+///   each id in `FROSTFLAKE_LOCK_DIR`, or the temporary directory if unset.
+///   This is unique among the processes of a host, like the old and new
+///   processes overlapping during a blue/green deploy, but not across
+///   hosts. The lock is held until the process exits, even if it crashes.
+///
+/// With the `k8s` feature, two more read what the downward API of Kubernetes
+/// exposes:
+///
+/// - `StatefulSetOrdinal` is `PodOrdinal`, falling back to `HOSTNAME`, which
+///   is the pod name in a StatefulSet, when `POD_NAME` isn't set.
+/// - `PodIp` hashes `POD_IP`, set from `status.podIP`. Like with
+///   `Hostname`, pods collide with a chance of 1 in 2^`node_bits`.
+///
+/// Use a coordinator, see [`NodeLease`], where uniqueness is required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NodeIdStrategy {
+    MacAddress,
+    Hostname,
+    IpAddress,
+    PodOrdinal,
+    RangeLease {
+        lo: u64,
+        hi: u64,
+    },
+    #[cfg(feature = "k8s")]
+    StatefulSetOrdinal,
+    #[cfg(feature = "k8s")]
+    PodIp,
+}
+
+impl NodeIdStrategy {
+    /// Derives a node id fitting in `node_bits`.
+    ///
+    /// The MAC address and the hash of the hostname are cut to `node_bits`,
+    /// while an IP octet or pod ordinal that doesn't fit is an error, since
+    /// cutting it would make collisions likely.
+    pub fn node(&self, node_bits: u8) -> io::Result<u64> {
+        let max_node = crate::max(node_bits);
+        match self {
+            NodeIdStrategy::MacAddress => Ok(mac_address()? & max_node),
+            NodeIdStrategy::Hostname => Ok(fnv1a(hostname()?.as_bytes()) & max_node),
+            NodeIdStrategy::IpAddress => fit(last_octet(local_ip()?), max_node),
+            NodeIdStrategy::PodOrdinal => {
+                let name = env::var("POD_NAME")
+                    .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "POD_NAME is not set"))?;
+                let ordinal = pod_ordinal(&name).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "POD_NAME has no ordinal")
+                })?;
+                fit(ordinal, max_node)
+            }
+            NodeIdStrategy::RangeLease { lo, hi } => {
+                let dir = env::var_os("FROSTFLAKE_LOCK_DIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(env::temp_dir);
+                claim_range(&dir, *lo, fit(*hi, max_node)?)
+            }
+            #[cfg(feature = "k8s")]
+            NodeIdStrategy::StatefulSetOrdinal => {
+                fit(stateful_set_ordinal(|name| env::var(name).ok())?, max_node)
+            }
+            #[cfg(feature = "k8s")]
+            NodeIdStrategy::PodIp => Ok(ip_hash(pod_ip(|name| env::var(name).ok())?) & max_node),
+        }
+    }
+}
+
+// lock files of the node ids claimed by RangeLease, held until exit
+static CLAIMED: Mutex<Vec<fs::File>> = Mutex::new(Vec::new());
+
+// locks the file of the first free id from lo to hi, starting at an offset
+// by process id so that processes starting together rarely race for one id
+fn claim_range(dir: &Path, lo: u64, hi: u64) -> io::Result<u64> {
+    if lo > hi {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("node id range {}..={} is empty", lo, hi),
+        ));
+    }
+
+    let len = hi - lo + 1;
+    let offset = std::process::id() as u64 % len;
+    for i in 0..len {
+        let node = lo + (offset + i) % len;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(format!("frostflake-node-{}.lock", node)))?;
+        match file.try_lock() {
+            Ok(()) => {
+                CLAIMED.lock().unwrap_or_else(|e| e.into_inner()).push(file);
+                return Ok(node);
+            }
+            Err(fs::TryLockError::WouldBlock) => continue,
+            Err(fs::TryLockError::Error(e)) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::ResourceBusy,
+        format!("all node ids from {} to {} are taken", lo, hi),
+    ))
+}
+
+fn fit(node: u64, max_node: u64) -> io::Result<u64> {
+    if node > max_node {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("node id {} exceeds node_bits limit {}", node, max_node),
+        ));
+    }
+    Ok(node)
+}
+
+// MAC address of the first network interface by name, skipping loopback
+fn mac_address() -> io::Result<u64> {
+    let mut names = fs::read_dir("/sys/class/net")?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .collect::<Vec<_>>();
+    names.sort();
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            fs::read_to_string(Path::new("/sys/class/net").join(name).join("address")).ok()
+        })
+        .filter_map(|address| parse_mac(address.trim()))
+        .find(|mac| *mac != 0)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no network interface with a MAC address",
+            )
+        })
+}
+
+fn parse_mac(s: &str) -> Option<u64> {
+    let octets = s
+        .split(':')
+        .map(|octet| u8::from_str_radix(octet, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    (octets.len() == 6).then(|| octets.iter().fold(0, |mac, o| (mac << 8) | *o as u64))
+}
+
+fn hostname() -> io::Result<String> {
+    let name = match fs::read_to_string("/proc/sys/kernel/hostname") {
+        Ok(name) => name,
+        Err(_) => env::var("HOSTNAME")
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "hostname is unknown"))?,
+    };
+    Ok(name.trim().to_string())
+}
+
+// FNV-1a, stable across Rust versions unlike the hasher of std
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// address a UDP socket would send from; connecting UDP sends no packets
+fn local_ip() -> io::Result<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect("192.0.2.1:9")?;
+    Ok(socket.local_addr()?.ip())
+}
+
+fn last_octet(ip: IpAddr) -> u64 {
+    match ip {
+        IpAddr::V4(ip) => ip.octets()[3] as u64,
+        IpAddr::V6(ip) => ip.octets()[15] as u64,
+    }
+}
+
+fn pod_ordinal(name: &str) -> Option<u64> {
+    name.rsplit_once('-')?.1.parse().ok()
+}
+
+#[cfg(feature = "k8s")]
+fn stateful_set_ordinal(var: impl Fn(&str) -> Option<String>) -> io::Result<u64> {
+    let name = var("POD_NAME").or_else(|| var("HOSTNAME")).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "neither POD_NAME nor HOSTNAME is set",
+        )
+    })?;
+    pod_ordinal(&name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("pod name {} has no ordinal", name),
+        )
+    })
+}
+
+#[cfg(feature = "k8s")]
+fn pod_ip(var: impl Fn(&str) -> Option<String>) -> io::Result<IpAddr> {
+    let ip = var("POD_IP").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "POD_IP is not set, set it from status.podIP",
+        )
+    })?;
+    ip.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("POD_IP {} is not an IP address", ip),
+        )
+    })
+}
+
+#[cfg(feature = "k8s")]
+fn ip_hash(ip: IpAddr) -> u64 {
+    match ip {
+        IpAddr::V4(ip) => fnv1a(&ip.octets()),
+        IpAddr::V6(ip) => fnv1a(&ip.octets()),
+    }
+}
+
+/// Tracks released node ids until their cool-down is over.
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    cooldown: Duration,
+    released: HashMap<u64, Instant>,
+}
+
+impl Quarantine {
+    pub fn new(cooldown: Duration) -> Self {
+        Quarantine {
+            cooldown,
+            released: HashMap::new(),
+        }
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    /// Puts `node` in quarantine from now on.
+    pub fn release(&mut self, node: u64) {
+        self.released.insert(node, Instant::now());
+    }
+
+    /// How long `node` remains unavailable, or `None` if it is available.
+    pub fn remaining(&self, node: u64) -> Option<Duration> {
+        let released = self.released.get(&node)?;
+        self.cooldown
+            .checked_sub(released.elapsed())
+            .filter(|d| !d.is_zero())
+    }
+
+    pub fn is_available(&self, node: u64) -> bool {
+        self.remaining(node).is_none()
+    }
+
+    /// Forgets node ids whose cool-down is over.
+    pub fn purge(&mut self) {
+        let cooldown = self.cooldown;
+        self.released
+            .retain(|_, released| released.elapsed() < cooldown);
+    }
+}
+
+/// In-process node id allocator honoring a [`Quarantine`].
+///
+/// ```rust
+/// use frostflake::node_id::NodeIdAllocator;
+/// use std::time::Duration;
+///
+/// let mut nodes = NodeIdAllocator::new(1023, Duration::from_secs(10));
+/// let node = nodes.acquire().unwrap();
+/// nodes.release(node);
+/// // the released id is not handed out again during the cool-down
+/// assert_ne!(nodes.acquire(), Some(node));
+/// ```
+#[derive(Debug, Clone)]
+pub struct NodeIdAllocator {
+    max_node: u64,
+    in_use: Vec<u64>,
+    quarantine: Quarantine,
+}
+
+impl NodeIdAllocator {
+    /// Allocates node ids in `0..=max_node`.
+    pub fn new(max_node: u64, cooldown: Duration) -> Self {
+        NodeIdAllocator {
+            max_node,
+            in_use: vec![],
+            quarantine: Quarantine::new(cooldown),
+        }
+    }
+
+    /// Takes the smallest node id that is neither in use nor in quarantine.
+    pub fn acquire(&mut self) -> Option<u64> {
+        self.quarantine.purge();
+
+        let node = (0..=self.max_node)
+            .find(|n| !self.in_use.contains(n) && self.quarantine.is_available(*n))?;
+        self.in_use.push(node);
+        Some(node)
+    }
+
+    /// Gives `node` back; it becomes available after the cool-down.
+    pub fn release(&mut self, node: u64) {
+        if let Some(i) = self.in_use.iter().position(|n| *n == node) {
+            self.in_use.swap_remove(i);
+            self.quarantine.release(node);
+        }
+    }
+
+    pub fn quarantine(&self) -> &Quarantine {
+        &self.quarantine
+    }
+}
+
+/// What an old process hands over to its replacement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandoverState {
+    pub node: u64,
+    pub state: GeneratorState,
+    /// Fencing token, incremented with every handover.
+    ///
+    /// Pass it along with writes to shared storage, so that the storage can
+    /// reject writes of a process that was already replaced.
+    pub token: u64,
+}
+
+/// Local handover of a node id between processes through a state file.
+///
+/// The old process stops generating ids and calls [`offer`](Self::offer)
+/// with its node id and generator state. The new process calls
+/// [`claim`](Self::claim) and resumes generating right after that state,
+/// without a cool-down. At most one process can claim an offer.
+///
+/// ```rust
+/// use frostflake::node_id::{Handover, HandoverState};
+/// use frostflake::{Generator, GeneratorOptions};
+///
+/// let handover = Handover::new(std::env::temp_dir().join("frostflake-handover-doctest"));
+///
+/// // old process, on shutdown
+/// let old = Generator::new(GeneratorOptions::default().node(3));
+/// old.generate();
+/// handover.offer(&HandoverState { node: 3, state: old.state(), token: 0 }).unwrap();
+///
+/// // new process, on startup
+/// let claimed = handover.claim().unwrap().unwrap();
+/// assert_eq!(claimed.token, 1);
+/// let new = Generator::resume(GeneratorOptions::default().node(claimed.node), claimed.state);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Handover {
+    path: PathBuf,
+}
+
+const HANDOVER_HEADER: &str = "frostflake-handover 1";
+
+impl Handover {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Handover {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Writes the offer, replacing a previous unclaimed one.
+    ///
+    /// The file is written to a temporary path and renamed into place, so a
+    /// claimer never sees a partial offer.
+    pub fn offer(&self, offer: &HandoverState) -> io::Result<()> {
+        let tmp = self.sibling("tmp");
+        {
+            let mut file = fs::File::create(&tmp)?;
+            write!(
+                file,
+                "{}\nnode={}\nlast_ts={}\nseq={}\ntoken={}\n",
+                HANDOVER_HEADER, offer.node, offer.state.last_ts, offer.state.seq, offer.token
+            )?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// Takes the offer, if there is one, with the fencing token incremented.
+    pub fn claim(&self) -> io::Result<Option<HandoverState>> {
+        // renaming is atomic, so only one of concurrent claimers gets the file
+        let claimed = self.sibling("claimed");
+        match fs::rename(&self.path, &claimed) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let content = fs::read_to_string(&claimed)?;
+        fs::remove_file(&claimed)?;
+
+        let mut offer = parse_handover(&content)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed handover file"))?;
+        offer.token += 1;
+        Ok(Some(offer))
+    }
+
+    fn sibling(&self, ext: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".");
+        path.push(ext);
+        path.into()
+    }
+}
+
+fn parse_handover(content: &str) -> Option<HandoverState> {
+    let mut lines = content.lines();
+    if lines.next()? != HANDOVER_HEADER {
+        return None;
+    }
+
+    let mut field = |name: &str| -> Option<u64> {
+        let (key, value) = lines.next()?.split_once('=')?;
+        (key == name).then(|| value.parse().ok())?
+    };
+
+    Some(HandoverState {
+        node: field("node")?,
+        state: GeneratorState {
+            last_ts: field("last_ts")?,
+            seq: field("seq")?,
+        },
+        token: field("token")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_strategy() {
+        assert_eq!(parse_mac("02:42:ac:11:00:02"), Some(0x0242ac110002));
+        assert_eq!(parse_mac("02:42:ac:11:00"), None);
+        assert_eq!(parse_mac("02:42:ac:11:00:zz"), None);
+
+        assert_eq!(pod_ordinal("web-12"), Some(12));
+        assert_eq!(pod_ordinal("my-web-0"), Some(0));
+        assert_eq!(pod_ordinal("web"), None);
+        assert_eq!(pod_ordinal("web-abc"), None);
+
+        assert_eq!(last_octet("10.0.3.7".parse().unwrap()), 7);
+        assert_eq!(last_octet("fe80::1:2a".parse().unwrap()), 0x2a);
+
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+
+        assert_eq!(fit(1023, 1023).unwrap(), 1023);
+        assert!(fit(1024, 1023).is_err());
+    }
+
+    #[test]
+    fn test_pod_ordinal() {
+        env::set_var("POD_NAME", "web-5");
+        assert_eq!(NodeIdStrategy::PodOrdinal.node(10).unwrap(), 5);
+        assert!(NodeIdStrategy::PodOrdinal.node(2).is_err());
+
+        let opts = crate::GeneratorOptions::default().node_strategy(NodeIdStrategy::PodOrdinal);
+        assert_eq!(crate::Generator::new(opts).generate_id().node(), 5);
+
+        env::set_var("POD_NAME", "web");
+        assert!(NodeIdStrategy::PodOrdinal.node(10).is_err());
+        env::remove_var("POD_NAME");
+        assert!(NodeIdStrategy::PodOrdinal.node(10).is_err());
+    }
+
+    #[cfg(feature = "k8s")]
+    #[test]
+    fn test_stateful_set_ordinal() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert_eq!(
+            stateful_set_ordinal(env(&[("POD_NAME", "web-3")])).unwrap(),
+            3
+        );
+        assert_eq!(
+            stateful_set_ordinal(env(&[("HOSTNAME", "web-7")])).unwrap(),
+            7
+        );
+        assert_eq!(
+            stateful_set_ordinal(env(&[("POD_NAME", "web-1"), ("HOSTNAME", "web-2")])).unwrap(),
+            1
+        );
+        assert_eq!(
+            stateful_set_ordinal(env(&[("HOSTNAME", "web")]))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            stateful_set_ordinal(env(&[])).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        assert_eq!(
+            pod_ip(env(&[("POD_IP", "10.1.2.3")])).unwrap(),
+            "10.1.2.3".parse::<IpAddr>().unwrap()
+        );
+        assert!(pod_ip(env(&[("POD_IP", "web-0")])).is_err());
+        assert!(pod_ip(env(&[])).is_err());
+    }
+
+    #[cfg(feature = "k8s")]
+    #[test]
+    fn test_pod_ip() {
+        env::set_var("POD_IP", "10.1.2.3");
+        let node = NodeIdStrategy::PodIp.node(10).unwrap();
+        assert_eq!(node, fnv1a(&[10, 1, 2, 3]) & 1023);
+
+        let opts = crate::GeneratorOptions::default().node_strategy(NodeIdStrategy::PodIp);
+        assert_eq!(crate::Generator::new(opts).generate_id().node(), node);
+
+        env::remove_var("POD_IP");
+        assert!(NodeIdStrategy::PodIp.node(10).is_err());
+    }
+
+    #[test]
+    fn test_range_lease() {
+        let dir = std::env::temp_dir().join(format!("frostflake-range-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // every claim locks another id, also within a process
+        let mut nodes: Vec<_> = (0..3).map(|_| claim_range(&dir, 4, 6).unwrap()).collect();
+        nodes.sort();
+        assert_eq!(nodes, [4, 5, 6]);
+        assert_eq!(
+            claim_range(&dir, 4, 6).unwrap_err().kind(),
+            io::ErrorKind::ResourceBusy
+        );
+        assert!(claim_range(&dir, 7, 6).is_err());
+
+        // released once the lock files are closed
+        CLAIMED.lock().unwrap().clear();
+        assert!((4..=6).contains(&claim_range(&dir, 4, 6).unwrap()));
+        CLAIMED.lock().unwrap().clear();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let strategy = NodeIdStrategy::RangeLease { lo: 0, hi: 1024 };
+        assert!(strategy.node(10).is_err());
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let mut q = Quarantine::new(Duration::from_secs(60));
+        assert!(q.is_available(1));
+
+        q.release(1);
+        assert!(!q.is_available(1));
+        assert!(q.remaining(1).unwrap() <= Duration::from_secs(60));
+
+        let mut q = Quarantine::new(Duration::ZERO);
+        q.release(1);
+        assert!(q.is_available(1));
+        q.purge();
+        assert!(q.released.is_empty());
+    }
+
+    #[test]
+    fn test_allocator() {
+        let mut nodes = NodeIdAllocator::new(2, Duration::from_secs(60));
+        assert_eq!(nodes.acquire(), Some(0));
+        assert_eq!(nodes.acquire(), Some(1));
+
+        nodes.release(0);
+        assert_eq!(nodes.acquire(), Some(2));
+        assert_eq!(nodes.acquire(), None);
+
+        let mut nodes = NodeIdAllocator::new(2, Duration::ZERO);
+        assert_eq!(nodes.acquire(), Some(0));
+        nodes.release(0);
+        assert_eq!(nodes.acquire(), Some(0));
+    }
+
+    #[test]
+    fn test_handover() {
+        let path = std::env::temp_dir().join(format!("frostflake-handover-{}", std::process::id()));
+        let handover = Handover::new(&path);
+        assert_eq!(handover.claim().unwrap(), None);
+
+        let offer = HandoverState {
+            node: 3,
+            state: GeneratorState {
+                last_ts: 1483228800000 + 123,
+                seq: 7,
+            },
+            token: 41,
+        };
+        handover.offer(&offer).unwrap();
+        assert_eq!(
+            handover.claim().unwrap(),
+            Some(HandoverState { token: 42, ..offer })
+        );
+
+        // an offer is claimed only once
+        assert_eq!(handover.claim().unwrap(), None);
+
+        fs::write(&path, "garbage").unwrap();
+        assert!(handover.claim().is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_parse_handover() {
+        assert_eq!(parse_handover(""), None);
+        assert_eq!(
+            parse_handover("frostflake-handover 1\nnode=1\nlast_ts=2\nseq=3\n"),
+            None
+        );
+        assert_eq!(
+            parse_handover("frostflake-handover 1\nnode=1\nlast_ts=2\nseq=3\ntoken=4\n"),
+            Some(HandoverState {
+                node: 1,
+                state: GeneratorState { last_ts: 2, seq: 3 },
+                token: 4,
+            })
+        );
+    }
+}
@@ -0,0 +1,475 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::lease::{unique_token, LeaseState};
+use super::NodeLease;
+
+/// Allocates node ids from etcd, through the JSON gateway of its v3 API.
+///
+/// Each node id is a key put with a transaction that only succeeds if the
+/// key doesn't exist yet, attached to an etcd lease unique to the process.
+/// A background thread keeps the etcd lease alive every third of the TTL,
+/// and the node lease becomes invalid once etcd reports the lease gone, or
+/// keeping it alive fails for longer than the TTL. On drop, the etcd lease
+/// is no longer kept alive, and its key is removed by etcd when the TTL runs
+/// out, which serves as the cool-down explained in the
+/// [module documentation](super).
+///
+/// ```no_run
+/// use frostflake::node_id::EtcdNodeAllocator;
+/// use frostflake::{Generator, GeneratorOptions};
+/// use std::time::Duration;
+///
+/// let lease = EtcdNodeAllocator::new("http://127.0.0.1:2379")
+///     .ttl(Duration::from_secs(30))
+///     .acquire(1023)
+///     .unwrap();
+/// let generator = Generator::new(GeneratorOptions::default().node_lease(lease));
+/// ```
+#[derive(Debug, Clone)]
+pub struct EtcdNodeAllocator {
+    endpoint: String,
+    key_prefix: String,
+    ttl: Duration,
+    retry_interval: Duration,
+}
+
+impl EtcdNodeAllocator {
+    /// `endpoint` is in the form of `http://host[:port]`.
+    pub fn new(endpoint: &str) -> Self {
+        EtcdNodeAllocator {
+            endpoint: endpoint.to_string(),
+            key_prefix: "frostflake/node".to_string(),
+            ttl: Duration::from_secs(10),
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Keys are named `{prefix}/{node}`, `frostflake/node` by default.
+    pub fn key_prefix(mut self, prefix: &str) -> Self {
+        self.key_prefix = prefix.to_string();
+        self
+    }
+
+    /// Defaults to 10 seconds. etcd counts leases in seconds, so the lease
+    /// in etcd is rounded up to whole seconds.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// How long to wait before trying again when all node ids are taken.
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Leases a node id in `0..=max_node`, blocking until one is free.
+    pub fn acquire(&self, max_node: u64) -> io::Result<EtcdNodeLease> {
+        let client = Client::new(&self.endpoint)?;
+        let value = base64(unique_token().as_bytes());
+        let ttl = self.ttl.as_millis().div_ceil(1000).max(1);
+
+        loop {
+            let sent_at = Instant::now();
+            let reply = client.post("/v3/lease/grant", &format!(r#"{{"TTL":"{}"}}"#, ttl))?;
+            let id = json_value(&reply, "ID")
+                .ok_or_else(|| unexpected(&reply))?
+                .to_string();
+
+            for node in 0..=max_node {
+                let key = base64(format!("{}/{}", self.key_prefix, node).as_bytes());
+                let txn = format!(
+                    r#"{{"compare":[{{"key":"{key}","result":"EQUAL","target":"CREATE","create_revision":"0"}}],"success":[{{"request_put":{{"key":"{key}","value":"{value}","lease":"{id}"}}}}]}}"#
+                );
+                let reply = client.post("/v3/kv/txn", &txn)?;
+                if json_value(&reply, "succeeded") == Some("true") {
+                    return Ok(EtcdNodeLease::start(self, client, id, node, sent_at));
+                }
+            }
+
+            // all taken; the lease would expire on its own, but there's no
+            // need to keep it around until then
+            let _ = client.post("/v3/lease/revoke", &format!(r#"{{"ID":"{}"}}"#, id));
+            thread::sleep(self.retry_interval);
+        }
+    }
+}
+
+/// Node id leased by [`EtcdNodeAllocator`], given up on drop.
+pub struct EtcdNodeLease {
+    node: u64,
+    state: Arc<LeaseState>,
+    stop: Option<Sender<()>>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl EtcdNodeLease {
+    fn start(
+        allocator: &EtcdNodeAllocator,
+        client: Client,
+        id: String,
+        node: u64,
+        sent_at: Instant,
+    ) -> Self {
+        let ttl = allocator.ttl;
+        let state = Arc::new(LeaseState::new(sent_at, ttl));
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let shared = state.clone();
+
+        let heartbeat = thread::spawn(move || {
+            let body = format!(r#"{{"ID":"{}"}}"#, id);
+            loop {
+                match stopped.recv_timeout(ttl / 3) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // dropped; the key goes away with the lease
+                    _ => return,
+                }
+
+                let sent_at = Instant::now();
+                match client.post("/v3/lease/keepalive", &body) {
+                    Ok(reply) => {
+                        match json_value(&reply, "TTL").and_then(|t| t.parse::<i64>().ok()) {
+                            Some(ttl_secs) if ttl_secs > 0 => shared.extend(sent_at, ttl),
+                            _ => {
+                                // the lease expired or was revoked, and the node id
+                                // may belong to someone else now
+                                shared.lose();
+                                return;
+                            }
+                        }
+                    }
+                    Err(_) if shared.remaining() < ttl / 3 => {
+                        // the lease would expire before the next beat
+                        shared.lose();
+                        return;
+                    }
+                    // try again on the next beat; the lease expires on its own meanwhile
+                    Err(_) => {}
+                }
+            }
+        });
+
+        EtcdNodeLease {
+            node,
+            state,
+            stop: Some(stop),
+            heartbeat: Some(heartbeat),
+        }
+    }
+}
+
+impl NodeLease for EtcdNodeLease {
+    fn node(&self) -> u64 {
+        self.node
+    }
+
+    fn is_valid(&self) -> bool {
+        self.state.is_valid()
+    }
+
+    fn expires_at(&self) -> Option<SystemTime> {
+        Some(self.state.expires_at())
+    }
+
+    fn on_revoked(&self, callback: Box<dyn FnOnce() + Send>) {
+        self.state.on_revoked(callback);
+    }
+}
+
+impl Drop for EtcdNodeLease {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(heartbeat) = self.heartbeat.take() {
+            let _ = heartbeat.join();
+        }
+    }
+}
+
+fn unexpected(reply: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected reply from etcd: {}", reply),
+    )
+}
+
+/// Minimal HTTP/1.1 client, enough for the JSON gateway of etcd.
+struct Client {
+    host: String,
+    port: u16,
+}
+
+impl Client {
+    fn new(endpoint: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid etcd endpoint");
+
+        let rest = endpoint.strip_prefix("http://").ok_or_else(invalid)?;
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (rest, 2379),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Client {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    fn post(&self, path: &str, body: &str) -> io::Result<String> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            self.host,
+            self.port,
+            body.len(),
+            body
+        )?;
+        let mut response = vec![];
+        stream.read_to_end(&mut response)?;
+
+        parse_response(&String::from_utf8_lossy(&response))
+    }
+}
+
+fn parse_response(response: &str) -> io::Result<String> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid reply from etcd");
+
+    let (head, body) = response.split_once("\r\n\r\n").ok_or_else(invalid)?;
+    let mut lines = head.lines();
+    let status = lines.next().ok_or_else(invalid)?;
+    if status.split(' ').nth(1) != Some("200") {
+        return Err(io::Error::other(format!("etcd: {}: {}", status, body)));
+    }
+
+    let chunked = lines.any(|line| {
+        line.to_ascii_lowercase()
+            .replace(' ', "")
+            .starts_with("transfer-encoding:chunked")
+    });
+    if !chunked {
+        return Ok(body.to_string());
+    }
+
+    let mut rest = body;
+    let mut decoded = String::new();
+    loop {
+        let (size, data) = rest.split_once("\r\n").ok_or_else(invalid)?;
+        let size = usize::from_str_radix(size.trim(), 16).map_err(|_| invalid())?;
+        if size == 0 {
+            return Ok(decoded);
+        }
+        decoded.push_str(data.get(..size).ok_or_else(invalid)?);
+        rest = data.get(size + 2..).ok_or_else(invalid)?;
+    }
+}
+
+/// Raw value of the first `field` in a JSON document, without the quotes
+/// of a string.
+///
+/// The replies of etcd are small and flat enough for this, and all the
+/// fields needed are unique within a reply.
+fn json_value<'a>(json: &'a str, field: &str) -> Option<&'a str> {
+    let start = json.find(&format!("\"{}\"", field))? + field.len() + 2;
+    let rest = json[start..].trim_start().strip_prefix(':')?.trim_start();
+    match rest.strip_prefix('"') {
+        Some(s) => s.split('"').next(),
+        None => rest.split([',', '}', ']', ' ', '\n']).next(),
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::Entry;
+    use std::collections::{HashMap, HashSet};
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct FakeEtcd {
+        next_lease: u64,
+        leases: HashSet<String>,
+        // key to lease
+        keys: HashMap<String, String>,
+    }
+
+    /// Answers the calls of the allocator, without expiry.
+    fn fake_etcd() -> (String, Arc<Mutex<FakeEtcd>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let etcd = Arc::new(Mutex::new(FakeEtcd::default()));
+
+        let shared = etcd.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line.split(' ').nth(1).unwrap().to_string();
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(v) = line.strip_prefix("Content-Length: ") {
+                        len = v.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                let body = String::from_utf8(body).unwrap();
+
+                let mut etcd = shared.lock().unwrap();
+                let reply = match path.as_str() {
+                    "/v3/lease/grant" => {
+                        etcd.next_lease += 1;
+                        let id = etcd.next_lease.to_string();
+                        etcd.leases.insert(id.clone());
+                        format!(r#"{{"ID":"{}","TTL":"1"}}"#, id)
+                    }
+                    "/v3/kv/txn" => {
+                        let key = json_value(&body, "key").unwrap().to_string();
+                        let lease = json_value(&body, "lease").unwrap().to_string();
+                        match etcd.keys.entry(key) {
+                            Entry::Occupied(_) => r#"{"header":{}}"#.to_string(),
+                            Entry::Vacant(entry) => {
+                                entry.insert(lease);
+                                r#"{"header":{},"succeeded":true}"#.to_string()
+                            }
+                        }
+                    }
+                    "/v3/lease/keepalive" => {
+                        let id = json_value(&body, "ID").unwrap();
+                        if etcd.leases.contains(id) {
+                            format!(r#"{{"result":{{"ID":"{}","TTL":"1"}}}}"#, id)
+                        } else {
+                            format!(r#"{{"result":{{"ID":"{}"}}}}"#, id)
+                        }
+                    }
+                    "/v3/lease/revoke" => {
+                        let id = json_value(&body, "ID").unwrap().to_string();
+                        etcd.leases.remove(&id);
+                        etcd.keys.retain(|_, lease| *lease != id);
+                        r#"{"header":{}}"#.to_string()
+                    }
+                    _ => "not found".to_string(),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                )
+                .unwrap();
+            }
+        });
+
+        (endpoint, etcd)
+    }
+
+    #[test]
+    fn test_json_value() {
+        let json = r#"{"header":{"revision":"5"},"succeeded":true, "ID": "42","TTL":10}"#;
+        assert_eq!(json_value(json, "revision"), Some("5"));
+        assert_eq!(json_value(json, "succeeded"), Some("true"));
+        assert_eq!(json_value(json, "ID"), Some("42"));
+        assert_eq!(json_value(json, "TTL"), Some("10"));
+        assert_eq!(json_value(json, "missing"), None);
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"frostflake/node/1"), "ZnJvc3RmbGFrZS9ub2RlLzE=");
+    }
+
+    #[test]
+    fn test_parse_response() {
+        assert_eq!(
+            parse_response("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}").unwrap(),
+            "{}"
+        );
+        assert_eq!(
+            parse_response(
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\n{\"a\r\n5\r\n\":1}\n\r\n0\r\n\r\n"
+            )
+            .unwrap(),
+            "{\"a\":1}\n"
+        );
+        assert!(parse_response("HTTP/1.1 404 Not Found\r\n\r\nnope").is_err());
+        assert!(parse_response("garbage").is_err());
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let client = Client::new("http://10.0.0.1:2380/").unwrap();
+        assert_eq!((client.host.as_str(), client.port), ("10.0.0.1", 2380));
+        let client = Client::new("http://localhost").unwrap();
+        assert_eq!((client.host.as_str(), client.port), ("localhost", 2379));
+        assert!(Client::new("https://localhost").is_err());
+        assert!(Client::new("http://localhost:port").is_err());
+    }
+
+    #[test]
+    fn test_lease() {
+        let (endpoint, etcd) = fake_etcd();
+        let allocator = EtcdNodeAllocator::new(&endpoint).ttl(Duration::from_millis(300));
+
+        let a = allocator.acquire(1).unwrap();
+        let b = allocator.acquire(1).unwrap();
+        assert_eq!((a.node(), b.node()), (0, 1));
+        assert!(a.is_valid() && b.is_valid());
+
+        assert!(b.expires_at().unwrap() > SystemTime::now());
+        let (revoked, on_revoked) = mpsc::channel();
+        b.on_revoked(Box::new(move || revoked.send(()).unwrap()));
+
+        // the lease of b was revoked behind its back
+        etcd.lock().unwrap().leases.remove("2");
+        on_revoked.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(!b.is_valid());
+        assert!(a.is_valid());
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+type RevokedHook = Box<dyn FnOnce() + Send>;
+
+/// Validity of a lease, shared with the thread keeping it alive.
+pub(super) struct LeaseState {
+    since: Instant,
+    // milliseconds since `since`
+    valid_until: AtomicU64,
+    lost: AtomicBool,
+    revoked: Mutex<Vec<RevokedHook>>,
+}
+
+impl LeaseState {
+    /// A lease extended by `ttl` from `sent_at`.
+    pub(super) fn new(sent_at: Instant, ttl: Duration) -> Self {
+        let state = LeaseState {
+            since: sent_at,
+            valid_until: AtomicU64::new(0),
+            lost: AtomicBool::new(false),
+            revoked: Mutex::new(vec![]),
+        };
+        state.extend(sent_at, ttl);
+        state
+    }
+
+    /// Extends the lease to `ttl` after the request that extended it was
+    /// sent, so that the lease expires here no later than in the coordinator.
+    pub(super) fn extend(&self, sent_at: Instant, ttl: Duration) {
+        let until = sent_at.duration_since(self.since) + ttl;
+        self.valid_until
+            .store(until.as_millis() as u64, Ordering::Release);
+    }
+
+    /// Invalidates the lease for good, once the coordinator gave it away or
+    /// it can't be renewed in time, and calls the revoked callbacks.
+    pub(super) fn lose(&self) {
+        let hooks = {
+            let mut revoked = self.revoked.lock().unwrap_or_else(|e| e.into_inner());
+            self.lost.store(true, Ordering::Release);
+            std::mem::take(&mut *revoked)
+        };
+        for hook in hooks {
+            hook();
+        }
+    }
+
+    pub(super) fn on_revoked(&self, hook: RevokedHook) {
+        {
+            let mut revoked = self.revoked.lock().unwrap_or_else(|e| e.into_inner());
+            if !self.lost.load(Ordering::Acquire) {
+                revoked.push(hook);
+                return;
+            }
+        }
+        hook();
+    }
+
+    /// Time left until the lease expires, zero once it is no longer valid.
+    pub(super) fn remaining(&self) -> Duration {
+        if self.lost.load(Ordering::Acquire) {
+            return Duration::ZERO;
+        }
+        let until = Duration::from_millis(self.valid_until.load(Ordering::Acquire));
+        until.saturating_sub(self.since.elapsed())
+    }
+
+    pub(super) fn is_valid(&self) -> bool {
+        !self.remaining().is_zero()
+    }
+
+    pub(super) fn expires_at(&self) -> SystemTime {
+        SystemTime::now() + self.remaining()
+    }
+}
+
+/// Token telling the holders of a lease apart.
+pub(super) fn unique_token() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!(
+        "{}-{}-{}",
+        std::process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lease_state() {
+        let state = LeaseState::new(Instant::now(), Duration::from_secs(60));
+        assert!(state.is_valid());
+        assert!(state.remaining() > Duration::from_secs(59));
+        assert!(state.expires_at() > SystemTime::now() + Duration::from_secs(59));
+
+        let calls = Arc::new(AtomicU64::new(0));
+        let counter = calls.clone();
+        state.on_revoked(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        state.lose();
+        assert!(!state.is_valid());
+        assert_eq!(state.remaining(), Duration::ZERO);
+        state.lose();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // registered after the fact
+        let counter = calls.clone();
+        state.on_revoked(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let state = LeaseState::new(Instant::now(), Duration::ZERO);
+        assert!(!state.is_valid());
+    }
+}
@@ -0,0 +1,257 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::NodeLease;
+use crate::shm::Mapping;
+
+// node ids a file can hand out
+const SLOTS: usize = 4096;
+// set in the slot of a released node id, with the time of release in
+// milliseconds in the other bits
+const RELEASED: u64 = 1 << 63;
+
+/// Allocates node ids to the processes of a single host, through a
+/// memory-mapped file.
+///
+/// Each node id has a slot in the file holding the process it is leased
+/// to, claimed by a compare-and-swap. A slot whose process is gone, like
+/// after a crash, is taken over by the next process asking, so node ids
+/// don't leak. No coordinator or background thread is involved, and a lease
+/// stays valid as long as its process runs.
+///
+/// The previous holder of a node id may have issued ids on the current
+/// tick, so a node id that was held before is returned only after the
+/// cool-down: since its release, or in full when its process crashed.
+///
+/// With [`host`](Self::host), the node id is split into a host part set per
+/// host, like by [`NodeIdStrategy`](super::NodeIdStrategy) or by hand, and a
+/// process part claimed here, so that ids stay unique across hosts.
+///
+/// All processes sharing the file need the same settings, and the same pid
+/// namespace, since a process of another container looks gone. A file on a
+/// tmpfs like `/dev/shm` goes away on reboot, together with its processes.
+///
+/// ```rust
+/// use frostflake::node_id::{NodeLease, ShmNodeAllocator};
+/// use frostflake::{Generator, GeneratorOptions};
+///
+/// let path = std::env::temp_dir().join("frostflake-shm-doctest");
+/// // up to 16 processes on host 3
+/// let lease = ShmNodeAllocator::new(&path).host(3, 4).acquire(1023).unwrap();
+/// assert_eq!(lease.node() >> 4, 3);
+/// let generator = Generator::new(GeneratorOptions::default().node_lease(lease));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShmNodeAllocator {
+    path: PathBuf,
+    host: Option<(u64, u8)>,
+    cooldown: Duration,
+    retry_interval: Duration,
+}
+
+impl ShmNodeAllocator {
+    /// Allocates through the file at `path`, created if missing.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        ShmNodeAllocator {
+            path: path.as_ref().to_path_buf(),
+            host: None,
+            cooldown: Duration::from_secs(1),
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Leases node ids of `host` followed by `process_bits` bits, instead of
+    /// all node ids.
+    pub fn host(mut self, host: u64, process_bits: u8) -> Self {
+        self.host = Some((host, process_bits));
+        self
+    }
+
+    /// How long a node id that was held before stays reserved, 1 second by
+    /// default.
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// How long to wait before trying again when all node ids are taken.
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Leases a node id in `0..=max_node`, blocking until one is free.
+    ///
+    /// Only the first 4096 node ids are handed out.
+    pub fn acquire(&self, max_node: u64) -> io::Result<ShmNodeLease> {
+        let (first, last) = match self.host {
+            Some((host, bits)) => {
+                let first = crate::shl(host, bits);
+                (first, first | crate::max(bits))
+            }
+            None => (0, max_node),
+        };
+        if last > max_node {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("node ids of the host exceed {}", max_node),
+            ));
+        }
+        let slots = (last - first).min(SLOTS as u64 - 1) as usize + 1;
+
+        let mapping = Mapping::open(&self.path, SLOTS)?;
+        let tag = tag();
+        loop {
+            for (slot, word) in mapping.words()[..slots].iter().enumerate() {
+                if let Some(wait) = claim(word, tag, self.cooldown) {
+                    thread::sleep(wait);
+                    return Ok(ShmNodeLease {
+                        node: first + slot as u64,
+                        slot,
+                        tag,
+                        mapping,
+                    });
+                }
+            }
+            thread::sleep(self.retry_interval);
+        }
+    }
+}
+
+/// Node id leased by [`ShmNodeAllocator`], released on drop.
+pub struct ShmNodeLease {
+    node: u64,
+    slot: usize,
+    tag: u64,
+    mapping: Mapping,
+}
+
+impl ShmNodeLease {
+    fn word(&self) -> &AtomicU64 {
+        &self.mapping.words()[self.slot]
+    }
+}
+
+impl NodeLease for ShmNodeLease {
+    fn node(&self) -> u64 {
+        self.node
+    }
+
+    fn is_valid(&self) -> bool {
+        self.word().load(Ordering::Acquire) == self.tag
+    }
+}
+
+impl Drop for ShmNodeLease {
+    fn drop(&mut self) {
+        let released = RELEASED | unix_ms();
+        let _ =
+            self.word()
+                .compare_exchange(self.tag, released, Ordering::AcqRel, Ordering::Relaxed);
+    }
+}
+
+/// Claims `word` for `tag` if it is free, returning how long to wait before
+/// using its node id.
+fn claim(word: &AtomicU64, tag: u64, cooldown: Duration) -> Option<Duration> {
+    let current = word.load(Ordering::Acquire);
+    let wait = if current == 0 {
+        Duration::ZERO
+    } else if current & RELEASED != 0 {
+        let released = current & !RELEASED;
+        cooldown.saturating_sub(Duration::from_millis(unix_ms().saturating_sub(released)))
+    } else if is_alive(current as u32) {
+        return None;
+    } else {
+        cooldown
+    };
+    word.compare_exchange(current, tag, Ordering::AcqRel, Ordering::Relaxed)
+        .ok()
+        .map(|_| wait)
+}
+
+// the process id in the low bits, and a token telling leases of the same
+// process, or of a later process with the same id, apart in the others
+fn tag() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    let token = (nanos ^ (COUNTER.fetch_add(1, Ordering::Relaxed) << 20)) & 0x7fff_ffff;
+    (token << 32) | std::process::id() as u64
+}
+
+fn is_alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks whether the process exists
+    let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    exists || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_shm_node_allocator() {
+        let path = std::env::temp_dir().join(format!("frostflake-shm-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let allocator = ShmNodeAllocator::new(&path).cooldown(Duration::from_millis(100));
+
+        let a = allocator.acquire(1023).unwrap();
+        let b = allocator.acquire(1023).unwrap();
+        assert_eq!((a.node(), b.node()), (0, 1));
+        assert!(a.is_valid() && b.is_valid());
+
+        // a released node id is handed out again after the cool-down
+        drop(a);
+        let started = Instant::now();
+        let a = allocator.acquire(1023).unwrap();
+        assert_eq!(a.node(), 0);
+        assert!(started.elapsed() >= Duration::from_millis(80));
+
+        // the node id of a process that is gone is taken over
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id() as u64;
+        child.wait().unwrap();
+        let mapping = Mapping::open(&path, SLOTS).unwrap();
+        mapping.words()[2].store(pid, Ordering::SeqCst);
+        let c = allocator.acquire(1023).unwrap();
+        assert_eq!(c.node(), 2);
+
+        // and a lease taken over is no longer valid
+        mapping.words()[2].store(0, Ordering::SeqCst);
+        assert!(!c.is_valid());
+        drop(c);
+        assert_eq!(mapping.words()[2].load(Ordering::SeqCst), 0);
+
+        drop((a, b, mapping));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shm_node_allocator_host() {
+        let path = std::env::temp_dir().join(format!("frostflake-shm-host-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let allocator = ShmNodeAllocator::new(&path).host(3, 2);
+
+        let leases: Vec<_> = (0..4).map(|_| allocator.acquire(1023).unwrap()).collect();
+        let nodes: Vec<_> = leases.iter().map(|lease| lease.node()).collect();
+        assert_eq!(nodes, [12, 13, 14, 15]);
+        assert!(allocator.acquire(11).is_err());
+
+        drop(leases);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
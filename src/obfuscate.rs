@@ -0,0 +1,176 @@
+//! Reversible scrambling of ids, for exposing them publicly.
+//!
+//! Snowflake ids count up, so anyone seeing a few of them learns when they
+//! were made and how many ids a node generates. A [`Scrambler`] maps ids to
+//! public ids with Speck64/128, a small block cipher, keyed by a secret.
+//! The mapping is a permutation of all 64bit values, so public ids are as
+//! unique as the ids, and are turned back with the same key.
+//!
+//! Public ids look random and don't sort by time, so keep the ids
+//! themselves as keys of the storage, and scramble them at the edge.
+//!
+//! ```rust
+//! use frostflake::obfuscate::Scrambler;
+//!
+//! let scrambler = Scrambler::new(*b"sixteen byte key");
+//! let public_id = scrambler.scramble(442730451055673344);
+//! assert_ne!(public_id, 442730451055673344);
+//! assert_eq!(scrambler.unscramble(public_id), 442730451055673344);
+//! ```
+
+use core::fmt;
+
+// Speck64/128 has 27 rounds, with 32bit words rotated by 8 and 3 bits
+const ROUNDS: usize = 27;
+
+/// Scrambles ids with a secret key, by Speck64/128.
+///
+/// This hides the ids from casual inspection, but it is not meant to
+/// protect secrets: a 64bit block cipher can be told apart from random by
+/// someone collecting billions of public ids.
+#[derive(Clone)]
+pub struct Scrambler {
+    round_keys: [u32; ROUNDS],
+}
+
+impl fmt::Debug for Scrambler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scrambler").finish_non_exhaustive()
+    }
+}
+
+impl Scrambler {
+    /// Expands `key`, whose words are read in little endian order.
+    pub const fn new(key: [u8; 16]) -> Self {
+        let mut l = [word(&key, 1), word(&key, 2), word(&key, 3)];
+        let mut round_keys = [0; ROUNDS];
+        round_keys[0] = word(&key, 0);
+        let mut i = 0;
+        while i < ROUNDS - 1 {
+            let (x, y) = round(l[i % 3], round_keys[i], i as u32);
+            l[i % 3] = x;
+            round_keys[i + 1] = y;
+            i += 1;
+        }
+
+        Scrambler { round_keys }
+    }
+
+    /// Public id of `id`.
+    pub const fn scramble(&self, id: u64) -> u64 {
+        let (mut x, mut y) = ((id >> 32) as u32, id as u32);
+        let mut i = 0;
+        while i < ROUNDS {
+            (x, y) = round(x, y, self.round_keys[i]);
+            i += 1;
+        }
+        ((x as u64) << 32) | y as u64
+    }
+
+    /// Id of `public_id`, the inverse of `scramble`.
+    pub const fn unscramble(&self, public_id: u64) -> u64 {
+        let (mut x, mut y) = ((public_id >> 32) as u32, public_id as u32);
+        let mut i = ROUNDS;
+        while i > 0 {
+            i -= 1;
+            y = (y ^ x).rotate_right(3);
+            x = (x ^ self.round_keys[i]).wrapping_sub(y).rotate_left(8);
+        }
+        ((x as u64) << 32) | y as u64
+    }
+}
+
+const fn word(key: &[u8; 16], i: usize) -> u32 {
+    u32::from_le_bytes([key[i * 4], key[i * 4 + 1], key[i * 4 + 2], key[i * 4 + 3]])
+}
+
+// a round of Speck, also used by the key schedule with the round number as
+// the key
+const fn round(x: u32, y: u32, k: u32) -> (u32, u32) {
+    let x = x.rotate_right(8).wrapping_add(y) ^ k;
+    (x, y.rotate_left(3) ^ x)
+}
+
+#[cfg(feature = "std")]
+impl crate::Generator {
+    /// Same as `generate`, scrambled by the scrambler of
+    /// `GeneratorOptions::scrambler`.
+    ///
+    /// Panics if the options have no scrambler.
+    pub fn generate_public(&self) -> u64 {
+        self.try_generate_public()
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate_public`, but returns an error instead of panicking
+    /// when generating fails.
+    pub fn try_generate_public(&self) -> Result<u64, crate::Error> {
+        let scrambler = self
+            .core()
+            .opts
+            .scrambler
+            .clone()
+            .ok_or(crate::OptionsError::NoScrambler)?;
+        Ok(scrambler.scramble(self.try_generate()?))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Error, Generator, GeneratorOptions, OptionsError};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_speck() {
+        // test vector of Speck64/128 from the paper of the cipher
+        let key = [0, 1, 2, 3, 8, 9, 10, 11, 16, 17, 18, 19, 24, 25, 26, 27];
+        let scrambler = Scrambler::new(key);
+        assert_eq!(scrambler.scramble(0x3b7265747475432d), 0x8c6fa548454e028b);
+        assert_eq!(scrambler.unscramble(0x8c6fa548454e028b), 0x3b7265747475432d);
+    }
+
+    #[test]
+    fn test_scramble() {
+        let scrambler = Scrambler::new(*b"sixteen byte key");
+        let other = Scrambler::new(*b"another byte key");
+
+        let ids: Vec<u64> = (0..10_000).chain([u64::MAX, 1 << 63]).collect();
+        let public: HashSet<_> = ids.iter().map(|&id| scrambler.scramble(id)).collect();
+        assert_eq!(public.len(), ids.len());
+        for &id in &ids {
+            assert_eq!(scrambler.unscramble(scrambler.scramble(id)), id);
+            assert_ne!(other.scramble(id), scrambler.scramble(id));
+        }
+        // neighbours are far apart
+        let (a, b) = (scrambler.scramble(100), scrambler.scramble(101));
+        assert!((a ^ b).count_ones() > 16);
+        assert_eq!(format!("{:?}", scrambler), "Scrambler { .. }");
+    }
+
+    #[test]
+    fn test_generate_public() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let scrambler = Scrambler::new(*b"sixteen byte key");
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .scrambler(scrambler.clone());
+        let g = Generator::new(opts);
+        assert_eq!(scrambler.unscramble(g.generate_public()), 123 << 22);
+        assert_eq!(
+            g.try_generate_public().map(|id| scrambler.unscramble(id)),
+            Ok((123 << 22) + 1)
+        );
+        // the ids themselves are not scrambled
+        assert_eq!(g.generate(), (123 << 22) + 2);
+
+        let g = Generator::new(GeneratorOptions::default());
+        assert_eq!(
+            g.try_generate_public(),
+            Err(Error::InvalidOptions(OptionsError::NoScrambler))
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::thread;
+
+use crate::{Error, Generator, GeneratorOptions, OptionsError};
+
+// ids generated at once by `par_assign`, per worker
+const CHUNK: usize = 1024;
+
+/// Assigns ids to large numbers of items on several threads, for backfills.
+///
+/// Every worker thread has a `Generator` of its own, numbered in the upper
+/// bits of the node number like the threads of a `ThreadLocalGenerator`, so
+/// the workers never contend and their ids never collide. The work is split
+/// into one contiguous part per worker, run on scoped threads.
+///
+/// Ids are ascending within the part of a worker, but not across parts.
+///
+/// ```rust
+/// use frostflake::{GeneratorOptions, ParallelIdAssigner};
+///
+/// struct Row {
+///     id: u64,
+/// }
+///
+/// let assigner = ParallelIdAssigner::new(GeneratorOptions::default(), 4).unwrap();
+/// let mut rows: Vec<_> = (0..100_000).map(|_| Row { id: 0 }).collect();
+/// assigner.par_assign(&mut rows, |row, id| row.id = id).unwrap();
+///
+/// let ids = assigner.par_generate(100_000).unwrap();
+/// assert_eq!(ids.len(), 100_000);
+/// ```
+pub struct ParallelIdAssigner {
+    generators: Vec<Generator>,
+}
+
+impl ParallelIdAssigner {
+    /// Numbers `workers` generators within the node bits of `opts`.
+    ///
+    /// Fails with [`OptionsError::TooManyThreads`] if the node bits can't
+    /// tell that many workers apart, and with
+    /// [`OptionsError::NodeOutOfRange`] if the node number doesn't fit in
+    /// the bits left beside the worker number, and with
+    /// [`OptionsError::ZeroCount`] for 0 workers.
+    pub fn new(opts: GeneratorOptions, workers: usize) -> Result<Self, OptionsError> {
+        if workers == 0 {
+            return Err(OptionsError::ZeroCount { name: "workers" });
+        }
+        let node_bits = opts.split_node_bits(workers as u64)?;
+
+        Ok(ParallelIdAssigner {
+            generators: (0..workers as u64)
+                .map(|worker| Generator::new(opts.split_node(worker, node_bits)))
+                .collect(),
+        })
+    }
+
+    pub fn workers(&self) -> usize {
+        self.generators.len()
+    }
+
+    /// Generates `n` ids on all workers.
+    pub fn par_generate(&self, n: usize) -> Result<Vec<u64>, Error> {
+        let mut ids = vec![0; n];
+        self.par_fill(&mut ids)?;
+        Ok(ids)
+    }
+
+    /// Fills `buf` with fresh ids on all workers.
+    ///
+    /// On error, the ids written so far are valid, but which part of `buf`
+    /// was written is unspecified, like `Generator::generate_into`.
+    pub fn par_fill(&self, buf: &mut [u64]) -> Result<(), Error> {
+        self.run(buf, |generator, part| generator.generate_into(part))
+    }
+
+    /// Calls `assign` with every item and a fresh id for it, on all workers.
+    pub fn par_assign<T, F>(&self, items: &mut [T], assign: F) -> Result<(), Error>
+    where
+        T: Send,
+        F: Fn(&mut T, u64) + Sync,
+    {
+        self.run(items, |generator, part| {
+            let mut ids = [0; CHUNK];
+            for chunk in part.chunks_mut(CHUNK) {
+                let ids = &mut ids[..chunk.len()];
+                generator.generate_into(ids)?;
+                for (item, id) in chunk.iter_mut().zip(ids.iter()) {
+                    assign(item, *id);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    // runs `f` on one part of `items` per worker, and returns the first error
+    fn run<T, F>(&self, items: &mut [T], f: F) -> Result<(), Error>
+    where
+        T: Send,
+        F: Fn(&Generator, &mut [T]) -> Result<(), Error> + Sync,
+    {
+        if items.is_empty() {
+            return Ok(());
+        }
+        let part = items.len().div_ceil(self.generators.len());
+        if part == items.len() {
+            return f(&self.generators[0], items);
+        }
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = items
+                .chunks_mut(part)
+                .zip(&self.generators)
+                .map(|(part, generator)| scope.spawn(|| f(generator, part)))
+                .collect();
+            handles.into_iter().try_for_each(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_par_generate() {
+        let assigner = ParallelIdAssigner::new(GeneratorOptions::default(), 8).unwrap();
+        assert_eq!(assigner.workers(), 8);
+
+        let ids = assigner.par_generate(200_000).unwrap();
+        assert_eq!(ids.len(), 200_000);
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+        // ascending within the part of each worker
+        for part in ids.chunks(25_000) {
+            assert!(part.windows(2).all(|w| w[0] < w[1]));
+        }
+
+        assert!(assigner.par_generate(0).unwrap().is_empty());
+        assert_eq!(assigner.par_generate(3).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_par_assign() {
+        let opts = GeneratorOptions::default().bits(42, 3, 19).node(1);
+        let assigner = ParallelIdAssigner::new(opts, 4).unwrap();
+
+        let mut rows = vec![(0u64, 0u64); 100_000];
+        assigner
+            .par_assign(&mut rows, |row, id| row.1 = id)
+            .unwrap();
+        let ids: HashSet<_> = rows.iter().map(|row| row.1).collect();
+        assert_eq!(ids.len(), rows.len());
+        // worker numbers above the node number
+        let nodes: HashSet<_> = ids.iter().map(|id| (id >> 19) & 7).collect();
+        assert_eq!(nodes, HashSet::from([1, 3, 5, 7]));
+
+        assert_eq!(
+            ParallelIdAssigner::new(GeneratorOptions::default().bits(42, 3, 19), 9).err(),
+            Some(OptionsError::TooManyThreads { threads: 9, max: 8 })
+        );
+        assert_eq!(
+            ParallelIdAssigner::new(GeneratorOptions::default(), 0).err(),
+            Some(OptionsError::ZeroCount { name: "workers" })
+        );
+    }
+}
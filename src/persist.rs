@@ -0,0 +1,357 @@
+//! Persisting the high-water timestamp across restarts.
+//!
+//! A generator with a [`StateBackend`] stores the timestamp of every new tick
+//! before issuing ids on it, and on startup refuses to issue ids on or before
+//! the stored timestamp. So a restarted process never reissues ids, even if
+//! the clock was set back in the meantime.
+//!
+//! [`FileState`] keeps the timestamp in a plain file on any platform, and
+//! `MmapState` with the `mmap` feature in a memory-mapped one on unix.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::Core;
+
+/// Storage for the high-water timestamp.
+pub trait StateBackend {
+    /// Loads the stored timestamp, if any.
+    fn load(&self) -> Option<u64>;
+    /// Stores `ts`, which is called once per tick and should be cheap.
+    fn store(&self, ts: u64);
+}
+
+impl Core {
+    /// Continues after the high-water timestamp of the state backend.
+    pub(crate) fn load_high_water(&mut self) {
+        let Some(ts) = self.opts.state_backend.as_ref().and_then(|b| b.load()) else {
+            return;
+        };
+        if ts >= self.last_ts {
+            // ids of that tick may have been issued; skip to the next one
+            self.last_ts = ts;
+            self.seq = self.compiled.max_seq;
+        }
+    }
+}
+
+/// State backend checkpointing the high-water timestamp to a file.
+///
+/// Writing the file on every tick would make generating as slow as the disk,
+/// so the file instead holds a timestamp ahead of the last issued one, by the
+/// checkpoint interval. It is rewritten and synced only when a tick passes
+/// it, at most once per interval. After a restart, the generator issues no
+/// ids until the clock passes the stored timestamp, so restarting may pause
+/// generating for up to the interval.
+///
+/// The file holds the timestamp as decimal text, and should not be shared by
+/// generators with different node numbers. A failed write is retried on the
+/// next tick.
+///
+/// ```rust
+/// use frostflake::persist::FileState;
+/// use frostflake::{Generator, GeneratorOptions};
+///
+/// let path = std::env::temp_dir().join("frostflake-file-doctest");
+/// let opts = GeneratorOptions::default().state_backend(FileState::open(&path).unwrap());
+/// let generator = Generator::new(opts);
+/// generator.generate();
+/// ```
+#[derive(Debug)]
+pub struct FileState {
+    file: Mutex<File>,
+    interval: u64,
+    loaded: Option<u64>,
+    checkpoint: AtomicU64,
+}
+
+impl FileState {
+    /// Opens or creates the state file at `path`, checkpointing 1000 ticks,
+    /// a second by default, ahead.
+    ///
+    /// Fails if the file holds something other than a timestamp.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        let loaded = match text.trim() {
+            "" => None,
+            ts => Some(
+                ts.parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            ),
+        };
+
+        Ok(FileState {
+            file: Mutex::new(file),
+            interval: 1000,
+            loaded,
+            checkpoint: AtomicU64::new(loaded.unwrap_or(0)),
+        })
+    }
+
+    /// Sets how far ahead of the last issued timestamp the file is written,
+    /// in ticks of the generator, which are milliseconds by default. An
+    /// interval of 0 rewrites the file for every id.
+    pub fn interval(mut self, ticks: u64) -> Self {
+        self.interval = ticks;
+        self
+    }
+
+    fn write(&self, ts: u64) -> io::Result<()> {
+        // the lock poisons only on a panic while writing, which is retried
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        writeln!(file, "{}", ts)?;
+        file.sync_data()
+    }
+}
+
+impl StateBackend for FileState {
+    fn load(&self) -> Option<u64> {
+        self.loaded
+    }
+
+    fn store(&self, ts: u64) {
+        let checkpoint = self.checkpoint.load(Ordering::Acquire);
+        if ts < checkpoint {
+            return;
+        }
+        let next = ts.saturating_add(self.interval);
+        if self.write(next).is_ok() {
+            self.checkpoint.fetch_max(next, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "mmap"))]
+pub use mmap::MmapState;
+
+#[cfg(all(unix, feature = "mmap"))]
+mod mmap {
+    use std::fs::OpenOptions;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::StateBackend;
+
+    /// State backend keeping the high-water timestamp in a memory-mapped file.
+    ///
+    /// Storing is a single atomic write to shared memory, without a system
+    /// call or fsync. The kernel writes the page back on its own, so the
+    /// timestamp survives the process being killed, even with `SIGKILL`, but
+    /// not necessarily a power loss or kernel crash.
+    ///
+    /// The file is 8 bytes in native byte order, and should not be shared by
+    /// generators with different node numbers.
+    ///
+    /// ```rust
+    /// use frostflake::persist::MmapState;
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let path = std::env::temp_dir().join("frostflake-mmap-doctest");
+    /// let opts = GeneratorOptions::default().state_backend(MmapState::open(&path).unwrap());
+    /// let generator = Generator::new(opts);
+    /// generator.generate();
+    /// ```
+    pub struct MmapState {
+        ptr: NonNull<AtomicU64>,
+    }
+
+    // the mapping is only accessed through the atomic
+    unsafe impl Send for MmapState {}
+    unsafe impl Sync for MmapState {}
+
+    const LEN: usize = std::mem::size_of::<u64>();
+
+    impl MmapState {
+        /// Opens or creates the state file at `path`.
+        pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            if file.metadata()?.len() < LEN as u64 {
+                file.set_len(LEN as u64)?;
+            }
+
+            // SAFETY: mapping a file we opened read-write, with a length
+            // within the file; the result is checked below
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    LEN,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            // mappings are page aligned, so this is aligned for AtomicU64
+            let ptr = NonNull::new(ptr as *mut AtomicU64)
+                .ok_or_else(|| io::Error::other("mmap returned null"))?;
+            Ok(MmapState { ptr })
+        }
+
+        fn atomic(&self) -> &AtomicU64 {
+            // SAFETY: the mapping is valid until drop
+            unsafe { self.ptr.as_ref() }
+        }
+    }
+
+    impl StateBackend for MmapState {
+        fn load(&self) -> Option<u64> {
+            Some(self.atomic().load(Ordering::Acquire)).filter(|ts| *ts != 0)
+        }
+
+        fn store(&self, ts: u64) {
+            self.atomic().fetch_max(ts, Ordering::Release);
+        }
+    }
+
+    impl Drop for MmapState {
+        fn drop(&mut self) {
+            // SAFETY: unmapping the mapping created in open
+            unsafe {
+                libc::munmap(self.ptr.as_ptr() as *mut libc::c_void, LEN);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_mmap_state() {
+            let path = std::env::temp_dir().join(format!("frostflake-mmap-{}", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+
+            let state = MmapState::open(&path).unwrap();
+            assert_eq!(state.load(), None);
+            state.store(123);
+            state.store(100);
+            assert_eq!(state.load(), Some(123));
+            drop(state);
+
+            let state = MmapState::open(&path).unwrap();
+            assert_eq!(state.load(), Some(123));
+            drop(state);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Generator, GeneratorOptions};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    struct Memory(AtomicU64);
+
+    impl StateBackend for Memory {
+        fn load(&self) -> Option<u64> {
+            Some(self.0.load(Ordering::SeqCst)).filter(|ts| *ts != 0)
+        }
+
+        fn store(&self, ts: u64) {
+            self.0.store(ts, Ordering::SeqCst);
+        }
+    }
+
+    impl StateBackend for Arc<Memory> {
+        fn load(&self) -> Option<u64> {
+            (**self).load()
+        }
+
+        fn store(&self, ts: u64) {
+            (**self).store(ts)
+        }
+    }
+
+    #[test]
+    fn test_state_backend() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let memory = Arc::new(Memory(AtomicU64::new(0)));
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .overflow_policy(crate::OverflowPolicy::Error)
+            .state_backend(memory.clone());
+
+        let g = Generator::new(opts.clone());
+        assert_eq!(g.try_generate(), Ok(123 << 22));
+        assert_eq!(memory.load(), Some(1483228800000 + 123));
+
+        // a restart within the same tick must not reissue its ids
+        let g = Generator::new(opts);
+        assert_eq!(g.try_generate(), Err(crate::Error::SequenceExhausted));
+    }
+
+    #[test]
+    fn test_file_state() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 10);
+        fn my_time_fn() -> u64 {
+            NOW.load(Ordering::SeqCst)
+        }
+
+        let path = std::env::temp_dir().join(format!("frostflake-file-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let opts = |state| {
+            GeneratorOptions::default()
+                .time_fn(my_time_fn)
+                .overflow_policy(crate::OverflowPolicy::Error)
+                .state_backend(state)
+        };
+
+        let state = FileState::open(&path).unwrap().interval(100);
+        assert_eq!(state.load(), None);
+        let g = Generator::new(opts(state));
+        g.generate();
+        NOW.store(1483228800000 + 50, Ordering::SeqCst);
+        g.generate();
+        drop(g);
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, "1483228800110\n");
+
+        // the clock was set back before the restart
+        NOW.store(1483228800000 + 60, Ordering::SeqCst);
+        let state = FileState::open(&path).unwrap();
+        assert_eq!(state.load(), Some(1483228800110));
+        let g = Generator::new(opts(state));
+        assert!(matches!(
+            g.try_generate(),
+            Err(crate::Error::ClockMovedBackwards { .. })
+        ));
+        NOW.store(1483228800000 + 111, Ordering::SeqCst);
+        assert_eq!(g.try_generate(), Ok(111 << 22));
+        drop(g);
+
+        std::fs::write(&path, "garbage").unwrap();
+        assert!(FileState::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,199 +1,533 @@
-use std::sync::Arc;
-use std::thread;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
 
 use crossbeam::channel::unbounded;
-use crossbeam::channel::Sender;
+use crossbeam::channel::{select, Receiver, RecvTimeoutError, Sender};
 
-use super::{Generator, GeneratorOptions};
+use super::error::panic_message;
+use super::{Error, Generator, GeneratorProgress, OptionsError};
+use crate::decode::PoolDecoded;
+use crate::pool_options::PoolClock;
 
-#[derive(Clone)]
-pub struct GeneratorPoolOptions {
-    bits: (u8, u8, u8, u8), // time, pool, node, seq
-    node: u64,
-    base_ts: u64,
-    time_fn: fn() -> u64,
+pub use crate::pool_options::{GeneratorPoolOptions, PoolLayout};
+
+enum Message {
+    // routine requests, answered through the reply slot of the caller
+    Generate(Reply),
+    Job {
+        tx: Sender<Result<u64, Error>>,
+        deadline: Option<Instant>,
+    },
+    Batch {
+        tx: Sender<Result<Vec<u64>, Error>>,
+        n: usize,
+    },
+    Progress(Sender<GeneratorProgress>),
+    // requests of async callers, answered without blocking them
+    #[cfg(feature = "tokio")]
+    Async(tokio::sync::oneshot::Sender<Result<u64, Error>>),
 }
 
-impl Default for GeneratorPoolOptions {
-    fn default() -> GeneratorPoolOptions {
-        GeneratorPoolOptions {
-            bits: (42, 4, 6, 12),
-            base_ts: 1483228800000, // 2017-01-01T00:00:00Z as milliseconds
-            node: 0,
-            time_fn: super::default_time_fn,
-        }
-    }
+/// Slot a calling thread receives its ids in, reused across requests so
+/// that routine generation doesn't allocate a channel per id.
+struct Slot {
+    state: Mutex<SlotState>,
+    ready: Condvar,
 }
 
-impl GeneratorPoolOptions {
-    pub fn bits(mut self, ts_bits: u8, pool_bits: u8, node_bits: u8, seq_bits: u8) -> Self {
-        assert!(
-            64 == ts_bits + pool_bits + node_bits + seq_bits,
-            "bits set should be total 64bit"
-        );
-        assert!(
-            self.base_ts <= super::max(ts_bits),
-            "base_ts exceeds ts_bits limit, set base_ts first"
-        );
-        assert!(
-            self.node <= super::max(node_bits),
-            "node number exceeds node_bits limit, set node number first"
-        );
+enum SlotState {
+    Waiting,
+    Done(Result<u64, Error>),
+    // the request was dropped without an answer
+    Abandoned,
+}
 
-        self.bits = (ts_bits, pool_bits, node_bits, seq_bits);
-        self
-    }
+thread_local! {
+    static SLOT: Arc<Slot> = Arc::new(Slot {
+        state: Mutex::new(SlotState::Waiting),
+        ready: Condvar::new(),
+    });
+}
 
-    pub fn base_ts(mut self, base_ts: u64) -> Self {
-        assert!(
-            base_ts <= super::max(self.bits.0),
-            "base_ts exceeds ts_bits limit, set bit width first"
-        );
+impl Slot {
+    fn lock(&self) -> std::sync::MutexGuard<'_, SlotState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
 
-        self.base_ts = base_ts;
-        self
+    fn wait(&self) -> Result<u64, Error> {
+        let mut state = self.lock();
+        loop {
+            match std::mem::replace(&mut *state, SlotState::Waiting) {
+                SlotState::Waiting => {
+                    state = self.ready.wait(state).unwrap_or_else(|e| e.into_inner())
+                }
+                SlotState::Done(result) => return result,
+                SlotState::Abandoned => return Err(Error::ChannelClosed),
+            }
+        }
     }
+}
 
-    pub fn node(mut self, node: u64) -> Self {
-        assert!(
-            node <= super::max(self.bits.2),
-            "node number exceeds node_bits limit, set bit width first"
-        );
+/// Answer side of a [`Slot`], held by the worker.
+///
+/// The slot is released once answered, as it may be waiting for the next
+/// request of the caller by the time this is dropped.
+struct Reply(Option<Arc<Slot>>);
 
-        self.node = node;
-        self
+impl Reply {
+    fn new(slot: &Arc<Slot>) -> Self {
+        Reply(Some(slot.clone()))
     }
 
-    pub fn time_fn(mut self, time_fn: fn() -> u64) -> Self {
-        self.time_fn = time_fn;
-        self
+    fn send(mut self, result: Result<u64, Error>) {
+        if let Some(slot) = self.0.take() {
+            *slot.lock() = SlotState::Done(result);
+            slot.ready.notify_one();
+        }
     }
 }
 
-enum Message {
-    Job(Sender<u64>),
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0.take() {
+            *slot.lock() = SlotState::Abandoned;
+            slot.ready.notify_one();
+        }
+    }
+}
+
+/// Lane a request is queued in.
+///
+/// Workers always serve queued `High` requests before `Low` ones, so bulk
+/// jobs sharing the pool don't add latency to interactive traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    #[default]
+    High,
+    Low,
 }
 
+/// Generates ids on `size` worker threads, each with a node number of its own.
+///
+/// Every worker has its own queues, and requests are handed to the workers
+/// in turn, so callers don't contend on a single queue and throughput grows
+/// with the number of workers.
 pub struct GeneratorPool {
     size: usize,
     opts: GeneratorPoolOptions,
-    tx: Sender<Message>,
+    // lanes of each worker, None once shut down
+    lanes: RwLock<Option<Vec<Lanes>>>,
+    // worker the next request goes to
+    next: AtomicUsize,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    clock: Option<Arc<PoolClock>>,
+}
+
+struct Lanes {
+    high: Sender<Message>,
+    low: Sender<Message>,
 }
 
 impl GeneratorPool {
+    /// Panics if `size` exceeds the workers allowed by the pool bits, see
+    /// [`try_new`](Self::try_new).
     pub fn new(size: usize, opts: GeneratorPoolOptions) -> Arc<GeneratorPool> {
-        let generator_opts = GeneratorPool::generator_opts(opts.clone());
+        GeneratorPool::try_new(size, opts).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Fails with [`OptionsError::PoolTooLarge`] if `size` exceeds
+    /// 2^`pool_bits`, as workers would share pool numbers and generate
+    /// colliding ids.
+    pub fn try_new(
+        size: usize,
+        mut opts: GeneratorPoolOptions,
+    ) -> Result<Arc<GeneratorPool>, OptionsError> {
+        let max_workers = opts.max_workers();
+        if size as u64 > max_workers {
+            return Err(OptionsError::PoolTooLarge {
+                size,
+                max: max_workers,
+            });
+        }
+
+        let clock = opts.share_clock();
+        // with strict ordering, all workers take ids from the same generator
+        let shared = opts
+            .strict_ordering
+            .then(|| Arc::new(Generator::new(opts.worker_opts(0))));
 
-        let (tx, rx) = unbounded::<Message>();
+        #[cfg(feature = "affinity")]
+        let cores = opts.affinity.cores(size);
 
+        let mut lanes = Vec::with_capacity(size);
+        let mut workers = Vec::with_capacity(size);
         for i in 0..size {
-            let rx = rx.clone();
+            // each worker has queues of its own, so that callers don't contend
+            // on a single queue
+            let (tx, rx) = unbounded::<Message>();
+            let (low_tx, low_rx) = unbounded::<Message>();
+            lanes.push(Lanes {
+                high: tx,
+                low: low_tx,
+            });
 
-            let (_, _, node_bits, _) = opts.bits;
-            let pool_mask = super::bitmask(node_bits);
-            let node_mask = super::max(node_bits);
+            let mut generator = match &shared {
+                Some(generator) => generator.clone(),
+                None => Arc::new(Generator::new(opts.worker_opts(i as u64))),
+            };
 
-            let opts = generator_opts
-                .clone()
-                .node((((i as u64) << node_bits) & pool_mask) | (opts.node & node_mask));
+            let mut builder = thread::Builder::new();
+            if let Some(name) = &opts.thread_name {
+                builder = builder.name(format!("{}-{}", name, i));
+            }
+            #[cfg(feature = "affinity")]
+            let core = cores.get(i).copied().flatten();
 
-            thread::spawn(move || {
-                let mut generator = Generator::new(opts);
+            // a worker that fails to spawn drops its lanes, and is skipped
+            // like one that exited
+            let spawned = builder.spawn(move || {
+                #[cfg(feature = "affinity")]
+                if let Some(core) = core {
+                    // unpinned workers still work, just less steadily
+                    let _ = crate::affinity::pin(core);
+                }
 
-                while let Ok(msg) = rx.recv() {
+                while let Some(msg) = next_message(&rx, &low_rx) {
+                    // the caller may have stopped waiting in the meantime, so
+                    // failed sends are ignored
                     match msg {
-                        Message::Job(tx) => {
-                            if let Err(e) = tx.send(generator.generate()) {
-                                eprintln!("Failed to send generated result: {:?}", e);
-                            }
+                        Message::Generate(reply) => {
+                            reply.send(supervised(&mut generator, |g| g.try_generate()));
+                        }
+                        Message::Job {
+                            tx,
+                            deadline: Some(deadline),
+                        } if deadline <= Instant::now() => {
+                            // the caller stopped waiting; don't waste a sequence number
+                            let _ = tx.send(Err(Error::DeadlineExceeded));
+                        }
+                        Message::Job { tx, .. } => {
+                            let _ = tx.send(supervised(&mut generator, |g| g.try_generate()));
+                        }
+                        Message::Batch { tx, n } => {
+                            let _ = tx.send(supervised(&mut generator, |g| {
+                                let mut ids = vec![0; n];
+                                g.generate_into(&mut ids).map(|_| ids)
+                            }));
+                        }
+                        Message::Progress(tx) => {
+                            let _ = tx.send(generator.progress());
+                        }
+                        #[cfg(feature = "tokio")]
+                        Message::Async(tx) => {
+                            let _ = tx.send(supervised(&mut generator, |g| g.try_generate()));
                         }
                     }
                 }
             });
+            if let Ok(worker) = spawned {
+                workers.push(worker);
+            }
         }
 
-        Arc::new(GeneratorPool { size, opts, tx })
+        Ok(Arc::new(GeneratorPool {
+            size,
+            opts,
+            lanes: RwLock::new(Some(lanes)),
+            next: AtomicUsize::new(0),
+            workers: Mutex::new(workers),
+            clock,
+        }))
     }
 
-    fn generator_opts(opts: GeneratorPoolOptions) -> GeneratorOptions {
-        GeneratorOptions::default()
-            .base_ts(0)
-            .bits(opts.bits.0, opts.bits.1 + opts.bits.2, opts.bits.3)
-            .base_ts(opts.base_ts)
-            .time_fn(opts.time_fn)
+    /// Creates as many workers as the pool bits allow, but no more than the
+    /// available parallelism, since additional workers would only compete
+    /// for the same cores.
+    pub fn with_auto_size(opts: GeneratorPoolOptions) -> Arc<GeneratorPool> {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        let size = opts.max_workers().min(cores as u64) as usize;
+        GeneratorPool::new(size, opts)
     }
 
-    pub fn generate(&self) -> u64 {
-        let (tx, rx) = unbounded();
+    /// Stops the workers and waits for them to exit.
+    ///
+    /// Requests queued before the call are still answered. Later requests
+    /// fail with [`Error::ChannelClosed`]. This is also done when the pool is
+    /// dropped, so worker threads don't outlive it.
+    pub fn shutdown(&self) {
+        // dropping the senders disconnects the lanes once they are drained
+        self.lanes.write().unwrap_or_else(|e| e.into_inner()).take();
 
-        if let Err(e) = self.tx.send(Message::Job(tx)) {
-            eprintln!("failed to send generate request: {:?}", e);
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap_or_else(|e| e.into_inner()));
+        for worker in workers {
+            let _ = worker.join();
         }
+    }
 
-        rx.recv().unwrap()
+    /// Queues `msg` at the workers in turn.
+    ///
+    /// A worker whose thread is gone is skipped, so its share of requests
+    /// goes to the others; only when no worker is left this fails.
+    fn send(&self, priority: Priority, mut msg: Message) -> Result<(), Error> {
+        let lanes = self.lanes.read().unwrap_or_else(|e| e.into_inner());
+        let lanes = match lanes.as_deref() {
+            Some(lanes) if !lanes.is_empty() => lanes,
+            _ => return Err(Error::ChannelClosed),
+        };
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for i in 0..lanes.len() {
+            let lanes = &lanes[(start + i) % lanes.len()];
+            let lane = match priority {
+                Priority::High => &lanes.high,
+                Priority::Low => &lanes.low,
+            };
+            match lane.send(msg) {
+                Ok(()) => {
+                    if let Some(metrics) = &self.opts.metrics {
+                        metrics.queued(lane.len());
+                    }
+                    return Ok(());
+                }
+                Err(e) => msg = e.into_inner(),
+            }
+        }
+        Err(Error::ChannelClosed)
     }
 
-    pub fn extract(&self, id: u64) -> (u64, u64, u64, u64) {
-        let g = Generator::new(GeneratorPool::generator_opts(self.opts.clone()));
-        let (_, pool_bits, node_bits, _) = self.opts.bits;
-        let (ts, poolnode, seq) = g.extract(id);
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of time readings behind one another worker has seen, and the
+    /// largest distance behind, with `GeneratorPoolOptions::shared_clock`.
+    pub fn clock_skew(&self) -> Option<(u64, u64)> {
+        self.clock
+            .as_ref()
+            .map(|clock| (clock.skewed(), clock.max_skew()))
+    }
 
-        let pool = (poolnode >> node_bits) & super::max(pool_bits);
-        let node = poolnode & super::max(node_bits);
+    /// Errors of the worker, like `SequenceExhausted`, are passed through.
+    /// A panic while generating fails with [`Error::WorkerPanicked`], and the
+    /// worker carries on with a restarted generator. [`Error::ChannelClosed`]
+    /// is returned if no worker is running, or if the worker exits before
+    /// answering.
+    pub fn generate(&self) -> Result<u64, Error> {
+        self.generate_with_priority(Priority::High)
+    }
+
+    /// Same as `generate`, named after `Generator::try_generate`.
+    pub fn try_generate(&self) -> Result<u64, Error> {
+        self.generate()
+    }
 
-        (ts, pool, node, seq)
+    /// Requests are answered through a slot reused by the calling thread, so
+    /// this doesn't allocate apart from the growth of the queue.
+    pub fn generate_with_priority(&self, priority: Priority) -> Result<u64, Error> {
+        SLOT.with(|slot| {
+            *slot.lock() = SlotState::Waiting;
+            self.send(priority, Message::Generate(Reply::new(slot)))?;
+            slot.wait()
+        })
+    }
+
+    /// Gives up with [`Error::DeadlineExceeded`] if no id is generated by `deadline`.
+    ///
+    /// Workers skip requests whose deadline has passed by the time they are
+    /// dequeued, so an overloaded pool doesn't spend sequence numbers on ids
+    /// nobody waits for anymore.
+    pub fn generate_with_deadline(
+        &self,
+        priority: Priority,
+        deadline: Instant,
+    ) -> Result<u64, Error> {
+        // a late answer must not end up in the slot of the next request, so
+        // this takes a channel of its own
+        let (tx, rx) = unbounded();
+        let job = Message::Job {
+            tx,
+            deadline: Some(deadline),
+        };
+        self.send(priority, job)?;
+        rx.recv_deadline(deadline).map_err(|e| match e {
+            RecvTimeoutError::Timeout => Error::DeadlineExceeded,
+            RecvTimeoutError::Disconnected => Error::ChannelClosed,
+        })?
+    }
+
+    /// Fills `buf` with fresh ids, generated by a single worker in one go.
+    ///
+    /// Unlike `Generator::generate_into`, this allocates a buffer for passing
+    /// the ids from the worker.
+    pub fn generate_into(&self, buf: &mut [u64]) -> Result<(), Error> {
+        let ids = self.generate_many(buf.len())?;
+        buf.copy_from_slice(&ids);
+        Ok(())
+    }
+
+    /// Generates `n` ids in ascending order, by a single worker in one go.
+    ///
+    /// This pays the channel overhead once instead of once per id.
+    pub fn generate_many(&self, n: usize) -> Result<Vec<u64>, Error> {
+        let (tx, rx) = unbounded();
+        self.send(Priority::High, Message::Batch { tx, n })?;
+
+        rx.recv().map_err(|_| Error::ChannelClosed)?
+    }
+
+    /// Asks every worker for `Generator::progress`, for showing how far
+    /// each one got.
+    ///
+    /// The answer of a worker comes after the requests queued at it before,
+    /// so a worker stuck on a request holds this up too. A worker that is
+    /// gone reports `None`, as do all workers after a shutdown.
+    pub fn progress(&self) -> Vec<Option<GeneratorProgress>> {
+        let guard = self.lanes.read().unwrap_or_else(|e| e.into_inner());
+        let Some(lanes) = guard.as_deref() else {
+            return vec![None; self.size];
+        };
+
+        // the lanes are released before waiting, so a shutdown isn't held up
+        let answers: Vec<_> = lanes
+            .iter()
+            .map(|lanes| {
+                let (tx, rx) = unbounded();
+                lanes.high.send(Message::Progress(tx)).ok().map(|_| rx)
+            })
+            .collect();
+        drop(guard);
+        answers
+            .into_iter()
+            .map(|rx| rx.and_then(|rx| rx.recv().ok()))
+            .collect()
+    }
+
+    /// Same as `GeneratorPoolOptions::layout().decode(id)`.
+    pub fn extract(&self, id: u64) -> (u64, u64, u64, u64) {
+        self.opts.layout().decode(id)
+    }
+
+    /// Same as `extract`, with the fields named and the timestamp converted
+    /// to wall-clock time.
+    pub fn decode(&self, id: u64) -> PoolDecoded {
+        self.opts.decode(id)
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::sync::Mutex;
+/// Async handle of a [`GeneratorPool`], for programs mixing threads and
+/// tokio tasks that share one pool.
+///
+/// Requests are queued like those of `GeneratorPool::generate`, and the
+/// worker answers through a oneshot channel, so awaiting an id doesn't block
+/// the runtime, and no `spawn_blocking` thread is taken. Handles are cheap to
+/// clone, and the pool is shut down once the last handle and `Arc` of it are
+/// dropped.
+///
+/// ```rust
+/// use frostflake::{GeneratorPool, GeneratorPoolHandleAsync, GeneratorPoolOptions};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let pool = GeneratorPool::new(2, GeneratorPoolOptions::default());
+/// let handle = GeneratorPoolHandleAsync::new(pool.clone());
+///
+/// let from_thread = std::thread::spawn(move || pool.generate().unwrap());
+/// let from_task = handle.generate().await.unwrap();
+/// assert_ne!(from_task, from_thread.join().unwrap());
+/// # }
+/// ```
+#[cfg(feature = "tokio")]
+#[derive(Clone)]
+pub struct GeneratorPoolHandleAsync {
+    pool: Arc<GeneratorPool>,
+}
 
-    #[test]
-    fn test_options_default() {
-        let opts = GeneratorPoolOptions::default();
-        assert_eq!(opts.bits, (42, 4, 6, 12));
-        assert_eq!(opts.node, 0);
-        assert_eq!(opts.base_ts, 1483228800000);
+#[cfg(feature = "tokio")]
+impl GeneratorPoolHandleAsync {
+    pub fn new(pool: Arc<GeneratorPool>) -> Self {
+        GeneratorPoolHandleAsync { pool }
     }
 
-    #[test]
-    fn test_options_set_base() {
-        let opts = GeneratorPoolOptions::default().base_ts(123);
-        assert_eq!(opts.base_ts, 123);
+    /// The pool, for generating from blocking code.
+    pub fn pool(&self) -> &Arc<GeneratorPool> {
+        &self.pool
     }
 
-    #[test]
-    #[should_panic]
-    fn test_options_set_base_crash() {
-        let max = super::super::max(42);
-        let _ = GeneratorPoolOptions::default().base_ts(max + 1);
+    /// Same as `GeneratorPool::generate`, without blocking the runtime.
+    pub async fn generate(&self) -> Result<u64, Error> {
+        self.generate_with_priority(Priority::High).await
     }
 
-    #[test]
-    fn test_options_set_node() {
-        let opts = GeneratorPoolOptions::default().node(10);
-        assert_eq!(opts.node, 10);
+    /// Same as `generate`, named after `Generator::try_generate`.
+    pub async fn try_generate(&self) -> Result<u64, Error> {
+        self.generate().await
     }
 
-    #[test]
-    #[should_panic]
-    fn test_options_set_node_crash() {
-        let max = super::super::max(6);
-        let _ = GeneratorPoolOptions::default().node(max + 1);
+    /// Same as `GeneratorPool::generate_with_priority`, without blocking the
+    /// runtime.
+    ///
+    /// A request whose future is dropped is still answered by the worker,
+    /// spending its id.
+    pub async fn generate_with_priority(&self, priority: Priority) -> Result<u64, Error> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.pool.send(priority, Message::Async(tx))?;
+        rx.await.map_err(|_| Error::ChannelClosed)?
     }
+}
 
-    #[test]
-    fn test_options_set_time_fn() {
-        fn test_fn() -> u64 {
-            1483228800000 + 123
+/// Runs `f`, failing with [`Error::WorkerPanicked`] and restarting the
+/// generator if it panics, so that a panicking `time_fn` or the `Panic`
+/// overflow policy doesn't take the worker down.
+///
+/// The generator shared by strict ordering is kept, as a panic doesn't
+/// leave its state half updated.
+fn supervised<T>(
+    generator: &mut Arc<Generator>,
+    f: impl FnOnce(&Generator) -> Result<T, Error>,
+) -> Result<T, Error> {
+    match catch_unwind(AssertUnwindSafe(|| f(generator))) {
+        Ok(result) => result,
+        Err(panic) => {
+            if let Some(generator) = Arc::get_mut(generator) {
+                generator.restart();
+            }
+            Err(Error::WorkerPanicked {
+                reason: panic_message(&panic),
+            })
         }
+    }
+}
 
-        let opts = GeneratorPoolOptions::default().time_fn(test_fn);
-        assert_eq!((opts.time_fn)(), 1483228800000 + 123);
+impl Drop for GeneratorPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Waits for the next message, preferring the high priority lane.
+fn next_message(high: &Receiver<Message>, low: &Receiver<Message>) -> Option<Message> {
+    if let Ok(msg) = high.try_recv() {
+        return Some(msg);
+    }
+    if let Ok(msg) = low.try_recv() {
+        return Some(msg);
     }
 
+    // both lanes are empty; take whichever gets a message first
+    select! {
+        recv(high) -> msg => msg.ok(),
+        recv(low) -> msg => msg.ok(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::OverflowPolicy;
+    use std::sync::Mutex;
+
     #[cfg(test)]
     use std::collections::HashMap;
 
@@ -211,7 +545,7 @@ mod test {
             handles.push(thread::spawn(move || {
                 for _ in 0..10 {
                     let mut results = results.lock().unwrap();
-                    results.push(pool.generate());
+                    results.push(pool.generate().unwrap());
                 }
             }));
         }
@@ -224,10 +558,147 @@ mod test {
         let results = results.lock().unwrap();
         for r in results.iter() {
             // check uniqueness
-            assert_eq!(hash.contains_key(r), false);
+            assert!(!hash.contains_key(r));
             hash.insert(*r, 1);
-            assert_eq!(hash.contains_key(r), true);
+            assert!(hash.contains_key(r));
+        }
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let pool = GeneratorPool::new(3, GeneratorPoolOptions::default());
+        assert!(pool.generate().is_ok());
+
+        let waiting: Vec<_> = (0..5)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || pool.generate())
+            })
+            .collect();
+        pool.shutdown();
+        assert!(pool.workers.lock().unwrap().is_empty());
+        assert_eq!(pool.generate(), Err(Error::ChannelClosed));
+        assert_eq!(pool.generate_many(3), Err(Error::ChannelClosed));
+
+        // requests racing with the shutdown finish either way
+        for handle in waiting {
+            let _ = handle.join().unwrap();
         }
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_pool_size() {
+        let r = GeneratorPool::try_new(17, GeneratorPoolOptions::default());
+        assert_eq!(
+            r.err(),
+            Some(OptionsError::PoolTooLarge { size: 17, max: 16 })
+        );
+        assert!(GeneratorPool::try_new(16, GeneratorPoolOptions::default()).is_ok());
+
+        // without pool bits, a single worker
+        let opts = GeneratorPoolOptions::default().bits(42, 0, 10, 12).node(5);
+        let r = GeneratorPool::try_new(2, opts.clone());
+        assert_eq!(
+            r.err(),
+            Some(OptionsError::PoolTooLarge { size: 2, max: 1 })
+        );
+        let pool = GeneratorPool::new(1, opts);
+        let (_, pool_id, node, _) = pool.extract(pool.generate().unwrap());
+        assert_eq!((pool_id, node), (0, 5));
+
+        let opts = GeneratorPoolOptions::default()
+            .base_ts(0)
+            .bits(44, 1, 7, 12)
+            .base_ts(1483228800000);
+        let pool = GeneratorPool::with_auto_size(opts);
+        assert!((1..=2).contains(&pool.size()));
+        assert!(pool.generate().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "pool size 20 exceeds 16 workers")]
+    fn test_pool_size_crash() {
+        let _ = GeneratorPool::new(20, GeneratorPoolOptions::default());
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let pool = GeneratorPool::new(3, GeneratorPoolOptions::default());
+        let workers: Vec<_> = (0..6)
+            .map(|_| pool.extract(pool.generate().unwrap()).1)
+            .collect();
+        assert_eq!(workers, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_strict_ordering() {
+        let opts = GeneratorPoolOptions::default().strict_ordering(true);
+        let pool = GeneratorPool::new(3, opts);
+
+        let mut last = 0;
+        for _ in 0..1000 {
+            let id = pool.generate().unwrap();
+            assert!(id > last);
+            assert_eq!(pool.extract(id).1, 0);
+            last = id;
+        }
+
+        let ids = pool.generate_many(10).unwrap();
+        assert!(ids[0] > last);
+    }
+
+    #[test]
+    fn test_shared_clock() {
+        use std::sync::atomic::AtomicU64;
+
+        // steps back by 10ms on every other reading
+        let readings = Arc::new(AtomicU64::new(0));
+        let clock = move || 1483228800000 + 100 - readings.fetch_add(1, Ordering::SeqCst) % 2 * 10;
+        let opts = GeneratorPoolOptions::default().time_source(clock);
+
+        let pool = GeneratorPool::new(2, opts.clone().shared_clock(true));
+        let mut ids = std::collections::HashSet::new();
+        for _ in 0..200 {
+            assert!(ids.insert(pool.generate().unwrap()));
+        }
+        let (skewed, max_skew) = pool.clock_skew().unwrap();
+        assert!(skewed >= 99);
+        assert_eq!(max_skew, 10);
+
+        let pool = GeneratorPool::new(1, opts);
+        assert_eq!(pool.clock_skew(), None);
+        assert!((0..4).any(|_| matches!(pool.generate(), Err(Error::ClockMovedBackwards { .. }))));
+    }
+
+    #[test]
+    fn test_thread_name() {
+        let names = Arc::new(Mutex::new(std::collections::HashSet::new()));
+        let seen = names.clone();
+        let clock = move || {
+            let name = thread::current().name().map(str::to_string);
+            seen.lock().unwrap().insert(name);
+            1483228800000 + 1
+        };
+        let opts = GeneratorPoolOptions::default()
+            .time_source(clock)
+            .thread_name("ids");
+
+        let pool = GeneratorPool::new(2, opts);
+        for _ in 0..10 {
+            pool.generate().unwrap();
+        }
+        let names = names.lock().unwrap();
+        assert!(names.contains(&Some("ids-0".to_string())));
+        assert!(names.contains(&Some("ids-1".to_string())));
+    }
+
+    #[cfg(feature = "affinity")]
+    #[test]
+    fn test_affinity() {
+        let opts = GeneratorPoolOptions::default().affinity(crate::Affinity::Cores(vec![0]));
+        let pool = GeneratorPool::new(2, opts);
+        assert_eq!(pool.generate_many(10).unwrap().len(), 10);
     }
 
     #[test]
@@ -240,15 +711,15 @@ mod test {
 
         let pool = GeneratorPool::new(1, opts.clone());
 
-        let g = Generator::new(GeneratorPool::generator_opts(opts.clone()));
+        let g = Generator::new(opts.worker_opts(0));
 
-        let id = pool.generate();
+        let id = pool.generate().unwrap();
         let (ts, node, seq) = g.extract(id);
         assert_eq!(ts, 12345);
         assert_eq!(node, 0);
         assert_eq!(seq, 0);
 
-        let id = pool.generate();
+        let id = pool.generate().unwrap();
         let (ts, node, seq) = g.extract(id);
         assert_eq!(ts, 12345);
         assert_eq!(node, 0);
@@ -268,7 +739,7 @@ mod test {
         let mut ok = (false, false);
         let mut counters = (0, 0);
         for _ in 1..1000 {
-            let id = pool.generate();
+            let id = pool.generate().unwrap();
             let (ts, pool_id, node, seq) = pool.extract(id);
 
             assert_eq!(ts, 12345);
@@ -287,5 +758,320 @@ mod test {
         }
 
         assert!(ok.0 && ok.1);
+
+        let id = pool.generate().unwrap();
+        let (_, pool_id, _, seq) = pool.extract(id);
+        let decoded = pool.decode(id);
+        assert_eq!(
+            (
+                decoded.timestamp_raw,
+                decoded.pool_id,
+                decoded.node,
+                decoded.seq
+            ),
+            (12345, pool_id, 3, seq)
+        );
+        assert_eq!(decoded.unix_ms, 1483228800000 + 12345);
+        assert_eq!(
+            decoded.to_string(),
+            format!(
+                "2017-01-01T00:00:12.345Z pool={} node=3 seq={}",
+                pool_id, seq
+            )
+        );
+    }
+
+    #[test]
+    fn test_reply_slot() {
+        let slot = SLOT.with(Arc::clone);
+
+        *slot.lock() = SlotState::Waiting;
+        let reply = Reply::new(&slot);
+        thread::spawn(move || reply.send(Ok(1))).join().unwrap();
+        assert_eq!(slot.wait(), Ok(1));
+
+        // reused for the next request, and failed if the request is dropped
+        *slot.lock() = SlotState::Waiting;
+        drop(Reply::new(&slot));
+        assert_eq!(slot.wait(), Err(Error::ChannelClosed));
+
+        let pool = GeneratorPool::new(1, GeneratorPoolOptions::default());
+        let ids: Vec<_> = (0..100).map(|_| pool.generate().unwrap()).collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    /// Replaces the lanes of worker `i` by ones nobody receives from, as if
+    /// its thread had died.
+    fn kill_worker(pool: &GeneratorPool, i: usize) {
+        let (high, _) = unbounded();
+        let (low, _) = unbounded();
+        let mut lanes = pool.lanes.write().unwrap();
+        lanes.as_mut().unwrap()[i] = Lanes { high, low };
+    }
+
+    #[test]
+    fn test_dead_worker() {
+        let pool = GeneratorPool::new(3, GeneratorPoolOptions::default());
+        kill_worker(&pool, 1);
+
+        // the remaining workers take over
+        for _ in 0..10 {
+            assert!(pool.generate().is_ok());
+            assert!(pool.generate_with_priority(Priority::Low).is_ok());
+        }
+        assert_eq!(pool.generate_many(10).map(|ids| ids.len()), Ok(10));
+
+        kill_worker(&pool, 0);
+        kill_worker(&pool, 2);
+        let deadline = Instant::now() + std::time::Duration::from_secs(1);
+        assert_eq!(pool.generate(), Err(Error::ChannelClosed));
+        assert_eq!(pool.generate_many(10), Err(Error::ChannelClosed));
+        assert_eq!(
+            pool.generate_with_deadline(Priority::High, deadline),
+            Err(Error::ChannelClosed)
+        );
+    }
+
+    #[test]
+    fn test_progress() {
+        let pool = GeneratorPool::new(2, GeneratorPoolOptions::default());
+        assert_eq!(pool.progress()[0].map(|p| p.last_id), Some(None));
+
+        let ids = [pool.generate().unwrap(), pool.generate().unwrap()];
+        let progress = pool.progress();
+        assert_eq!(progress.len(), 2);
+        for p in progress.iter().flatten() {
+            assert!(ids.contains(&p.last_id.unwrap()));
+        }
+        assert!(progress.iter().all(|p| p.is_some()));
+
+        kill_worker(&pool, 1);
+        assert!(pool.progress()[0].is_some());
+        assert_eq!(pool.progress()[1], None);
+        pool.shutdown();
+        assert_eq!(pool.progress(), [None, None]);
+    }
+
+    #[test]
+    fn test_worker_exits_before_answering() {
+        let (tx, rx) = unbounded();
+        let (low_tx, _low_rx) = unbounded();
+        let pool = GeneratorPool::new(1, GeneratorPoolOptions::default());
+        pool.lanes.write().unwrap().as_mut().unwrap()[0] = Lanes {
+            high: tx,
+            low: low_tx,
+        };
+
+        // a worker that takes the request and exits without an answer
+        let worker = thread::spawn(move || drop(rx.recv().unwrap()));
+        assert_eq!(pool.generate(), Err(Error::ChannelClosed));
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn test_next_message_priority() {
+        let (high_tx, high_rx) = unbounded();
+        let (low_tx, low_rx) = unbounded();
+
+        let (tx, low_rx1) = unbounded();
+        low_tx.send(Message::Job { tx, deadline: None }).unwrap();
+        let (tx, high_rx1) = unbounded();
+        high_tx.send(Message::Job { tx, deadline: None }).unwrap();
+
+        let Some(Message::Job { tx, .. }) = next_message(&high_rx, &low_rx) else {
+            panic!("no message");
+        };
+        tx.send(Ok(1)).unwrap();
+        assert_eq!(high_rx1.try_recv(), Ok(Ok(1)));
+        assert!(low_rx1.try_recv().is_err());
+
+        let Some(Message::Job { tx, .. }) = next_message(&high_rx, &low_rx) else {
+            panic!("no message");
+        };
+        tx.send(Ok(2)).unwrap();
+        assert_eq!(low_rx1.try_recv(), Ok(Ok(2)));
+
+        drop((high_tx, low_tx));
+        assert!(next_message(&high_rx, &low_rx).is_none());
+    }
+
+    #[test]
+    fn test_generate_with_priority() {
+        let pool = GeneratorPool::new(2, GeneratorPoolOptions::default());
+        let mut ids: Vec<u64> = (0..100)
+            .map(|i| {
+                let priority = if i % 2 == 0 {
+                    Priority::High
+                } else {
+                    Priority::Low
+                };
+                pool.generate_with_priority(priority).unwrap()
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 100);
+    }
+
+    #[test]
+    fn test_generate_with_deadline() {
+        use std::time::Duration;
+
+        fn test_fn() -> u64 {
+            1483228800000 + 12345
+        }
+
+        let pool = GeneratorPool::new(1, GeneratorPoolOptions::default().time_fn(test_fn));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let id = pool
+            .generate_with_deadline(Priority::High, deadline)
+            .unwrap();
+        assert_eq!(pool.extract(id).3, 0);
+
+        let expired = Instant::now();
+        assert_eq!(
+            pool.generate_with_deadline(Priority::High, expired),
+            Err(Error::DeadlineExceeded)
+        );
+
+        // the expired request did not use up a sequence number
+        let id = pool.generate().unwrap();
+        assert_eq!(pool.extract(id).3, 1);
+    }
+
+    #[test]
+    fn test_generate_error() {
+        fn test_fn() -> u64 {
+            1483228800000 + 12345
+        }
+
+        let opts = GeneratorPoolOptions::default()
+            .base_ts(0)
+            .bits(52, 2, 6, 4)
+            .base_ts(1483228800000)
+            .time_fn(test_fn)
+            .overflow_policy(OverflowPolicy::Error);
+        let pool = GeneratorPool::new(1, opts);
+        for _ in 0..16 {
+            assert!(pool.generate().is_ok());
+        }
+
+        // the worker keeps running after an error
+        assert_eq!(pool.generate(), Err(Error::SequenceExhausted));
+        assert_eq!(pool.generate(), Err(Error::SequenceExhausted));
+
+        let pool = GeneratorPool::new(0, GeneratorPoolOptions::default());
+        assert_eq!(pool.generate(), Err(Error::ChannelClosed));
+    }
+
+    #[test]
+    fn test_worker_panic() {
+        fn test_fn() -> u64 {
+            1483228800000 + 12345
+        }
+
+        let opts = GeneratorPoolOptions::default()
+            .base_ts(0)
+            .bits(52, 2, 6, 4)
+            .base_ts(1483228800000)
+            .time_fn(test_fn)
+            .overflow_policy(OverflowPolicy::Panic);
+        let pool = GeneratorPool::new(1, opts);
+        for _ in 0..16 {
+            assert!(pool.generate().is_ok());
+        }
+
+        let reason = Error::SequenceExhausted.to_string();
+        assert_eq!(
+            pool.generate(),
+            Err(Error::WorkerPanicked {
+                reason: reason.clone()
+            })
+        );
+        // the restarted generator does not reissue the ids of the tick
+        assert_eq!(pool.generate(), Err(Error::WorkerPanicked { reason }));
+    }
+
+    #[test]
+    fn test_generate_into() {
+        let pool = GeneratorPool::new(2, GeneratorPoolOptions::default());
+        let mut ids = vec![0; 10_000];
+        pool.generate_into(&mut ids).unwrap();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_generate_many() {
+        use std::collections::HashSet;
+
+        let pool = GeneratorPool::new(2, GeneratorPoolOptions::default());
+        let mut all = HashSet::new();
+        for _ in 0..4 {
+            let ids = pool.generate_many(5_000).unwrap();
+            assert_eq!(ids.len(), 5_000);
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            all.extend(ids);
+        }
+        assert_eq!(all.len(), 20_000);
+    }
+
+    #[test]
+    fn test_metrics() {
+        use crate::metrics::AtomicMetrics;
+
+        let metrics = Arc::new(AtomicMetrics::default());
+        let pool = GeneratorPool::new(2, GeneratorPoolOptions::default().metrics(metrics.clone()));
+        for _ in 0..10 {
+            pool.generate().unwrap();
+        }
+        pool.generate_many(100).unwrap();
+        assert_eq!(metrics.ids_generated(), 110);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_handle_async() {
+        use std::collections::HashSet;
+
+        let pool = GeneratorPool::new(2, GeneratorPoolOptions::default());
+        let handle = GeneratorPoolHandleAsync::new(pool.clone());
+
+        // threads and tasks share the workers
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    (0..1000)
+                        .map(|_| pool.generate().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    let mut ids = vec![];
+                    for _ in 0..1000 {
+                        ids.push(handle.generate().await.unwrap());
+                    }
+                    ids
+                })
+            })
+            .collect();
+
+        let mut all = HashSet::new();
+        for t in threads {
+            all.extend(t.join().unwrap());
+        }
+        for t in tasks {
+            all.extend(t.await.unwrap());
+        }
+        assert_eq!(all.len(), 8000);
+
+        assert!(handle.generate_with_priority(Priority::Low).await.is_ok());
+        handle.pool().shutdown();
+        assert_eq!(handle.generate().await, Err(Error::ChannelClosed));
     }
 }
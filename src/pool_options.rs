@@ -0,0 +1,445 @@
+use std::sync::Arc;
+
+use crate::audit::AuditSink;
+use crate::clock::SharedClock;
+use crate::decode::PoolDecoded;
+use crate::diagnostics::UtilizationHook;
+use crate::metrics::Metrics;
+#[cfg(any(feature = "serde", feature = "config"))]
+use crate::OptionsError;
+use crate::{GeneratorOptions, OverflowPolicy, TimeSource, UtilizationAlert};
+
+/// Options of `GeneratorPool` and `GeneratorPoolAsync`.
+///
+/// The node bits of `GeneratorOptions` are split into pool bits, numbering
+/// the generators of the pool, and node bits for the node number.
+#[derive(Clone)]
+pub struct GeneratorPoolOptions {
+    pub(crate) bits: (u8, u8, u8, u8), // time, pool, node, seq
+    pub(crate) node: u64,
+    pub(crate) base_ts: u64,
+    pub(crate) overflow: OverflowPolicy,
+    pub(crate) clock: Arc<dyn TimeSource + Send + Sync>,
+    audit: Option<Arc<dyn AuditSink + Send + Sync>>,
+    utilization_alert: Option<(f64, Arc<UtilizationHook>)>,
+    pub(crate) metrics: Option<Arc<dyn Metrics + Send + Sync>>,
+    pub(crate) strict_ordering: bool,
+    shared_clock: bool,
+    pub(crate) thread_name: Option<String>,
+    #[cfg(feature = "affinity")]
+    pub(crate) affinity: crate::Affinity,
+}
+
+impl Default for GeneratorPoolOptions {
+    fn default() -> GeneratorPoolOptions {
+        GeneratorPoolOptions {
+            bits: (42, 4, 6, 12),
+            base_ts: 1483228800000, // 2017-01-01T00:00:00Z as milliseconds
+            node: 0,
+            overflow: OverflowPolicy::default(),
+            clock: Arc::new(super::default_time_fn as fn() -> u64),
+            audit: None,
+            utilization_alert: None,
+            metrics: None,
+            strict_ordering: false,
+            shared_clock: false,
+            thread_name: None,
+            #[cfg(feature = "affinity")]
+            affinity: crate::Affinity::None,
+        }
+    }
+}
+
+impl GeneratorPoolOptions {
+    pub fn bits(mut self, ts_bits: u8, pool_bits: u8, node_bits: u8, seq_bits: u8) -> Self {
+        assert!(
+            64 == ts_bits as u32 + pool_bits as u32 + node_bits as u32 + seq_bits as u32,
+            "bits set should be total 64bit"
+        );
+        assert!(ts_bits > 0, "ts_bits should be at least 1bit");
+        assert!(
+            self.base_ts <= super::max(ts_bits),
+            "base_ts exceeds ts_bits limit, set base_ts first"
+        );
+        assert!(
+            self.node <= super::max(node_bits),
+            "node number exceeds node_bits limit, set node number first"
+        );
+
+        self.bits = (ts_bits, pool_bits, node_bits, seq_bits);
+        self
+    }
+
+    pub fn base_ts(mut self, base_ts: u64) -> Self {
+        assert!(
+            base_ts <= super::max(self.bits.0),
+            "base_ts exceeds ts_bits limit, set bit width first"
+        );
+
+        self.base_ts = base_ts;
+        self
+    }
+
+    pub fn node(mut self, node: u64) -> Self {
+        assert!(
+            node <= super::max(self.bits.2),
+            "node number exceeds node_bits limit, set bit width first"
+        );
+
+        self.node = node;
+        self
+    }
+
+    pub fn time_fn(self, time_fn: fn() -> u64) -> Self {
+        self.time_source(time_fn)
+    }
+
+    /// Sets what happens when a worker has used all sequence numbers of a
+    /// tick, see `GeneratorOptions::overflow_policy`.
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
+    /// Same as `time_fn`, for closures and time sources that are not plain
+    /// functions.
+    ///
+    /// The source is shared by all workers of the pool.
+    pub fn time_source<T: TimeSource + Send + Sync + 'static>(mut self, clock: T) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Calls `hook` when a worker uses more than `threshold` of the sequence
+    /// space of a tick, see `GeneratorOptions::utilization_alert`.
+    pub fn utilization_alert<F>(mut self, threshold: f64, hook: F) -> Self
+    where
+        F: Fn(UtilizationAlert) + Send + Sync + 'static,
+    {
+        assert!(
+            (0.0..=1.0).contains(&threshold),
+            "threshold should be between 0.0 and 1.0"
+        );
+
+        self.utilization_alert = Some((threshold, Arc::new(hook)));
+        self
+    }
+
+    /// Reports issued ids to `sink`; each worker reports its own node number.
+    pub fn audit<A: AuditSink + Send + Sync + 'static>(mut self, sink: A) -> Self {
+        self.audit = Some(Arc::new(sink));
+        self
+    }
+
+    /// Reports generated ids and incidents of all workers to `metrics`,
+    /// along with the depth of the queue a request was handed to.
+    pub fn metrics<M: Metrics + Send + Sync + 'static>(mut self, metrics: M) -> Self {
+        self.metrics = Some(Arc::new(metrics));
+        self
+    }
+
+    /// Makes all workers take their ids from a single generator, so that ids
+    /// of the pool ascend in the order they are generated, like those of a
+    /// single `Generator`.
+    ///
+    /// Without it, every worker has a generator and pool number of its own,
+    /// and an id of a worker with a higher pool number sorts after later ids
+    /// of the other workers within the same tick. Strict ordering gives up
+    /// the throughput of the extra generators: workers still take requests
+    /// off the callers' queues, but they all wait for the same generator, so
+    /// the pool tops out at the rate of one generator. All ids carry pool
+    /// number 0.
+    pub fn strict_ordering(mut self, enabled: bool) -> Self {
+        self.strict_ordering = enabled;
+        self
+    }
+
+    /// Makes all workers read the time source through one [`SharedClock`],
+    /// so that no worker sees a time behind one another worker has seen.
+    ///
+    /// This is for time sources that aren't monotonic across threads. The
+    /// pools report how often and how far the source was behind with
+    /// `clock_skew`.
+    pub fn shared_clock(mut self, enabled: bool) -> Self {
+        self.shared_clock = enabled;
+        self
+    }
+
+    /// Names the worker threads of a `GeneratorPool` `{name}-{i}`, as shown
+    /// by debuggers and profilers.
+    pub fn thread_name(mut self, name: &str) -> Self {
+        self.thread_name = Some(name.to_string());
+        self
+    }
+
+    /// Pins the worker threads of a `GeneratorPool` to cores, see
+    /// [`Affinity`](crate::Affinity).
+    #[cfg(feature = "affinity")]
+    pub fn affinity(mut self, affinity: crate::Affinity) -> Self {
+        self.affinity = affinity;
+        self
+    }
+
+    /// Same as the setters, with errors instead of panics.
+    #[cfg(any(feature = "serde", feature = "config"))]
+    pub(crate) fn validated(
+        bits: (u8, u8, u8, u8),
+        base_ts: u64,
+        node: u64,
+    ) -> Result<Self, OptionsError> {
+        let (ts_bits, pool_bits, node_bits, seq_bits) = bits;
+        let total = ts_bits as u32 + pool_bits as u32 + node_bits as u32 + seq_bits as u32;
+        if total != 64 {
+            return Err(OptionsError::InvalidBits { total });
+        }
+        if ts_bits == 0 {
+            return Err(OptionsError::NoTimestampBits);
+        }
+        if base_ts > super::max(ts_bits) {
+            return Err(OptionsError::BaseTsOutOfRange {
+                base_ts,
+                max: super::max(ts_bits),
+            });
+        }
+        if node > super::max(node_bits) {
+            return Err(OptionsError::NodeOutOfRange {
+                node,
+                max: super::max(node_bits),
+            });
+        }
+
+        Ok(GeneratorPoolOptions {
+            bits,
+            base_ts,
+            node,
+            ..GeneratorPoolOptions::default()
+        })
+    }
+}
+
+/// Time source of a pool with `shared_clock`.
+pub(crate) type PoolClock = SharedClock<Arc<dyn TimeSource + Send + Sync>>;
+
+impl GeneratorPoolOptions {
+    /// Puts the time source behind a `SharedClock` with `shared_clock`, for
+    /// all workers created afterwards.
+    pub(crate) fn share_clock(&mut self) -> Option<Arc<PoolClock>> {
+        if !self.shared_clock {
+            return None;
+        }
+        let clock = Arc::new(SharedClock::new(self.clock.clone()));
+        self.clock = clock.clone();
+        Some(clock)
+    }
+
+    /// Options of the generator with pool number `worker`.
+    pub(crate) fn worker_opts(&self, worker: u64) -> GeneratorOptions {
+        let (_, _, node_bits, _) = self.bits;
+        let pool_mask = super::bitmask(node_bits);
+        let node_mask = super::max(node_bits);
+
+        self.generator_opts()
+            .node((super::shl(worker, node_bits) & pool_mask) | (self.node & node_mask))
+    }
+
+    fn generator_opts(&self) -> GeneratorOptions {
+        GeneratorOptions {
+            audit: self.audit.clone(),
+            utilization_alert: self.utilization_alert.clone(),
+            metrics: self.metrics.clone(),
+            ..GeneratorOptions::default()
+                .base_ts(0)
+                .bits(self.bits.0, self.bits.1 + self.bits.2, self.bits.3)
+                .base_ts(self.base_ts)
+                .time_source(self.clock.clone())
+                .overflow_policy(self.overflow)
+        }
+    }
+
+    /// Number of generators the pool bits can tell apart.
+    pub(crate) fn max_workers(&self) -> u64 {
+        super::max(self.bits.1).saturating_add(1)
+    }
+
+    /// Layout of the ids of a pool with these options, for decoding them
+    /// without a pool.
+    pub fn layout(&self) -> PoolLayout {
+        PoolLayout { bits: self.bits }
+    }
+
+    pub(crate) fn decode(&self, id: u64) -> PoolDecoded {
+        let (timestamp_raw, pool_id, node, seq) = self.layout().decode(id);
+        PoolDecoded {
+            id,
+            timestamp_raw,
+            unix_ms: timestamp_raw.saturating_add(self.base_ts),
+            pool_id,
+            node,
+            seq,
+        }
+    }
+}
+
+/// Bit widths of the ids of a pool, from `GeneratorPoolOptions::layout`.
+///
+/// ```rust
+/// use frostflake::GeneratorPoolOptions;
+///
+/// let layout = GeneratorPoolOptions::default().layout();
+/// let id = (123 << 22) | (3 << 18) | (5 << 12) | 7;
+/// assert_eq!(layout.decode(id), (123, 3, 5, 7));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PoolLayout {
+    bits: (u8, u8, u8, u8), // time, pool, node, seq
+}
+
+impl PoolLayout {
+    /// Bit widths of the timestamp, pool number, node number and sequence.
+    pub const fn bits(&self) -> (u8, u8, u8, u8) {
+        self.bits
+    }
+
+    /// Splits an id into timestamp, pool number, node number and sequence.
+    ///
+    /// Like `Generator::extract`, the timestamp is counted from `base_ts`.
+    pub const fn decode(&self, id: u64) -> (u64, u64, u64, u64) {
+        let (ts_bits, pool_bits, node_bits, seq_bits) = self.bits;
+
+        let ts = super::shr(id, pool_bits + node_bits + seq_bits) & super::max(ts_bits);
+        let pool = super::shr(id, node_bits + seq_bits) & super::max(pool_bits);
+        let node = super::shr(id, seq_bits) & super::max(node_bits);
+        let seq = id & super::max(seq_bits);
+
+        (ts, pool, node, seq)
+    }
+}
+
+/// Fields read when deserializing pool options, all optional.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PoolOptionsConfig {
+    ts_bits: u8,
+    pool_bits: u8,
+    node_bits: u8,
+    seq_bits: u8,
+    base_ts: u64,
+    node: u64,
+    overflow_policy: OverflowPolicy,
+}
+
+#[cfg(feature = "serde")]
+impl Default for PoolOptionsConfig {
+    fn default() -> Self {
+        let opts = GeneratorPoolOptions::default();
+        PoolOptionsConfig {
+            ts_bits: opts.bits.0,
+            pool_bits: opts.bits.1,
+            node_bits: opts.bits.2,
+            seq_bits: opts.bits.3,
+            base_ts: opts.base_ts,
+            node: opts.node,
+            overflow_policy: opts.overflow,
+        }
+    }
+}
+
+/// Reads `ts_bits`, `pool_bits`, `node_bits`, `seq_bits`, `base_ts`, `node`
+/// and `overflow_policy`, with the defaults for missing fields.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for GeneratorPoolOptions {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let c = PoolOptionsConfig::deserialize(deserializer)?;
+        let bits = (c.ts_bits, c.pool_bits, c.node_bits, c.seq_bits);
+        let opts = GeneratorPoolOptions::validated(bits, c.base_ts, c.node)
+            .map_err(serde::de::Error::custom)?;
+        Ok(opts.overflow_policy(c.overflow_policy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_options_deserialize() {
+        use serde::de::value::{Error, MapDeserializer};
+        use serde::Deserialize;
+
+        let fields = [("pool_bits", 2), ("node_bits", 8), ("node", 200)];
+        let opts =
+            GeneratorPoolOptions::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()))
+                .unwrap();
+        assert_eq!(opts.bits, (42, 2, 8, 12));
+        assert_eq!(opts.node, 200);
+
+        let fields = [("pool_bits", 2)];
+        let e =
+            GeneratorPoolOptions::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()));
+        assert!(e.is_err());
+    }
+
+    #[test]
+    fn test_options_default() {
+        let opts = GeneratorPoolOptions::default();
+        assert_eq!(opts.bits, (42, 4, 6, 12));
+        assert_eq!(opts.node, 0);
+        assert_eq!(opts.base_ts, 1483228800000);
+    }
+
+    #[test]
+    fn test_layout_decode() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorPoolOptions::default()
+            .bits(41, 3, 8, 12)
+            .node(200)
+            .time_fn(my_time_fn);
+        let layout = opts.layout();
+        assert_eq!(layout.bits(), (41, 3, 8, 12));
+
+        let g = crate::Generator::new(opts.worker_opts(5));
+        g.generate();
+        assert_eq!(layout.decode(g.generate()), (123, 5, 200, 1));
+    }
+
+    #[test]
+    fn test_options_set_base() {
+        let opts = GeneratorPoolOptions::default().base_ts(123);
+        assert_eq!(opts.base_ts, 123);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_options_set_base_crash() {
+        let max = super::super::max(42);
+        let _ = GeneratorPoolOptions::default().base_ts(max + 1);
+    }
+
+    #[test]
+    fn test_options_set_node() {
+        let opts = GeneratorPoolOptions::default().node(10);
+        assert_eq!(opts.node, 10);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_options_set_node_crash() {
+        let max = super::super::max(6);
+        let _ = GeneratorPoolOptions::default().node(max + 1);
+    }
+
+    #[test]
+    fn test_options_set_time_fn() {
+        fn test_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorPoolOptions::default().time_fn(test_fn);
+        assert_eq!(opts.clock.now(), 1483228800000 + 123);
+    }
+}
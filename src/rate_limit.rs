@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use crate::{Core, Error, RateLimitPolicy};
+
+impl Core {
+    /// How long until `max_rate` allows the next id, when the policy is to
+    /// wait for it.
+    pub(crate) fn rate_wait(&mut self) -> Option<Duration> {
+        if self.opts.rate_limit_policy != RateLimitPolicy::Wait {
+            return None;
+        }
+        self.rate_bucket.as_mut()?.wait()
+    }
+
+    /// Takes up to `n` ids of `max_rate`, at least one, and returns how many
+    /// were taken. Ids not used have to be put back.
+    pub(crate) fn acquire(&mut self, n: u64) -> Result<u64, Error> {
+        while let Some(wait) = self.rate_wait() {
+            std::thread::sleep(wait);
+        }
+        let Some(bucket) = &mut self.rate_bucket else {
+            return Ok(n);
+        };
+        match bucket.take(n) {
+            0 => Err(Error::RateLimited),
+            taken => Ok(taken),
+        }
+    }
+
+    pub(crate) fn put_back(&mut self, n: u64) {
+        if let Some(bucket) = &mut self.rate_bucket {
+            bucket.put_back(n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Generator, GeneratorOptions, RateLimitPolicy};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_max_rate() {
+        let g = Generator::new(GeneratorOptions::default().max_rate(100));
+        // a second of ids as a burst
+        for _ in 0..100 {
+            assert!(g.try_generate().is_ok());
+        }
+        assert_eq!(g.try_generate(), Err(Error::RateLimited));
+        assert_eq!(g.diagnostics().stats.issued, 100);
+
+        std::thread::sleep(Duration::from_millis(50));
+        let ids = (0..10).filter(|_| g.try_generate().is_ok()).count();
+        assert!((4..=10).contains(&ids), "{}", ids);
+
+        // batches take what is left
+        let g = Generator::new(GeneratorOptions::default().max_rate(100));
+        let mut ids = [0; 150];
+        assert_eq!(g.generate_into(&mut ids), Err(Error::RateLimited));
+        assert!(ids[..100].iter().all(|&id| id != 0));
+    }
+
+    #[test]
+    fn test_max_rate_wait() {
+        let opts = GeneratorOptions::default()
+            .max_rate(1000)
+            .rate_limit_policy(RateLimitPolicy::Wait);
+        let g = Generator::new(opts);
+
+        let started = Instant::now();
+        let mut ids = [0; 1100];
+        g.generate_into(&mut ids).unwrap();
+        for _ in 0..50 {
+            g.generate();
+        }
+        // 150 ids beyond the burst, at 1000/s
+        assert!(started.elapsed() >= Duration::from_millis(140));
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+use crate::{Error, Generator};
+
+impl Generator {
+    /// Reserves `n` fresh ids, counted as issued only once the reservation
+    /// is committed.
+    ///
+    /// This suits transactional outboxes and sagas asking for ids before
+    /// they know whether the transaction goes through: commit the
+    /// reservation with the transaction, and a rolled back reservation is
+    /// dropped and counted as burned. Either way its ids are spent, and are
+    /// never generated again.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let generator = Generator::new(GeneratorOptions::default());
+    ///
+    /// let reservation = generator.reserve(3).unwrap();
+    /// let ids = reservation.commit();
+    /// assert_eq!(ids.len(), 3);
+    ///
+    /// // rolled back
+    /// drop(generator.reserve(2).unwrap());
+    ///
+    /// let stats = generator.diagnostics().stats;
+    /// assert_eq!((stats.issued, stats.burned), (3, 2));
+    /// ```
+    pub fn reserve(&self, n: usize) -> Result<Reservation<'_>, Error> {
+        let mut ids = vec![0; n];
+        self.core().fill(&mut ids, false)?;
+        Ok(Reservation {
+            generator: self,
+            ids,
+            committed: false,
+        })
+    }
+}
+
+/// Ids reserved by `Generator::reserve`, in ascending order.
+///
+/// Dropping it without `commit` counts the ids as burned, in
+/// `GeneratorStats::burned` and `Metrics::burned`.
+#[must_use = "a reservation dropped without commit burns its ids"]
+pub struct Reservation<'a> {
+    generator: &'a Generator,
+    ids: Vec<u64>,
+    committed: bool,
+}
+
+impl Reservation<'_> {
+    pub fn ids(&self) -> &[u64] {
+        &self.ids
+    }
+
+    /// Counts the ids as issued, and returns them.
+    pub fn commit(mut self) -> Vec<u64> {
+        self.committed = true;
+        self.generator.core().record_issued(self.ids.len() as u64);
+        std::mem::take(&mut self.ids)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.generator.core().record_burned(self.ids.len() as u64);
+        }
+    }
+}
+
+impl fmt::Debug for Reservation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reservation")
+            .field("ids", &self.ids)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::AtomicMetrics;
+    use crate::{Generator, GeneratorOptions};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reserve() {
+        let metrics = Arc::new(AtomicMetrics::default());
+        let g = Generator::new(GeneratorOptions::default().metrics(metrics.clone()));
+
+        let committed = g.reserve(10_000).unwrap();
+        assert!(committed.ids().windows(2).all(|w| w[0] < w[1]));
+        // not issued until committed
+        assert_eq!(g.diagnostics().stats.issued, 0);
+        let committed = committed.commit();
+
+        let burned = g.reserve(5).unwrap();
+        let burned_ids = burned.ids().to_vec();
+        drop(burned);
+
+        // burned ids are never generated again
+        let next = g.generate();
+        assert!(next > burned_ids[4] && burned_ids[0] > committed[9_999]);
+
+        let stats = g.diagnostics().stats;
+        assert_eq!((stats.issued, stats.burned), (10_001, 5));
+        assert_eq!(metrics.ids_generated(), 10_001);
+        assert_eq!(metrics.ids_burned(), 5);
+    }
+}
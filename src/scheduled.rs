@@ -0,0 +1,104 @@
+use crate::{Core, Error, Generator};
+
+impl Generator {
+    /// Generates an id whose timestamp is not before `ts`.
+    ///
+    /// `ts` is in the unit of `time_fn`, like `base_ts`. If `ts` is in the
+    /// future, the id carries `ts` itself, so that scheduling systems can
+    /// encode the activation time in the id. Otherwise this is the same as
+    /// `try_generate`.
+    ///
+    /// Future-dated ids take their sequence numbers from the top of the
+    /// sequence space, counting down, while ids generated once the clock
+    /// reaches `ts` count up from zero. Both therefore never collide, but
+    /// share the capacity of that tick.
+    pub fn generate_not_before(&self, ts: u64) -> Result<u64, Error> {
+        self.core().generate_not_before(ts)
+    }
+}
+
+impl Core {
+    fn generate_not_before(&mut self, ts: u64) -> Result<u64, Error> {
+        let now = self.opts.clock.now();
+        if ts <= now {
+            return self.try_generate();
+        }
+
+        if ts <= self.opts.base_ts {
+            return Err(Error::TimeBeforeBaseTs {
+                now: ts,
+                base_ts: self.opts.base_ts,
+            });
+        }
+
+        let elapsed = ts - self.opts.base_ts;
+        if elapsed > self.compiled.max_ts {
+            return Err(Error::EpochExhausted { ts });
+        }
+
+        self.scheduled.retain(|&t, _| t >= now);
+
+        let count = self.scheduled.entry(ts).or_insert(0);
+        if *count > self.compiled.max_seq {
+            return Err(Error::SequenceExhausted);
+        }
+        let seq = self.compiled.max_seq - *count;
+        *count += 1;
+
+        self.audit_scheduled(elapsed);
+        self.record_issued(1);
+        Ok(self.compose(elapsed, seq))
+    }
+
+    /// Number of sequence numbers taken by future-dated ids at `ts`.
+    pub(crate) fn scheduled_at(&mut self, ts: u64) -> u64 {
+        if self.scheduled.is_empty() {
+            return 0;
+        }
+
+        self.scheduled.retain(|&t, _| t >= ts);
+        self.scheduled.get(&ts).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Generator, GeneratorOptions, OverflowPolicy};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_generate_not_before() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 100);
+        fn my_time_fn() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+
+        // 4 ids per tick
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .overflow_policy(OverflowPolicy::Error)
+            .base_ts(0)
+            .bits(42, 20, 2)
+            .base_ts(1483228800000);
+        let g = Generator::new(opts);
+
+        let later = 1483228800000 + 200;
+        assert_eq!(g.generate_not_before(later), Ok((200 << 22) + 3));
+        assert_eq!(g.generate_not_before(later), Ok((200 << 22) + 2));
+
+        // past timestamps fall back to the current time
+        assert_eq!(g.generate_not_before(1483228800000 + 50), Ok(100 << 22));
+
+        NOW.store(later, Ordering::Relaxed);
+        assert_eq!(g.try_generate(), Ok(200 << 22));
+        assert_eq!(g.try_generate(), Ok((200 << 22) + 1));
+        assert_eq!(g.try_generate(), Err(Error::SequenceExhausted));
+    }
+
+    #[test]
+    fn test_generate_not_before_out_of_range() {
+        let g = Generator::new(GeneratorOptions::default());
+        let ts = 1483228800000 + (1 << 42);
+        assert_eq!(g.generate_not_before(ts), Err(Error::EpochExhausted { ts }));
+    }
+}
@@ -0,0 +1,328 @@
+//! Ids handed out from blocks allocated by a database, like
+//! [Leaf](https://github.com/Meituan-Dianping/Leaf)'s segment mode.
+//!
+//! Instead of a timestamp, node number and sequence, a [`SegmentGenerator`]
+//! hands out plain ascending numbers from a segment, a contiguous range of
+//! ids leased from a [`SegmentStore`]. Once half of the segment is used, the
+//! next one is fetched on a background thread, so generating only waits for
+//! the store when it can't keep up. Ids are unique across all generators of
+//! the same store and key, and ascending within one generator, but not
+//! across generators.
+//!
+//! [`MemorySegmentStore`] keeps the counter in memory, and
+//! `RedisSegmentStore` with the `redis` feature in a Redis key. A SQL
+//! database needs a table with a counter per key and a transaction like:
+//!
+//! ```sql
+//! UPDATE segments SET max_id = max_id + $size WHERE key = $key RETURNING max_id;
+//! ```
+//!
+//! ```rust
+//! use frostflake::segment::{MemorySegmentStore, SegmentGenerator};
+//!
+//! let generator = SegmentGenerator::new(MemorySegmentStore::new(), 1000).unwrap();
+//! assert_eq!(generator.generate().unwrap(), 1);
+//! assert_eq!(generator.generate().unwrap(), 2);
+//! ```
+
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use crate::{Error, IdGenerator, OptionsError};
+
+/// Storage leasing segments of ids.
+pub trait SegmentStore {
+    /// Reserves the next `size` ids for the caller, and returns them.
+    ///
+    /// Ranges returned to all callers must not overlap, also across
+    /// restarts.
+    fn allocate(&self, size: u64) -> io::Result<Range<u64>>;
+}
+
+impl<S: SegmentStore + ?Sized> SegmentStore for Arc<S> {
+    fn allocate(&self, size: u64) -> io::Result<Range<u64>> {
+        (**self).allocate(size)
+    }
+}
+
+/// Segment store counting in memory, for a single process and for tests.
+///
+/// Ids start at 1.
+#[derive(Debug, Default)]
+pub struct MemorySegmentStore {
+    max_id: AtomicU64,
+}
+
+impl MemorySegmentStore {
+    pub fn new() -> Self {
+        MemorySegmentStore::default()
+    }
+}
+
+impl SegmentStore for MemorySegmentStore {
+    fn allocate(&self, size: u64) -> io::Result<Range<u64>> {
+        let start = self.max_id.fetch_add(size, Ordering::Relaxed);
+        Ok(start + 1..start + size + 1)
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis::RedisSegmentStore;
+
+#[cfg(feature = "redis")]
+mod redis {
+    use std::io;
+    use std::ops::Range;
+    use std::sync::Mutex;
+
+    use super::SegmentStore;
+    use crate::node_id::redis::{unexpected, Connection, Reply};
+
+    /// Segment store counting in a Redis key with `INCRBY`.
+    ///
+    /// Ids start at 1. The connection is opened on the first allocation, and
+    /// again after it failed.
+    ///
+    /// ```no_run
+    /// use frostflake::segment::{RedisSegmentStore, SegmentGenerator};
+    ///
+    /// let store = RedisSegmentStore::new("redis://127.0.0.1:6379", "frostflake:segment:orders");
+    /// let generator = SegmentGenerator::new(store, 100_000).unwrap();
+    /// let id = generator.generate().unwrap();
+    /// ```
+    pub struct RedisSegmentStore {
+        url: String,
+        key: String,
+        conn: Mutex<Option<Connection>>,
+    }
+
+    impl RedisSegmentStore {
+        /// `url` is in the form of `redis://[:password@]host[:port][/db]`.
+        pub fn new(url: &str, key: &str) -> Self {
+            RedisSegmentStore {
+                url: url.to_string(),
+                key: key.to_string(),
+                conn: Mutex::new(None),
+            }
+        }
+    }
+
+    impl SegmentStore for RedisSegmentStore {
+        fn allocate(&self, size: u64) -> io::Result<Range<u64>> {
+            let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+            let mut c = match conn.take() {
+                Some(c) => c,
+                None => Connection::open(&self.url)?,
+            };
+            // the connection may be broken after an error, and is dropped
+            let reply = c.command(&["INCRBY", &self.key, &size.to_string()])?;
+            *conn = Some(c);
+
+            match reply {
+                Reply::Integer(max_id) if max_id >= size as i64 => {
+                    let max_id = max_id as u64;
+                    Ok(max_id - size + 1..max_id + 1)
+                }
+                reply => Err(unexpected(reply)),
+            }
+        }
+    }
+}
+
+/// Generator handing out ids from segments of a [`SegmentStore`], see the
+/// [module documentation](self).
+pub struct SegmentGenerator {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    store: Box<dyn SegmentStore + Send + Sync>,
+    size: u64,
+    segments: Mutex<Segments>,
+    loaded: Condvar,
+}
+
+struct Segments {
+    current: Range<u64>,
+    next: Option<Range<u64>>,
+    // the next segment is being fetched in the background
+    loading: bool,
+}
+
+impl SegmentGenerator {
+    /// Hands out ids from `store` in segments of `size`. The first segment is
+    /// fetched on the first id. Fails with [`OptionsError::ZeroCount`] for a
+    /// `size` of 0.
+    pub fn new<S: SegmentStore + Send + Sync + 'static>(
+        store: S,
+        size: u64,
+    ) -> Result<Self, OptionsError> {
+        if size == 0 {
+            return Err(OptionsError::ZeroCount {
+                name: "segment size",
+            });
+        }
+
+        Ok(SegmentGenerator {
+            inner: Arc::new(Inner {
+                store: Box::new(store),
+                size,
+                segments: Mutex::new(Segments {
+                    current: 0..0,
+                    next: None,
+                    loading: false,
+                }),
+                loaded: Condvar::new(),
+            }),
+        })
+    }
+
+    /// Fails with [`Error::SegmentUnavailable`] if the current segment is
+    /// used up and the store fails to allocate the next one.
+    pub fn generate(&self) -> Result<u64, Error> {
+        let mut segments = self.inner.lock();
+        if segments.current.is_empty() {
+            segments = self.inner.next_segment(segments)?;
+        }
+
+        let id = segments.current.start;
+        segments.current.start += 1;
+        if segments.current.end - segments.current.start < self.inner.size / 2
+            && segments.next.is_none()
+            && !segments.loading
+        {
+            segments.loading = true;
+            let inner = self.inner.clone();
+            thread::spawn(move || inner.prefetch());
+        }
+        Ok(id)
+    }
+
+    /// Number of ids left in the current segment, without the prefetched
+    /// one.
+    pub fn remaining(&self) -> u64 {
+        let segments = self.inner.lock();
+        segments.current.end - segments.current.start
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, Segments> {
+        self.segments.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Switches to the prefetched segment, waiting for it while it is
+    /// fetched, or fetches one right away.
+    fn next_segment<'a>(
+        &self,
+        mut segments: MutexGuard<'a, Segments>,
+    ) -> Result<MutexGuard<'a, Segments>, Error> {
+        while segments.loading {
+            segments = self
+                .loaded
+                .wait(segments)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        if !segments.current.is_empty() {
+            // another caller switched already
+            return Ok(segments);
+        }
+
+        segments.current = match segments.next.take() {
+            Some(next) => next,
+            None => self
+                .store
+                .allocate(self.size)
+                .map_err(|e| Error::SegmentUnavailable {
+                    reason: e.to_string(),
+                })?,
+        };
+        Ok(segments)
+    }
+
+    fn prefetch(&self) {
+        // a failure is retried in the foreground once the segment is used up
+        let next = self.store.allocate(self.size).ok();
+        let mut segments = self.lock();
+        segments.next = next;
+        segments.loading = false;
+        self.loaded.notify_all();
+    }
+}
+
+impl IdGenerator for SegmentGenerator {
+    fn generate(&self) -> Result<u64, Error> {
+        SegmentGenerator::generate(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_segments() {
+        let store = Arc::new(MemorySegmentStore::new());
+        let a = SegmentGenerator::new(store.clone(), 10).unwrap();
+        let b = SegmentGenerator::new(store.clone(), 10).unwrap();
+
+        let mut ids = HashSet::new();
+        let mut last = 0;
+        for _ in 0..100 {
+            let id = a.generate().unwrap();
+            assert!(id > last);
+            last = id;
+            assert!(ids.insert(id));
+            assert!(ids.insert(b.generate().unwrap()));
+        }
+        assert_eq!(ids.len(), 200);
+
+        assert_eq!(
+            SegmentGenerator::new(store, 0).err(),
+            Some(OptionsError::ZeroCount {
+                name: "segment size"
+            })
+        );
+    }
+
+    struct Flaky(AtomicBool, MemorySegmentStore);
+
+    impl SegmentStore for Flaky {
+        fn allocate(&self, size: u64) -> io::Result<Range<u64>> {
+            if self.0.load(Ordering::SeqCst) {
+                return Err(io::Error::other("down"));
+            }
+            self.1.allocate(size)
+        }
+    }
+
+    #[test]
+    fn test_store_down() {
+        let store = Arc::new(Flaky(AtomicBool::new(false), MemorySegmentStore::new()));
+        let g = SegmentGenerator::new(store.clone(), 4).unwrap();
+        let ids: Vec<_> = (0..3).map(|_| g.generate().unwrap()).collect();
+        assert_eq!(ids, [1, 2, 3]);
+        while g.inner.lock().next.is_none() {
+            thread::yield_now();
+        }
+        store.0.store(true, Ordering::SeqCst);
+
+        // the current segment and the prefetched one are still there
+        let ids: Vec<_> = (0..5).map(|_| g.generate().unwrap()).collect();
+        assert_eq!(ids, [4, 5, 6, 7, 8]);
+        assert_eq!(
+            g.generate(),
+            Err(Error::SegmentUnavailable {
+                reason: "down".to_string()
+            })
+        );
+
+        store.0.store(false, Ordering::SeqCst);
+        assert_eq!(g.generate(), Ok(9));
+    }
+}
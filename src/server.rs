@@ -0,0 +1,232 @@
+//! Serving ids over the memcached text protocol, compatible with
+//! [katsubushi](https://github.com/kayac/go-katsubushi).
+//!
+//! Every key of a `get` or `gets` request is answered with a fresh id, so
+//! any memcache client can fetch ids, and fetch several at once with a
+//! multi-key get:
+//!
+//! ```text
+//! get id
+//! VALUE id 0 18
+//! 442730451055673344
+//! END
+//! ```
+//!
+//! `version` and `quit` are supported as well, and `stats` reports the
+//! uptime, connections, and gets of the server like katsubushi does,
+//! followed by the node number, layout, ids issued, queue depth and clock
+//! anomalies of the generator:
+//!
+//! ```text
+//! stats
+//! STAT pid 4242
+//! STAT uptime 3600
+//! ...
+//! STAT clock_stalled 0
+//! END
+//! ```
+//!
+//! ```ignore
+//! use frostflake::server::MemcachedServer;
+//! use frostflake::{GeneratorAsync, GeneratorOptions};
+//! use tokio::net::TcpListener;
+//!
+//! #[tokio::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let generator = GeneratorAsync::spawn(GeneratorOptions::default());
+//!     let listener = TcpListener::bind("127.0.0.1:11212").await?;
+//!     MemcachedServer::new(generator).serve(listener).await
+//! }
+//! ```
+
+use std::io;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use crate::server_stats::{self, ServerStats};
+use crate::GeneratorAsync;
+
+// longer lines are rejected, like the 250 byte key limit of memcached
+const MAX_LINE: usize = 8192;
+
+#[derive(Clone)]
+pub struct MemcachedServer {
+    generator: Arc<GeneratorAsync>,
+    stats: Arc<ServerStats>,
+}
+
+impl MemcachedServer {
+    pub fn new(generator: Arc<GeneratorAsync>) -> Self {
+        MemcachedServer {
+            generator,
+            stats: Arc::new(ServerStats::new()),
+        }
+    }
+
+    /// Accepts connections on `listener`, each handled in its own task.
+    ///
+    /// This only returns when accepting fails.
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                let _ = server.handle(stream).await;
+            });
+        }
+    }
+
+    /// Answers requests on a single connection until the client quits or
+    /// disconnects.
+    pub async fn handle<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: S) -> io::Result<()> {
+        let _connection = self.stats.connected();
+        let mut stream = BufReader::new(stream);
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            // one byte past the limit, so that a longer line is caught
+            // before it is buffered in full
+            let mut limited = (&mut stream).take(MAX_LINE as u64 + 1);
+            if limited.read_line(&mut line).await? == 0 {
+                return Ok(());
+            }
+            if line.len() > MAX_LINE {
+                stream.write_all(b"CLIENT_ERROR line too long\r\n").await?;
+                return Ok(());
+            }
+
+            let mut words = line.split_whitespace();
+            let response = match words.next() {
+                Some(cmd) if cmd.eq_ignore_ascii_case("get") => {
+                    self.get(words.collect(), false).await
+                }
+                Some(cmd) if cmd.eq_ignore_ascii_case("gets") => {
+                    self.get(words.collect(), true).await
+                }
+                Some(cmd) if cmd.eq_ignore_ascii_case("version") => {
+                    format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION"))
+                }
+                Some(cmd) if cmd.eq_ignore_ascii_case("stats") => {
+                    match self.stats.report(&self.generator).await {
+                        Ok(stats) => server_stats::memcached(&stats),
+                        Err(e) => format!("SERVER_ERROR {}\r\n", e),
+                    }
+                }
+                Some(cmd) if cmd.eq_ignore_ascii_case("quit") => return Ok(()),
+                _ => "ERROR\r\n".to_string(),
+            };
+            stream.write_all(response.as_bytes()).await?;
+        }
+    }
+
+    async fn get(&self, keys: Vec<&str>, cas: bool) -> String {
+        if keys.is_empty() {
+            return "ERROR\r\n".to_string();
+        }
+
+        let result = self.generator.generate_many(keys.len()).await;
+        self.stats.get(keys.len() as u64, result.is_ok());
+        let ids = match result {
+            Ok(ids) => ids,
+            Err(e) => return format!("SERVER_ERROR {}\r\n", e),
+        };
+
+        let mut response = String::new();
+        for (key, id) in keys.iter().zip(ids) {
+            let value = id.to_string();
+            if cas {
+                response += &format!("VALUE {} 0 {} 0\r\n{}\r\n", key, value.len(), value);
+            } else {
+                response += &format!("VALUE {} 0 {}\r\n{}\r\n", key, value.len(), value);
+            }
+        }
+        response + "END\r\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+    use tokio::net::TcpStream;
+
+    async fn roundtrip(stream: &mut TcpStream, request: &str, lines: usize) -> Vec<String> {
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        while response.matches("\r\n").count() < lines {
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0, "connection closed");
+            response += std::str::from_utf8(&buf[..n]).unwrap();
+        }
+        response.lines().map(str::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn test_memcached_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let generator = GeneratorAsync::spawn(GeneratorOptions::default());
+        tokio::spawn(MemcachedServer::new(generator).serve(listener));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let lines = roundtrip(&mut stream, "get id\r\n", 3).await;
+        let id: u64 = lines[1].parse().unwrap();
+        assert_eq!(lines[0], format!("VALUE id 0 {}", lines[1].len()));
+        assert_eq!(lines[2], "END");
+
+        let lines = roundtrip(&mut stream, "GETS a b\r\n", 5).await;
+        let a: u64 = lines[1].parse().unwrap();
+        let b: u64 = lines[3].parse().unwrap();
+        assert_eq!(lines[0], format!("VALUE a 0 {} 0", lines[1].len()));
+        assert_eq!(lines[2], format!("VALUE b 0 {} 0", lines[3].len()));
+        assert!(id < a && a < b);
+
+        assert_eq!(roundtrip(&mut stream, "get\r\n", 1).await, ["ERROR"]);
+        assert_eq!(
+            roundtrip(&mut stream, "set id 0 0 1\r\n", 1).await,
+            ["ERROR"]
+        );
+        assert_eq!(
+            roundtrip(&mut stream, "version\r\n", 1).await,
+            [format!("VERSION {}", env!("CARGO_PKG_VERSION"))]
+        );
+
+        let lines = roundtrip(&mut stream, "stats\r\n", 21).await;
+        assert_eq!(lines[0], format!("STAT pid {}", std::process::id()));
+        assert_eq!(lines[20], "END");
+        for stat in [
+            "STAT curr_connections 1",
+            "STAT cmd_get 2",
+            "STAT get_hits 3",
+            "STAT get_misses 0",
+            "STAT node_bits 10",
+            "STAT ids_issued 3",
+            "STAT clock_moved_backwards 0",
+        ] {
+            assert!(lines.iter().any(|line| line == stat), "{}", stat);
+        }
+
+        stream.write_all(b"quit\r\n").await.unwrap();
+        assert_eq!(stream.read(&mut [0; 16]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_line_too_long() {
+        let server = MemcachedServer::new(GeneratorAsync::spawn(GeneratorOptions::default()));
+        let (mut client, stream) = tokio::io::duplex(4 * MAX_LINE);
+        let handle = tokio::spawn(async move { server.handle(stream).await });
+
+        // a line without an end is cut off at the limit
+        let request = format!("get {}", "a".repeat(2 * MAX_LINE));
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert_eq!(response, "CLIENT_ERROR line too long\r\n");
+        assert!(handle.await.unwrap().is_ok());
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{Error, GeneratorAsync};
+
+/// Counters of a server, reported by the memcached `stats` command and by
+/// `GET /stats`.
+///
+/// The first stats are named and counted like those of katsubushi, so that
+/// its dashboards keep working: `cmd_get` counts requests for ids,
+/// `get_hits` the ids returned, and `get_misses` the ids that failed.
+#[derive(Debug)]
+pub(crate) struct ServerStats {
+    started: Instant,
+    curr_connections: AtomicU64,
+    total_connections: AtomicU64,
+    cmd_get: AtomicU64,
+    get_hits: AtomicU64,
+    get_misses: AtomicU64,
+}
+
+pub(crate) enum Stat {
+    Number(u64),
+    Text(String),
+}
+
+/// Counts a connection as current until dropped.
+pub(crate) struct Connection(Arc<ServerStats>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.curr_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ServerStats {
+    pub(crate) fn new() -> Self {
+        ServerStats {
+            started: Instant::now(),
+            curr_connections: AtomicU64::new(0),
+            total_connections: AtomicU64::new(0),
+            cmd_get: AtomicU64::new(0),
+            get_hits: AtomicU64::new(0),
+            get_misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn connected(self: &Arc<Self>) -> Connection {
+        self.curr_connections.fetch_add(1, Ordering::Relaxed);
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        Connection(self.clone())
+    }
+
+    /// Counts a request for `n` ids, which failed unless `ok`.
+    pub(crate) fn get(&self, n: u64, ok: bool) {
+        self.cmd_get.fetch_add(1, Ordering::Relaxed);
+        match ok {
+            true => self.get_hits.fetch_add(n, Ordering::Relaxed),
+            false => self.get_misses.fetch_add(n, Ordering::Relaxed),
+        };
+    }
+
+    /// The stats of the server and of `generator`, in the order reported.
+    pub(crate) async fn report(
+        &self,
+        generator: &GeneratorAsync,
+    ) -> Result<Vec<(&'static str, Stat)>, Error> {
+        let diagnostics = generator.diagnostics().await?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let counter = |c: &AtomicU64| Stat::Number(c.load(Ordering::Relaxed));
+        let layout = diagnostics.layout;
+
+        Ok(vec![
+            ("pid", Stat::Number(std::process::id() as u64)),
+            ("uptime", Stat::Number(self.started.elapsed().as_secs())),
+            ("time", Stat::Number(time)),
+            ("version", Stat::Text(env!("CARGO_PKG_VERSION").to_string())),
+            ("curr_connections", counter(&self.curr_connections)),
+            ("total_connections", counter(&self.total_connections)),
+            ("cmd_get", counter(&self.cmd_get)),
+            ("get_hits", counter(&self.get_hits)),
+            ("get_misses", counter(&self.get_misses)),
+            ("node", Stat::Number(diagnostics.node)),
+            ("ts_bits", Stat::Number(layout.ts_bits() as u64)),
+            ("node_bits", Stat::Number(layout.node_bits() as u64)),
+            ("seq_bits", Stat::Number(layout.seq_bits() as u64)),
+            ("base_ts", Stat::Number(layout.base_ts())),
+            ("tick_ms", Stat::Number(layout.tick_ms())),
+            ("ids_issued", Stat::Number(diagnostics.stats.issued)),
+            ("queue_depth", Stat::Number(generator.queue_depth() as u64)),
+            (
+                "sequence_exhausted",
+                Stat::Number(diagnostics.stats.sequence_exhausted),
+            ),
+            (
+                "clock_moved_backwards",
+                Stat::Number(diagnostics.stats.clock_moved_backwards),
+            ),
+            (
+                "clock_stalled",
+                Stat::Number(diagnostics.stats.clock_stalled),
+            ),
+        ])
+    }
+}
+
+/// Renders `stats` as the answer to the memcached `stats` command.
+#[cfg(feature = "server")]
+pub(crate) fn memcached(stats: &[(&str, Stat)]) -> String {
+    let mut out = String::new();
+    for (name, value) in stats {
+        let _ = match value {
+            Stat::Number(n) => write!(out, "STAT {} {}\r\n", name, n),
+            Stat::Text(s) => write!(out, "STAT {} {}\r\n", name, s),
+        };
+    }
+    out + "END\r\n"
+}
+
+/// Renders `stats` as a JSON object.
+#[cfg(feature = "http-server")]
+pub(crate) fn json(stats: &[(&str, Stat)]) -> String {
+    let mut out = String::from("{");
+    for (i, (name, value)) in stats.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        // the texts are versions, without characters to escape
+        let _ = match value {
+            Stat::Number(n) => write!(out, "\"{}\":{}", name, n),
+            Stat::Text(s) => write!(out, "\"{}\":\"{}\"", name, s),
+        };
+    }
+    out + "}"
+}
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use crate::traits::BoxFuture;
+use crate::{AsyncIdGenerator, AtomicGenerator, Error, Generator, GeneratorOptions, IdGenerator};
+
+/// Boxed generator, for storing a generator without naming its type.
+pub type BoxIdGenerator = Box<dyn IdGenerator + Send + Sync>;
+
+/// Cheaply cloneable handle to any generator.
+///
+/// Clones share the same underlying generator, so this can be stored in
+/// dependency injection or app-state containers (axum `State`, actix `Data`)
+/// without making them generic, and without wrapping a `Generator` in
+/// `Arc<Mutex<_>>` by hand.
+///
+/// ```rust
+/// use frostflake::{GeneratorOptions, SharedGenerator};
+///
+/// let g = SharedGenerator::from_options(GeneratorOptions::default());
+/// let g2 = g.clone();
+/// assert_ne!(g.generate().unwrap(), g2.generate().unwrap());
+/// ```
+///
+/// `generate_async` does not block on anything but the short critical section
+/// of the underlying generator, so it can be called on any executor. Unlike
+/// `GeneratorAsync`, there is no background task and no channel round-trip
+/// per id, which makes it the cheaper choice when ids are generated in the
+/// calling task anyway.
+#[derive(Clone)]
+pub struct SharedGenerator {
+    inner: Arc<dyn IdGenerator + Send + Sync>,
+}
+
+impl SharedGenerator {
+    pub fn new<G: IdGenerator + Send + Sync + 'static>(generator: G) -> Self {
+        SharedGenerator {
+            inner: Arc::new(generator),
+        }
+    }
+
+    /// Creates a handle over a single `Generator`.
+    pub fn from_options(opts: GeneratorOptions) -> Self {
+        SharedGenerator::new(Generator::new(opts))
+    }
+
+    /// Creates a handle over an `AtomicGenerator`, which never takes a lock.
+    pub fn atomic(opts: GeneratorOptions) -> Self {
+        SharedGenerator::new(AtomicGenerator::new(opts))
+    }
+
+    pub fn generate(&self) -> Result<u64, Error> {
+        self.inner.generate()
+    }
+
+    pub async fn generate_async(&self) -> Result<u64, Error> {
+        self.inner.generate()
+    }
+}
+
+impl Default for SharedGenerator {
+    fn default() -> Self {
+        SharedGenerator::from_options(GeneratorOptions::default())
+    }
+}
+
+impl From<GeneratorOptions> for SharedGenerator {
+    fn from(opts: GeneratorOptions) -> Self {
+        SharedGenerator::from_options(opts)
+    }
+}
+
+impl From<BoxIdGenerator> for SharedGenerator {
+    fn from(generator: BoxIdGenerator) -> Self {
+        SharedGenerator {
+            inner: Arc::from(generator),
+        }
+    }
+}
+
+impl From<Arc<dyn IdGenerator + Send + Sync>> for SharedGenerator {
+    fn from(inner: Arc<dyn IdGenerator + Send + Sync>) -> Self {
+        SharedGenerator { inner }
+    }
+}
+
+impl IdGenerator for SharedGenerator {
+    fn generate(&self) -> Result<u64, Error> {
+        self.inner.generate()
+    }
+}
+
+impl AsyncIdGenerator for SharedGenerator {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        Box::pin(self.generate_async())
+    }
+}
+
+impl Generator {
+    /// Creates a generator boxed as a trait object.
+    pub fn boxed(opts: GeneratorOptions) -> BoxIdGenerator {
+        Box::new(Generator::new(opts))
+    }
+}
+
+#[cfg(feature = "std-thread")]
+impl crate::GeneratorPool {
+    /// Creates a pool boxed as a trait object.
+    pub fn boxed(size: usize, opts: crate::GeneratorPoolOptions) -> BoxIdGenerator {
+        Box::new(crate::GeneratorPool::new(size, opts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_shared() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g = SharedGenerator::from(Generator::boxed(
+            GeneratorOptions::default().time_fn(my_time_fn),
+        ));
+        assert_eq!(g.generate(), Ok(123 << 22));
+
+        let g2 = g.clone();
+        thread::spawn(move || assert_eq!(g2.generate(), Ok((123 << 22) + 1)))
+            .join()
+            .unwrap();
+        assert_eq!(g.generate(), Ok((123 << 22) + 2));
+    }
+
+    #[tokio::test]
+    async fn test_shared_async() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g = SharedGenerator::from(GeneratorOptions::default().time_fn(my_time_fn));
+        assert_eq!(g.generate_async().await, Ok(123 << 22));
+
+        let g2 = g.clone();
+        let id = tokio::spawn(async move { g2.generate_async().await })
+            .await
+            .unwrap();
+        assert_eq!(id, Ok((123 << 22) + 1));
+        assert_eq!(g.generate(), Ok((123 << 22) + 2));
+    }
+
+    #[tokio::test]
+    async fn test_shared_atomic() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g = SharedGenerator::atomic(GeneratorOptions::default().time_fn(my_time_fn));
+        assert_eq!(g.generate_async().await, Ok(123 << 22));
+        assert_eq!(g.clone().generate(), Ok((123 << 22) + 1));
+    }
+}
@@ -0,0 +1,71 @@
+//! Words of a memory-mapped file, shared by the processes mapping it.
+
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::atomic::AtomicU64;
+
+/// A file of `len` words mapped into memory, created zeroed if missing.
+pub(crate) struct Mapping {
+    ptr: NonNull<AtomicU64>,
+    len: usize,
+}
+
+// the mapping is only accessed through atomics
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    pub(crate) fn open(path: &Path, len: usize) -> io::Result<Self> {
+        let bytes = len * std::mem::size_of::<u64>();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < bytes as u64 {
+            file.set_len(bytes as u64)?;
+        }
+
+        // SAFETY: mapping a file we opened read-write, with a length within
+        // the file; the result is checked below
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                bytes,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // mappings are page aligned, so this is aligned for AtomicU64
+        let ptr = NonNull::new(ptr as *mut AtomicU64)
+            .ok_or_else(|| io::Error::other("mmap returned null"))?;
+        Ok(Mapping { ptr, len })
+    }
+
+    pub(crate) fn words(&self) -> &[AtomicU64] {
+        // SAFETY: the mapping holds `len` words and is valid until drop
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: unmapping the mapping created in open
+        unsafe {
+            libc::munmap(
+                self.ptr.as_ptr() as *mut libc::c_void,
+                self.len * std::mem::size_of::<u64>(),
+            );
+        }
+    }
+}
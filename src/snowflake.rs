@@ -0,0 +1,572 @@
+//! Typed representations of generated ids.
+
+use std::fmt;
+use std::num::{ParseIntError, TryFromIntError};
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::layout::DEFAULT_LAYOUT;
+use crate::{bitmask, max, shl, shr, Layout};
+
+/// A generated id, together with the layout it was generated with.
+///
+/// This tells ids apart from other integers in application code, and splits
+/// them into their fields without passing the layout around. Ids created from
+/// a bare `u64` or parsed from a string assume the default layout; use
+/// [`with_layout`](Self::with_layout) otherwise.
+///
+/// Ids are equal when both the raw id and the layout are, and sort by the
+/// raw id first. The archived form of the `rkyv` feature stores the layout
+/// next to the raw id.
+///
+/// With `serde`, ids of the default layout are serialized as strings, since
+/// JSON numbers above 2^53 lose precision in JavaScript, and ids of other
+/// layouts as an `id` string together with the `layout`, so that they
+/// deserialize to the same id. Strings, numbers and both forms are accepted
+/// when deserializing. Use [`serde_u64`](crate::serde_u64) to serialize a
+/// field as a number instead, which keeps the raw id only.
+///
+/// ```rust
+/// use frostflake::{Generator, GeneratorOptions};
+///
+/// let generator = Generator::new(GeneratorOptions::default().node(3));
+/// let id = generator.generate_id();
+/// assert_eq!(id.node(), 3);
+/// assert_eq!(id.to_string().parse::<u64>().unwrap(), id.as_u64());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash))
+)]
+pub struct Snowflake {
+    id: u64,
+    layout: Layout,
+}
+
+impl Snowflake {
+    pub const fn new(id: u64) -> Self {
+        Snowflake {
+            id,
+            layout: DEFAULT_LAYOUT,
+        }
+    }
+
+    pub const fn with_layout(id: u64, layout: Layout) -> Self {
+        Snowflake { id, layout }
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.id
+    }
+
+    /// Big-endian bytes, which sort like the ids, and thus by timestamp, as
+    /// keys of byte-ordered stores like compacted Kafka topics.
+    pub const fn to_be_bytes(self) -> [u8; 8] {
+        self.id.to_be_bytes()
+    }
+
+    /// Reads big-endian bytes, assuming the default layout like `new`.
+    pub const fn from_be_bytes(bytes: [u8; 8]) -> Self {
+        Snowflake::new(u64::from_be_bytes(bytes))
+    }
+
+    /// Smallest id of the timestamp `ts` in the default layout, as the lower
+    /// bound of a pagination cursor or a range query.
+    ///
+    /// `ts` is counted from the Unix epoch, like [`timestamp`](Self::timestamp).
+    /// Timestamps before `base_ts` are treated as `base_ts`, and ones past the
+    /// end of the timestamp field as its last tick. For other layouts, use
+    /// `Layout::compose(ts, 0, 0)`.
+    pub const fn first_of(ts: u64) -> Self {
+        Snowflake::new(DEFAULT_LAYOUT.compose(clamp_ts(ts), 0, 0))
+    }
+
+    /// Largest id of the timestamp `ts` in the default layout, by any node.
+    pub const fn last_of(ts: u64) -> Self {
+        Snowflake::new(DEFAULT_LAYOUT.compose(clamp_ts(ts), u64::MAX, u64::MAX))
+    }
+
+    /// The next larger id, or `None` after the largest one.
+    ///
+    /// Past the last sequence number, this is the first one of the next node
+    /// or tick, so `id.succ()` is an exclusive lower bound turned inclusive,
+    /// as in `WHERE id >= cursor.succ()`.
+    pub const fn succ(self) -> Option<Self> {
+        match self.id.checked_add(1) {
+            Some(id) => Some(Snowflake::with_layout(id, self.layout)),
+            None => None,
+        }
+    }
+
+    /// The next smaller id, or `None` before 0.
+    pub const fn pred(self) -> Option<Self> {
+        match self.id.checked_sub(1) {
+            Some(id) => Some(Snowflake::with_layout(id, self.layout)),
+            None => None,
+        }
+    }
+
+    /// The same id with the timestamp moved `duration` later, or `None` if
+    /// it doesn't fit in the timestamp bits.
+    ///
+    /// The node number and the sequence are kept, and durations are counted
+    /// in whole ticks of the layout, truncating the rest.
+    pub fn checked_add(self, duration: Duration) -> Option<Self> {
+        let elapsed = self.elapsed().checked_add(self.ticks(duration)?)?;
+        self.with_elapsed(elapsed)
+    }
+
+    /// The same id with the timestamp moved `duration` earlier, or `None`
+    /// if that is before `base_ts`.
+    pub fn checked_sub(self, duration: Duration) -> Option<Self> {
+        let elapsed = self.elapsed().checked_sub(self.ticks(duration)?)?;
+        self.with_elapsed(elapsed)
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Milliseconds since the Unix epoch, or the unit of `time_fn` counted
+    /// from the epoch if it is not milliseconds.
+    pub fn timestamp(&self) -> u64 {
+        self.layout.decode(self.id).timestamp
+    }
+
+    /// When the id was generated, assuming `time_fn` counts the ticks of the
+    /// layout.
+    pub fn datetime(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp().saturating_mul(self.layout.tick_ms()))
+    }
+
+    pub fn node(&self) -> u64 {
+        self.layout.decode(self.id).node
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.layout.decode(self.id).seq
+    }
+
+    /// One of `n` shards, for routing rows by id.
+    ///
+    /// The id is mixed before taking it modulo `n`, since the low bits of ids
+    /// are the sequence number, which is 0 for most ids at low rates. The mix
+    /// is fixed, so an id stays on its shard as long as `n` does. For ids of
+    /// `Generator::generate_for_key`, route by `Generator::shard` instead,
+    /// which is the same for all ids of a key.
+    ///
+    /// Returns `None` if `n` is 0.
+    pub fn shard(&self, n: u64) -> Option<u64> {
+        // finalizer of MurmurHash3
+        let mut h = self.id;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51afd7ed558ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+        h ^= h >> 33;
+        h.checked_rem(n)
+    }
+}
+
+impl Snowflake {
+    // ticks since base_ts
+    fn elapsed(&self) -> u64 {
+        let layout = &self.layout;
+        shr(self.id, layout.node_bits() + layout.seq_bits()) & max(layout.ts_bits())
+    }
+
+    fn ticks(&self, duration: Duration) -> Option<u64> {
+        u64::try_from(duration.as_millis() / self.layout.tick_ms() as u128).ok()
+    }
+
+    fn with_elapsed(self, elapsed: u64) -> Option<Self> {
+        let layout = &self.layout;
+        if elapsed > max(layout.ts_bits()) {
+            return None;
+        }
+
+        let shift = layout.node_bits() + layout.seq_bits();
+        let id = (self.id & !bitmask(shift)) | shl(elapsed, shift);
+        Some(Snowflake::with_layout(id, self.layout))
+    }
+}
+
+// timestamp within the range of the default layout
+const fn clamp_ts(ts: u64) -> u64 {
+    let last = DEFAULT_LAYOUT.base_ts() + max(DEFAULT_LAYOUT.ts_bits());
+    if ts > last {
+        last
+    } else {
+        ts
+    }
+}
+
+/// Moves the timestamp later, see [`Snowflake::checked_add`].
+///
+/// Panics if the timestamp doesn't fit in the timestamp bits.
+impl Add<Duration> for Snowflake {
+    type Output = Snowflake;
+
+    fn add(self, duration: Duration) -> Snowflake {
+        match self.checked_add(duration) {
+            Some(id) => id,
+            None => panic!(
+                "timestamp of id {} + {:?} exceeds ts_bits limit",
+                self, duration
+            ),
+        }
+    }
+}
+
+/// Moves the timestamp earlier, see [`Snowflake::checked_sub`].
+///
+/// Panics if the timestamp would be before `base_ts`.
+impl Sub<Duration> for Snowflake {
+    type Output = Snowflake;
+
+    fn sub(self, duration: Duration) -> Snowflake {
+        match self.checked_sub(duration) {
+            Some(id) => id,
+            None => panic!(
+                "timestamp of id {} - {:?} is before base_ts",
+                self, duration
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Snowflake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.id, f)
+    }
+}
+
+impl FromStr for Snowflake {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Snowflake::new)
+    }
+}
+
+impl From<u64> for Snowflake {
+    fn from(id: u64) -> Self {
+        Snowflake::new(id)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Snowflake {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        if self.layout == DEFAULT_LAYOUT {
+            return serializer.collect_str(self);
+        }
+        let mut state = serializer.serialize_struct("Snowflake", 2)?;
+        state.serialize_field("id", &self.id.to_string())?;
+        state.serialize_field("layout", &self.layout)?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Snowflake {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = Snowflake;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("an id as a string or an unsigned integer, or with its layout")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Snowflake, E> {
+                Ok(Snowflake::new(v))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Snowflake, E> {
+                u64::try_from(v)
+                    .map(Snowflake::new)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(v), &self))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Snowflake, E> {
+                v.parse()
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Snowflake, A::Error> {
+                let (mut id, mut layout) = (None, None);
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "id" => id = Some(map.next_value::<Snowflake>()?.id),
+                        "layout" => layout = Some(map.next_value::<Layout>()?),
+                        key => return Err(serde::de::Error::unknown_field(key, &["id", "layout"])),
+                    }
+                }
+                let id = id.ok_or_else(|| serde::de::Error::missing_field("id"))?;
+                Ok(Snowflake::with_layout(id, layout.unwrap_or(DEFAULT_LAYOUT)))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Serializes a [`Snowflake`] as a number rather than a string, for use with
+/// `#[serde(with = "frostflake::serde_u64")]`.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct Row {
+///     #[serde(with = "frostflake::serde_u64")]
+///     id: Snowflake,
+/// }
+/// ```
+#[cfg(feature = "serde")]
+pub mod serde_u64 {
+    use super::Snowflake;
+
+    pub fn serialize<S: serde::Serializer>(
+        id: &Snowflake,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(id.as_u64())
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Snowflake, D::Error> {
+        serde::Deserialize::deserialize(deserializer)
+    }
+}
+
+impl From<Snowflake> for u64 {
+    fn from(id: Snowflake) -> Self {
+        id.id
+    }
+}
+
+/// For signed `BIGINT` columns. Fails for ids with the top bit set, which a
+/// 42bit timestamp reaches about 70 years after `base_ts`, in 2086 for the
+/// default layout. A 41bit timestamp never does.
+impl TryFrom<Snowflake> for i64 {
+    type Error = TryFromIntError;
+
+    fn try_from(id: Snowflake) -> Result<Self, Self::Error> {
+        i64::try_from(id.id)
+    }
+}
+
+/// Reads a signed `BIGINT` column. Fails for negative numbers.
+impl TryFrom<i64> for Snowflake {
+    type Error = TryFromIntError;
+
+    fn try_from(id: i64) -> Result<Self, Self::Error> {
+        u64::try_from(id).map(Snowflake::new)
+    }
+}
+
+/// An id split into its fields.
+///
+/// `timestamp` is the elapsed time since `base_ts`, in the unit of `time_fn`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(derive(Debug, PartialEq, Eq, Hash))
+)]
+pub struct DecomposedId {
+    pub timestamp: u64,
+    pub node: u64,
+    pub seq: u64,
+}
+
+impl From<(u64, u64, u64)> for DecomposedId {
+    fn from((timestamp, node, seq): (u64, u64, u64)) -> Self {
+        DecomposedId {
+            timestamp,
+            node,
+            seq,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversion() {
+        let id = Snowflake::from(123u64);
+        assert_eq!(id.as_u64(), 123);
+        assert_eq!(u64::from(id), 123);
+        assert!(Snowflake::new(1) < Snowflake::new(2));
+
+        assert_eq!(i64::try_from(Snowflake::new(123)), Ok(123));
+        assert!(i64::try_from(Snowflake::new(u64::MAX)).is_err());
+        assert_eq!(Snowflake::try_from(123i64).unwrap(), Snowflake::new(123));
+
+        let (a, b) = (Snowflake::new(255), Snowflake::new(256));
+        assert!(a.to_be_bytes() < b.to_be_bytes());
+        assert_eq!(Snowflake::from_be_bytes(b.to_be_bytes()), b);
+        assert!(Snowflake::try_from(-1i64).is_err());
+    }
+
+    #[test]
+    fn test_shard() {
+        let layout = Layout::default();
+        let mut counts = [0; 4];
+        for ts in 0..1000 {
+            let id = Snowflake::with_layout(layout.compose(layout.base_ts() + ts, 3, 0), layout);
+            let shard = id.shard(4).unwrap();
+            counts[shard as usize] += 1;
+            assert_eq!(id.shard(4), Some(shard));
+        }
+        // ids of the same node and sequence spread over all shards
+        assert!(counts.iter().all(|&n| n > 200), "{:?}", counts);
+        assert_eq!(Snowflake::new(123).shard(1), Some(0));
+        assert_eq!(Snowflake::new(123).shard(0), None);
+    }
+
+    #[test]
+    fn test_accessors() {
+        let id = Snowflake::new((123 << 22) | (3 << 12) | 7);
+        assert_eq!(id.timestamp(), 1483228800000 + 123);
+        assert_eq!(
+            id.datetime(),
+            UNIX_EPOCH + Duration::from_millis(1483228800000 + 123)
+        );
+        assert_eq!((id.node(), id.seq()), (3, 7));
+
+        let layout = Layout::new(52, 8, 4).with_base_ts(0);
+        let id = Snowflake::with_layout((123 << 12) | (3 << 4) | 7, layout);
+        assert_eq!((id.timestamp(), id.node(), id.seq()), (123, 3, 7));
+
+        // the same raw id of another layout is another id
+        assert_ne!(id, Snowflake::new(id.as_u64()));
+    }
+
+    #[test]
+    fn test_first_last_of() {
+        let ts = 1483228800000 + 123;
+        assert_eq!(Snowflake::first_of(ts).as_u64(), 123 << 22);
+        assert_eq!(Snowflake::last_of(ts).as_u64(), (124 << 22) - 1);
+        assert_eq!(Snowflake::first_of(0).as_u64(), 0);
+        assert_eq!(Snowflake::last_of(u64::MAX).as_u64(), u64::MAX);
+
+        let id = Snowflake::first_of(ts);
+        assert_eq!(id.succ(), Some(Snowflake::new((123 << 22) + 1)));
+        assert_eq!(id.pred(), Some(Snowflake::last_of(ts - 1)));
+        assert_eq!(Snowflake::new(0).pred(), None);
+        assert_eq!(Snowflake::new(u64::MAX).succ(), None);
+    }
+
+    #[test]
+    fn test_duration_arithmetic() {
+        let id = Snowflake::new((123 << 22) | (3 << 12) | 7);
+        let later = id + Duration::from_secs(1);
+        assert_eq!(later.timestamp(), id.timestamp() + 1000);
+        assert_eq!((later.node(), later.seq()), (3, 7));
+        assert_eq!(later - Duration::from_secs(1), id);
+        assert_eq!(id.checked_sub(Duration::from_millis(124)), None);
+        assert_eq!(
+            Snowflake::last_of(u64::MAX).checked_add(Duration::from_millis(1)),
+            None
+        );
+
+        // whole ticks of the layout
+        let layout = Layout::sonyflake();
+        let id = Snowflake::with_layout(layout.compose(layout.base_ts() + 5, 1, 0), layout);
+        let later = id + Duration::from_millis(25);
+        assert_eq!(later.timestamp(), id.timestamp() + 2);
+        assert_eq!(later.layout(), layout);
+    }
+
+    #[test]
+    #[should_panic(expected = "is before base_ts")]
+    fn test_duration_arithmetic_crash() {
+        let _ = Snowflake::new(0) - Duration::from_millis(1);
+    }
+
+    #[test]
+    fn test_parse() {
+        let id: Snowflake = "442730451055673344".parse().unwrap();
+        assert_eq!(id.as_u64(), 442730451055673344);
+        assert_eq!(id.to_string(), "442730451055673344");
+        assert!("x".parse::<Snowflake>().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use serde::de::value::{
+            Error, I64Deserializer, MapDeserializer, StrDeserializer, U64Deserializer,
+        };
+        use serde::de::IntoDeserializer;
+        use serde::Deserialize;
+
+        // ids of the default layout are serialized by their `Display`
+        let id = Snowflake::new(442730451055673344);
+        assert_eq!(id.to_string(), "442730451055673344");
+
+        let s: StrDeserializer<Error> = "442730451055673344".into_deserializer();
+        assert_eq!(Snowflake::deserialize(s).unwrap(), id);
+        let n: U64Deserializer<Error> = 442730451055673344u64.into_deserializer();
+        assert_eq!(Snowflake::deserialize(n).unwrap(), id);
+        let n: U64Deserializer<Error> = 442730451055673344u64.into_deserializer();
+        assert_eq!(crate::serde_u64::deserialize(n).unwrap(), id);
+        let n: I64Deserializer<Error> = (-1i64).into_deserializer();
+        assert!(Snowflake::deserialize(n).is_err());
+        let s: StrDeserializer<Error> = "x".into_deserializer();
+        assert!(Snowflake::deserialize(s).is_err());
+
+        // ids of other layouts are an id and a layout
+        let map: MapDeserializer<_, Error> = MapDeserializer::new([("id", "123")].into_iter());
+        assert_eq!(Snowflake::deserialize(map).unwrap(), Snowflake::new(123));
+        let map: MapDeserializer<_, Error> = MapDeserializer::new([("layout", "x")].into_iter());
+        assert!(Snowflake::deserialize(map).is_err());
+
+        let fields = [
+            ("ts_bits", 52),
+            ("node_bits", 8),
+            ("seq_bits", 4),
+            ("tick_ms", 10),
+        ];
+        let map: MapDeserializer<_, Error> = MapDeserializer::new(fields.into_iter());
+        let layout = Layout::new(52, 8, 4).with_tick_ms(10);
+        assert_eq!(Layout::deserialize(map).unwrap(), layout);
+        let map: MapDeserializer<_, Error> = MapDeserializer::new([("seq_bits", 13)].into_iter());
+        assert!(Layout::deserialize(map).is_err());
+    }
+
+    #[cfg(feature = "rkyv")]
+    #[test]
+    fn test_rkyv_roundtrip() {
+        use rkyv::rancor::Error;
+
+        let id = Snowflake::with_layout(123 << 22, Layout::discord());
+        let bytes = rkyv::to_bytes::<Error>(&id).unwrap();
+        let archived = rkyv::access::<ArchivedSnowflake, Error>(&bytes).unwrap();
+        assert_eq!(archived.id, 123 << 22);
+        let deserialized = rkyv::deserialize::<Snowflake, Error>(archived).unwrap();
+        assert_eq!(deserialized.layout(), Layout::discord());
+
+        let decomposed = DecomposedId::from((123, 3, 1));
+        let bytes = rkyv::to_bytes::<Error>(&decomposed).unwrap();
+        let archived = rkyv::access::<ArchivedDecomposedId, Error>(&bytes).unwrap();
+        assert_eq!(archived.node, 3);
+        assert_eq!(
+            rkyv::deserialize::<DecomposedId, Error>(archived).unwrap(),
+            decomposed
+        );
+    }
+}
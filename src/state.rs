@@ -0,0 +1,277 @@
+use crate::{Core, Error, Generator, GeneratorOptions};
+
+/// Position of a generator, for handing it over to another instance.
+///
+/// A generator resumed from this state never generates ids this state was
+/// taken after, as long as it keeps the same node number.
+///
+/// With the `serde` feature, the state can be saved between runs, as the
+/// fields `last_ts` and `seq`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GeneratorState {
+    /// Timestamp of the last generated id, in the unit of `time_fn`.
+    pub last_ts: u64,
+    /// Sequence number of the last generated id.
+    pub seq: u64,
+}
+
+/// Where a generator is at, for monitoring, as returned by
+/// [`Generator::progress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GeneratorProgress {
+    /// The last id, see [`Generator::last_id`].
+    pub last_id: Option<u64>,
+    /// Timestamp of the next id, see [`Generator::peek_next_ts`].
+    pub next_ts: u64,
+}
+
+impl Generator {
+    pub fn state(&self) -> GeneratorState {
+        let core = self.core();
+        GeneratorState {
+            last_ts: core.last_ts,
+            seq: core.seq,
+        }
+    }
+
+    /// Creates a generator continuing after `state`.
+    ///
+    /// Until the clock catches up with `state.last_ts`, generating fails with
+    /// `ClockMovedBackwards`.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let old = Generator::new(GeneratorOptions::default());
+    /// let id = old.generate();
+    ///
+    /// let new = Generator::resume(GeneratorOptions::default(), old.state());
+    /// assert!(new.generate() > id);
+    /// ```
+    pub fn resume(opts: GeneratorOptions, state: GeneratorState) -> Generator {
+        let mut generator = Generator::new(opts);
+        let core = generator.core_mut();
+        core.last_ts = state.last_ts;
+        core.seq = state.seq;
+        core.load_high_water();
+        generator
+    }
+
+    /// Same as `state`, for saving with `restore` in mind.
+    pub fn snapshot(&self) -> GeneratorState {
+        self.state()
+    }
+
+    /// Same as `resume`, but fails with [`Error::ClockMovedBackwards`] right
+    /// away if the clock of `opts` is behind `state`, like after restoring
+    /// a snapshot on a machine with a clock running late.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let old = Generator::new(GeneratorOptions::default());
+    /// let id = old.generate();
+    /// let snapshot = old.snapshot();
+    ///
+    /// let new = Generator::restore(GeneratorOptions::default(), snapshot).unwrap();
+    /// assert!(new.generate() > id);
+    /// ```
+    pub fn restore(opts: GeneratorOptions, state: GeneratorState) -> Result<Generator, Error> {
+        let now = opts.clock.now();
+        if now < state.last_ts {
+            return Err(Error::ClockMovedBackwards {
+                now,
+                last_ts: state.last_ts,
+            });
+        }
+        Ok(Generator::resume(opts, state))
+    }
+
+    /// The id generated last, or `None` before the first one.
+    ///
+    /// This is the id of `state`, with the node number of the options, so
+    /// it is the id generated last by `generate` and its variants with the
+    /// plain node number, and a resumed generator reports the id of the
+    /// state it was resumed from. Ids of `generate_at` and
+    /// `generate_not_before` are not counted.
+    ///
+    /// ```rust
+    /// use frostflake::{Generator, GeneratorOptions};
+    ///
+    /// let generator = Generator::new(GeneratorOptions::default());
+    /// assert_eq!(generator.last_id(), None);
+    /// let id = generator.generate();
+    /// assert_eq!(generator.last_id(), Some(id));
+    /// ```
+    pub fn last_id(&self) -> Option<u64> {
+        self.core().last_id()
+    }
+
+    /// Timestamp the next id would carry, in the unit of `time_fn`, without
+    /// generating it.
+    ///
+    /// This is the current time, or the timestamp of the last id while the
+    /// clock is behind it, or the tick after it if its sequence is used up.
+    /// A timestamp that stays ahead of the clock, or a last id that stays
+    /// behind this, tells that the generator is stalled.
+    pub fn peek_next_ts(&self) -> u64 {
+        self.core().peek_next_ts()
+    }
+
+    /// Same as `last_id` and `peek_next_ts`, read together so that they
+    /// agree with each other.
+    pub fn progress(&self) -> GeneratorProgress {
+        let core = self.core();
+        GeneratorProgress {
+            last_id: core.last_id(),
+            next_ts: core.peek_next_ts(),
+        }
+    }
+}
+
+impl Core {
+    fn last_id(&self) -> Option<u64> {
+        (self.last_ts > self.opts.base_ts)
+            .then(|| self.id_of(self.last_ts - self.opts.base_ts, self.seq))
+    }
+
+    fn peek_next_ts(&self) -> u64 {
+        let now = self.opts.clock.now();
+        if now != self.last_ts {
+            return now.max(self.last_ts);
+        }
+
+        // like next_id, but without dropping passed scheduled ticks
+        let scheduled = self.scheduled.get(&now).copied().unwrap_or(0);
+        let taken = scheduled + self.backfilled_at(now);
+        if self.seq + 1 + taken > self.compiled.max_seq {
+            now + 1
+        } else {
+            now
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn test_resume() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default().time_fn(my_time_fn);
+        let g = Generator::new(opts.clone());
+        g.generate();
+        g.generate();
+        assert_eq!(
+            g.state(),
+            GeneratorState {
+                last_ts: 1483228800000 + 123,
+                seq: 1
+            }
+        );
+
+        let g = Generator::resume(opts.clone(), g.state());
+        assert_eq!(g.try_generate(), Ok((123 << 22) + 2));
+
+        let state = GeneratorState {
+            last_ts: 1483228800000 + 124,
+            seq: 0,
+        };
+        let g = Generator::resume(opts, state);
+        assert!(matches!(
+            g.try_generate(),
+            Err(Error::ClockMovedBackwards { .. })
+        ));
+    }
+
+    #[test]
+    fn test_restore() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default().time_fn(my_time_fn);
+        let g = Generator::new(opts.clone());
+        g.generate();
+        let g = Generator::restore(opts.clone(), g.snapshot()).unwrap();
+        assert_eq!(g.try_generate(), Ok((123 << 22) + 1));
+
+        let state = GeneratorState {
+            last_ts: 1483228800000 + 124,
+            seq: 0,
+        };
+        assert_eq!(
+            Generator::restore(opts, state).err(),
+            Some(Error::ClockMovedBackwards {
+                now: 1483228800000 + 123,
+                last_ts: 1483228800000 + 124
+            })
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize() {
+        use serde::de::value::{Error, MapDeserializer};
+        use serde::Deserialize;
+
+        let fields = [("last_ts", 5), ("seq", 7)];
+        let state =
+            GeneratorState::deserialize(MapDeserializer::<_, Error>::new(fields.into_iter()))
+                .unwrap();
+        assert_eq!(state, GeneratorState { last_ts: 5, seq: 7 });
+    }
+
+    #[test]
+    fn test_progress() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        // 2 ids per tick
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .overflow_policy(crate::OverflowPolicy::Error)
+            .bits(42, 21, 1)
+            .node(5);
+        let g = Generator::new(opts.clone());
+        assert_eq!(
+            g.progress(),
+            GeneratorProgress {
+                last_id: None,
+                next_ts: 1483228800000 + 123
+            }
+        );
+
+        let id = g.generate();
+        assert_eq!(g.last_id(), Some(id));
+        assert_eq!(g.peek_next_ts(), 1483228800000 + 123);
+        let id = g.generate();
+        assert_eq!(g.last_id(), Some((123 << 22) + (5 << 1) + 1));
+        assert_eq!(g.last_id(), Some(id));
+        // the sequence is used up
+        assert_eq!(g.peek_next_ts(), 1483228800000 + 124);
+        assert_eq!(g.state().seq, 1);
+
+        // the clock is behind a resumed state
+        let state = GeneratorState {
+            last_ts: 1483228800000 + 200,
+            seq: 0,
+        };
+        let g = Generator::resume(opts, state);
+        assert_eq!(
+            g.progress(),
+            GeneratorProgress {
+                last_id: Some((200 << 22) + (5 << 1)),
+                next_ts: 1483228800000 + 200
+            }
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Utilities for testing code that consumes generators.
+//!
+//! Application code that takes an [`IdGenerator`] or an [`AsyncIdGenerator`]
+//! instead of a concrete generator works with every flavor, `Generator`,
+//! `AtomicGenerator`, the pools and async generators, and with a
+//! [`FixedGenerator`] in tests. [`MockClock`] drives the time of a real
+//! generator by hand:
+//!
+//! ```rust
+//! use frostflake::testing::{FixedGenerator, MockClock};
+//! use frostflake::{Generator, GeneratorOptions, IdGenerator};
+//!
+//! fn new_order<G: IdGenerator>(ids: &G) -> u64 {
+//!     ids.generate().unwrap()
+//! }
+//!
+//! assert_eq!(new_order(&FixedGenerator::new([7, 8])), 7);
+//!
+//! let clock = MockClock::new(1483228800000 + 1, 1);
+//! let generator = Generator::new(GeneratorOptions::default().time_source(clock.clone()));
+//! assert_eq!(new_order(&generator), 1 << 22);
+//! clock.advance();
+//! assert_eq!(new_order(&generator), 2 << 22);
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::{AsyncIdGenerator, BoxFuture, Error, IdGenerator};
+
+/// A clock that only moves when told to, as `advance` or `advance_by`.
+pub use crate::clock::VirtualClock as MockClock;
+
+/// Generator handing out a scripted sequence of ids or errors.
+///
+/// After the script is used up, generating fails with
+/// [`Error::SequenceExhausted`].
+#[derive(Debug)]
+pub struct FixedGenerator {
+    script: Mutex<VecDeque<Result<u64, Error>>>,
+}
+
+impl FixedGenerator {
+    pub fn new<I: IntoIterator<Item = u64>>(ids: I) -> Self {
+        FixedGenerator::with_results(ids.into_iter().map(Ok))
+    }
+
+    /// Hands out `results` in order, for testing how errors are handled.
+    pub fn with_results<I: IntoIterator<Item = Result<u64, Error>>>(results: I) -> Self {
+        FixedGenerator {
+            script: Mutex::new(results.into_iter().collect()),
+        }
+    }
+
+    /// Number of results not handed out yet.
+    pub fn remaining(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Result<u64, Error>>> {
+        self.script.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl IdGenerator for FixedGenerator {
+    fn generate(&self) -> Result<u64, Error> {
+        self.lock()
+            .pop_front()
+            .unwrap_or(Err(Error::SequenceExhausted))
+    }
+}
+
+impl AsyncIdGenerator for FixedGenerator {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        Box::pin(std::future::ready(IdGenerator::generate(self)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_generator() {
+        let g = FixedGenerator::with_results([Ok(1), Err(Error::RateLimited), Ok(3)]);
+        assert_eq!(g.remaining(), 3);
+        assert_eq!(IdGenerator::generate(&g), Ok(1));
+        assert_eq!(IdGenerator::generate(&g), Err(Error::RateLimited));
+        assert_eq!(IdGenerator::generate(&g), Ok(3));
+        assert_eq!(IdGenerator::generate(&g), Err(Error::SequenceExhausted));
+        assert_eq!(g.remaining(), 0);
+    }
+}
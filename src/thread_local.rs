@@ -0,0 +1,217 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::{Error, Generator, GeneratorOptions, IdGenerator, OptionsError};
+
+// tells apart the generators of different instances in the same thread
+static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static GENERATORS: RefCell<Vec<Local>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Generator giving every thread a `Generator` of its own, for the highest
+/// throughput within a single process.
+///
+/// The upper bits of the node number count the threads, like the pool
+/// number of a `GeneratorPool`, and the rest holds the node number of the
+/// options. A thread gets its generator on its first id, and the threads
+/// never wait for each other after that. When a thread exits, its generator
+/// is kept, state and all, for the next new thread, so threads coming and
+/// going don't use up the thread numbers.
+///
+/// ```rust
+/// use frostflake::{GeneratorOptions, ThreadLocalGenerator};
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let generator = Arc::new(ThreadLocalGenerator::new(GeneratorOptions::default(), 16).unwrap());
+/// let handles: Vec<_> = (0..4)
+///     .map(|_| {
+///         let generator = generator.clone();
+///         thread::spawn(move || generator.generate().unwrap())
+///     })
+///     .collect();
+/// for handle in handles {
+///     handle.join().unwrap();
+/// }
+/// ```
+pub struct ThreadLocalGenerator {
+    key: u64,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    opts: GeneratorOptions,
+    max_threads: u64,
+    // bits of the node number below the thread number
+    node_bits: u8,
+    next_thread: AtomicU64,
+    // generators of exited threads
+    idle: Mutex<Vec<Generator>>,
+}
+
+/// Generator of a thread, handed back to the instance when the thread exits.
+struct Local {
+    key: u64,
+    generator: Option<Generator>,
+    shared: Arc<Shared>,
+}
+
+impl ThreadLocalGenerator {
+    /// Numbers up to `max_threads` threads within the node bits of `opts`.
+    ///
+    /// Fails with [`OptionsError::TooManyThreads`] if the node bits can't
+    /// tell that many threads apart, and with
+    /// [`OptionsError::NodeOutOfRange`] if the node number doesn't fit in
+    /// the bits left beside the thread number, and with
+    /// [`OptionsError::ZeroCount`] for 0 threads.
+    pub fn new(opts: GeneratorOptions, max_threads: u64) -> Result<Self, OptionsError> {
+        if max_threads == 0 {
+            return Err(OptionsError::ZeroCount {
+                name: "max_threads",
+            });
+        }
+        let node_bits = opts.split_node_bits(max_threads)?;
+
+        Ok(ThreadLocalGenerator {
+            key: NEXT_KEY.fetch_add(1, Ordering::Relaxed),
+            shared: Arc::new(Shared {
+                opts,
+                max_threads,
+                node_bits,
+                next_thread: AtomicU64::new(0),
+                idle: Mutex::new(vec![]),
+            }),
+        })
+    }
+
+    /// Generates an id with the generator of the calling thread.
+    ///
+    /// Fails with [`Error::TooManyThreads`] when more than `max_threads`
+    /// threads are generating at once.
+    pub fn generate(&self) -> Result<u64, Error> {
+        GENERATORS.with(|locals| {
+            if let Some(local) = locals.borrow().iter().find(|l| l.key == self.key) {
+                if let Some(generator) = &local.generator {
+                    return generator.try_generate();
+                }
+            }
+
+            let generator = self.shared.checkout()?;
+            let id = generator.try_generate();
+            locals.borrow_mut().push(Local {
+                key: self.key,
+                generator: Some(generator),
+                shared: self.shared.clone(),
+            });
+            id
+        })
+    }
+
+    /// Number of threads that got a generator so far, including exited ones.
+    pub fn threads(&self) -> u64 {
+        let started = self.shared.next_thread.load(Ordering::Relaxed);
+        started.min(self.shared.max_threads)
+    }
+}
+
+impl Shared {
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, Vec<Generator>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Generator of an exited thread, or a new one with the next thread
+    /// number.
+    fn checkout(&self) -> Result<Generator, Error> {
+        if let Some(generator) = self.lock_idle().pop() {
+            return Ok(generator);
+        }
+
+        let thread = self.next_thread.fetch_add(1, Ordering::Relaxed);
+        if thread >= self.max_threads {
+            return Err(Error::TooManyThreads {
+                max: self.max_threads,
+            });
+        }
+        Ok(Generator::new(self.opts.split_node(thread, self.node_bits)))
+    }
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        if let Some(generator) = self.generator.take() {
+            self.shared.lock_idle().push(generator);
+        }
+    }
+}
+
+impl IdGenerator for ThreadLocalGenerator {
+    fn generate(&self) -> Result<u64, Error> {
+        ThreadLocalGenerator::generate(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::thread;
+
+    #[test]
+    fn test_thread_local_generator() {
+        let opts = GeneratorOptions::default().node(3);
+        let g = Arc::new(ThreadLocalGenerator::new(opts, 4).unwrap());
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let g = g.clone();
+                thread::spawn(move || (0..1000).map(|_| g.generate().unwrap()).collect::<Vec<_>>())
+            })
+            .collect();
+        let mut ids = HashSet::new();
+        let mut nodes = HashSet::new();
+        for handle in handles {
+            for id in handle.join().unwrap() {
+                assert!(ids.insert(id));
+                nodes.insert((id >> 12) & 1023);
+            }
+        }
+        // each thread has a thread number above the node number of the options
+        assert!(nodes.iter().all(|node| node & 255 == 3));
+        assert!(g.threads() <= 4);
+
+        // exited threads hand their generators to new ones
+        for _ in 0..10 {
+            let g = g.clone();
+            thread::spawn(move || g.generate().unwrap()).join().unwrap();
+        }
+        assert!(g.threads() <= 4);
+    }
+
+    #[test]
+    fn test_too_many_threads() {
+        let opts = GeneratorOptions::default().bits(42, 2, 20);
+        assert_eq!(
+            ThreadLocalGenerator::new(opts.clone(), 5).err(),
+            Some(OptionsError::TooManyThreads { threads: 5, max: 4 })
+        );
+        assert_eq!(
+            ThreadLocalGenerator::new(opts.clone().node(1), 4).err(),
+            Some(OptionsError::NodeOutOfRange { node: 1, max: 0 })
+        );
+        assert_eq!(
+            ThreadLocalGenerator::new(opts.clone(), 0).err(),
+            Some(OptionsError::ZeroCount {
+                name: "max_threads"
+            })
+        );
+
+        let g = Arc::new(ThreadLocalGenerator::new(opts, 1).unwrap());
+        assert!(g.generate().is_ok());
+        let other = g.clone();
+        let result = thread::spawn(move || other.generate()).join().unwrap();
+        assert_eq!(result, Err(Error::TooManyThreads { max: 1 }));
+    }
+}
@@ -1,46 +1,699 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
 use tokio::sync::{mpsc, oneshot};
 
-use crate::{Generator, GeneratorOptions};
+use crate::decode::PoolDecoded;
+use crate::error::panic_message;
+use crate::metrics::Metrics;
+use crate::pool_options::PoolClock;
+use crate::{
+    BoxFuture, Diagnostics, Error, Generator, GeneratorOptions, GeneratorPoolOptions,
+    GeneratorProgress, OptionsError,
+};
 
 #[derive(Debug)]
 pub enum Event {
-    Generate(oneshot::Sender<u64>),
+    Generate(oneshot::Sender<Result<u64, Error>>),
+    GenerateChunk(u64, oneshot::Sender<Result<Vec<u64>, Error>>),
+    /// Stops the task once the requests queued before are answered.
+    Shutdown(oneshot::Sender<()>),
+    /// Asks for `Generator::progress` of the task.
+    Progress(oneshot::Sender<GeneratorProgress>),
+    /// Asks for `Generator::diagnostics` of the task.
+    Diagnostics(oneshot::Sender<Diagnostics>),
 }
 
+/// Passed to the hook of [`GeneratorAsync::spawn_with_restart_hook`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskRestart {
+    /// Message of the panic that caused the restart.
+    pub reason: String,
+    /// State carried over to the restarted generator.
+    pub last_ts: u64,
+    pub seq: u64,
+    /// Number of restarts so far, including this one.
+    pub restarts: u64,
+}
+
+type RestartHook = dyn Fn(&TaskRestart) + Send + Sync;
+
+// requests queued for the task by default
+const DEFAULT_CAPACITY: usize = 10;
+
+const RUNNING: u8 = 0;
+const SHUT_DOWN: u8 = 1;
+const ABORTED: u8 = 2;
+
+/// Whether the task was stopped on purpose, shared by the handle, the chunk
+/// iterators and the task.
+#[derive(Clone, Default)]
+struct Lifecycle(Arc<AtomicU8>);
+
+impl Lifecycle {
+    // an abort overrides a shutdown, but not the other way around
+    fn stop(&self, state: u8) {
+        self.0.fetch_max(state, Ordering::SeqCst);
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.0.load(Ordering::SeqCst) == ABORTED
+    }
+
+    /// Fails with [`Error::GeneratorClosed`] once the task was stopped.
+    fn check(&self) -> Result<(), Error> {
+        match self.0.load(Ordering::SeqCst) {
+            RUNNING => Ok(()),
+            _ => Err(Error::GeneratorClosed),
+        }
+    }
+
+    /// Error of a request the task didn't take.
+    fn gone(&self) -> Error {
+        self.check().err().unwrap_or(Error::ChannelClosed)
+    }
+
+    /// Error of a request the task took, but dropped without an answer.
+    fn canceled(&self) -> Error {
+        self.check().err().unwrap_or(Error::Canceled)
+    }
+}
+
+/// Handle of a generator task, shared by its callers.
+///
+/// The task runs until [`shutdown`](Self::shutdown) or
+/// [`abort`](Self::abort), or until the handle and all chunk iterators and
+/// streams are dropped.
 pub struct GeneratorAsync {
     tx: mpsc::Sender<Event>,
+    metrics: Option<Arc<dyn Metrics + Send + Sync>>,
+    lifecycle: Lifecycle,
 }
 
 impl GeneratorAsync {
+    /// Spawns the generator task.
+    ///
+    /// The task is supervised: if generating an id panics, e.g. in a custom
+    /// `time_fn`, the request fails, and the generator is restarted with the
+    /// state of the previous one so that later ids are still unique.
     pub fn spawn(opts: GeneratorOptions) -> Arc<Self> {
-        let (tx, rx) = mpsc::channel(10);
-        tokio::spawn(async move { generator_task(rx, opts).await });
-        Arc::new(GeneratorAsync { tx })
+        GeneratorAsync::spawn_with(opts, |task| {
+            tokio::spawn(task);
+        })
+    }
+
+    /// Same as `spawn`, but hands the generator task to `spawner` instead of
+    /// spawning it on tokio.
+    ///
+    /// The task and the handle only rely on the channels of tokio, which
+    /// work on any executor, so this runs the generator on other runtimes
+    /// like async-std or smol without a tokio runtime.
+    ///
+    /// ```ignore
+    /// let generator = GeneratorAsync::spawn_with(GeneratorOptions::default(), |task| {
+    ///     async_std::task::spawn(task);
+    /// });
+    /// ```
+    pub fn spawn_with<S>(opts: GeneratorOptions, spawner: S) -> Arc<Self>
+    where
+        S: FnOnce(BoxFuture<'static, ()>),
+    {
+        GeneratorAsync::spawn_inner(opts, None, DEFAULT_CAPACITY, spawner)
+    }
+
+    /// Same as `spawn`, but queues up to `capacity` requests for the task
+    /// instead of 10.
+    ///
+    /// A larger queue absorbs bursts of requests, and with
+    /// [`try_generate_nowait`](Self::try_generate_nowait) it bounds the load
+    /// before requests are shed. Fails with [`OptionsError::ZeroCount`] for a
+    /// `capacity` of 0.
+    pub fn spawn_with_capacity(
+        opts: GeneratorOptions,
+        capacity: usize,
+    ) -> Result<Arc<Self>, OptionsError> {
+        if capacity == 0 {
+            return Err(OptionsError::ZeroCount { name: "capacity" });
+        }
+
+        Ok(GeneratorAsync::spawn_inner(opts, None, capacity, |task| {
+            tokio::spawn(task);
+        }))
+    }
+
+    /// Same as `spawn`, and calls `hook` every time the task is restarted.
+    pub fn spawn_with_restart_hook<F>(opts: GeneratorOptions, hook: F) -> Arc<Self>
+    where
+        F: Fn(&TaskRestart) + Send + Sync + 'static,
+    {
+        GeneratorAsync::spawn_inner(opts, Some(Arc::new(hook)), DEFAULT_CAPACITY, |task| {
+            tokio::spawn(task);
+        })
+    }
+
+    fn spawn_inner<S>(
+        opts: GeneratorOptions,
+        hook: Option<Arc<RestartHook>>,
+        capacity: usize,
+        spawner: S,
+    ) -> Arc<Self>
+    where
+        S: FnOnce(BoxFuture<'static, ()>),
+    {
+        let (tx, rx) = mpsc::channel(capacity);
+        let metrics = opts.metrics.clone();
+        let lifecycle = Lifecycle::default();
+        let task_lifecycle = lifecycle.clone();
+        spawner(Box::pin(async move {
+            generator_task(rx, opts, hook, task_lifecycle).await;
+        }));
+        Arc::new(GeneratorAsync {
+            tx,
+            metrics,
+            lifecycle,
+        })
+    }
+
+    /// Stops the task once the requests already queued are answered, and
+    /// waits for it.
+    ///
+    /// Requests made from then on fail with [`Error::GeneratorClosed`].
+    pub async fn shutdown(&self) {
+        self.lifecycle.stop(SHUT_DOWN);
+        let (tx, rx) = oneshot::channel();
+        if self.tx.send(Event::Shutdown(tx)).await.is_ok() {
+            // also done if the task exits for another shutdown
+            let _ = rx.await;
+        }
+    }
+
+    /// Stops the task without answering the requests queued, which fail
+    /// with [`Error::GeneratorClosed`] like the requests made from then on.
+    ///
+    /// The task exits once it is polled next, without waiting for it.
+    pub fn abort(&self) {
+        self.lifecycle.stop(ABORTED);
+        // wakes the task up if idle; a full queue wakes it up anyway
+        let (tx, _) = oneshot::channel();
+        let _ = self.tx.try_send(Event::Shutdown(tx));
+    }
+
+    /// Errors of the generator, like `SequenceExhausted`, are passed
+    /// through. [`Error::GeneratorClosed`] is returned after `shutdown` or
+    /// `abort`, [`Error::ChannelClosed`] if the task is gone otherwise, and
+    /// [`Error::Canceled`] if generating panicked.
+    ///
+    /// Dropping the future before it completes, like on a timeout, withdraws
+    /// the request: the task skips requests nobody waits for anymore, and
+    /// never blocks on an abandoned one.
+    pub async fn generate(&self) -> Result<u64, Error> {
+        self.try_generate().await
+    }
+
+    /// Same as `generate`, but fails with [`Error::DeadlineExceeded`] if no
+    /// id is generated within `timeout`, waiting in the queue included.
+    ///
+    /// Unlike the other methods, this needs a tokio runtime with the time
+    /// driver enabled.
+    pub async fn generate_timeout(&self, timeout: Duration) -> Result<u64, Error> {
+        tokio::time::timeout(timeout, self.generate())
+            .await
+            .map_err(|_| Error::DeadlineExceeded)?
+    }
+
+    /// Same as `generate`, named after `Generator::try_generate`.
+    pub async fn try_generate(&self) -> Result<u64, Error> {
+        self.lifecycle.check()?;
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Event::Generate(tx))
+            .await
+            .map_err(|_| self.lifecycle.gone())?;
+        if let Some(metrics) = &self.metrics {
+            metrics.queued(self.tx.max_capacity() - self.tx.capacity());
+        }
+        rx.await.map_err(|_| self.lifecycle.canceled())?
+    }
+
+    /// Same as `try_generate`, but fails with [`Error::Busy`] right away
+    /// instead of waiting when the queue of the task is full, so that callers
+    /// can shed load.
+    ///
+    /// Once queued, the request still waits for its id.
+    pub async fn try_generate_nowait(&self) -> Result<u64, Error> {
+        self.lifecycle.check()?;
+        let (tx, rx) = oneshot::channel();
+        self.tx.try_send(Event::Generate(tx)).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => Error::Busy,
+            mpsc::error::TrySendError::Closed(_) => self.lifecycle.gone(),
+        })?;
+        if let Some(metrics) = &self.metrics {
+            metrics.queued(self.tx.max_capacity() - self.tx.capacity());
+        }
+        rx.await.map_err(|_| self.lifecycle.canceled())?
+    }
+
+    /// Generates `n` ids, handed out in chunks as they are produced.
+    ///
+    /// A chunk holds at most the ids available within a single tick, and the
+    /// next chunk is only generated once the previous one is consumed, so a
+    /// large `n` never keeps the generator busy for other callers.
+    ///
+    /// ```ignore
+    /// let mut chunks = generator.generate_chunked(100_000);
+    /// while let Some(chunk) = chunks.next().await {
+    ///     for id in chunk? {
+    ///         // ...
+    ///     }
+    /// }
+    /// ```
+    pub fn generate_chunked(&self, n: u64) -> IdChunks {
+        IdChunks {
+            tx: self.tx.clone(),
+            lifecycle: self.lifecycle.clone(),
+            remaining: n,
+        }
+    }
+
+    /// Hands out ids one by one for as long as the caller wants, fetched
+    /// from the task in chunks of up to 256 ids, so there is no round trip
+    /// per id.
+    ///
+    /// Ids are generated when their chunk is fetched, so their timestamps lag
+    /// behind when a consumer is slow. `IdStream` has an async `next` rather
+    /// than implementing `Stream` of the futures crate, which it can be
+    /// turned into by `futures::stream::unfold`.
+    ///
+    /// ```ignore
+    /// let mut ids = generator.stream();
+    /// loop {
+    ///     let id = ids.next().await?;
+    ///     // ...
+    /// }
+    /// ```
+    pub fn stream(&self) -> IdStream {
+        IdStream {
+            tx: self.tx.clone(),
+            lifecycle: self.lifecycle.clone(),
+            buffered: VecDeque::new(),
+        }
+    }
+
+    /// Generates `n` ids in ascending order, requested in chunks of up to a
+    /// tick.
+    pub async fn generate_many(&self, n: usize) -> Result<Vec<u64>, Error> {
+        let mut ids = Vec::with_capacity(n);
+        let mut chunks = self.generate_chunked(n as u64);
+        while let Some(chunk) = chunks.next().await {
+            ids.extend(chunk?);
+        }
+        Ok(ids)
+    }
+
+    /// Fills `buf` with fresh ids, requested in chunks of up to a tick.
+    pub async fn generate_into(&self, buf: &mut [u64]) -> Result<(), Error> {
+        let mut chunks = self.generate_chunked(buf.len() as u64);
+        let mut filled = 0;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            buf[filled..filled + chunk.len()].copy_from_slice(&chunk);
+            filled += chunk.len();
+        }
+        Ok(())
+    }
+
+    /// `Generator::progress` of the task, answered after the requests queued
+    /// before.
+    ///
+    /// Fails like `generate` once the task is stopped or gone.
+    pub async fn progress(&self) -> Result<GeneratorProgress, Error> {
+        self.lifecycle.check()?;
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Event::Progress(tx))
+            .await
+            .map_err(|_| self.lifecycle.gone())?;
+        rx.await.map_err(|_| self.lifecycle.canceled())
+    }
+
+    /// `Generator::diagnostics` of the task, answered after the requests
+    /// queued before.
+    ///
+    /// Fails like `generate` once the task is stopped or gone.
+    pub async fn diagnostics(&self) -> Result<Diagnostics, Error> {
+        self.lifecycle.check()?;
+        let (tx, rx) = oneshot::channel();
+        self.tx
+            .send(Event::Diagnostics(tx))
+            .await
+            .map_err(|_| self.lifecycle.gone())?;
+        rx.await.map_err(|_| self.lifecycle.canceled())
+    }
+
+    /// Number of requests waiting for the task.
+    pub fn queue_depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+}
+
+/// Chunks of ids returned by [`GeneratorAsync::generate_chunked`].
+pub struct IdChunks {
+    tx: mpsc::Sender<Event>,
+    lifecycle: Lifecycle,
+    remaining: u64,
+}
+
+impl IdChunks {
+    /// Number of ids not handed out yet.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
     }
 
-    pub async fn generate(&self) -> anyhow::Result<u64> {
+    /// Waits for the next chunk, or returns `None` when all ids are handed out.
+    ///
+    /// After an error is returned, the remaining ids are abandoned.
+    pub async fn next(&mut self) -> Option<Result<Vec<u64>, Error>> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let chunk = match self.lifecycle.check() {
+            Ok(_) => match self.tx.send(Event::GenerateChunk(self.remaining, tx)).await {
+                Ok(_) => rx.await.unwrap_or_else(|_| Err(self.lifecycle.canceled())),
+                Err(_) => Err(self.lifecycle.gone()),
+            },
+            Err(e) => Err(e),
+        };
+
+        match &chunk {
+            Ok(ids) => self.remaining -= ids.len() as u64,
+            Err(_) => self.remaining = 0,
+        }
+        Some(chunk)
+    }
+}
+
+/// Endless ids returned by [`GeneratorAsync::stream`].
+pub struct IdStream {
+    tx: mpsc::Sender<Event>,
+    lifecycle: Lifecycle,
+    buffered: VecDeque<u64>,
+}
+
+// ids fetched at once by IdStream
+const STREAM_CHUNK: u64 = 256;
+
+impl IdStream {
+    /// Returns the next id, fetching a chunk from the task when none is left.
+    pub async fn next(&mut self) -> Result<u64, Error> {
+        if let Some(id) = self.buffered.pop_front() {
+            return Ok(id);
+        }
+
+        self.lifecycle.check()?;
         let (tx, rx) = oneshot::channel();
-        self.tx.send(Event::Generate(tx)).await?;
-        Ok(rx.await?)
+        self.tx
+            .send(Event::GenerateChunk(STREAM_CHUNK, tx))
+            .await
+            .map_err(|_| self.lifecycle.gone())?;
+        self.buffered = rx.await.map_err(|_| self.lifecycle.canceled())??.into();
+        self.buffered.pop_front().ok_or(Error::ChannelClosed)
+    }
+}
+
+/// Generates ids on `size` generator tasks, each with a pool number of its own.
+///
+/// This is the async counterpart of `GeneratorPool`: requests are handed to
+/// the tasks in turn, so callers don't queue up behind a single task.
+pub struct GeneratorPoolAsync {
+    opts: GeneratorPoolOptions,
+    generators: Vec<Arc<GeneratorAsync>>,
+    // generator the next request goes to
+    next: AtomicUsize,
+    clock: Option<Arc<PoolClock>>,
+}
+
+impl GeneratorPoolAsync {
+    /// Spawns the generator tasks.
+    ///
+    /// Panics if `size` exceeds the generators allowed by the pool bits, see
+    /// [`try_spawn`](Self::try_spawn).
+    pub fn spawn(size: usize, opts: GeneratorPoolOptions) -> Arc<Self> {
+        GeneratorPoolAsync::try_spawn(size, opts).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Fails with [`OptionsError::PoolTooLarge`] if `size` exceeds
+    /// 2^`pool_bits`, as tasks would share pool numbers and generate
+    /// colliding ids.
+    pub fn try_spawn(
+        size: usize,
+        mut opts: GeneratorPoolOptions,
+    ) -> Result<Arc<Self>, OptionsError> {
+        let max_workers = opts.max_workers();
+        if size as u64 > max_workers {
+            return Err(OptionsError::PoolTooLarge {
+                size,
+                max: max_workers,
+            });
+        }
+
+        let clock = opts.share_clock();
+        // with strict ordering, a single task serves all requests
+        let generators = match opts.strict_ordering {
+            true if size > 0 => vec![GeneratorAsync::spawn(opts.worker_opts(0)); size],
+            _ => (0..size)
+                .map(|i| GeneratorAsync::spawn(opts.worker_opts(i as u64)))
+                .collect(),
+        };
+        Ok(Arc::new(GeneratorPoolAsync {
+            opts,
+            generators,
+            next: AtomicUsize::new(0),
+            clock,
+        }))
+    }
+
+    pub fn size(&self) -> usize {
+        self.generators.len()
+    }
+
+    /// Number of time readings behind one another task has seen, and the
+    /// largest distance behind, with `GeneratorPoolOptions::shared_clock`.
+    pub fn clock_skew(&self) -> Option<(u64, u64)> {
+        self.clock
+            .as_ref()
+            .map(|clock| (clock.skewed(), clock.max_skew()))
+    }
+
+    /// Same as `GeneratorAsync::generate` on the next task.
+    /// [`Error::ChannelClosed`] is returned if the pool has no tasks.
+    pub async fn generate(&self) -> Result<u64, Error> {
+        self.pick()?.generate().await
+    }
+
+    /// Same as `generate`, named after `Generator::try_generate`.
+    pub async fn try_generate(&self) -> Result<u64, Error> {
+        self.generate().await
+    }
+
+    /// Same as `GeneratorAsync::generate_timeout` on the next task.
+    pub async fn generate_timeout(&self, timeout: Duration) -> Result<u64, Error> {
+        self.pick()?.generate_timeout(timeout).await
+    }
+
+    /// Generates `n` ids in ascending order, all by a single task.
+    pub async fn generate_many(&self, n: usize) -> Result<Vec<u64>, Error> {
+        self.pick()?.generate_many(n).await
+    }
+
+    /// `GeneratorAsync::progress` of every task, `None` for a task that is
+    /// stopped or gone.
+    pub async fn progress(&self) -> Vec<Option<GeneratorProgress>> {
+        let mut progress = Vec::with_capacity(self.generators.len());
+        for generator in &self.generators {
+            progress.push(generator.progress().await.ok());
+        }
+        progress
+    }
+
+    /// Shuts all tasks down, see [`GeneratorAsync::shutdown`].
+    pub async fn shutdown(&self) {
+        for generator in &self.generators {
+            generator.shutdown().await;
+        }
+    }
+
+    /// Aborts all tasks, see [`GeneratorAsync::abort`].
+    pub fn abort(&self) {
+        for generator in &self.generators {
+            generator.abort();
+        }
+    }
+
+    /// Same as `GeneratorPoolOptions::layout().decode(id)`.
+    pub fn extract(&self, id: u64) -> (u64, u64, u64, u64) {
+        self.opts.layout().decode(id)
+    }
+
+    /// Same as `extract`, with the fields named and the timestamp converted
+    /// to wall-clock time.
+    pub fn decode(&self, id: u64) -> PoolDecoded {
+        self.opts.decode(id)
+    }
+
+    fn pick(&self) -> Result<&GeneratorAsync, Error> {
+        if self.generators.is_empty() {
+            return Err(Error::ChannelClosed);
+        }
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.generators.len();
+        Ok(&self.generators[i])
+    }
+}
+
+/// Generates up to `n` ids within the current tick.
+fn fill_chunk(generator: &mut Generator, n: u64) -> Result<Vec<u64>, Error> {
+    let generator = generator.core_mut();
+    let allowed = generator.acquire(n)?;
+    let mut ids = vec![];
+    let mut result = Ok(());
+    while (ids.len() as u64) < allowed {
+        match generator.try_generate_once() {
+            Ok(id) => ids.push(id),
+            Err(Error::SequenceExhausted) => break,
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    generator.put_back(allowed - ids.len() as u64);
+    result.map(|_| ids)
+}
+
+struct Supervisor {
+    hook: Option<Arc<RestartHook>>,
+    restarts: u64,
+}
+
+impl Supervisor {
+    /// Runs `f`, restarting the generator if it panics.
+    fn run<T>(
+        &mut self,
+        generator: &mut Generator,
+        f: impl FnOnce(&mut Generator) -> T,
+    ) -> Option<T> {
+        let panic = match catch_unwind(AssertUnwindSafe(|| f(generator))) {
+            Ok(v) => return Some(v),
+            Err(panic) => panic,
+        };
+
+        generator.restart();
+        let state = generator.state();
+
+        self.restarts += 1;
+        if let Some(hook) = &self.hook {
+            hook(&TaskRestart {
+                reason: panic_message(&panic),
+                last_ts: state.last_ts,
+                seq: state.seq,
+                restarts: self.restarts,
+            });
+        }
+        None
+    }
+}
+
+/// Yields to the executor once, like `tokio::task::yield_now` but without
+/// needing a tokio runtime.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
     }
 }
 
 async fn generator_task(
     mut rx: mpsc::Receiver<Event>,
     opts: GeneratorOptions,
-) -> anyhow::Result<()> {
+    hook: Option<Arc<RestartHook>>,
+    lifecycle: Lifecycle,
+) {
     let mut generator = Generator::new(opts);
+    let mut supervisor = Supervisor { hook, restarts: 0 };
+
+    // wait out the startup guard here, instead of sleeping in next_id
+    while generator.core_mut().startup_wait().is_some() {
+        YieldNow(false).await;
+    }
+
+    // a request whose generation panicked is dropped, failing it on the caller side
+    // requests still queued are dropped after a shutdown or abort, failing
+    // them on the caller side too
     while let Some(evt) = rx.recv().await {
+        if lifecycle.is_aborted() {
+            break;
+        }
+        if matches!(evt, Event::Generate(_) | Event::GenerateChunk(..)) {
+            // with RateLimitPolicy::Wait, yield instead of sleeping until
+            // the rate limit allows the id
+            while generator.core_mut().rate_wait().is_some() {
+                YieldNow(false).await;
+            }
+        }
         match evt {
+            // the caller is gone, e.g. timed out
+            Event::Generate(tx) if tx.is_closed() => {}
+            Event::GenerateChunk(_, tx) if tx.is_closed() => {}
             Event::Generate(tx) => {
-                let id = generator.generate();
-                tx.send(id).expect("failed to send oneshot message");
+                if let Some(id) = supervisor.run(&mut generator, |g| g.try_generate()) {
+                    let _ = tx.send(id);
+                }
+            }
+            Event::GenerateChunk(n, tx) => loop {
+                match supervisor.run(&mut generator, |g| fill_chunk(g, n)) {
+                    // the current tick is exhausted; wait for the next one,
+                    // unless the caller gave up in the meantime
+                    Some(Ok(ids)) if ids.is_empty() => {
+                        if tx.is_closed() {
+                            break;
+                        }
+                        YieldNow(false).await
+                    }
+                    Some(chunk) => {
+                        let _ = tx.send(chunk);
+                        break;
+                    }
+                    None => break,
+                }
+            },
+            Event::Progress(tx) => {
+                let _ = tx.send(generator.progress());
+            }
+            Event::Diagnostics(tx) => {
+                let _ = tx.send(generator.diagnostics());
+            }
+            Event::Shutdown(done) => {
+                drop(rx);
+                let _ = done.send(());
+                return;
             }
         }
     }
-    Ok(())
 }
 
 #[cfg(test)]
@@ -80,4 +733,414 @@ mod tests {
         }
         assert!(rx.try_recv().is_err());
     }
+
+    #[tokio::test]
+    async fn test_async_id_generator() {
+        use crate::AsyncIdGenerator;
+
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g: Arc<dyn AsyncIdGenerator + Send + Sync> =
+            GeneratorAsync::spawn(GeneratorOptions::default().time_fn(my_time_fn));
+        assert_eq!(g.generate().await, Ok(123 << 22));
+        assert_eq!(g.generate().await, Ok((123 << 22) + 1));
+    }
+
+    #[tokio::test]
+    async fn test_generate_chunked() {
+        use std::collections::HashSet;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        fn my_time_fn() -> u64 {
+            1483228800000 + 1 + CALLS.fetch_add(1, Ordering::Relaxed) / 10
+        }
+
+        // 2bit sequence gives at most 4 ids per tick
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 20, 2)
+            .base_ts(1483228800000);
+        let g = GeneratorAsync::spawn(opts);
+
+        let mut chunks = g.generate_chunked(10);
+        let mut ids = HashSet::new();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.unwrap();
+            assert!(!chunk.is_empty() && chunk.len() <= 4);
+            ids.extend(chunk);
+        }
+        assert_eq!(ids.len(), 10);
+        assert_eq!(chunks.remaining(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_try_generate() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 21, 1)
+            .base_ts(1483228800000)
+            .overflow_policy(crate::OverflowPolicy::Error);
+        let g = GeneratorAsync::spawn(opts);
+        assert_eq!(g.try_generate().await, Ok(123 << 22));
+        assert_eq!(g.try_generate().await, Ok((123 << 22) + 1));
+        assert_eq!(g.try_generate().await, Err(Error::SequenceExhausted));
+
+        assert_eq!(g.generate().await, Err(Error::SequenceExhausted));
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let g = GeneratorAsync::spawn(GeneratorOptions::default());
+        let mut ids = g.stream();
+        let mut last = 0;
+        for _ in 0..1000 {
+            let id = ids.next().await.unwrap();
+            assert!(id > last);
+            last = id;
+        }
+        assert!(g.try_generate().await.unwrap() > last);
+    }
+
+    #[tokio::test]
+    async fn test_try_generate_nowait() {
+        // the task only starts once the queue is full
+        let mut task = None;
+        let g =
+            GeneratorAsync::spawn_inner(GeneratorOptions::default(), None, 2, |t| task = Some(t));
+
+        let queued: Vec<_> = (0..2)
+            .map(|_| {
+                let g = g.clone();
+                tokio::spawn(async move { g.try_generate_nowait().await })
+            })
+            .collect();
+        while g.tx.capacity() > 0 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(g.try_generate_nowait().await, Err(Error::Busy));
+
+        tokio::spawn(task.unwrap());
+        for handle in queued {
+            assert!(handle.await.unwrap().is_ok());
+        }
+        assert!(g.try_generate_nowait().await.is_ok());
+
+        let g = GeneratorAsync::spawn_with_capacity(GeneratorOptions::default(), 8).unwrap();
+        assert!(g.generate().await.is_ok());
+        assert_eq!(
+            GeneratorAsync::spawn_with_capacity(GeneratorOptions::default(), 0).err(),
+            Some(OptionsError::ZeroCount { name: "capacity" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_timeout() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        // the task only starts after the request timed out
+        let mut task = None;
+        let opts = GeneratorOptions::default().time_fn(my_time_fn);
+        let g = GeneratorAsync::spawn_inner(opts, None, 4, |t| task = Some(t));
+        assert_eq!(
+            g.generate_timeout(Duration::from_millis(10)).await,
+            Err(Error::DeadlineExceeded)
+        );
+
+        // the abandoned request is skipped instead of taking an id
+        tokio::spawn(task.unwrap());
+        assert_eq!(
+            g.generate_timeout(Duration::from_secs(10)).await,
+            Ok(123 << 22)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_chunk() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        // 2 ids, and then the clock never moves on to the next tick
+        let opts = GeneratorOptions::default()
+            .time_fn(my_time_fn)
+            .base_ts(0)
+            .bits(42, 21, 1)
+            .base_ts(1483228800000)
+            .overflow_policy(crate::OverflowPolicy::Error);
+        let g = GeneratorAsync::spawn(opts);
+        let mut chunks = g.generate_chunked(10);
+        assert_eq!(chunks.next().await.unwrap().unwrap().len(), 2);
+        let next = tokio::time::timeout(Duration::from_millis(10), chunks.next()).await;
+        assert!(next.is_err());
+
+        // the task gave up on the chunk, and answers other requests again
+        assert_eq!(g.try_generate().await, Err(Error::SequenceExhausted));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        // the task only starts once the requests are queued
+        let mut task = None;
+        let g =
+            GeneratorAsync::spawn_inner(GeneratorOptions::default(), None, 4, |t| task = Some(t));
+        let queued: Vec<_> = (0..2)
+            .map(|_| {
+                let g = g.clone();
+                tokio::spawn(async move { g.try_generate().await })
+            })
+            .collect();
+        while g.tx.capacity() > 2 {
+            tokio::task::yield_now().await;
+        }
+        let task = tokio::spawn(task.unwrap());
+
+        // queued requests are answered before the task stops
+        g.shutdown().await;
+        for handle in queued {
+            assert!(handle.await.unwrap().is_ok());
+        }
+        task.await.unwrap();
+
+        assert_eq!(g.try_generate().await, Err(Error::GeneratorClosed));
+        assert_eq!(g.generate().await, Err(Error::GeneratorClosed));
+        assert_eq!(g.stream().next().await, Err(Error::GeneratorClosed));
+        g.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_abort() {
+        let mut task = None;
+        let g =
+            GeneratorAsync::spawn_inner(GeneratorOptions::default(), None, 4, |t| task = Some(t));
+        let queued: Vec<_> = (0..2)
+            .map(|_| {
+                let g = g.clone();
+                tokio::spawn(async move { g.try_generate().await })
+            })
+            .collect();
+        while g.tx.capacity() > 2 {
+            tokio::task::yield_now().await;
+        }
+
+        // queued requests fail instead of being answered
+        g.abort();
+        tokio::spawn(task.unwrap()).await.unwrap();
+        for handle in queued {
+            assert_eq!(handle.await.unwrap(), Err(Error::GeneratorClosed));
+        }
+        assert_eq!(g.generate_many(10).await, Err(Error::GeneratorClosed));
+
+        let pool = GeneratorPoolAsync::spawn(2, GeneratorPoolOptions::default());
+        assert!(pool.generate().await.is_ok());
+        pool.shutdown().await;
+        assert_eq!(pool.try_generate().await, Err(Error::GeneratorClosed));
+    }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let g = GeneratorAsync::spawn(GeneratorOptions::default());
+        assert_eq!(g.progress().await.map(|p| p.last_id), Ok(None));
+        let id = g.generate().await.unwrap();
+        let progress = g.progress().await.unwrap();
+        assert_eq!(progress.last_id, Some(id));
+        assert!(progress.next_ts >= GeneratorOptions::default().base_ts + (id >> 22));
+
+        let pool = GeneratorPoolAsync::spawn(2, GeneratorPoolOptions::default());
+        let ids = pool.generate_many(3).await.unwrap();
+        let progress = pool.progress().await;
+        assert_eq!(progress[0].and_then(|p| p.last_id), ids.last().copied());
+        assert_eq!(progress[1].map(|p| p.last_id), Some(None));
+
+        pool.shutdown().await;
+        assert_eq!(pool.progress().await, [None, None]);
+        g.abort();
+        assert_eq!(g.progress().await, Err(Error::GeneratorClosed));
+    }
+
+    #[tokio::test]
+    async fn test_max_rate() {
+        use crate::RateLimitPolicy;
+
+        let g = GeneratorAsync::spawn(GeneratorOptions::default().max_rate(10));
+        assert_eq!(g.generate_many(10).await.map(|ids| ids.len()), Ok(10));
+        assert_eq!(g.generate().await, Err(Error::RateLimited));
+
+        let opts = GeneratorOptions::default()
+            .max_rate(100)
+            .rate_limit_policy(RateLimitPolicy::Wait);
+        let g = GeneratorAsync::spawn(opts);
+        let started = std::time::Instant::now();
+        let ids = g.generate_many(110).await.unwrap();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(g.generate().await.is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics() {
+        let g = GeneratorAsync::spawn(GeneratorOptions::default().node(3));
+        g.generate_many(5).await.unwrap();
+        let diagnostics = g.diagnostics().await.unwrap();
+        assert_eq!((diagnostics.node, diagnostics.stats.issued), (3, 5));
+        assert_eq!(g.queue_depth(), 0);
+
+        g.abort();
+        assert_eq!(g.diagnostics().await, Err(Error::GeneratorClosed));
+    }
+
+    #[tokio::test]
+    async fn test_pool() {
+        use std::collections::HashSet;
+
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorPoolOptions::default().time_fn(my_time_fn).node(5);
+        let g = GeneratorPoolAsync::spawn(3, opts.clone());
+        assert_eq!(g.size(), 3);
+
+        let mut ids = HashSet::new();
+        for i in 0..6 {
+            let id = g.generate().await.unwrap();
+            assert_eq!(g.extract(id), (123, i % 3, 5, i / 3));
+            ids.insert(id);
+        }
+        ids.extend(g.generate_many(10).await.unwrap());
+        assert_eq!(ids.len(), 16);
+
+        let g = GeneratorPoolAsync::spawn(3, opts.strict_ordering(true));
+        for i in 0..6 {
+            let id = g.generate().await.unwrap();
+            assert_eq!(g.extract(id), (123, 0, 5, i));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pool_size() {
+        let opts = GeneratorPoolOptions::default().bits(42, 1, 9, 12);
+        assert_eq!(
+            GeneratorPoolAsync::try_spawn(3, opts).err(),
+            Some(OptionsError::PoolTooLarge { size: 3, max: 2 })
+        );
+        assert_eq!(
+            GeneratorPoolAsync::spawn(0, GeneratorPoolOptions::default())
+                .try_generate()
+                .await,
+            Err(Error::ChannelClosed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_into() {
+        let g = GeneratorAsync::spawn(GeneratorOptions::default());
+        let mut ids = vec![0; 10_000];
+        g.generate_into(&mut ids).await.unwrap();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[tokio::test]
+    async fn test_generate_many() {
+        use std::collections::HashSet;
+
+        let g = GeneratorAsync::spawn(GeneratorOptions::default());
+        let mut handles = vec![];
+        for _ in 0..4 {
+            let g = g.clone();
+            handles.push(tokio::spawn(async move { g.generate_many(5_000).await }));
+        }
+
+        let mut all = HashSet::new();
+        for h in handles {
+            let ids = h.await.unwrap().unwrap();
+            assert_eq!(ids.len(), 5_000);
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            all.extend(ids);
+        }
+        assert_eq!(all.len(), 20_000);
+    }
+
+    #[tokio::test]
+    async fn test_restart() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Mutex;
+
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        fn my_time_fn() -> u64 {
+            if CALLS.fetch_add(1, Ordering::Relaxed) == 2 {
+                panic!("broken clock");
+            }
+            1483228800000 + 123
+        }
+
+        let restarts = Arc::new(Mutex::new(vec![]));
+        let sink = restarts.clone();
+        let g = GeneratorAsync::spawn_with_restart_hook(
+            GeneratorOptions::default().time_fn(my_time_fn),
+            move |r| sink.lock().unwrap().push(r.clone()),
+        );
+
+        assert_eq!(g.generate().await.unwrap(), 123 << 22);
+        assert_eq!(g.generate().await.unwrap(), (123 << 22) + 1);
+        assert_eq!(g.generate().await, Err(Error::Canceled));
+        // the sequence carries on instead of restarting from zero
+        assert_eq!(g.generate().await.unwrap(), (123 << 22) + 2);
+
+        assert_eq!(
+            *restarts.lock().unwrap(),
+            vec![TaskRestart {
+                reason: "broken clock".to_string(),
+                last_ts: 1483228800000 + 123,
+                seq: 1,
+                restarts: 1,
+            }]
+        );
+    }
+
+    // a minimal executor, to check that no tokio runtime is needed
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unparker(std::thread::Thread);
+        impl std::task::Wake for Unparker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Arc::new(Unparker(std::thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(v) => return v,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_spawn_with() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let opts = GeneratorOptions::default().time_fn(my_time_fn);
+        let g = GeneratorAsync::spawn_with(opts, |task| {
+            std::thread::spawn(move || block_on(task));
+        });
+        assert_eq!(block_on(g.try_generate()), Ok(123 << 22));
+        let ids = block_on(g.generate_many(100)).unwrap();
+        assert_eq!(ids.len(), 100);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
 }
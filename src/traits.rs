@@ -0,0 +1,162 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use crate::{Error, Generator};
+
+/// Future returned by [`AsyncIdGenerator::generate`].
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Common interface of id generators.
+///
+/// Implemented by `Generator`, `AtomicGenerator` and `GeneratorPool`, and by
+/// the wrappers in [`combinators`](crate::combinators). The trait is
+/// object safe, so application code can hold a
+/// `Box<dyn IdGenerator + Send + Sync>` or an
+/// `Arc<dyn IdGenerator + Send + Sync>` instead of a concrete type.
+pub trait IdGenerator {
+    fn generate(&self) -> Result<u64, Error>;
+}
+
+/// Async flavor of [`IdGenerator`].
+///
+/// Implemented by `GeneratorAsync`, `GeneratorPoolAsync`, `Generator`,
+/// `AtomicGenerator` and `Mutex<Generator>`. This is object safe as well.
+pub trait AsyncIdGenerator {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>>;
+}
+
+impl IdGenerator for Generator {
+    fn generate(&self) -> Result<u64, Error> {
+        self.try_generate()
+    }
+}
+
+// for code written before Generator took &self
+impl IdGenerator for Mutex<Generator> {
+    fn generate(&self) -> Result<u64, Error> {
+        // a panic while holding the lock can not leave the generator in a broken state
+        let g = self.lock().unwrap_or_else(|e| e.into_inner());
+        g.try_generate()
+    }
+}
+
+#[cfg(feature = "std-thread")]
+impl IdGenerator for crate::GeneratorPool {
+    fn generate(&self) -> Result<u64, Error> {
+        crate::GeneratorPool::generate(self)
+    }
+}
+
+impl<G: IdGenerator + ?Sized> IdGenerator for &G {
+    fn generate(&self) -> Result<u64, Error> {
+        (**self).generate()
+    }
+}
+
+impl<G: IdGenerator + ?Sized> IdGenerator for Box<G> {
+    fn generate(&self) -> Result<u64, Error> {
+        (**self).generate()
+    }
+}
+
+impl<G: IdGenerator + ?Sized> IdGenerator for Arc<G> {
+    fn generate(&self) -> Result<u64, Error> {
+        (**self).generate()
+    }
+}
+
+impl AsyncIdGenerator for Generator {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        Box::pin(std::future::ready(self.try_generate()))
+    }
+}
+
+impl AsyncIdGenerator for Mutex<Generator> {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        Box::pin(std::future::ready(IdGenerator::generate(self)))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncIdGenerator for crate::GeneratorAsync {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        Box::pin(crate::GeneratorAsync::try_generate(self))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncIdGenerator for crate::GeneratorPoolAsync {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        Box::pin(crate::GeneratorPoolAsync::try_generate(self))
+    }
+}
+
+impl<G: AsyncIdGenerator + ?Sized> AsyncIdGenerator for &G {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        (**self).generate()
+    }
+}
+
+impl<G: AsyncIdGenerator + ?Sized> AsyncIdGenerator for Box<G> {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        (**self).generate()
+    }
+}
+
+impl<G: AsyncIdGenerator + ?Sized> AsyncIdGenerator for Arc<G> {
+    fn generate(&self) -> BoxFuture<'_, Result<u64, Error>> {
+        (**self).generate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+
+    #[test]
+    fn test_mutex_generator() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g: Box<dyn IdGenerator> = Box::new(Mutex::new(Generator::new(
+            GeneratorOptions::default().time_fn(my_time_fn),
+        )));
+        assert_eq!(g.generate(), Ok(123 << 22));
+        assert_eq!(g.generate(), Ok((123 << 22) + 1));
+    }
+
+    #[tokio::test]
+    async fn test_async_mutex_generator() {
+        fn my_time_fn() -> u64 {
+            1483228800000 + 123
+        }
+
+        let g: Arc<dyn AsyncIdGenerator + Send + Sync> = Arc::new(Mutex::new(Generator::new(
+            GeneratorOptions::default().time_fn(my_time_fn),
+        )));
+        assert_eq!(g.generate().await, Ok(123 << 22));
+        assert_eq!(g.generate().await, Ok((123 << 22) + 1));
+    }
+
+    #[tokio::test]
+    async fn test_async_atomic_generator() {
+        let g: Arc<dyn AsyncIdGenerator + Send + Sync> =
+            Arc::new(crate::AtomicGenerator::new(GeneratorOptions::default()));
+        let id = g.generate().await.unwrap();
+        assert!(g.generate().await.unwrap() > id);
+    }
+
+    #[cfg(feature = "std-thread")]
+    #[test]
+    fn test_pool() {
+        use std::collections::HashSet;
+
+        let g: Arc<dyn IdGenerator + Send + Sync> =
+            crate::GeneratorPool::new(2, crate::GeneratorPoolOptions::default());
+        let ids: HashSet<u64> = (0..100).map(|_| g.generate().unwrap()).collect();
+        assert_eq!(ids.len(), 100);
+    }
+}
@@ -0,0 +1,201 @@
+//! Ids tagged with the kind of entity they identify.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use crate::{Error, Generator, IdGenerator};
+
+/// An id of a `T`, so that e.g. an `Id<User>` can't be passed where an
+/// `Id<Order>` is expected.
+///
+/// The bits are a plain id of the generator, and `T` is only a marker: it
+/// needs no trait implementations, and `Id<T>` is always `Copy`, `Ord`,
+/// `Hash`, `Send` and `Sync`. With `serde`, ids are serialized as strings,
+/// like [`Snowflake`](crate::Snowflake).
+///
+/// ```rust
+/// use frostflake::{GeneratorOptions, Id, TypedGenerator};
+///
+/// struct User;
+/// struct Order;
+///
+/// fn find_user(id: Id<User>) -> u64 {
+///     id.as_u64()
+/// }
+///
+/// let users = TypedGenerator::<User>::with_options(GeneratorOptions::default());
+/// let orders = TypedGenerator::<Order>::with_options(GeneratorOptions::default().node(1));
+///
+/// let user = users.generate();
+/// let order = orders.generate();
+/// find_user(user);
+/// // find_user(order); // mismatched types
+/// ```
+pub struct Id<T> {
+    id: u64,
+    // fn() -> T keeps Id<T> Send and Sync regardless of T
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Id<T> {
+    pub const fn new(id: u64) -> Self {
+        Id {
+            id,
+            marker: PhantomData,
+        }
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.id
+    }
+}
+
+impl<T> Clone for Id<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Id<T> {}
+
+impl<T> PartialEq for Id<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Id<T> {}
+
+impl<T> PartialOrd for Id<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Id<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl<T> Hash for Id<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl<T> fmt::Debug for Id<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Id<{}>({})", std::any::type_name::<T>(), self.id)
+    }
+}
+
+impl<T> fmt::Display for Id<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.id, f)
+    }
+}
+
+impl<T> FromStr for Id<T> {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Id::new)
+    }
+}
+
+impl<T> From<u64> for Id<T> {
+    fn from(id: u64) -> Self {
+        Id::new(id)
+    }
+}
+
+impl<T> From<Id<T>> for u64 {
+    fn from(id: Id<T>) -> Self {
+        id.id
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T> serde::Serialize for Id<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> serde::Deserialize<'de> for Id<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::Snowflake::deserialize(deserializer).map(|id| Id::new(id.as_u64()))
+    }
+}
+
+/// Generates [`Id<T>`](Id) with any [`IdGenerator`], a `Generator` by
+/// default.
+pub struct TypedGenerator<T, G = Generator> {
+    generator: G,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedGenerator<T> {
+    pub fn with_options(opts: crate::GeneratorOptions) -> Self {
+        TypedGenerator::new(Generator::new(opts))
+    }
+}
+
+impl<T, G: IdGenerator> TypedGenerator<T, G> {
+    pub fn new(generator: G) -> Self {
+        TypedGenerator {
+            generator,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn generate(&self) -> Id<T> {
+        self.try_generate().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as `generate`, but returns an error instead of panicking.
+    pub fn try_generate(&self) -> Result<Id<T>, Error> {
+        self.generator.generate().map(Id::new)
+    }
+
+    pub fn inner(&self) -> &G {
+        &self.generator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+    use std::sync::Arc;
+
+    struct User;
+
+    #[test]
+    fn test_typed_generator() {
+        let users = TypedGenerator::<User>::with_options(GeneratorOptions::default().node(3));
+        let a = users.generate();
+        let b = users.generate();
+        assert!(a < b);
+        assert_eq!(users.inner().extract(a.as_u64()).1, 3);
+
+        let copy = a;
+        assert_eq!(copy, a);
+        assert_eq!(a.to_string().parse::<Id<User>>().unwrap(), a);
+        assert_eq!(
+            format!("{:?}", Id::<User>::new(5)),
+            format!("Id<{}>(5)", std::any::type_name::<User>())
+        );
+
+        // any IdGenerator works, shared as well
+        let shared =
+            TypedGenerator::<User, _>::new(Arc::new(Generator::new(GeneratorOptions::default())));
+        assert!(shared.try_generate().is_ok());
+    }
+}
@@ -0,0 +1,92 @@
+use uuid::Uuid;
+
+use crate::{Generator, Snowflake};
+
+// bits of the id in the custom fields of a version 8 UUID
+const HIGH_BITS: u32 = 48;
+const MID_BITS: u32 = 12;
+
+impl Snowflake {
+    /// Embeds the id in a version 8 UUID of RFC 9562.
+    ///
+    /// The upper 48 bits of the id fill `custom_a`, the next 12 bits
+    /// `custom_b`, and the last 4 bits lead `custom_c`, whose other bits are
+    /// zero. UUIDs sort like the ids they embed, and the same id always
+    /// gives the same UUID.
+    ///
+    /// ```rust
+    /// use frostflake::Snowflake;
+    ///
+    /// let id = Snowflake::new(442730451055673344);
+    /// let uuid = id.to_uuid();
+    /// assert_eq!(uuid.to_string(), "0624e4ff-e26c-8000-8000-000000000000");
+    /// assert_eq!(Snowflake::from_uuid(uuid), Some(id));
+    /// ```
+    pub fn to_uuid(&self) -> Uuid {
+        let id = self.as_u64() as u128;
+        let high = id >> (64 - HIGH_BITS);
+        let mid = (id >> (64 - HIGH_BITS - MID_BITS)) & 0xfff;
+        let low = id & 0xf;
+
+        Uuid::from_u128((high << 80) | (0x8 << 76) | (mid << 64) | (0b10 << 62) | (low << 58))
+    }
+
+    /// Extracts the id embedded by [`to_uuid`](Self::to_uuid).
+    ///
+    /// Returns `None` for UUIDs of other versions or variants, and for
+    /// version 8 UUIDs with bits set past the embedded id. The id assumes
+    /// the default layout.
+    pub fn from_uuid(uuid: Uuid) -> Option<Snowflake> {
+        let v = uuid.as_u128();
+        if (v >> 76) & 0xf != 0x8 || (v >> 62) & 0b11 != 0b10 || v & ((1 << 58) - 1) != 0 {
+            return None;
+        }
+
+        let high = v >> 80;
+        let mid = (v >> 64) & 0xfff;
+        let low = (v >> 58) & 0xf;
+        let id = (high << (64 - HIGH_BITS)) | (mid << (64 - HIGH_BITS - MID_BITS)) | low;
+        Some(Snowflake::new(id as u64))
+    }
+}
+
+impl Generator {
+    /// Same as `generate`, but returns the id embedded in a UUID, see
+    /// [`Snowflake::to_uuid`].
+    pub fn generate_uuid(&self) -> Uuid {
+        Snowflake::new(self.generate()).to_uuid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+
+    #[test]
+    fn test_round_trip() {
+        for id in [0, 1, 0xf, 0x1234_5678_9abc_def0, u64::MAX] {
+            let uuid = Snowflake::new(id).to_uuid();
+            assert_eq!(uuid.get_version_num(), 8);
+            assert_eq!(uuid.get_variant(), uuid::Variant::RFC4122);
+            assert_eq!(Snowflake::from_uuid(uuid).map(|s| s.as_u64()), Some(id));
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let g = Generator::new(GeneratorOptions::default());
+        let uuids: Vec<_> = (0..1000).map(|_| g.generate_uuid()).collect();
+        assert!(uuids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_from_uuid_rejects() {
+        assert_eq!(Snowflake::from_uuid(Uuid::nil()), None);
+        assert_eq!(Snowflake::from_uuid(Uuid::max()), None);
+
+        // version 8, but with bits set past the embedded id
+        let uuid = Uuid::from_u128(Snowflake::new(1).to_uuid().as_u128() | 1);
+        assert_eq!(Snowflake::from_uuid(uuid), None);
+    }
+}
@@ -0,0 +1,279 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Core, Error, Generator};
+
+// readings taken to see that the clock doesn't go backwards
+const SAMPLES: u32 = 5;
+// how long the clock may take to advance, on top of two of its ticks
+const ADVANCE_SLACK: Duration = Duration::from_millis(100);
+
+impl Generator {
+    /// Checks that the clock is sane, as done before the first id with
+    /// `GeneratorOptions::verify_clock`.
+    ///
+    /// The clock is sampled for a few milliseconds, and must not go
+    /// backwards, must advance within two ticks, and must be after
+    /// `base_ts`. With the `sntp` feature and a server set with
+    /// `GeneratorOptions::verify_clock_ntp`, it must also be within the
+    /// allowed offset from the time of the server.
+    ///
+    /// This blocks for at least a few milliseconds, and up to a tick or a
+    /// round trip to the NTP server more, so call it at startup rather than
+    /// on a hot path.
+    pub fn verify_clock(&self) -> Result<(), Error> {
+        self.core().verify_clock()
+    }
+}
+
+impl Core {
+    pub(crate) fn verify_clock(&self) -> Result<(), Error> {
+        let clock = &self.opts.clock;
+        let started = Instant::now();
+        let first = clock.now();
+
+        let mut last = first;
+        for _ in 0..SAMPLES {
+            thread::sleep(Duration::from_millis(1));
+            let now = clock.now();
+            if now < last {
+                return Err(Error::ClockMovedBackwards { now, last_ts: last });
+            }
+            last = now;
+        }
+
+        let deadline = Duration::from_millis(self.opts.tick_ms.saturating_mul(2)) + ADVANCE_SLACK;
+        while last == first {
+            if started.elapsed() > deadline {
+                return Err(Error::ClockStalled {
+                    ts: first,
+                    stalled_for: started.elapsed(),
+                });
+            }
+            thread::sleep(Duration::from_millis(1));
+            last = clock.now();
+        }
+
+        if last <= self.opts.base_ts {
+            return Err(Error::TimeBeforeBaseTs {
+                now: last,
+                base_ts: self.opts.base_ts,
+            });
+        }
+
+        #[cfg(feature = "sntp")]
+        if let Some((server, max_offset)) = &self.opts.ntp_server {
+            let ntp_ms =
+                sntp::unix_ms(server, sntp::TIMEOUT).map_err(|e| Error::NtpUnavailable {
+                    reason: format!("{}: {}", server, e),
+                })?;
+            let clock_ms = clock.now().saturating_mul(self.opts.tick_ms) as i64;
+            let offset_ms = clock_ms - ntp_ms;
+            // the clock is truncated to its ticks
+            let max_ms = max_offset.as_millis() as i64 + self.opts.tick_ms as i64;
+            if offset_ms.abs() > max_ms {
+                return Err(Error::ClockOffset {
+                    offset_ms,
+                    max_offset: *max_offset,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A minimal SNTP client, see RFC 4330.
+#[cfg(feature = "sntp")]
+pub(crate) mod sntp {
+    use std::io;
+    use std::net::UdpSocket;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    pub(crate) const TIMEOUT: Duration = Duration::from_secs(2);
+
+    // seconds from 1900-01-01, the NTP epoch, to 1970-01-01
+    const UNIX_OFFSET: i64 = 2_208_988_800;
+
+    /// Current Unix time in milliseconds, as told by `server`, like
+    /// `pool.ntp.org` or `time.example.com:123`.
+    pub(crate) fn unix_ms(server: &str, timeout: Duration) -> io::Result<i64> {
+        let addr = match server.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => server.to_string(),
+            _ => format!("{}:123", server),
+        };
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.connect(addr)?;
+
+        // version 3, client mode
+        let mut request = [0u8; 48];
+        request[0] = 0x1b;
+        let sent = now_ms();
+        socket.send(&request)?;
+        let mut response = [0u8; 48];
+        let len = socket.recv(&mut response)?;
+        let received = now_ms();
+
+        if len < 48 || response[0] & 0x7 != 4 || response[1] == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a valid NTP server response",
+            ));
+        }
+        let server_received = timestamp_ms(&response[32..40]);
+        let server_sent = timestamp_ms(&response[40..48]);
+
+        // the offset of the local clock, with the network delay halved
+        let offset = ((server_received - sent) + (server_sent - received)) / 2;
+        Ok(now_ms() + offset)
+    }
+
+    fn now_ms() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0)
+    }
+
+    // NTP timestamp, seconds and fraction since 1900, as Unix milliseconds
+    pub(crate) fn timestamp_ms(bytes: &[u8]) -> i64 {
+        let secs = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64;
+        let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as i64;
+        (secs - UNIX_OFFSET) * 1000 + ((fraction * 1000) >> 32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GeneratorOptions;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_verify_clock() {
+        let g = Generator::new(GeneratorOptions::default());
+        assert_eq!(g.verify_clock(), Ok(()));
+
+        fn stopped() -> u64 {
+            1483228800000 + 123
+        }
+        let g = Generator::new(GeneratorOptions::default().time_fn(stopped));
+        assert!(matches!(
+            g.verify_clock(),
+            Err(Error::ClockStalled {
+                ts: 1483228800123,
+                ..
+            })
+        ));
+
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 100);
+        fn backwards() -> u64 {
+            NOW.fetch_sub(1, Ordering::Relaxed)
+        }
+        let g = Generator::new(GeneratorOptions::default().time_fn(backwards));
+        assert!(matches!(
+            g.verify_clock(),
+            Err(Error::ClockMovedBackwards { .. })
+        ));
+
+        // the clock is sane, but before the epoch
+        let g = Generator::new(GeneratorOptions::default().base_ts(u64::MAX >> 23));
+        assert!(matches!(
+            g.verify_clock(),
+            Err(Error::TimeBeforeBaseTs { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_clock_option() {
+        static NOW: AtomicU64 = AtomicU64::new(1483228800000 + 100);
+        fn my_time_fn() -> u64 {
+            NOW.load(Ordering::Relaxed)
+        }
+
+        let g = Generator::new(GeneratorOptions::default().time_fn(my_time_fn));
+        assert!(g.try_generate().is_ok());
+
+        // every id fails until the clock passes the check
+        let g = Generator::new(
+            GeneratorOptions::default()
+                .time_fn(my_time_fn)
+                .verify_clock(true),
+        );
+        assert!(matches!(g.try_generate(), Err(Error::ClockStalled { .. })));
+        assert!(matches!(g.try_generate(), Err(Error::ClockStalled { .. })));
+
+        let ticking = thread::spawn(|| {
+            for _ in 0..200 {
+                NOW.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        assert!(g.try_generate().is_ok());
+        ticking.join().unwrap();
+        // checked only once
+        assert!(g.try_generate().is_ok());
+    }
+
+    #[cfg(feature = "sntp")]
+    #[test]
+    fn test_verify_clock_ntp() {
+        use std::net::UdpSocket;
+
+        // a server answering with its clock off by `offset_ms`
+        fn server(offset_ms: i64) -> String {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let addr = socket.local_addr().unwrap().to_string();
+            thread::spawn(move || {
+                let mut request = [0; 48];
+                let (_, peer) = socket.recv_from(&mut request).unwrap();
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap();
+                let ms = now.as_millis() as i64 + offset_ms;
+                let secs = (ms.div_euclid(1000) + 2_208_988_800) as u32;
+                let fraction = ((ms.rem_euclid(1000) << 32) / 1000) as u32;
+
+                let mut response = [0; 48];
+                // version 3, server mode, stratum 1
+                response[0] = 0x1c;
+                response[1] = 1;
+                for at in [32, 40] {
+                    response[at..at + 4].copy_from_slice(&secs.to_be_bytes());
+                    response[at + 4..at + 8].copy_from_slice(&fraction.to_be_bytes());
+                }
+                socket.send_to(&response, peer).unwrap();
+            });
+            addr
+        }
+
+        let opts = GeneratorOptions::default();
+        let g = Generator::new(
+            opts.clone()
+                .verify_clock_ntp(&server(0), Duration::from_millis(500)),
+        );
+        assert_eq!(g.verify_clock(), Ok(()));
+
+        let g = Generator::new(
+            opts.clone()
+                .verify_clock_ntp(&server(-10_000), Duration::from_millis(500)),
+        );
+        match g.verify_clock() {
+            Err(Error::ClockOffset { offset_ms, .. }) => {
+                assert!((9_000..11_000).contains(&offset_ms))
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+
+        // nobody answers
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = silent.local_addr().unwrap().to_string();
+        assert!(sntp::unix_ms(&addr, Duration::from_millis(50)).is_err());
+
+        assert_eq!(
+            sntp::timestamp_ms(&[0x83, 0xaa, 0x7e, 0x80, 0x80, 0, 0, 0]),
+            500
+        );
+    }
+}